@@ -0,0 +1,96 @@
+// This file is dual licensed under the MIT and the University of Illinois Open
+// Source Licenses. See LICENSE.TXT for details.
+//
+// Times parsing, cloning and demangling the names of the differential test
+// corpus, and counts the memory they allocate. Run with `cargo bench`.
+
+extern crate flate2;
+extern crate msvc_demangler;
+
+use flate2::read::GzDecoder;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Read;
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use msvc_demangler::{DemangleOptions, ParseResult, Type};
+
+const CORPUS: &[u8] = include_bytes!("../tests/corpus/llvm-undname.tsv.gz");
+
+// How often every name is processed.
+const ROUNDS: usize = 200;
+
+// Counts the allocations of the program and their bytes.
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+// Runs `f` on all of `names` ROUNDS times and prints the time, allocations
+// and allocated bytes per name.
+fn bench<F: FnMut(usize, &str)>(label: &str, names: &[&str], mut f: F) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let bytes = BYTES.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        for (i, name) in names.iter().enumerate() {
+            f(i, name);
+        }
+    }
+    let count = ROUNDS * names.len();
+    println!(
+        "{:<10} {:>6} ns {:>6.1} allocations {:>6} bytes per name",
+        label,
+        start.elapsed().as_nanos() / count as u128,
+        (ALLOCATIONS.load(Ordering::Relaxed) - allocations) as f64 / count as f64,
+        (BYTES.load(Ordering::Relaxed) - bytes) / count,
+    );
+}
+
+fn main() {
+    let mut corpus = String::new();
+    GzDecoder::new(CORPUS).read_to_string(&mut corpus).unwrap();
+    let names: Vec<&str> = corpus
+        .lines()
+        .filter_map(|line| line.split('\t').next())
+        .filter(|name| msvc_demangler::parse(name).is_ok())
+        .collect();
+    let parsed: Vec<ParseResult> = names.iter().map(|name| msvc_demangler::parse(name).unwrap()).collect();
+
+    println!("size_of::<Type>() = {}", mem::size_of::<Type>());
+    println!("{} names, {} rounds", names.len(), ROUNDS);
+    bench("parse", &names, |_, name| {
+        let _ = msvc_demangler::parse(name);
+    });
+    bench("clone", &names, |i, _| {
+        let _ = parsed[i].clone();
+    });
+    bench("serialize", &names, |i, _| {
+        let _ = msvc_demangler::serialize(&parsed[i], DemangleOptions::new());
+    });
+    bench("demangle", &names, |_, name| {
+        let _ = msvc_demangler::demangle(name, DemangleOptions::new());
+    });
+}
@@ -8,7 +8,7 @@ fn main() {
         std::process::exit(1);
     }
 
-    match demangle(&args[1], DemangleFlags::LotsOfWhitespace) {
+    match demangle(&args[1], DemangleOptions::new()) {
         Ok(s) => {
             println!("{}", s);
         }
@@ -0,0 +1,57 @@
+/* This file is dual licensed under the MIT and the University of Illinois Open
+ * Source Licenses. See LICENSE.TXT for details.
+ *
+ * C interface of msvc-demangler, see src/capi.rs. */
+
+#ifndef MSVC_DEMANGLER_H
+#define MSVC_DEMANGLER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Flags, see the DemangleOptions constants of the same names. */
+#define MSVC_DEMANGLE_LESS_WHITESPACE          0x00001
+#define MSVC_DEMANGLE_NO_RETURN_TYPE           0x00002
+#define MSVC_DEMANGLE_NO_ACCESS_SPECIFIERS     0x00004
+#define MSVC_DEMANGLE_NO_MEMBER_TYPE           0x00008
+#define MSVC_DEMANGLE_NO_ARGUMENTS             0x00010
+#define MSVC_DEMANGLE_NO_CV_THISTYPE           0x00020
+#define MSVC_DEMANGLE_UNDNAME_COMPAT           0x00040
+#define MSVC_DEMANGLE_LLVM_UNDNAME_COMPAT      0x00080
+#define MSVC_DEMANGLE_NO_MS_KEYWORDS           0x00100
+#define MSVC_DEMANGLE_GNU_STYLE                0x00200
+#define MSVC_DEMANGLE_NO_CLASS_KEYWORDS        0x00400
+#define MSVC_DEMANGLE_STD_TYPEDEFS             0x00800
+#define MSVC_DEMANGLE_NO_DEFAULT_TEMPLATE_ARGS 0x01000
+#define MSVC_DEMANGLE_ENUM_UNDERLYING_TYPES    0x02000
+#define MSVC_DEMANGLE_NO_LEADING_UNDERSCORES   0x04000
+#define MSVC_DEMANGLE_NO_SPECIAL_SYMS          0x08000
+#define MSVC_DEMANGLE_NO_TEMPLATE_ARGS         0x10000
+#define MSVC_DEMANGLE_DESCRIBE_SPECIAL_SYMS    0x20000
+#define MSVC_DEMANGLE_NAME_ONLY                0x40000
+#define MSVC_DEMANGLE_ANONYMOUS_NAMESPACE_PARENS 0x80000
+#define MSVC_DEMANGLE_STRICT                   0x100000
+#define MSVC_DEMANGLE_LOSSY                    0x200000
+
+/* Error codes. */
+#define MSVC_DEMANGLE_ERR_NULL    (-1)
+#define MSVC_DEMANGLE_ERR_UTF8    (-2)
+#define MSVC_DEMANGLE_ERR_INVALID (-3)
+#define MSVC_DEMANGLE_ERR_TOO_LONG (-4)
+#define MSVC_DEMANGLE_ERR_PANIC   (-5)
+
+/* Demangles the NUL-terminated string input into buf, which has room for
+ * buf_len bytes. Like snprintf(), returns the length of the demangled name
+ * and writes as much of it as fits, NUL-terminated unless buf_len is 0.
+ * Returns a negative MSVC_DEMANGLE_ERR_* code on errors. */
+int msvc_demangle(const char *input, char *buf, size_t buf_len, uint32_t flags);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* MSVC_DEMANGLER_H */
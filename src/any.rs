@@ -0,0 +1,166 @@
+// This file is dual licensed under the MIT and the University of Illinois Open
+// Source Licenses. See LICENSE.TXT for details.
+//
+// Demangling of names from other compilers, delegated to cpp_demangle
+// (the "itanium" feature) and rustc-demangle (the "rust" feature).
+
+use super::{demangle, DemangleOptions, Error, Result};
+#[cfg(any(feature = "itanium", feature = "rust"))]
+use super::OptionFlags;
+
+/// The mangling schemes `demangle_any()` knows about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scheme {
+    /// The scheme of the Microsoft compiler, e.g. `?f@@YAXXZ`.
+    Msvc,
+    /// The Itanium C++ ABI used by GCC and Clang, e.g. `_Z1fv`.
+    Itanium,
+    /// Rust's legacy and v0 schemes, e.g. `_ZN3foo3bar17h05af221e174051e9E`
+    /// or `_RNvCs1234_3foo3bar`.
+    Rust,
+}
+
+/// Tells which scheme `input` is mangled with from its prefix, without
+/// checking the rest. This works regardless of the enabled features.
+///
+/// Mach-O adds an underscore to all names, so `__Z` and `__R` are
+/// recognized as well. Rust's legacy scheme is a subset of the Itanium one
+/// and is told apart by the hash ending the path, `17h<16 hex digits>E`.
+pub fn detect_scheme(input: &str) -> Option<Scheme> {
+    if input.starts_with('?') {
+        return Some(Scheme::Msvc);
+    }
+    let input = if input.starts_with("__") { &input[1..] } else { input };
+    if input.starts_with("_R") || (input.starts_with("_ZN") && is_legacy_rust(input)) {
+        Some(Scheme::Rust)
+    } else if input.starts_with("_Z") {
+        Some(Scheme::Itanium)
+    } else {
+        None
+    }
+}
+
+// Whether `input` ends in the hash of the legacy Rust scheme, possibly
+// followed by a suffix added by LLVM such as `.llvm.1234`.
+fn is_legacy_rust(input: &str) -> bool {
+    let name = match input.find('.') {
+        Some(dot) => &input[..dot],
+        None => input,
+    };
+    let bytes = name.as_bytes();
+    bytes.len() >= 20 && {
+        let hash = &bytes[bytes.len() - 20..];
+        hash.starts_with(b"17h") && hash[19] == b'E' && hash[3..19].iter().all(u8::is_ascii_hexdigit)
+    }
+}
+
+/// Demangles `input` with whichever scheme it is mangled with, so that
+/// programs processing symbols from all platforms, such as minidump
+/// processors, need only one demangling interface.
+///
+/// MSVC names are demangled by this crate and honor all `flags`. Itanium
+/// names need the "itanium" feature and are demangled by `cpp_demangle`,
+/// which only supports `NO_ARGUMENTS` and `NO_RETURN_TYPE`. Rust names need
+/// the "rust" feature and are demangled by `rustc-demangle`, leaving out
+/// the hash with `NAME_ONLY`. Names of schemes whose feature is disabled,
+/// and names of no known scheme, are errors.
+///
+/// ```
+/// use msvc_demangler::any::demangle_any;
+/// use msvc_demangler::DemangleOptions;
+///
+/// let demangled = demangle_any("?f@@YAXXZ", DemangleOptions::new());
+/// assert_eq!(demangled.unwrap(), "void __cdecl f(void)");
+/// ```
+pub fn demangle_any<O: Into<DemangleOptions>>(input: &str, flags: O) -> Result<String> {
+    let flags = flags.into();
+    match detect_scheme(input) {
+        Some(Scheme::Msvc) => demangle(input, flags),
+        Some(Scheme::Itanium) => demangle_itanium(input, flags),
+        Some(Scheme::Rust) => demangle_rust(input, flags),
+        None => Err(Error::new(format!("unknown mangling scheme: {:?}", input))),
+    }
+}
+
+#[cfg(feature = "itanium")]
+fn demangle_itanium(input: &str, flags: DemangleOptions) -> Result<String> {
+    let mut options = cpp_demangle::DemangleOptions::new();
+    if flags.flags.contains(OptionFlags::NO_ARGUMENTS) {
+        options = options.no_params();
+    }
+    if flags.flags.contains(OptionFlags::NO_RETURN_TYPE) {
+        options = options.no_return_type();
+    }
+    let symbol = cpp_demangle::Symbol::new(input.as_bytes())
+        .map_err(|err| Error::new(format!("invalid Itanium name: {}", err)))?;
+    symbol
+        .demangle_with_options(&options)
+        .map_err(|_| Error::new(format!("cannot demangle Itanium name: {:?}", input)))
+}
+
+#[cfg(not(feature = "itanium"))]
+fn demangle_itanium(_input: &str, _flags: DemangleOptions) -> Result<String> {
+    Err(Error::new("demangling Itanium names needs the \"itanium\" feature".to_owned()))
+}
+
+#[cfg(feature = "rust")]
+fn demangle_rust(input: &str, flags: DemangleOptions) -> Result<String> {
+    let demangled = rustc_demangle::try_demangle(input)
+        .map_err(|_| Error::new(format!("invalid Rust name: {:?}", input)))?;
+    if flags.flags.contains(OptionFlags::NAME_ONLY) {
+        Ok(format!("{:#}", demangled))
+    } else {
+        Ok(demangled.to_string())
+    }
+}
+
+#[cfg(not(feature = "rust"))]
+fn demangle_rust(_input: &str, _flags: DemangleOptions) -> Result<String> {
+    Err(Error::new("demangling Rust names needs the \"rust\" feature".to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{demangle_any, detect_scheme, Scheme};
+
+    #[test]
+    fn schemes() {
+        let itanium = "_ZN5space3fooEibc";
+        let legacy_rust = "_ZN4core3fmt5write17h05af221e174051e9E";
+        let v0_rust = "_RNvCs1234_7mycrate3foo";
+        assert_eq!(detect_scheme("?f@@YAXXZ"), Some(Scheme::Msvc));
+        assert_eq!(detect_scheme(itanium), Some(Scheme::Itanium));
+        assert_eq!(detect_scheme("__Z1fv"), Some(Scheme::Itanium));
+        assert_eq!(detect_scheme(legacy_rust), Some(Scheme::Rust));
+        assert_eq!(detect_scheme("_ZN4core3fmt5write17h05af221e174051e9E.llvm.42"), Some(Scheme::Rust));
+        assert_eq!(detect_scheme("_ZN4core3fmt5write17h05af221e174051ezE"), Some(Scheme::Itanium));
+        assert_eq!(detect_scheme(v0_rust), Some(Scheme::Rust));
+        assert_eq!(detect_scheme("__RNvC7mycrate3foo"), Some(Scheme::Rust));
+        assert_eq!(detect_scheme("main"), None);
+
+        let flags = ::DemangleOptions::new();
+        assert_eq!(demangle_any("?f@@YAXXZ", flags).unwrap(), "void __cdecl f(void)");
+        assert_eq!(demangle_any("?f@@YAXXZ", ::DemangleOptions::cxxfilt()).unwrap(), "f()");
+        assert!(demangle_any("main", flags).is_err());
+
+        #[cfg(feature = "itanium")]
+        {
+            assert_eq!(demangle_any(itanium, flags).unwrap(), "space::foo(int, bool, char)");
+            assert_eq!(demangle_any(itanium, ::DemangleOptions::NO_ARGUMENTS).unwrap(), "space::foo");
+            assert!(demangle_any("_Zx", flags).is_err());
+        }
+        #[cfg(not(feature = "itanium"))]
+        assert!(demangle_any(itanium, flags).unwrap_err().to_string().contains("\"itanium\" feature"));
+
+        #[cfg(feature = "rust")]
+        {
+            assert_eq!(demangle_any(legacy_rust, flags).unwrap(), "core::fmt::write::h05af221e174051e9");
+            assert_eq!(demangle_any(legacy_rust, ::DemangleOptions::NAME_ONLY).unwrap(), "core::fmt::write");
+            assert_eq!(demangle_any(v0_rust, flags).unwrap(), "mycrate[3c1c0]::foo");
+            assert_eq!(demangle_any(v0_rust, ::DemangleOptions::NAME_ONLY).unwrap(), "mycrate::foo");
+            assert!(demangle_any("_Rx", flags).is_err());
+        }
+        #[cfg(not(feature = "rust"))]
+        assert!(demangle_any(v0_rust, flags).unwrap_err().to_string().contains("\"rust\" feature"));
+    }
+}
@@ -0,0 +1,558 @@
+extern crate msvc_demangler;
+
+use msvc_demangler::{DemangleOptions, Demangler};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+const USAGE: &str = "\
+Usage: msvc-demangler [OPTIONS] [SYMBOL...]
+       msvc-demangler [OPTIONS] --files FILE|DIRECTORY...
+
+Demangles the given symbols, or the symbols read from stdin, one per line.
+Symbols which cannot be demangled are printed unchanged.
+
+With --files, demangles the lines of the given files and of the files in
+the given directories, several at a time, writing the output for each to a
+file of the same name with .demangled appended. With --output-dir, the
+files must have distinct names.
+
+With --diff, compares the demangled symbols read from stdin with the lines
+of a reference file, e.g. the output of undname.exe for the same symbols,
+and sums up the differences. Exits with status 1 if there are any.
+
+Options:
+  --undname                 imitate Microsoft's undname.exe
+  --llvm-undname            imitate LLVM's llvm-undname
+  --cxxfilt                 compact output like GNU c++filt
+  --summary                 short descriptions like `vtable for ns::Foo`
+  --breakpad                the names in Breakpad .sym files
+  --text                    demangle all names embedded in each line
+  --map                     demangle the symbol column of dumpbin /symbols
+                            or /exports output or of a link.exe map file
+  --max-length N            cut the output off after N bytes
+  --wrap-width N            spread long lists over lines of N columns
+  --undname-flags N         the UNDNAME_* flags of undname.exe, e.g. 0x1000
+  --files                   the arguments are files or directories
+  --output-dir DIR          write the output for --files to DIR instead
+  --jobs N                  process N files at a time
+  --diff FILE               compare with the demangled symbols in FILE
+  --stats                   print how well the symbols could be demangled
+  -h, --help                print this help
+
+Switches, see the DemangleOptions constants of the same names:
+";
+
+// The switches that can be given on the command line, e.g.
+// --no-return-type for DemangleOptions::NO_RETURN_TYPE.
+const SWITCHES: &[(&str, DemangleOptions)] = &[
+    ("less-whitespace", DemangleOptions::LESS_WHITESPACE),
+    ("no-return-type", DemangleOptions::NO_RETURN_TYPE),
+    ("no-access-specifiers", DemangleOptions::NO_ACCESS_SPECIFIERS),
+    ("no-member-type", DemangleOptions::NO_MEMBER_TYPE),
+    ("no-arguments", DemangleOptions::NO_ARGUMENTS),
+    ("no-cv-thistype", DemangleOptions::NO_CV_THISTYPE),
+    ("no-ms-keywords", DemangleOptions::NO_MS_KEYWORDS),
+    ("gnu-style", DemangleOptions::GNU_STYLE),
+    ("no-class-keywords", DemangleOptions::NO_CLASS_KEYWORDS),
+    ("std-typedefs", DemangleOptions::STD_TYPEDEFS),
+    ("no-default-template-args", DemangleOptions::NO_DEFAULT_TEMPLATE_ARGS),
+    ("enum-underlying-types", DemangleOptions::ENUM_UNDERLYING_TYPES),
+    ("no-leading-underscores", DemangleOptions::NO_LEADING_UNDERSCORES),
+    ("no-special-syms", DemangleOptions::NO_SPECIAL_SYMS),
+    ("no-template-args", DemangleOptions::NO_TEMPLATE_ARGS),
+    ("name-only", DemangleOptions::NAME_ONLY),
+    ("anonymous-namespace-parens", DemangleOptions::ANONYMOUS_NAMESPACE_PARENS),
+    ("strict", DemangleOptions::STRICT),
+];
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("msvc-demangler: {}", message);
+    eprintln!("Try `msvc-demangler --help' for more information.");
+    process::exit(2);
+}
+
+fn print_usage() {
+    print!("{}", USAGE);
+    for &(name, _) in SWITCHES {
+        println!("  --{}", name);
+    }
+}
+
+fn number_arg(args: &mut env::Args, option: &str) -> usize {
+    match args.next().map(|arg| arg.parse()) {
+        Some(Ok(n)) => n,
+        _ => usage_error(&format!("{} needs a number", option)),
+    }
+}
+
+// What each line of input is.
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Symbol,
+    Text,
+    Map,
+}
+
+// The byte range of the symbol in a line of `dumpbin /symbols` or
+// `dumpbin /exports` output or of a link.exe map file, if there is one.
+fn symbol_column(line: &str) -> Option<(usize, usize)> {
+    let is_hex = |field: &str| field.bytes().all(|c| c.is_ascii_hexdigit());
+    let mut fields = Vec::new();
+    let mut start = None;
+    for (i, c) in line.char_indices().chain(Some((line.len(), ' '))) {
+        match (start, c.is_whitespace()) {
+            (None, false) => start = Some(i),
+            (Some(s), true) => {
+                fields.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    let field = |i: usize| fields.get(i).map(|&(start, end)| &line[start..end]);
+
+    // dumpbin /symbols: "008 00000000 SECT3  notype ()    External     | ?f@@YAXXZ"
+    if let Some(bar) = line.find("| ") {
+        return fields.iter().find(|&&(start, _)| start > bar).cloned();
+    }
+    // link.exe map: " 0001:00000000       ?f@@YAXXZ       00401000 f   f.obj"
+    let first = field(0)?;
+    let mut address = first.splitn(2, ':');
+    if let (Some(section), Some(offset)) = (address.next(), address.next()) {
+        if section.len() == 4 && is_hex(section) && offset.len() == 8 && is_hex(offset) && fields.len() >= 3 {
+            return Some(fields[1]);
+        }
+        return None;
+    }
+    // dumpbin /exports: "          1    0 00001000 ?f@@YAXXZ", where the
+    // RVA is missing for forwarded functions.
+    if first.bytes().all(|c| c.is_ascii_digit()) && is_hex(field(1)?) {
+        let rva = field(2)?;
+        if rva.len() == 8 && is_hex(rva) {
+            return fields.get(3).cloned();
+        }
+        return Some(fields[2]);
+    }
+    None
+}
+
+// Reads a number given in decimal or, like undname flags, in hex with 0x.
+fn flags_arg(args: &mut env::Args, option: &str) -> u32 {
+    let n = args.next().and_then(|arg| {
+        if arg.starts_with("0x") || arg.starts_with("0X") {
+            u32::from_str_radix(&arg[2..], 16).ok()
+        } else {
+            arg.parse().ok()
+        }
+    });
+    match n {
+        Some(n) => n,
+        None => usage_error(&format!("{} needs a number", option)),
+    }
+}
+
+// Writes the demangled form of `line` followed by a newline to `w`.
+fn write_demangled<W: Write>(
+    w: &mut W,
+    mode: Mode,
+    demangler: &mut Demangler,
+    line: &str,
+) -> io::Result<()> {
+    match mode {
+        Mode::Symbol => {
+            let symbol = line.trim();
+            writeln!(w, "{}", demangler.demangle(symbol).unwrap_or(symbol))
+        }
+        Mode::Text => writeln!(w, "{}", msvc_demangler::demangle_text(line, demangler.options())),
+        Mode::Map => match symbol_column(line) {
+            Some((start, end)) => {
+                let symbol = &line[start..end];
+                let demangled = demangler.demangle(symbol).unwrap_or(symbol);
+                writeln!(w, "{}{}{}", &line[..start], demangled, &line[end..])
+            }
+            None => writeln!(w, "{}", line),
+        },
+    }
+}
+
+// The files named on the command line, with directories replaced by the
+// files in them. Outputs of earlier runs are skipped.
+fn input_files(args: &[String]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for arg in args {
+        let path = Path::new(arg);
+        if !path.is_dir() {
+            files.push(path.to_owned());
+            continue;
+        }
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() && entry.path().extension().is_none_or(|ext| ext != "demangled") {
+                entries.push(entry.path());
+            }
+        }
+        entries.sort();
+        files.extend(entries);
+    }
+    Ok(files)
+}
+
+// Demangles the lines of `input` into `output`.
+fn demangle_file(input: &Path, output: &Path, mode: Mode, demangler: &mut Demangler) -> io::Result<()> {
+    let reader = io::BufReader::new(fs::File::open(input)?);
+    let mut writer = BufWriter::new(fs::File::create(output)?);
+    for line in reader.lines() {
+        write_demangled(&mut writer, mode, demangler, &line?)?;
+    }
+    writer.flush()
+}
+
+// The input and output file of each of `inputs`. Fails if two of them
+// would be written to the same file, as files of the same name from
+// different directories would be in `output_dir`, or if an output would
+// overwrite an input.
+fn output_files(inputs: Vec<PathBuf>, output_dir: Option<&Path>) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+    let files: Vec<(PathBuf, PathBuf)> = inputs
+        .into_iter()
+        .map(|input| {
+            let mut name = input.file_name().unwrap_or_default().to_owned();
+            let output = match output_dir {
+                Some(dir) => dir.join(name),
+                None => {
+                    name.push(".demangled");
+                    input.with_file_name(name)
+                }
+            };
+            (input, output)
+        })
+        .collect();
+    let mut writers: BTreeMap<&Path, &Path> = BTreeMap::new();
+    for (input, output) in &files {
+        if let Some(other) = writers.insert(output, input) {
+            return Err(format!(
+                "{} and {} would both be written to {}",
+                other.display(),
+                input.display(),
+                output.display()
+            ));
+        }
+    }
+    for (input, _) in &files {
+        if let Some(writer) = writers.get(input.as_path()) {
+            return Err(format!(
+                "{} would be overwritten by the output for {}",
+                input.display(),
+                writer.display()
+            ));
+        }
+    }
+    Ok(files)
+}
+
+// Demangles the input files of `files` into their output files with `jobs`
+// threads. Returns whether all of them were written successfully.
+fn demangle_files(files: &[(PathBuf, PathBuf)], jobs: usize, mode: Mode, flags: DemangleOptions) -> bool {
+    let next = AtomicUsize::new(0);
+    let ok = AtomicBool::new(true);
+    let worker = || {
+        let mut demangler = Demangler::new(flags);
+        while let Some((input, output)) = files.get(next.fetch_add(1, Ordering::Relaxed)) {
+            if let Err(err) = demangle_file(input, output, mode, &mut demangler) {
+                eprintln!("msvc-demangler: {}: {}", input.display(), err);
+                ok.store(false, Ordering::Relaxed);
+            }
+        }
+    };
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, files.len().max(1)) {
+            scope.spawn(worker);
+        }
+    });
+    ok.into_inner()
+}
+
+// The kind of an error, the part of messages like "unknown primitive
+// type: X" before the colon.
+fn error_kind(err: &msvc_demangler::Error) -> String {
+    err.to_string().split(':').next().unwrap_or_default().to_owned()
+}
+
+// For errors about an unknown code, the kind and the code without the rest
+// of the symbol after it, e.g. "unknown primitive type: _Z". Codes are a
+// single character, or several after `_`, `$` or `?` prefixes.
+fn unknown_code(err: &msvc_demangler::Error) -> Option<String> {
+    let message = err.to_string();
+    if !message.starts_with("unknown") {
+        return None;
+    }
+    let (kind, rest) = match message.split_once(": ") {
+        Some(parts) => parts,
+        None => return Some(message),
+    };
+    let prefix = rest.find(|c| !matches!(c, '_' | '$' | '?')).unwrap_or(rest.len());
+    let end = rest[prefix..].chars().next().map_or(prefix, |c| prefix + c.len_utf8());
+    Some(format!("{}: {}", kind, &rest[..end]))
+}
+
+// Compares the demangled forms of the symbols read from stdin with the
+// lines of `reference`. Returns whether all of them are the same.
+fn diff(reference: &Path, demangler: &mut Demangler) -> io::Result<bool> {
+    let reference = fs::read_to_string(reference)?;
+    let mut references = reference.lines();
+    let mut mismatches: BTreeMap<String, usize> = BTreeMap::new();
+    let mut total = 0;
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line?;
+        let symbol = line.trim();
+        let expected = match references.next() {
+            Some(expected) => expected.trim(),
+            None => return Err(io::Error::other("the reference has fewer lines than the input")),
+        };
+        total += 1;
+        let demangled = demangler.demangle(symbol);
+        let kind = match demangled {
+            Ok(demangled) if demangled == expected => {
+                println!("match     {}", symbol);
+                continue;
+            }
+            _ if expected == symbol => "not demangled by the reference".to_owned(),
+            Ok(demangled) if demangled.split_whitespace().eq(expected.split_whitespace()) => {
+                "whitespace".to_owned()
+            }
+            Ok(demangled) if demangled.replace(' ', "") == expected.replace(' ', "") => "spacing".to_owned(),
+            Ok(_) => "other difference".to_owned(),
+            Err(ref err) => format!("error: {}", error_kind(err)),
+        };
+        println!("mismatch  {}", symbol);
+        println!("  expected: {}", expected);
+        match demangled {
+            Ok(demangled) => println!("  ours:     {}", demangled),
+            Err(err) => println!("  ours:     error: {}", err),
+        }
+        *mismatches.entry(kind).or_default() += 1;
+    }
+    if references.next().is_some() {
+        return Err(io::Error::other("the reference has more lines than the input"));
+    }
+
+    let mismatched: usize = mismatches.values().sum();
+    println!();
+    for (kind, count) in &mismatches {
+        println!("{:>8}  {}", count, kind);
+    }
+    println!("{:>8}  of {} symbols differ", mismatched, total);
+    Ok(mismatched == 0)
+}
+
+// Demangles `symbols` and prints how many succeeded, why the others
+// failed and which symbols are the longest and the most complex.
+fn stats<I: Iterator<Item = io::Result<String>>>(symbols: I, demangler: &mut Demangler) -> io::Result<()> {
+    // The number of entries in the lists of the longest and most complex
+    // symbols.
+    const TOP: usize = 5;
+
+    let flags = demangler.options();
+    let mut total = 0;
+    let mut failures: BTreeMap<String, usize> = BTreeMap::new();
+    let mut unknown_codes: BTreeMap<String, usize> = BTreeMap::new();
+    let mut longest: Vec<(usize, String)> = Vec::new();
+    let mut most_complex: Vec<(usize, String)> = Vec::new();
+    // Keeps the TOP largest entries of `list`, largest first.
+    let record = |list: &mut Vec<(usize, String)>, size: usize, symbol: &str| {
+        if list.len() < TOP || list[TOP - 1].0 < size {
+            list.push((size, symbol.to_owned()));
+            list.sort_by_key(|&(size, _)| Reverse(size));
+            list.truncate(TOP);
+        }
+    };
+
+    for symbol in symbols {
+        let symbol = symbol?;
+        let symbol = symbol.trim();
+        total += 1;
+        match demangler.demangle(symbol) {
+            Ok(demangled) => {
+                record(&mut longest, demangled.len(), symbol);
+                let mut events = 0;
+                let _ = msvc_demangler::parse_events(symbol, flags, |_| events += 1);
+                record(&mut most_complex, events, symbol);
+            }
+            Err(err) => {
+                if let Some(code) = unknown_code(&err) {
+                    *unknown_codes.entry(code).or_default() += 1;
+                }
+                *failures.entry(error_kind(&err)).or_default() += 1;
+            }
+        }
+    }
+
+    let failed: usize = failures.values().sum();
+    let percent = |n: usize| if total == 0 { 0.0 } else { n as f64 * 100.0 / total as f64 };
+    println!("{:>8}  symbols", total);
+    println!("{:>8}  demangled ({:.1}%)", total - failed, percent(total - failed));
+    println!("{:>8}  failed ({:.1}%)", failed, percent(failed));
+    let by_count = |title: &str, counts: BTreeMap<String, usize>| {
+        if counts.is_empty() {
+            return;
+        }
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by_key(|&(_, count)| Reverse(count));
+        println!("\n{}:", title);
+        for (what, count) in counts.into_iter().take(20) {
+            println!("{:>8}  {}", count, what);
+        }
+    };
+    by_count("Failures by error", failures);
+    by_count("Most common unknown codes", unknown_codes);
+    let by_size = |title: &str, list: &[(usize, String)]| {
+        if list.is_empty() {
+            return;
+        }
+        println!("\n{}:", title);
+        for (size, symbol) in list {
+            println!("{:>8}  {}", size, symbol);
+        }
+    };
+    by_size("Longest demangled names (bytes)", &longest);
+    by_size("Most complex symbols (parse events)", &most_complex);
+    Ok(())
+}
+
+fn main() {
+    let mut flags = DemangleOptions::new();
+    let mut mode = Mode::Symbol;
+    let mut files = false;
+    let mut output_dir = None;
+    let mut reference = None;
+    let mut print_stats = false;
+    let mut jobs = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut symbols = Vec::new();
+
+    let mut args = env::args();
+    args.next();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-h" | "--help" => {
+                print_usage();
+                return;
+            }
+            "--undname" => flags |= DemangleOptions::undname(),
+            "--llvm-undname" => flags |= DemangleOptions::llvm_undname(),
+            "--cxxfilt" => flags |= DemangleOptions::cxxfilt(),
+            "--summary" => flags |= DemangleOptions::summary(),
+            "--breakpad" => flags |= DemangleOptions::breakpad(),
+            "--text" => mode = Mode::Text,
+            "--map" => mode = Mode::Map,
+            "--files" => files = true,
+            "--output-dir" => match args.next() {
+                Some(dir) => output_dir = Some(PathBuf::from(dir)),
+                None => usage_error("--output-dir needs a directory"),
+            },
+            "--jobs" => jobs = number_arg(&mut args, &arg),
+            "--stats" => print_stats = true,
+            "--diff" => match args.next() {
+                Some(file) => reference = Some(PathBuf::from(file)),
+                None => usage_error("--diff needs a file"),
+            },
+            "--max-length" => flags = flags.max_length(number_arg(&mut args, &arg)),
+            "--wrap-width" => flags = flags.wrap_width(number_arg(&mut args, &arg)),
+            "--undname-flags" => flags |= DemangleOptions::from_undname_flags(flags_arg(&mut args, &arg)),
+            _ if arg.starts_with("--") => {
+                match SWITCHES.iter().find(|&&(name, _)| name == &arg[2..]) {
+                    Some(&(_, switch)) => flags |= switch,
+                    None => usage_error(&format!("unknown option {}", arg)),
+                }
+            }
+            _ => symbols.push(arg),
+        }
+    }
+
+    if files {
+        if symbols.is_empty() {
+            usage_error("--files needs files or directories");
+        }
+        let inputs = match input_files(&symbols) {
+            Ok(inputs) => inputs,
+            Err(err) => {
+                eprintln!("msvc-demangler: {}", err);
+                process::exit(1);
+            }
+        };
+        if inputs.is_empty() {
+            eprintln!("msvc-demangler: no files to demangle");
+            process::exit(1);
+        }
+        let files = match output_files(inputs, output_dir.as_deref()) {
+            Ok(files) => files,
+            Err(err) => {
+                eprintln!("msvc-demangler: {}", err);
+                process::exit(1);
+            }
+        };
+        if let Some(ref dir) = output_dir {
+            if let Err(err) = fs::create_dir_all(dir) {
+                eprintln!("msvc-demangler: {}: {}", dir.display(), err);
+                process::exit(1);
+            }
+        }
+        if !demangle_files(&files, jobs, mode, flags) {
+            process::exit(1);
+        }
+        return;
+    }
+
+    let mut demangler = Demangler::new(flags);
+    if let Some(reference) = reference {
+        match diff(&reference, &mut demangler) {
+            Ok(true) => return,
+            Ok(false) => process::exit(1),
+            Err(err) => {
+                eprintln!("msvc-demangler: {}: {}", reference.display(), err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if print_stats {
+        let result = if symbols.is_empty() {
+            let stdin = io::stdin();
+            let lines = stdin.lock().lines();
+            stats(lines, &mut demangler)
+        } else {
+            stats(symbols.into_iter().map(Ok), &mut demangler)
+        };
+        if let Err(err) = result {
+            eprintln!("msvc-demangler: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let result = if symbols.is_empty() {
+        let stdin = io::stdin();
+        stdin
+            .lock()
+            .lines()
+            .try_for_each(|line| write_demangled(&mut stdout, mode, &mut demangler, &line?))
+    } else {
+        symbols
+            .iter()
+            .try_for_each(|symbol| write_demangled(&mut stdout, mode, &mut demangler, symbol))
+    };
+    if let Err(err) = result {
+        eprintln!("msvc-demangler: {}", err);
+        process::exit(1);
+    }
+}
@@ -8,11 +8,7 @@ fn main() {
     let args = env::args();
 
     let print_demangled = |sym: &str| {
-        let demangled = msvc_demangler::demangle(&sym, msvc_demangler::DemangleFlags::LotsOfWhitespace);
-        match demangled {
-            Ok(ref string) => println!("{}", string),
-            _ => println!("{}", sym),
-        }
+        println!("{}", msvc_demangler::try_demangle(sym, msvc_demangler::DemangleOptions::new()));
     };
 
     if args.len() == 1 {
@@ -0,0 +1,190 @@
+// This file is dual licensed under the MIT and the University of Illinois Open
+// Source Licenses. See LICENSE.TXT for details.
+//
+// A cache of demangled names, enabled with the "cache" feature.
+
+use std::collections::HashMap;
+use std::mem;
+
+use super::{demangle_into, DemangleOptions, Result};
+
+/// Remembers the demangled forms of the most recently used names, for
+/// programs that see the same names over and over, such as the frames of
+/// crash reports. Once `capacity` names are cached, the least recently
+/// used one is dropped for each new name.
+///
+/// ```
+/// use msvc_demangler::cache::DemangleCache;
+/// use msvc_demangler::DemangleOptions;
+///
+/// let mut cache = DemangleCache::new(1000);
+/// for _ in 0..3 {
+///     let demangled = cache.demangle("?f@@YAXXZ", DemangleOptions::new());
+///     assert_eq!(demangled, Ok("void __cdecl f(void)"));
+/// }
+/// assert_eq!(cache.len(), 1);
+/// ```
+pub struct DemangleCache {
+    capacity: usize,
+    // The position in `entries` of every cached name, by options.
+    index: HashMap<DemangleOptions, HashMap<String, usize>>,
+    entries: Vec<Entry>,
+    // The most and the least recently used entries.
+    head: Option<usize>,
+    tail: Option<usize>,
+}
+
+struct Entry {
+    input: String,
+    flags: DemangleOptions,
+    output: Result<String>,
+    // The next more and less recently used entries.
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+impl DemangleCache {
+    /// Creates a cache for up to `capacity` names.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is 0.
+    pub fn new(capacity: usize) -> DemangleCache {
+        assert!(capacity > 0, "the capacity of a DemangleCache must not be 0");
+        DemangleCache {
+            capacity,
+            index: HashMap::new(),
+            entries: Vec::new(),
+            head: None,
+            tail: None,
+        }
+    }
+
+    /// The number of names the cache can hold.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The number of names in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Drops all cached names.
+    pub fn clear(&mut self) {
+        self.index.clear();
+        self.entries.clear();
+        self.head = None;
+        self.tail = None;
+    }
+
+    /// Like `demangle()`, but returns the cached result if `input` was
+    /// demangled with the same options before. Errors are cached, too.
+    pub fn demangle<O: Into<DemangleOptions>>(&mut self, input: &str, flags: O) -> Result<&str> {
+        let flags = flags.into();
+        let cached = self.index.get(&flags).and_then(|names| names.get(input)).cloned();
+        let i = match cached {
+            Some(i) => {
+                self.unlink(i);
+                i
+            }
+            None => self.insert(input, flags),
+        };
+        self.push_front(i);
+        match self.entries[i].output {
+            Ok(ref output) => Ok(output),
+            Err(ref err) => Err(err.clone()),
+        }
+    }
+
+    // Demangles `input` into a new entry, or into the least recently used
+    // one if the cache is full, and returns its position.
+    fn insert(&mut self, input: &str, flags: DemangleOptions) -> usize {
+        let (i, mut output) = if self.entries.len() < self.capacity {
+            self.entries.push(Entry {
+                input: String::new(),
+                flags,
+                output: Ok(String::new()),
+                prev: None,
+                next: None,
+            });
+            (self.entries.len() - 1, String::new())
+        } else {
+            let i = self.tail.expect("a full cache has a least recently used entry");
+            self.unlink(i);
+            let entry = &mut self.entries[i];
+            if let Some(names) = self.index.get_mut(&entry.flags) {
+                names.remove(&entry.input);
+            }
+            // Reuse the memory of the old output.
+            let output = match entry.output {
+                Ok(ref mut output) => mem::take(output),
+                Err(_) => String::new(),
+            };
+            (i, output)
+        };
+        let result = demangle_into(input, flags, &mut output);
+        let entry = &mut self.entries[i];
+        entry.input.clear();
+        entry.input.push_str(input);
+        entry.flags = flags;
+        entry.output = result.map(|()| output);
+        self.index.entry(flags).or_default().insert(input.to_owned(), i);
+        i
+    }
+
+    // Takes the entry `i` out of the list of entries by recency.
+    fn unlink(&mut self, i: usize) {
+        let (prev, next) = (self.entries[i].prev, self.entries[i].next);
+        match prev {
+            Some(prev) => self.entries[prev].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.entries[next].prev = prev,
+            None => self.tail = prev,
+        }
+        self.entries[i].prev = None;
+        self.entries[i].next = None;
+    }
+
+    // Makes the entry `i` the most recently used one.
+    fn push_front(&mut self, i: usize) {
+        self.entries[i].next = self.head;
+        if let Some(head) = self.head {
+            self.entries[head].prev = Some(i);
+        }
+        self.head = Some(i);
+        if self.tail.is_none() {
+            self.tail = Some(i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DemangleCache;
+
+    #[test]
+    fn cache() {
+        let mut cache = DemangleCache::new(2);
+        let flags = ::DemangleOptions::new();
+        assert_eq!(cache.demangle("?f@@YAXXZ", flags), Ok("void __cdecl f(void)"));
+        assert!(cache.demangle("?f@@YAX", flags).is_err());
+        assert_eq!(cache.demangle("?f@@YAXXZ", flags), Ok("void __cdecl f(void)"));
+        // The same name with other options is another entry, and replaces
+        // the least recently used one.
+        assert_eq!(cache.demangle("?f@@YAXXZ", ::DemangleOptions::cxxfilt()), Ok("f()"));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.demangle("?x@@3HA", flags), Ok("int x"));
+        assert_eq!(cache.demangle("?f@@YAXXZ", ::DemangleOptions::cxxfilt()), Ok("f()"));
+        assert_eq!(cache.len(), 2);
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.demangle("?f@@YAXXZ", flags), Ok("void __cdecl f(void)"));
+    }
+}
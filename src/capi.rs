@@ -0,0 +1,123 @@
+// This file is dual licensed under the MIT and the University of Illinois Open
+// Source Licenses. See LICENSE.TXT for details.
+//
+// C interface, enabled with the "capi" feature. Build the shared and the
+// static library for C and C++ programs with
+//
+//   cargo rustc --release --lib --features capi --crate-type cdylib
+//   cargo rustc --release --lib --features capi --crate-type staticlib
+//
+// and use the declarations in include/msvc_demangler.h.
+
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+use std::panic;
+use std::ptr;
+use std::str;
+
+use super::{demangle_bytes, DemangleOptions};
+
+/// The input pointer was null.
+pub const MSVC_DEMANGLE_ERR_NULL: c_int = -1;
+/// The input was not valid UTF-8, and `MSVC_DEMANGLE_LOSSY` wasn't given.
+pub const MSVC_DEMANGLE_ERR_UTF8: c_int = -2;
+/// The input was not a valid MSVC-mangled name.
+pub const MSVC_DEMANGLE_ERR_INVALID: c_int = -3;
+/// The demangled name is too long for its length to fit in an `int`.
+pub const MSVC_DEMANGLE_ERR_TOO_LONG: c_int = -4;
+/// The demangler panicked, which is a bug.
+pub const MSVC_DEMANGLE_ERR_PANIC: c_int = -5;
+
+/// Demangles the NUL-terminated string `input` into `buf`, which has room
+/// for `buf_len` bytes. `flags` is a combination of the `MSVC_DEMANGLE_*`
+/// flags in msvc_demangler.h, which have the values of the corresponding
+/// `DemangleOptions` switches.
+///
+/// Like `snprintf()`, this returns the length of the demangled name without
+/// the terminating NUL, and writes as much of it as fits, always terminated
+/// by a NUL unless `buf_len` is 0. `buf` may be null if `buf_len` is 0,
+/// which can be used to find out the size of the buffer needed. Returns one
+/// of the negative `MSVC_DEMANGLE_ERR_*` codes on errors.
+///
+/// # Safety
+///
+/// `input` must be null or point to a NUL-terminated string, and `buf` must
+/// point to at least `buf_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn msvc_demangle(
+    input: *const c_char,
+    buf: *mut c_char,
+    buf_len: usize,
+    flags: u32,
+) -> c_int {
+    // Unwinding into C is undefined behavior.
+    panic::catch_unwind(|| demangle_into_buf(input, buf, buf_len, flags))
+        .unwrap_or(MSVC_DEMANGLE_ERR_PANIC)
+}
+
+unsafe fn demangle_into_buf(
+    input: *const c_char,
+    buf: *mut c_char,
+    buf_len: usize,
+    flags: u32,
+) -> c_int {
+    if input.is_null() {
+        return MSVC_DEMANGLE_ERR_NULL;
+    }
+    let input = CStr::from_ptr(input).to_bytes();
+    let flags = DemangleOptions::from_bits_truncate(flags);
+    if !flags.contains(DemangleOptions::LOSSY) && str::from_utf8(input).is_err() {
+        return MSVC_DEMANGLE_ERR_UTF8;
+    }
+    let demangled = match demangle_bytes(input, flags) {
+        Ok(demangled) => demangled,
+        Err(_) => return MSVC_DEMANGLE_ERR_INVALID,
+    };
+    let demangled_len = match c_int::try_from(demangled.len()) {
+        Ok(len) => len,
+        Err(_) => return MSVC_DEMANGLE_ERR_TOO_LONG,
+    };
+    if buf_len > 0 && !buf.is_null() {
+        let len = demangled.len().min(buf_len - 1);
+        ptr::copy_nonoverlapping(demangled.as_ptr(), buf as *mut u8, len);
+        *buf.add(len) = 0;
+    }
+    demangled_len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{msvc_demangle, MSVC_DEMANGLE_ERR_INVALID, MSVC_DEMANGLE_ERR_NULL, MSVC_DEMANGLE_ERR_UTF8};
+
+    #[test]
+    fn capi() {
+        use std::ffi::CStr;
+        use std::os::raw::c_char;
+        use std::ptr;
+
+        let input = b"?f@@YAHH@Z\0".as_ptr() as *const c_char;
+        let mut buf = [0 as c_char; 64];
+        unsafe {
+            let len = msvc_demangle(input, buf.as_mut_ptr(), buf.len(), 0);
+            assert_eq!(len, 18);
+            assert_eq!(CStr::from_ptr(buf.as_ptr()).to_str(), Ok("int __cdecl f(int)"));
+
+            // GNU_STYLE | NO_RETURN_TYPE | NO_MS_KEYWORDS
+            let len = msvc_demangle(input, buf.as_mut_ptr(), 4, 0x200 | 0x2 | 0x100);
+            assert_eq!(len, 6);
+            assert_eq!(CStr::from_ptr(buf.as_ptr()).to_str(), Ok("f(i"));
+
+            assert_eq!(msvc_demangle(input, ptr::null_mut(), 0, 0), 18);
+            assert_eq!(msvc_demangle(ptr::null(), buf.as_mut_ptr(), buf.len(), 0), MSVC_DEMANGLE_ERR_NULL);
+            let invalid = b"f\0".as_ptr() as *const c_char;
+            assert_eq!(msvc_demangle(invalid, buf.as_mut_ptr(), buf.len(), 0), MSVC_DEMANGLE_ERR_INVALID);
+
+            let latin1 = b"?x\xe4@@3HA\0".as_ptr() as *const c_char;
+            assert_eq!(msvc_demangle(latin1, buf.as_mut_ptr(), buf.len(), 0), MSVC_DEMANGLE_ERR_UTF8);
+            // LOSSY
+            assert_eq!(msvc_demangle(latin1, buf.as_mut_ptr(), buf.len(), 0x200000), 8);
+            assert_eq!(CStr::from_ptr(buf.as_ptr()).to_str(), Ok("int x\u{fffd}"));
+        }
+    }
+}
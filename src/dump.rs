@@ -0,0 +1,242 @@
+// This file is dual licensed under the MIT and the University of Illinois Open
+// Source Licenses. See LICENSE.TXT for details.
+//
+// An indented dump of parse trees, in the style of `clang -ast-dump`.
+
+use super::{resolve_name, FuncClass, Name, NameSequence, Operator, Params, ParseResult, StorageClass, Symbol, Type};
+
+// The names written for the qualifiers of types and functions.
+static STORAGE_CLASSES: &[(StorageClass, &str)] = &[
+    (StorageClass::CONST, "const"),
+    (StorageClass::VOLATILE, "volatile"),
+    (StorageClass::FAR, "far"),
+    (StorageClass::HUGE, "huge"),
+    (StorageClass::UNALIGNED, "unaligned"),
+    (StorageClass::RESTRICT, "restrict"),
+    (StorageClass::LVALUE_QUAL, "lvalue_qual"),
+    (StorageClass::RVALUE_QUAL, "rvalue_qual"),
+    (StorageClass::PTR64, "ptr64"),
+];
+
+static FUNC_CLASSES: &[(FuncClass, &str)] = &[
+    (FuncClass::PUBLIC, "public"),
+    (FuncClass::PROTECTED, "protected"),
+    (FuncClass::PRIVATE, "private"),
+    (FuncClass::GLOBAL, "global"),
+    (FuncClass::STATIC, "static"),
+    (FuncClass::VIRTUAL, "virtual"),
+    (FuncClass::FAR, "far"),
+    (FuncClass::THUNK, "thunk"),
+];
+
+// A line of the dump and the lines nested below it.
+struct Node {
+    label: String,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn leaf(label: String) -> Node {
+        Node {
+            label,
+            children: Vec::new(),
+        }
+    }
+
+    fn new(label: String, children: Vec<Node>) -> Node {
+        Node { label, children }
+    }
+
+    fn write(&self, out: &mut String, prefix: &mut String, last: bool, root: bool) {
+        if !root {
+            out.push_str(prefix);
+            out.push_str(if last { "`-" } else { "|-" });
+        }
+        out.push_str(&self.label);
+        out.push('\n');
+        let len = prefix.len();
+        if !root {
+            prefix.push_str(if last { "  " } else { "| " });
+        }
+        for (i, child) in self.children.iter().enumerate() {
+            child.write(out, prefix, i + 1 == self.children.len(), false);
+        }
+        prefix.truncate(len);
+    }
+}
+
+// Appends the names of the flags of `table` that `contains` accepts.
+fn with_flags<T: Copy>(mut label: String, table: &[(T, &str)], contains: impl Fn(T) -> bool) -> String {
+    for &(flag, name) in table {
+        if contains(flag) {
+            label.push(' ');
+            label.push_str(name);
+        }
+    }
+    label
+}
+
+fn with_storage_class(label: &str, sc: StorageClass) -> String {
+    with_flags(label.to_owned(), STORAGE_CLASSES, |flag| sc.contains(flag))
+}
+
+struct Dumper<'b, 'a: 'b> {
+    names: &'b [Name<'a>],
+}
+
+impl<'b, 'a> Dumper<'b, 'a> {
+    fn parse_result(&self, parse_result: &ParseResult) -> Node {
+        Node::new(
+            format!("ParseResult {:?}", parse_result.symbol_kind),
+            vec![self.symbol(&parse_result.symbol), self.type_(&parse_result.symbol_type)],
+        )
+    }
+
+    fn symbol(&self, symbol: &Symbol) -> Node {
+        let mut children = Vec::new();
+        if !symbol.scope.names.is_empty() {
+            children.push(self.scope(&symbol.scope));
+        }
+        children.push(self.name(&symbol.name));
+        Node::new("Symbol".to_owned(), children)
+    }
+
+    // The scope, outermost first, as it is written.
+    fn scope(&self, scope: &NameSequence) -> Node {
+        Node::new(
+            "Scope".to_owned(),
+            scope.names.iter().rev().map(|name| self.name(name)).collect(),
+        )
+    }
+
+    fn name(&self, name: &Name) -> Node {
+        match *name {
+            Name::Operator(Operator::Conversion(ref target)) => Node::new(
+                "Operator Conversion".to_owned(),
+                target.iter().map(|t| self.type_(t)).collect(),
+            ),
+            Name::Operator(ref op) => Node::leaf(format!("Operator {:?}", op)),
+            Name::NonTemplate(ref name) => Node::leaf(format!("NonTemplate {:?}", name)),
+            Name::Template(ref name, ref params) => Node::new(
+                "Template".to_owned(),
+                vec![self.name(name), self.params("TemplateArgs", params)],
+            ),
+            Name::Discriminator(n) => Node::leaf(format!("Discriminator {}", n)),
+            Name::ParsedName(ref parse_result) => {
+                Node::new("ParsedName".to_owned(), vec![self.parse_result(parse_result)])
+            }
+            Name::AnonymousNamespace => Node::leaf("AnonymousNamespace".to_owned()),
+            Name::Backref(i) => {
+                let resolved = resolve_name(self.names, name);
+                let children = if resolved != name { vec![self.name(resolved)] } else { Vec::new() };
+                Node::new(format!("Backref {}", i), children)
+            }
+        }
+    }
+
+    fn params(&self, label: &str, params: &Params) -> Node {
+        Node::new(label.to_owned(), params.types.iter().map(|t| self.type_(t)).collect())
+    }
+
+    fn function(&self, label: String, ret: &Type, params: &Params, mut children: Vec<Node>) -> Node {
+        children.push(Node::new("Return".to_owned(), vec![self.type_(ret)]));
+        children.push(self.params("Params", params));
+        Node::new(label, children)
+    }
+
+    fn type_(&self, t: &Type) -> Node {
+        let user_type =
+            |label: &str, symbol: &Symbol, sc| Node::new(with_storage_class(label, sc), vec![self.symbol(symbol)]);
+        let primitive = |label: &str, sc| Node::leaf(with_storage_class(label, sc));
+        match *t {
+            Type::None => Node::leaf("None".to_owned()),
+            Type::MemberFunction(func_class, calling_conv, ref params, sc, ref ret) => {
+                let label = with_flags("MemberFunction".to_owned(), FUNC_CLASSES, |flag| func_class.contains(flag));
+                let label = with_storage_class(&format!("{} {:?}", label, calling_conv), sc);
+                self.function(label, ret, params, Vec::new())
+            }
+            Type::MemberFunctionPointer(ref symbol, func_class, calling_conv, ref params, sc, ref ret) => {
+                let label = with_flags("MemberFunctionPointer".to_owned(), FUNC_CLASSES, |flag| {
+                    func_class.contains(flag)
+                });
+                let label = with_storage_class(&format!("{} {:?}", label, calling_conv), sc);
+                self.function(label, ret, params, vec![self.symbol(symbol)])
+            }
+            Type::NonMemberFunction(calling_conv, ref params, sc, ref ret) => {
+                let label = with_storage_class(&format!("NonMemberFunction {:?}", calling_conv), sc);
+                self.function(label, ret, params, Vec::new())
+            }
+            Type::CXXVBTable(ref names, sc) => Node::new(with_storage_class("CXXVBTable", sc), vec![self.scope(names)]),
+            Type::CXXVFTable(ref names, sc) => Node::new(with_storage_class("CXXVFTable", sc), vec![self.scope(names)]),
+            Type::TemplateParameterWithIndex(n) => Node::leaf(format!("TemplateParameterWithIndex {}", n)),
+            Type::ThreadSafeStaticGuard(n) => Node::leaf(format!("ThreadSafeStaticGuard {}", n)),
+            Type::Constant(n) => Node::leaf(format!("Constant {}", n)),
+            Type::ConstantString(ref s) => Node::leaf(format!("ConstantString {:?}", String::from_utf8_lossy(s))),
+            Type::Ptr(ref inner, sc) => Node::new(with_storage_class("Ptr", sc), vec![self.type_(inner)]),
+            Type::Ref(ref inner, sc) => Node::new(with_storage_class("Ref", sc), vec![self.type_(inner)]),
+            Type::RValueRef(ref inner, sc) => Node::new(with_storage_class("RValueRef", sc), vec![self.type_(inner)]),
+            Type::Array(len, ref inner, sc) => {
+                Node::new(with_storage_class(&format!("Array {}", len), sc), vec![self.type_(inner)])
+            }
+            Type::Struct(ref symbol, sc) => user_type("Struct", symbol, sc),
+            Type::Union(ref symbol, sc) => user_type("Union", symbol, sc),
+            Type::Class(ref symbol, sc) => user_type("Class", symbol, sc),
+            Type::Typedef(ref symbol, sc) => user_type("Typedef", symbol, sc),
+            Type::Enum(ref symbol, ref underlying, sc) => Node::new(
+                with_storage_class("Enum", sc),
+                vec![self.symbol(symbol), self.type_(underlying)],
+            ),
+            Type::Void(sc) => primitive("Void", sc),
+            Type::Bool(sc) => primitive("Bool", sc),
+            Type::Char(sc) => primitive("Char", sc),
+            Type::Schar(sc) => primitive("Schar", sc),
+            Type::Uchar(sc) => primitive("Uchar", sc),
+            Type::Short(sc) => primitive("Short", sc),
+            Type::Ushort(sc) => primitive("Ushort", sc),
+            Type::Int(sc) => primitive("Int", sc),
+            Type::Uint(sc) => primitive("Uint", sc),
+            Type::Long(sc) => primitive("Long", sc),
+            Type::Ulong(sc) => primitive("Ulong", sc),
+            Type::Int64(sc) => primitive("Int64", sc),
+            Type::Uint64(sc) => primitive("Uint64", sc),
+            Type::Wchar(sc) => primitive("Wchar", sc),
+            Type::Char16(sc) => primitive("Char16", sc),
+            Type::Char32(sc) => primitive("Char32", sc),
+            Type::Float(sc) => primitive("Float", sc),
+            Type::Double(sc) => primitive("Double", sc),
+            Type::Ldouble(sc) => primitive("Ldouble", sc),
+            Type::VarArgs => Node::leaf("VarArgs".to_owned()),
+            Type::EmptyParameterPack => Node::leaf("EmptyParameterPack".to_owned()),
+            Type::Nullptr => Node::leaf("Nullptr".to_owned()),
+            Type::Extension(ref name, sc) => primitive(&format!("Extension {:?}", name), sc),
+            Type::Shared(ref inner) => Node::new("Shared".to_owned(), vec![self.type_(inner)]),
+        }
+    }
+}
+
+impl<'a> ParseResult<'a> {
+    /// An indented tree of the parsed symbol, one node per line, in the
+    /// style of `clang -ast-dump`, for bug reports and debugging:
+    ///
+    /// ```text
+    /// ParseResult Function
+    /// |-Symbol
+    /// | `-NonTemplate "f"
+    /// `-NonMemberFunction Cdecl
+    ///   |-Return
+    ///   | `-Void
+    ///   `-Params
+    ///     `-Ptr
+    ///       `-Int const
+    /// ```
+    ///
+    /// Nodes are labeled with the names of the `Name` and `Type` variants
+    /// and their attributes, qualifiers last. `Name::Backref`s are shown
+    /// with the name they refer to.
+    pub fn dump(&self) -> String {
+        let root = Dumper { names: &self.names }.parse_result(self);
+        let mut out = String::new();
+        root.write(&mut out, &mut String::new(), true, true);
+        out
+    }
+}
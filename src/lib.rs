@@ -5,57 +5,141 @@
 
 #[macro_use]
 extern crate bitflags;
-
-use std::cmp::min;
-use std::io::Write;
+extern crate memchr;
+#[cfg(feature = "itanium")]
+extern crate cpp_demangle;
+// The pyo3 macros refer to `::core`.
+#[cfg(feature = "python")]
+extern crate core;
+#[cfg(feature = "object")]
+extern crate object;
+#[cfg(feature = "pdb")]
+extern crate pdb;
+#[cfg(feature = "python")]
+extern crate pyo3;
+#[cfg(feature = "rust")]
+extern crate rustc_demangle;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+
+pub mod any;
+#[cfg(feature = "cache")]
+pub mod cache;
+#[cfg(feature = "capi")]
+pub mod capi;
+mod dump;
+pub mod mangle;
+#[cfg(feature = "python")]
+mod python;
+pub mod query;
+#[cfg(any(feature = "object", feature = "pdb"))]
+pub mod symbols;
+#[cfg(feature = "trace")]
+pub mod trace;
+pub mod tree;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+use std::borrow::Cow;
+use std::cmp::{self, max, min};
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::iter;
 use std::result;
+use std::slice;
+use std::sync::Arc;
 use std::str;
 use std::mem;
+use std::ops;
+use std::panic;
+use std::ptr;
+use std::thread;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Error {
     s: String,
+    kind: ErrorKind,
+}
+
+/// The reason demangling failed, see `Error::kind()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The input is not a valid mangled name.
+    Invalid,
+    /// The input exceeds one of the limits set in `DemangleOptions`, such
+    /// as `max_depth()` or `max_nodes()`. Valid names may fail this way, but
+    /// these limits protect against crafted names that would take a lot of
+    /// time and memory.
+    LimitExceeded,
+    /// The demangled name would be longer than `DemangleOptions::max_output()`
+    /// or `max_expansion()` allow.
+    OutputLimitExceeded,
 }
 
 impl Error {
     fn new(s: String) -> Error {
-        Error { s }
+        Error { s, kind: ErrorKind::Invalid }
+    }
+
+    fn limit_exceeded(s: String) -> Error {
+        Error { s, kind: ErrorKind::LimitExceeded }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.s)
     }
 }
 
+impl std::error::Error for Error {}
+
 impl From<std::str::Utf8Error> for Error {
     fn from(t: std::str::Utf8Error) -> Error {
-        Error {
-            s: format!("{:?}", t),
-        }
+        Error::new(format!("{:?}", t))
     }
 }
+impl From<SerializeError> for Error {
+    fn from(t: SerializeError) -> Error {
+        Error { s: t.s, kind: t.kind }
+    }
+}
+
 impl From<std::string::FromUtf8Error> for Error {
     fn from(t: std::string::FromUtf8Error) -> Error {
-        Error {
-            s: format!("{:?}", t),
-        }
+        Error::new(format!("{:?}", t))
     }
 }
 
 #[derive(Debug, Clone)]
 struct SerializeError {
     s: String,
+    kind: ErrorKind,
 }
 
-impl From<std::str::Utf8Error> for SerializeError {
-    fn from(err: std::str::Utf8Error) -> SerializeError {
-        SerializeError {
-            s: format!("{:?}", err),
-        }
+impl SerializeError {
+    fn new(s: String) -> SerializeError {
+        SerializeError { s, kind: ErrorKind::Invalid }
+    }
+
+    fn limit_exceeded(s: String) -> SerializeError {
+        SerializeError { s, kind: ErrorKind::LimitExceeded }
+    }
+
+    fn output_limit_exceeded(s: String) -> SerializeError {
+        SerializeError { s, kind: ErrorKind::OutputLimitExceeded }
     }
 }
 
-impl From<std::io::Error> for SerializeError {
-    fn from(err: std::io::Error) -> SerializeError {
-        SerializeError {
-            s: format!("{:?}", err),
-        }
+impl From<fmt::Error> for SerializeError {
+    fn from(err: fmt::Error) -> SerializeError {
+        SerializeError::new(format!("{:?}", err))
     }
 }
 
@@ -73,17 +157,486 @@ bitflags! {
         const RESTRICT    = 0b00100000;
         const LVALUE_QUAL = 0b01000000;
         const RVALUE_QUAL = 0b10000000;
+        const PTR64       = 0b100000000;
+    }
+}
+
+bitflags! {
+    #[derive(Default)]
+    struct OptionFlags: u32 {
+        const LESS_WHITESPACE = 0b00000001;
+        const NO_RETURN_TYPE  = 0b00000010;
+        const NO_ACCESS_SPECIFIERS = 0b00000100;
+        const NO_MEMBER_TYPE  = 0b00001000;
+        const NO_ARGUMENTS    = 0b00010000;
+        const NO_CV_THISTYPE  = 0b00100000;
+        const UNDNAME_COMPAT  = 0b01000000;
+        const LLVM_UNDNAME_COMPAT = 0b10000000;
+        const NO_MS_KEYWORDS  = 0b100000000;
+        const GNU_STYLE       = 0b1000000000;
+        const NO_CLASS_KEYWORDS = 0b10000000000;
+        const STD_TYPEDEFS    = 0b100000000000;
+        const NO_DEFAULT_TEMPLATE_ARGS = 0b1000000000000;
+        const ENUM_UNDERLYING_TYPES = 0b10000000000000;
+        const NO_LEADING_UNDERSCORES = 0b100000000000000;
+        const NO_SPECIAL_SYMS = 0b1000000000000000;
+        const NO_TEMPLATE_ARGS = 0b10000000000000000;
+        const DESCRIBE_SPECIAL_SYMS = 0b100000000000000000;
+        const NAME_ONLY = 0b1000000000000000000;
+        const ANONYMOUS_NAMESPACE_PARENS = 0b10000000000000000000;
+        const STRICT = 0b100000000000000000000;
+        const LOSSY = 0b1000000000000000000000;
+    }
+}
+
+/// How the 64-bit integer types are spelled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Int64Spelling {
+    /// `int64_t` and `uint64_t`. This is the default.
+    Stdint,
+    /// `__int64` and `unsigned __int64`, as undname writes them.
+    Ms,
+    /// `long long` and `unsigned long long`.
+    LongLong,
+}
+
+/// How deeply types and names may be nested unless changed with
+/// `DemangleOptions::max_depth()`.
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+/// How deeply types and names may be nested at most, whatever
+/// `DemangleOptions::max_depth()` says. Parse results are parsed, written,
+/// cloned, compared, hashed and walked recursively, taking up to about
+/// 10 KiB of stack per level in unoptimized builds, so that this many
+/// levels fit in the 2 MiB stack of a thread spawned by `std::thread`.
+pub const MAX_RECURSIVE_DEPTH: usize = 192;
+
+// The depth limit for the `max_depth` of the options.
+fn depth_limit(max_depth: Option<usize>) -> usize {
+    min(max_depth.unwrap_or(DEFAULT_MAX_DEPTH), MAX_RECURSIVE_DEPTH)
+}
+
+/// A frozen version of the output format.
+///
+/// The output of `DemangleOptions` without a profile may change between
+/// releases as formatting is improved. Selecting a profile with
+/// `DemangleOptions::format_profile()` keeps the output identical across
+/// crate versions, which matters for symbol servers and caches keyed by
+/// demangled names. Formatting changes only ever land in new profiles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FormatProfile {
+    /// The output format of msvc-demangler 0.5.1.
+    V1,
+}
+
+/// Options controlling how a parsed symbol is turned into a string.
+///
+/// The default produces the complete, undname-style output. Individual
+/// switches are exposed as associated constants which can be combined with
+/// `|`, for example `DemangleOptions::LESS_WHITESPACE`. Settings that take a
+/// value are set with builder methods such as `int64_spelling()`; when two
+/// options are combined with `|`, settings made on the right-hand side win.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct DemangleOptions {
+    flags: OptionFlags,
+    int64_spelling: Option<Int64Spelling>,
+    max_template_depth: Option<usize>,
+    max_length: Option<usize>,
+    wrap_width: Option<usize>,
+    hex_threshold: Option<u64>,
+    format_profile: Option<FormatProfile>,
+    max_depth: Option<usize>,
+    max_nodes: Option<usize>,
+    max_expansion: Option<usize>,
+    max_output: Option<usize>,
+}
+
+impl DemangleOptions {
+    /// Only put spaces where they are needed to separate tokens, e.g.
+    /// `int*x` rather than `int * x`.
+    pub const LESS_WHITESPACE: DemangleOptions = DemangleOptions::from_flags(OptionFlags::LESS_WHITESPACE);
+    /// Leave out the return type of functions (undname's
+    /// `UNDNAME_NO_FUNCTION_RETURNS`). Return types of function types
+    /// appearing in parameters are still written.
+    pub const NO_RETURN_TYPE: DemangleOptions = DemangleOptions::from_flags(OptionFlags::NO_RETURN_TYPE);
+    /// Leave out `public:`, `protected:` and `private:` on member functions.
+    pub const NO_ACCESS_SPECIFIERS: DemangleOptions = DemangleOptions::from_flags(OptionFlags::NO_ACCESS_SPECIFIERS);
+    /// Leave out `static` and `virtual` on member functions.
+    pub const NO_MEMBER_TYPE: DemangleOptions = DemangleOptions::from_flags(OptionFlags::NO_MEMBER_TYPE);
+    /// Leave out the parameter list of functions, along with the qualifiers
+    /// following it, so that all overloads print as `ns::Class::method`.
+    pub const NO_ARGUMENTS: DemangleOptions = DemangleOptions::from_flags(OptionFlags::NO_ARGUMENTS);
+    /// Leave out the `const` and `volatile` qualifiers of the `this` pointer
+    /// of member functions (DbgHelp's `UNDNAME_NO_CV_THISTYPE`).
+    pub const NO_CV_THISTYPE: DemangleOptions = DemangleOptions::from_flags(OptionFlags::NO_CV_THISTYPE);
+    /// Follow the quirks of Microsoft's undname.exe: print `__ptr64` on
+    /// 64-bit pointers, don't separate calling conventions from parameter
+    /// lists, and attach `this` qualifiers to the parameter list. Usually
+    /// used through `DemangleOptions::undname()`.
+    pub const UNDNAME_COMPAT: DemangleOptions = DemangleOptions::from_flags(OptionFlags::UNDNAME_COMPAT);
+    /// Follow the formatting of LLVM's llvm-undname: separate parameters
+    /// with `, `, put calling conventions inside the parentheses of function
+    /// pointers, and write `this` qualifiers as `(void) const`. Usually used
+    /// through `DemangleOptions::llvm_undname()`.
+    pub const LLVM_UNDNAME_COMPAT: DemangleOptions = DemangleOptions::from_flags(OptionFlags::LLVM_UNDNAME_COMPAT);
+    /// Leave out calling conventions and the `__ptr64` and `__restrict`
+    /// keywords (undname's `UNDNAME_NO_MS_KEYWORDS`).
+    pub const NO_MS_KEYWORDS: DemangleOptions = DemangleOptions::from_flags(OptionFlags::NO_MS_KEYWORDS);
+    /// Format like GNU c++filt: `, ` between parameters, pointer declarators
+    /// attached to the type (`char const*`) and `()` for empty parameter
+    /// lists. Usually used through `DemangleOptions::cxxfilt()`.
+    pub const GNU_STYLE: DemangleOptions = DemangleOptions::from_flags(OptionFlags::GNU_STYLE);
+    /// Leave out the `class`, `struct`, `union` and `enum` keywords in front
+    /// of type names, e.g. `std::basic_string<...>` rather than
+    /// `class std::basic_string<...>`.
+    pub const NO_CLASS_KEYWORDS: DemangleOptions = DemangleOptions::from_flags(OptionFlags::NO_CLASS_KEYWORDS);
+    /// Write well-known standard library typedefs instead of the template
+    /// instances they stand for, e.g. `std::string` rather than
+    /// `class std::basic_string<char,struct std::char_traits<char>,class std::allocator<char> >`.
+    /// This covers the string, string view, stream and stream buffer
+    /// templates.
+    pub const STD_TYPEDEFS: DemangleOptions = DemangleOptions::from_flags(OptionFlags::STD_TYPEDEFS);
+    /// Leave out trailing template arguments of standard library templates
+    /// that are equal to their defaults, such as allocators, traits,
+    /// comparators, hashers and deleters: `std::vector<int>` rather than
+    /// `std::vector<int,std::allocator<int> >`.
+    pub const NO_DEFAULT_TEMPLATE_ARGS: DemangleOptions = DemangleOptions::from_flags(OptionFlags::NO_DEFAULT_TEMPLATE_ARGS);
+    /// Write the underlying type of enums, e.g. `enum foo : unsigned char`.
+    pub const ENUM_UNDERLYING_TYPES: DemangleOptions = DemangleOptions::from_flags(OptionFlags::ENUM_UNDERLYING_TYPES);
+    /// Strip the leading underscores from Microsoft keywords, e.g. `cdecl`
+    /// rather than `__cdecl`, like `UNDNAME_NO_LEADING_UNDERSCORES`.
+    pub const NO_LEADING_UNDERSCORES: DemangleOptions = DemangleOptions::from_flags(OptionFlags::NO_LEADING_UNDERSCORES);
+    /// Write compiler-generated symbols such as vftables, vbtables, guard
+    /// variables and deleting destructors as the name of the class or
+    /// function they belong to, e.g. `Foo` rather than
+    /// ``public: virtual void * __thiscall Foo::`scalar deleting destructor'(unsigned int)``.
+    pub const NO_SPECIAL_SYMS: DemangleOptions = DemangleOptions::from_flags(OptionFlags::NO_SPECIAL_SYMS);
+    /// Leave out all template arguments, e.g. `std::vector<>::push_back`,
+    /// so that all instances of a template are written the same way.
+    pub const NO_TEMPLATE_ARGS: DemangleOptions = DemangleOptions::from_flags(OptionFlags::NO_TEMPLATE_ARGS);
+    /// Describe compiler-generated symbols in words followed by the class
+    /// or function they belong to, e.g. `vtable for ns::Foo` or
+    /// `scalar deleting destructor for ns::Foo`. Usually used through
+    /// `DemangleOptions::summary()`.
+    pub const DESCRIBE_SPECIAL_SYMS: DemangleOptions = DemangleOptions::from_flags(OptionFlags::DESCRIBE_SPECIAL_SYMS);
+    /// Write only the qualified name of the symbol, e.g. `ns::Foo::bar`,
+    /// without its type, like `UNDNAME_NAME_ONLY`.
+    pub const NAME_ONLY: DemangleOptions = DemangleOptions::from_flags(OptionFlags::NAME_ONLY);
+    /// Write anonymous namespaces as `(anonymous namespace)`, as
+    /// `GNU_STYLE` does, without changing anything else.
+    pub const ANONYMOUS_NAMESPACE_PARENS: DemangleOptions =
+        DemangleOptions::from_flags(OptionFlags::ANONYMOUS_NAMESPACE_PARENS);
+    /// Fail on input following the mangled name, and on names that end in
+    /// the middle of a parameter list, for tools validating names rather
+    /// than displaying them. By default, `parse()` and `demangle()` ignore
+    /// such trailing input; `parse_with_trailing()` returns it.
+    pub const STRICT: DemangleOptions = DemangleOptions::from_flags(OptionFlags::STRICT);
+    /// Make `demangle_bytes()` replace invalid UTF-8 sequences in the
+    /// input with U+FFFD rather than fail.
+    pub const LOSSY: DemangleOptions = DemangleOptions::from_flags(OptionFlags::LOSSY);
+
+    // The switches given as the bits of `OptionFlags`, as in the C and
+    // WebAssembly interfaces.
+    #[allow(dead_code)]
+    fn from_bits_truncate(bits: u32) -> DemangleOptions {
+        DemangleOptions::from_flags(OptionFlags::from_bits_truncate(bits))
+    }
+
+    const fn from_flags(flags: OptionFlags) -> DemangleOptions {
+        DemangleOptions {
+            flags,
+            int64_spelling: None,
+            max_template_depth: None,
+            max_length: None,
+            wrap_width: None,
+            hex_threshold: None,
+            format_profile: None,
+            max_depth: None,
+            max_nodes: None,
+            max_expansion: None,
+            max_output: None,
+        }
+    }
+
+    /// The default options, producing complete output.
+    pub fn new() -> DemangleOptions {
+        DemangleOptions::default()
+    }
+
+    /// Options reproducing the output of Microsoft's undname.exe (and
+    /// DbgHelp's `UnDecorateSymbolName`) as closely as possible.
+    pub fn undname() -> DemangleOptions {
+        DemangleOptions::UNDNAME_COMPAT.int64_spelling(Int64Spelling::Ms)
+    }
+
+    /// Options reproducing the output of LLVM's llvm-undname.
+    pub fn llvm_undname() -> DemangleOptions {
+        DemangleOptions::LLVM_UNDNAME_COMPAT.int64_spelling(Int64Spelling::Ms)
+    }
+
+    /// Compact options resembling GNU c++filt output for Itanium symbols,
+    /// e.g. `ns::Class::method(int, float) const`. Useful to normalize
+    /// stack traces across platforms.
+    pub fn cxxfilt() -> DemangleOptions {
+        (DemangleOptions::GNU_STYLE
+            | DemangleOptions::NO_ACCESS_SPECIFIERS
+            | DemangleOptions::NO_MEMBER_TYPE
+            | DemangleOptions::NO_RETURN_TYPE
+            | DemangleOptions::NO_MS_KEYWORDS
+            | DemangleOptions::NO_CLASS_KEYWORDS)
+            .int64_spelling(Int64Spelling::LongLong)
+    }
+
+    /// Short, GDB-like descriptions for crash reports: `ns::Foo::bar(int)`,
+    /// `vtable for ns::Foo` or `guard variable for f()`.
+    pub fn summary() -> DemangleOptions {
+        DemangleOptions::cxxfilt() | DemangleOptions::DESCRIBE_SPECIAL_SYMS
+    }
+
+    /// The names Breakpad's dump_syms writes to the FUNC and PUBLIC records
+    /// of .sym files for Windows binaries, e.g.
+    /// `(anonymous namespace)::Foo::bar(int,char const *)`: the flags it
+    /// passes to `UnDecorateSymbolName()`, with anonymous namespaces written
+    /// the way they are for other platforms.
+    pub fn breakpad() -> DemangleOptions {
+        // UNDNAME_NO_MS_KEYWORDS | UNDNAME_NO_FUNCTION_RETURNS |
+        // UNDNAME_NO_ALLOCATION_MODEL | UNDNAME_NO_ALLOCATION_LANGUAGE |
+        // UNDNAME_NO_THISTYPE | UNDNAME_NO_ACCESS_SPECIFIERS |
+        // UNDNAME_NO_THROW_SIGNATURES | UNDNAME_NO_MEMBER_TYPE |
+        // UNDNAME_NO_RETURN_UDT_MODEL | UNDNAME_NO_ECSU
+        DemangleOptions::from_undname_flags(0x87fe) | DemangleOptions::ANONYMOUS_NAMESPACE_PARENS
+    }
+
+    /// Options for the `UNDNAME_*` flags of `UnDecorateSymbolName()` and
+    /// undname.exe, e.g. 0x1000 for `UNDNAME_NAME_ONLY`, on top of
+    /// `DemangleOptions::undname()`. `UNDNAME_NO_ALLOCATION_MODEL`,
+    /// `UNDNAME_NO_ALLOCATION_LANGUAGE`, `UNDNAME_NO_MS_THISTYPE`,
+    /// `UNDNAME_NO_THROW_SIGNATURES`, `UNDNAME_NO_RETURN_UDT_MODEL` and
+    /// `UNDNAME_32_BIT_DECODE` have no counterpart here and are ignored, as
+    /// are unknown bits.
+    pub fn from_undname_flags(undname_flags: u32) -> DemangleOptions {
+        const UNDNAME_FLAGS: &[(u32, DemangleOptions)] = &[
+            // UNDNAME_NO_LEADING_UNDERSCORES
+            (0x0001, DemangleOptions::NO_LEADING_UNDERSCORES),
+            // UNDNAME_NO_MS_KEYWORDS
+            (0x0002, DemangleOptions::NO_MS_KEYWORDS),
+            // UNDNAME_NO_FUNCTION_RETURNS
+            (0x0004, DemangleOptions::NO_RETURN_TYPE),
+            // UNDNAME_NO_CV_THISTYPE, also part of UNDNAME_NO_THISTYPE
+            (0x0040, DemangleOptions::NO_CV_THISTYPE),
+            // UNDNAME_NO_ACCESS_SPECIFIERS
+            (0x0080, DemangleOptions::NO_ACCESS_SPECIFIERS),
+            // UNDNAME_NO_MEMBER_TYPE
+            (0x0200, DemangleOptions::NO_MEMBER_TYPE),
+            // UNDNAME_NAME_ONLY
+            (0x1000, DemangleOptions::NAME_ONLY),
+            // UNDNAME_NO_ARGUMENTS
+            (0x2000, DemangleOptions::NO_ARGUMENTS),
+            // UNDNAME_NO_SPECIAL_SYMS
+            (0x4000, DemangleOptions::NO_SPECIAL_SYMS),
+            // UNDNAME_NO_ECSU
+            (0x8000, DemangleOptions::NO_CLASS_KEYWORDS),
+        ];
+        UNDNAME_FLAGS
+            .iter()
+            .filter(|&&(bit, _)| undname_flags & bit != 0)
+            .fold(DemangleOptions::undname(), |flags, &(_, switch)| flags | switch)
+    }
+
+    /// Whether all switches set in `other` are also set in `self`.
+    pub fn contains(&self, other: DemangleOptions) -> bool {
+        self.flags.contains(other.flags)
+    }
+
+    /// Selects how `__int64` and `unsigned __int64` are written.
+    pub fn int64_spelling(mut self, spelling: Int64Spelling) -> DemangleOptions {
+        self.int64_spelling = Some(spelling);
+        self
+    }
+
+    /// Writes template arguments nested more than `depth` levels deep as
+    /// `<...>`, e.g. `A<B<...> >` for a depth of 1. A depth of 0 hides all
+    /// template arguments.
+    pub fn max_template_depth(mut self, depth: usize) -> DemangleOptions {
+        self.max_template_depth = Some(depth);
+        self
+    }
+
+    /// Stops writing after `len` bytes and appends `...`, so the output is
+    /// never longer than `len + 3` bytes.
+    pub fn max_length(mut self, len: usize) -> DemangleOptions {
+        self.max_length = Some(len);
+        self
+    }
+
+    /// Spreads parameter and template argument lists that would make a
+    /// line longer than `width` over multiple lines, one element per line,
+    /// indented by four spaces per nesting level.
+    pub fn wrap_width(mut self, width: usize) -> DemangleOptions {
+        self.wrap_width = Some(width);
+        self
+    }
+
+    /// Fails on symbols whose types and names are nested more than `depth`
+    /// levels deep, rather than risking a stack overflow on crafted input.
+    /// The default is `DEFAULT_MAX_DEPTH`. Limits beyond
+    /// `MAX_RECURSIVE_DEPTH` act as `MAX_RECURSIVE_DEPTH`.
+    pub fn max_depth(mut self, depth: usize) -> DemangleOptions {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Fails on symbols made of more than `nodes` types and names, counting
+    /// every back-reference to a type again.
+    pub fn max_nodes(mut self, nodes: usize) -> DemangleOptions {
+        self.max_nodes = Some(nodes);
+        self
+    }
+
+    /// Fails on symbols whose demangled form would be more than `factor`
+    /// times as long as the mangled one. Back-references let a short name
+    /// stand for a huge one, which this stops early. Only applies to the
+    /// functions demangling a string, like `demangle()`, not to
+    /// `serialize()`.
+    pub fn max_expansion(mut self, factor: usize) -> DemangleOptions {
+        self.max_expansion = Some(factor);
+        self
+    }
+
+    /// Fails with `ErrorKind::OutputLimitExceeded` rather than write more
+    /// than `bytes` bytes for a symbol, however short the mangled name is.
+    /// Unlike `max_expansion()`, this also applies to `serialize()`, and
+    /// unlike `max_length()`, it doesn't truncate.
+    pub fn max_output(mut self, bytes: usize) -> DemangleOptions {
+        self.max_output = Some(bytes);
+        self
+    }
+
+    /// Freezes the output format at `profile`, see `FormatProfile`.
+    pub fn format_profile(mut self, profile: FormatProfile) -> DemangleOptions {
+        self.format_profile = Some(profile);
+        self
+    }
+
+    /// Writes integer template arguments whose absolute value is greater
+    /// than `threshold` in hexadecimal, e.g. `0xdeadbeef`.
+    pub fn hex_constants_above(mut self, threshold: u64) -> DemangleOptions {
+        self.hex_threshold = Some(threshold);
+        self
+    }
+}
+
+impl ops::BitOr for DemangleOptions {
+    type Output = DemangleOptions;
+
+    fn bitor(self, other: DemangleOptions) -> DemangleOptions {
+        DemangleOptions {
+            flags: self.flags | other.flags,
+            int64_spelling: other.int64_spelling.or(self.int64_spelling),
+            max_template_depth: other.max_template_depth.or(self.max_template_depth),
+            max_length: other.max_length.or(self.max_length),
+            wrap_width: other.wrap_width.or(self.wrap_width),
+            hex_threshold: other.hex_threshold.or(self.hex_threshold),
+            format_profile: other.format_profile.or(self.format_profile),
+            max_depth: other.max_depth.or(self.max_depth),
+            max_nodes: other.max_nodes.or(self.max_nodes),
+            max_expansion: other.max_expansion.or(self.max_expansion),
+            max_output: other.max_output.or(self.max_output),
+        }
+    }
+}
+
+impl ops::BitOrAssign for DemangleOptions {
+    fn bitor_assign(&mut self, other: DemangleOptions) {
+        *self = *self | other;
     }
 }
 
+#[deprecated(since = "0.5.1", note = "use DemangleOptions instead")]
 #[derive(PartialEq, Clone, Copy)]
 pub enum DemangleFlags {
     LessWhitespace,
     LotsOfWhitespace,
 }
 
+#[allow(deprecated)]
+impl From<DemangleFlags> for DemangleOptions {
+    fn from(flags: DemangleFlags) -> DemangleOptions {
+        match flags {
+            DemangleFlags::LessWhitespace => DemangleOptions::LESS_WHITESPACE,
+            DemangleFlags::LotsOfWhitespace => DemangleOptions::new(),
+        }
+    }
+}
+
+/// How mangled names are parsed, as opposed to how they are written, which
+/// `DemangleOptions` controls. The parsing functions take either: the
+/// `DemangleOptions` settings that affect parsing, `max_depth()`,
+/// `max_nodes()`, `STRICT` and `LOSSY`, carry over.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ParseOptions {
+    max_depth: Option<usize>,
+    max_nodes: Option<usize>,
+    strict: bool,
+    allow_non_utf8: bool,
+}
+
+impl ParseOptions {
+    /// The default options: lenient, with the default depth limit.
+    pub fn new() -> ParseOptions {
+        ParseOptions::default()
+    }
+
+    /// Fails on symbols whose types and names are nested more than `depth`
+    /// levels deep. The default is `DEFAULT_MAX_DEPTH`. Limits beyond
+    /// `MAX_RECURSIVE_DEPTH` act as `MAX_RECURSIVE_DEPTH`.
+    pub fn max_depth(mut self, depth: usize) -> ParseOptions {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Fails on symbols made of more than `nodes` types and names, counting
+    /// every back-reference to a type again. Unlimited by default.
+    pub fn max_nodes(mut self, nodes: usize) -> ParseOptions {
+        self.max_nodes = Some(nodes);
+        self
+    }
+
+    /// Fails on input following the mangled name and on names that end in
+    /// the middle of a parameter list, like `DemangleOptions::STRICT`.
+    pub fn strict(mut self, strict: bool) -> ParseOptions {
+        self.strict = strict;
+        self
+    }
+
+    /// Makes `parse_bytes()` replace invalid UTF-8 sequences with U+FFFD
+    /// rather than fail, like `DemangleOptions::LOSSY`.
+    pub fn allow_non_utf8(mut self, allow: bool) -> ParseOptions {
+        self.allow_non_utf8 = allow;
+        self
+    }
+}
+
+impl From<DemangleOptions> for ParseOptions {
+    fn from(options: DemangleOptions) -> ParseOptions {
+        ParseOptions {
+            max_depth: options.max_depth,
+            max_nodes: options.max_nodes,
+            strict: options.flags.contains(OptionFlags::STRICT),
+            allow_non_utf8: options.flags.contains(OptionFlags::LOSSY),
+        }
+    }
+}
+
+#[allow(deprecated)]
+impl From<DemangleFlags> for ParseOptions {
+    fn from(flags: DemangleFlags) -> ParseOptions {
+        DemangleOptions::from(flags).into()
+    }
+}
+
 // Calling conventions
-#[derive(Clone, Copy, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum CallingConv {
     Cdecl,
     Pascal,
@@ -107,18 +660,110 @@ bitflags! {
 }
 
 // Represents an identifier which may be a template.
-#[derive(Clone, Debug, PartialEq)]
+//
+// Name, Operator, Type and SymbolKind are non_exhaustive as the grammar
+// keeps growing; build names and types with the helpers below, e.g.
+// `Type::pointer(Type::class(Symbol::new(Name::identifier("Foo"), vec![])))`.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
 pub enum Name<'a> {
-    Operator(Operator),
-    NonTemplate(&'a [u8]),
+    Operator(Operator<'a>),
+    NonTemplate(Cow<'a, str>),
     Template(Box<Name<'a>>, Params<'a>),
     Discriminator(i32),
     ParsedName(Box<ParseResult<'a>>),
     AnonymousNamespace,
+    /// A template name that can be back-referenced, stored once in
+    /// `ParseResult::names` and referred to by its index there.
+    Backref(usize),
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub enum Operator {
+impl<'a> Name<'a> {
+    /// A namespace, class, function or variable name.
+    pub fn identifier<S: Into<Cow<'a, str>>>(name: S) -> Name<'a> {
+        Name::NonTemplate(name.into())
+    }
+
+    /// A template name with its arguments, e.g. `vector<int>`.
+    pub fn template(name: Name<'a>, args: Vec<Type<'a>>) -> Name<'a> {
+        Name::Template(Box::new(name), Params { types: args })
+    }
+
+    /// Copies the identifiers borrowed from the mangled symbol so that the
+    /// name can outlive it.
+    pub fn into_owned(self) -> Name<'static> {
+        match self {
+            Name::Operator(op) => Name::Operator(op.into_owned()),
+            Name::NonTemplate(name) => Name::NonTemplate(Cow::Owned(name.into_owned())),
+            Name::Template(name, params) => Name::Template(Box::new(name.into_owned()), params.into_owned()),
+            Name::Discriminator(n) => Name::Discriminator(n),
+            Name::ParsedName(parse_result) => Name::ParsedName(Box::new(parse_result.into_owned())),
+            Name::AnonymousNamespace => Name::AnonymousNamespace,
+            Name::Backref(i) => Name::Backref(i),
+        }
+    }
+
+    /// The template arguments of a template name, e.g. `int` for
+    /// `vector<int>`, or `None` for other names. A `Name::Backref` has to
+    /// be looked up with `ParseResult::resolve_name()` first.
+    pub fn template_args(&self) -> Option<&[Type<'a>]> {
+        match *self {
+            Name::Template(_, ref params) => Some(match params.types.last() {
+                Some(&Type::EmptyParameterPack) => &params.types[..params.types.len() - 1],
+                _ => &params.types,
+            }),
+            _ => None,
+        }
+    }
+
+    // Whether this is a (possibly templated) conversion operator, whose
+    // target type is encoded as the function's return type.
+    fn is_conversion_operator(&self) -> bool {
+        match *self {
+            Name::Operator(Operator::Conversion(_)) => true,
+            Name::Template(ref name, _) => name.is_conversion_operator(),
+            _ => false,
+        }
+    }
+
+    // Whether this names a compiler-generated table, guard variable or
+    // helper function such as `` `scalar deleting destructor' ``.
+    fn is_special_name(&self) -> bool {
+        match *self {
+            Name::Operator(
+                Operator::VFTable
+                | Operator::VBTable
+                | Operator::VCall
+                | Operator::LocalStaticGuard
+                | Operator::VBaseDtor
+                | Operator::VectorDeletingDtor
+                | Operator::DefaultCtorClosure
+                | Operator::ScalarDeletingDtor
+                | Operator::VectorCtorIterator
+                | Operator::VectorDtorIterator
+                | Operator::VectorVBaseCtorIterator
+                | Operator::VirtualDisplacementMap
+                | Operator::EHVectorCtorIterator
+                | Operator::EHVectorDtorIterator
+                | Operator::EHVectorVBaseCtorIterator
+                | Operator::CopyCtorClosure
+                | Operator::LocalVFTable
+                | Operator::LocalVFTableCtorClosure
+                | Operator::PlacementDeleteClosure
+                | Operator::PlacementArrayDeleteClosure,
+            ) => true,
+            Name::Operator(ref op) if op.is_rtti() => true,
+            Name::Template(ref name, _) => name.is_special_name(),
+            _ => false,
+        }
+    }
+}
+
+/// An operator, constructor or destructor, or a special name such as
+/// `` `vftable' ``. See `Operator::to_str()` for their spellings.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Operator<'a> {
     Ctor,
     Dtor,
     New,
@@ -130,7 +775,10 @@ pub enum Operator {
     EqualEqual,
     BangEqual,
     Subscript,
-    Conversion, // TODO
+    /// A conversion operator, with the type it converts to. The type is
+    /// encoded as the return type of the function, so it is `None` if the
+    /// type of the symbol wasn't parsed.
+    Conversion(Option<Box<Type<'a>>>),
     Arrow,
     Star,
     PlusPlus,
@@ -190,32 +838,309 @@ pub enum Operator {
     PlacementArrayDeleteClosure,
 
     CoroutineAwait,
-    LiteralOperatorName,
+
+    /// The type descriptor of a class for RTTI. The class is the type of
+    /// the symbol.
+    RTTITypeDescriptor,
+    /// A base class descriptor of a class for RTTI, with the offset of the
+    /// base class, the offset of its vbtable pointer, the offset of its
+    /// entry in the vbtable, and the attributes of the base class.
+    RTTIBaseClassDescriptor(i32, i32, i32, i32),
+    RTTIBaseClassArray,
+    RTTIClassHierarchyDescriptor,
+    RTTICompleteObjectLocator,
+
+    /// A literal operator, with its suffix, e.g. `_km` for `operator ""_km`.
+    LiteralOperator(Cow<'a, str>),
+
+    /// The operator with the prefix `` `udt returning' ``, e.g.
+    /// `` `udt returning'operator-> ``.
+    UdtReturning(Box<Operator<'a>>),
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl<'a> Operator<'a> {
+    // Whether this names RTTI data.
+    fn is_rtti(&self) -> bool {
+        matches!(
+            *self,
+            Operator::RTTITypeDescriptor
+                | Operator::RTTIBaseClassDescriptor(..)
+                | Operator::RTTIBaseClassArray
+                | Operator::RTTIClassHierarchyDescriptor
+                | Operator::RTTICompleteObjectLocator
+        )
+    }
+
+    /// A conversion operator to `target`.
+    pub fn conversion(target: Type<'a>) -> Operator<'a> {
+        Operator::Conversion(Some(Box::new(target)))
+    }
+
+    /// A literal operator with the suffix `suffix`, e.g. `_km`.
+    pub fn literal<S: Into<Cow<'a, str>>>(suffix: S) -> Operator<'a> {
+        Operator::LiteralOperator(suffix.into())
+    }
+
+    /// The spelling of the operator, e.g. `operator+` or `` `vftable' ``.
+    /// Constructors and destructors are spelled `ctor` and `dtor` since
+    /// they take the name of their class, and conversion and literal
+    /// operators are spelled without their type and suffix.
+    pub fn to_str(&self) -> &'static str {
+        match *self {
+            Operator::Ctor => "ctor",
+            Operator::Dtor => "dtor",
+            Operator::New => "operator new",
+            Operator::Delete => "operator delete",
+            Operator::Equal => "operator=",
+            Operator::RShift => "operator>>",
+            Operator::LShift => "operator<<",
+            Operator::Bang => "operator!",
+            Operator::EqualEqual => "operator==",
+            Operator::BangEqual => "operator!=",
+            Operator::Subscript => "operator[]",
+            Operator::Conversion(_) => "operatorcast",
+            Operator::Arrow => "operator->",
+            Operator::Star => "operator*",
+            Operator::PlusPlus => "operator++",
+            Operator::MinusMinus => "operator--",
+            Operator::Minus => "operator-",
+            Operator::Plus => "operator+",
+            Operator::Amp => "operator&",
+            Operator::ArrowStar => "operator->*",
+            Operator::Slash => "operator/",
+            Operator::Percent => "operator%",
+            Operator::Less => "operator<",
+            Operator::LessEqual => "operator<=",
+            Operator::Greater => "operator>",
+            Operator::GreaterEqual => "operator>=",
+            Operator::Comma => "operator,",
+            Operator::Call => "operator()",
+            Operator::Tilde => "operator~",
+            Operator::Caret => "operator^",
+            Operator::Pipe => "operator|",
+            Operator::AmpAmp => "operator&&",
+            Operator::PipePipe => "operator||",
+            Operator::StarEqual => "operator*=",
+            Operator::PlusEqual => "operator+=",
+            Operator::MinusEqual => "operator-=",
+            Operator::SlashEqual => "operator/=",
+            Operator::PercentEqual => "operator%=",
+            Operator::GreaterGreaterEqual => "operator>>=",
+            Operator::LessLessEqual => "operator<<=",
+            Operator::AmpEqual => "operator&=",
+            Operator::PipeEqual => "operator|=",
+            Operator::CaretEqual => "operator^=",
+
+            Operator::VFTable => "`vftable'",
+            Operator::VBTable => "`vbtable'",
+            Operator::VCall => "`vcall'",
+            Operator::Typeof => "`typeof'",
+            Operator::LocalStaticGuard => "`local static guard'",
+            Operator::String => "`string'",
+            Operator::VBaseDtor => "`vbase destructor'",
+            Operator::VectorDeletingDtor => "`vector deleting destructor'",
+            Operator::DefaultCtorClosure => "`default constructor closure'",
+            Operator::ScalarDeletingDtor => "`scalar deleting destructor'",
+            Operator::VectorCtorIterator => "`vector constructor iterator'",
+            Operator::VectorDtorIterator => "`vector destructor iterator'",
+            Operator::VectorVBaseCtorIterator => "`vector vbase constructor iterator'",
+            Operator::VirtualDisplacementMap => "`virual displacement map'",
+            Operator::EHVectorCtorIterator => "`eh vector constructor iterator'",
+            Operator::EHVectorDtorIterator => "`eh vector destructor iterator'",
+            Operator::EHVectorVBaseCtorIterator => "`eh vector vbase constructor iterator'",
+            Operator::CopyCtorClosure => "`copy constructor closure",
+
+            Operator::LocalVFTable => "`local vftable'",
+            Operator::LocalVFTableCtorClosure => "`local vftable constructor closure'",
+            Operator::ArrayNew => "operator new[]",
+            Operator::ArrayDelete => "operator delete[]",
+            Operator::PlacementDeleteClosure => "`placement delete closure'",
+            Operator::PlacementArrayDeleteClosure => "`placement delete[] closure'",
+
+            Operator::CoroutineAwait => " co_await",
+            Operator::LiteralOperator(_) => "operator \"\"",
+
+            Operator::RTTITypeDescriptor => "`RTTI Type Descriptor'",
+            Operator::RTTIBaseClassDescriptor(..) => "`RTTI Base Class Descriptor'",
+            Operator::RTTIBaseClassArray => "`RTTI Base Class Array'",
+            Operator::RTTIClassHierarchyDescriptor => "`RTTI Class Hierarchy Descriptor'",
+            Operator::RTTICompleteObjectLocator => "`RTTI Complete Object Locator'",
+
+            Operator::UdtReturning(_) => "`udt returning'",
+        }
+    }
+
+    // How llvm-undname spells the operator where it differs from
+    // to_str(): "ctor" and "dtor" in special names.
+    fn to_llvm_str(&self) -> &'static str {
+        match *self {
+            Operator::VBaseDtor => "`vbase dtor'",
+            Operator::VectorDeletingDtor => "`vector deleting dtor'",
+            Operator::DefaultCtorClosure => "`default ctor closure'",
+            Operator::ScalarDeletingDtor => "`scalar deleting dtor'",
+            Operator::VectorCtorIterator => "`vector ctor iterator'",
+            Operator::VectorDtorIterator => "`vector dtor iterator'",
+            Operator::VectorVBaseCtorIterator => "`vector vbase ctor iterator'",
+            Operator::VirtualDisplacementMap => "`virtual displacement map'",
+            Operator::EHVectorCtorIterator => "`eh vector ctor iterator'",
+            Operator::EHVectorDtorIterator => "`eh vector dtor iterator'",
+            Operator::EHVectorVBaseCtorIterator => "`eh vector vbase ctor iterator'",
+            Operator::CopyCtorClosure => "`copy ctor closure'",
+            Operator::LocalVFTableCtorClosure => "`local vftable ctor closure'",
+            Operator::CoroutineAwait => "operator co_await",
+            _ => self.to_str(),
+        }
+    }
+
+    /// Copies the identifiers borrowed from the mangled symbol so that the
+    /// operator can outlive it.
+    pub fn into_owned(self) -> Operator<'static> {
+        match self {
+            Operator::Conversion(target) => Operator::Conversion(target.map(|target| Box::new(target.into_owned()))),
+            Operator::LiteralOperator(suffix) => Operator::LiteralOperator(Cow::Owned(suffix.into_owned())),
+            Operator::UdtReturning(op) => Operator::UdtReturning(Box::new(op.into_owned())),
+            Operator::RTTIBaseClassDescriptor(a, b, c, d) => Operator::RTTIBaseClassDescriptor(a, b, c, d),
+            Operator::Ctor => Operator::Ctor,
+            Operator::Dtor => Operator::Dtor,
+            Operator::New => Operator::New,
+            Operator::Delete => Operator::Delete,
+            Operator::Equal => Operator::Equal,
+            Operator::RShift => Operator::RShift,
+            Operator::LShift => Operator::LShift,
+            Operator::Bang => Operator::Bang,
+            Operator::EqualEqual => Operator::EqualEqual,
+            Operator::BangEqual => Operator::BangEqual,
+            Operator::Subscript => Operator::Subscript,
+            Operator::Arrow => Operator::Arrow,
+            Operator::Star => Operator::Star,
+            Operator::PlusPlus => Operator::PlusPlus,
+            Operator::MinusMinus => Operator::MinusMinus,
+            Operator::Minus => Operator::Minus,
+            Operator::Plus => Operator::Plus,
+            Operator::Amp => Operator::Amp,
+            Operator::ArrowStar => Operator::ArrowStar,
+            Operator::Slash => Operator::Slash,
+            Operator::Percent => Operator::Percent,
+            Operator::Less => Operator::Less,
+            Operator::LessEqual => Operator::LessEqual,
+            Operator::Greater => Operator::Greater,
+            Operator::GreaterEqual => Operator::GreaterEqual,
+            Operator::Comma => Operator::Comma,
+            Operator::Call => Operator::Call,
+            Operator::Tilde => Operator::Tilde,
+            Operator::Caret => Operator::Caret,
+            Operator::Pipe => Operator::Pipe,
+            Operator::AmpAmp => Operator::AmpAmp,
+            Operator::PipePipe => Operator::PipePipe,
+            Operator::StarEqual => Operator::StarEqual,
+            Operator::PlusEqual => Operator::PlusEqual,
+            Operator::MinusEqual => Operator::MinusEqual,
+            Operator::SlashEqual => Operator::SlashEqual,
+            Operator::PercentEqual => Operator::PercentEqual,
+            Operator::GreaterGreaterEqual => Operator::GreaterGreaterEqual,
+            Operator::LessLessEqual => Operator::LessLessEqual,
+            Operator::AmpEqual => Operator::AmpEqual,
+            Operator::PipeEqual => Operator::PipeEqual,
+            Operator::CaretEqual => Operator::CaretEqual,
+            Operator::VFTable => Operator::VFTable,
+            Operator::VBTable => Operator::VBTable,
+            Operator::VCall => Operator::VCall,
+            Operator::Typeof => Operator::Typeof,
+            Operator::LocalStaticGuard => Operator::LocalStaticGuard,
+            Operator::String => Operator::String,
+            Operator::VBaseDtor => Operator::VBaseDtor,
+            Operator::VectorDeletingDtor => Operator::VectorDeletingDtor,
+            Operator::DefaultCtorClosure => Operator::DefaultCtorClosure,
+            Operator::ScalarDeletingDtor => Operator::ScalarDeletingDtor,
+            Operator::VectorCtorIterator => Operator::VectorCtorIterator,
+            Operator::VectorDtorIterator => Operator::VectorDtorIterator,
+            Operator::VectorVBaseCtorIterator => Operator::VectorVBaseCtorIterator,
+            Operator::VirtualDisplacementMap => Operator::VirtualDisplacementMap,
+            Operator::EHVectorCtorIterator => Operator::EHVectorCtorIterator,
+            Operator::EHVectorDtorIterator => Operator::EHVectorDtorIterator,
+            Operator::EHVectorVBaseCtorIterator => Operator::EHVectorVBaseCtorIterator,
+            Operator::CopyCtorClosure => Operator::CopyCtorClosure,
+            Operator::LocalVFTable => Operator::LocalVFTable,
+            Operator::LocalVFTableCtorClosure => Operator::LocalVFTableCtorClosure,
+            Operator::ArrayNew => Operator::ArrayNew,
+            Operator::ArrayDelete => Operator::ArrayDelete,
+            Operator::PlacementDeleteClosure => Operator::PlacementDeleteClosure,
+            Operator::PlacementArrayDeleteClosure => Operator::PlacementArrayDeleteClosure,
+            Operator::CoroutineAwait => Operator::CoroutineAwait,
+            Operator::RTTITypeDescriptor => Operator::RTTITypeDescriptor,
+            Operator::RTTIBaseClassArray => Operator::RTTIBaseClassArray,
+            Operator::RTTIClassHierarchyDescriptor => Operator::RTTIClassHierarchyDescriptor,
+            Operator::RTTICompleteObjectLocator => Operator::RTTICompleteObjectLocator,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct NameSequence<'a> {
     pub names: Vec<Name<'a>>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl<'a> NameSequence<'a> {
+    /// See `Name::into_owned()`.
+    pub fn into_owned(self) -> NameSequence<'static> {
+        NameSequence {
+            names: self.names.into_iter().map(Name::into_owned).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Params<'a> {
     pub types: Vec<Type<'a>>,
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl<'a> Params<'a> {
+    /// See `Name::into_owned()`.
+    pub fn into_owned(self) -> Params<'static> {
+        Params {
+            types: self.types.into_iter().map(Type::into_owned).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Symbol<'a> {
     pub name: Name<'a>,
     pub scope: NameSequence<'a>
 }
 
+impl<'a> Symbol<'a> {
+    /// The name `name` declared in `scope`, the enclosing namespaces and
+    /// classes innermost first.
+    pub fn new(name: Name<'a>, scope: Vec<Name<'a>>) -> Symbol<'a> {
+        Symbol {
+            name,
+            scope: NameSequence { names: scope },
+        }
+    }
+
+    /// See `Name::into_owned()`.
+    pub fn into_owned(self) -> Symbol<'static> {
+        Symbol {
+            name: self.name.into_owned(),
+            scope: self.scope.into_owned(),
+        }
+    }
+}
+
 // The type class. Mangled symbols are first parsed and converted to
 // this type and then converted to string.
-#[derive(Clone, Debug, PartialEq)]
+//
+// Types are kept in parameter lists, so the symbols of the larger variants
+// are boxed to keep the enum small. Back-references to parameter types share
+// the memorized type through `Shared` instead of copying it. Comparing and
+// hashing look through `Shared`, so it is the same as the type it shares.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
 pub enum Type<'a> {
     None,
     MemberFunction(FuncClass, CallingConv, Params<'a>, StorageClass, Box<Type<'a>>), // StorageClass is for the 'this' pointer
-    MemberFunctionPointer(Symbol<'a>, FuncClass, CallingConv, Params<'a>, StorageClass, Box<Type<'a>>),
+    MemberFunctionPointer(Box<Symbol<'a>>, FuncClass, CallingConv, Params<'a>, StorageClass, Box<Type<'a>>),
     NonMemberFunction(CallingConv, Params<'a>, StorageClass, Box<Type<'a>>),
     CXXVBTable(NameSequence<'a>, StorageClass),
     CXXVFTable(NameSequence<'a>, StorageClass),
@@ -228,10 +1153,12 @@ pub enum Type<'a> {
     RValueRef(Box<Type<'a>>, StorageClass),
     Array(i32, Box<Type<'a>>, StorageClass),
 
-    Struct(Symbol<'a>, StorageClass),
-    Union(Symbol<'a>, StorageClass),
-    Class(Symbol<'a>, StorageClass),
-    Enum(Symbol<'a>, StorageClass),
+    Struct(Box<Symbol<'a>>, StorageClass),
+    Union(Box<Symbol<'a>>, StorageClass),
+    Class(Box<Symbol<'a>>, StorageClass),
+    Enum(Box<Symbol<'a>>, Box<Type<'a>>, StorageClass), // Box<Type> is the underlying type
+    // A type written as its name alone, e.g. `std::string` from StdTypedefs.
+    Typedef(Box<Symbol<'a>>, StorageClass),
 
     Void(StorageClass),
     Bool(StorageClass),
@@ -255,1841 +1182,7406 @@ pub enum Type<'a> {
     VarArgs,
     EmptyParameterPack,
     Nullptr,
+    // A type read by `Extensions::unknown_type()`.
+    Extension(Cow<'a, str>, StorageClass),
+    // A back-reference to a parameter type, see `Type::resolve()`.
+    Shared(Arc<Type<'a>>),
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct ParseResult<'a> {
-    pub symbol: Symbol<'a>,
-    pub symbol_type: Type<'a>,
-}
+impl<'a> Type<'a> {
+    /// A pointer to `pointee`.
+    pub fn pointer(pointee: Type<'a>) -> Type<'a> {
+        Type::Ptr(Box::new(pointee), StorageClass::empty())
+    }
 
-// Demangler class takes the main role in demangling symbols.
-// It has a set of functions to parse mangled symbols into Type instnaces.
-// It also has a set of functions to cnovert Type instances to strings.
-struct ParserState<'a> {
-    // Mangled symbol. read_* functions shorten this string
-    // as they parse it.
-    input: &'a [u8],
+    /// An lvalue reference to `referee`.
+    pub fn reference(referee: Type<'a>) -> Type<'a> {
+        Type::Ref(Box::new(referee), StorageClass::empty())
+    }
 
-    // The first 10 names in a mangled name can be back-referenced by
-    // special name @[0-9]. This is a storage for the first 10 names.
-    memorized_names: Vec<Name<'a>>,
+    /// An rvalue reference to `referee`.
+    pub fn rvalue_reference(referee: Type<'a>) -> Type<'a> {
+        Type::RValueRef(Box::new(referee), StorageClass::empty())
+    }
 
-    memorized_types: Vec<Type<'a>>,
-}
+    /// An array of `len` elements of type `element`.
+    pub fn array(len: i32, element: Type<'a>) -> Type<'a> {
+        Type::Array(len, Box::new(element), StorageClass::empty())
+    }
 
-impl<'a> ParserState<'a> {
-    fn parse(&mut self) -> Result<ParseResult<'a>> {
-        // MSVC-style mangled symbols must start with b'?'.
-        if !self.consume(b"?") {
-            return Err(Error::new("does not start with b'?'".to_owned()));
+    /// The class `symbol`.
+    pub fn class(symbol: Symbol<'a>) -> Type<'a> {
+        Type::Class(Box::new(symbol), StorageClass::empty())
+    }
+
+    /// The struct `symbol`.
+    pub fn structure(symbol: Symbol<'a>) -> Type<'a> {
+        Type::Struct(Box::new(symbol), StorageClass::empty())
+    }
+
+    /// A `__cdecl` function taking `params` and returning `return_type`.
+    pub fn function(params: Vec<Type<'a>>, return_type: Type<'a>) -> Type<'a> {
+        Type::NonMemberFunction(
+            CallingConv::Cdecl,
+            Params { types: params },
+            StorageClass::empty(),
+            Box::new(return_type),
+        )
+    }
+
+    /// See `Name::into_owned()`.
+    pub fn into_owned(mut self) -> Type<'static> {
+        match self {
+            Type::None => Type::None,
+            Type::MemberFunction(func_class, calling_conv, ref mut params, sc, ref mut ret) => Type::MemberFunction(
+                func_class,
+                calling_conv,
+                take_params(params).into_owned(),
+                sc,
+                Box::new(take_type(ret).into_owned()),
+            ),
+            Type::MemberFunctionPointer(ref mut symbol, func_class, calling_conv, ref mut params, sc, ref mut ret) => {
+                Type::MemberFunctionPointer(
+                    Box::new(take_symbol(symbol).into_owned()),
+                    func_class,
+                    calling_conv,
+                    take_params(params).into_owned(),
+                    sc,
+                    Box::new(take_type(ret).into_owned()),
+                )
+            }
+            Type::NonMemberFunction(calling_conv, ref mut params, sc, ref mut ret) => Type::NonMemberFunction(
+                calling_conv,
+                take_params(params).into_owned(),
+                sc,
+                Box::new(take_type(ret).into_owned()),
+            ),
+            Type::CXXVBTable(ref mut names, sc) => Type::CXXVBTable(take_names(names).into_owned(), sc),
+            Type::CXXVFTable(ref mut names, sc) => Type::CXXVFTable(take_names(names).into_owned(), sc),
+            Type::TemplateParameterWithIndex(n) => Type::TemplateParameterWithIndex(n),
+            Type::ThreadSafeStaticGuard(n) => Type::ThreadSafeStaticGuard(n),
+            Type::Constant(n) => Type::Constant(n),
+            Type::ConstantString(ref mut s) => Type::ConstantString(mem::take(s)),
+            Type::Ptr(ref mut inner, sc) => Type::Ptr(Box::new(take_type(inner).into_owned()), sc),
+            Type::Ref(ref mut inner, sc) => Type::Ref(Box::new(take_type(inner).into_owned()), sc),
+            Type::RValueRef(ref mut inner, sc) => Type::RValueRef(Box::new(take_type(inner).into_owned()), sc),
+            Type::Array(len, ref mut inner, sc) => Type::Array(len, Box::new(take_type(inner).into_owned()), sc),
+            Type::Struct(ref mut symbol, sc) => Type::Struct(Box::new(take_symbol(symbol).into_owned()), sc),
+            Type::Typedef(ref mut symbol, sc) => Type::Typedef(Box::new(take_symbol(symbol).into_owned()), sc),
+            Type::Union(ref mut symbol, sc) => Type::Union(Box::new(take_symbol(symbol).into_owned()), sc),
+            Type::Class(ref mut symbol, sc) => Type::Class(Box::new(take_symbol(symbol).into_owned()), sc),
+            Type::Enum(ref mut symbol, ref mut underlying, sc) => Type::Enum(
+                Box::new(take_symbol(symbol).into_owned()),
+                Box::new(take_type(underlying).into_owned()),
+                sc,
+            ),
+            Type::Void(sc) => Type::Void(sc),
+            Type::Bool(sc) => Type::Bool(sc),
+            Type::Char(sc) => Type::Char(sc),
+            Type::Schar(sc) => Type::Schar(sc),
+            Type::Uchar(sc) => Type::Uchar(sc),
+            Type::Short(sc) => Type::Short(sc),
+            Type::Ushort(sc) => Type::Ushort(sc),
+            Type::Int(sc) => Type::Int(sc),
+            Type::Uint(sc) => Type::Uint(sc),
+            Type::Long(sc) => Type::Long(sc),
+            Type::Ulong(sc) => Type::Ulong(sc),
+            Type::Int64(sc) => Type::Int64(sc),
+            Type::Uint64(sc) => Type::Uint64(sc),
+            Type::Wchar(sc) => Type::Wchar(sc),
+            Type::Char16(sc) => Type::Char16(sc),
+            Type::Char32(sc) => Type::Char32(sc),
+            Type::Float(sc) => Type::Float(sc),
+            Type::Double(sc) => Type::Double(sc),
+            Type::Ldouble(sc) => Type::Ldouble(sc),
+            Type::VarArgs => Type::VarArgs,
+            Type::EmptyParameterPack => Type::EmptyParameterPack,
+            Type::Nullptr => Type::Nullptr,
+            Type::Extension(ref name, sc) => Type::Extension(Cow::Owned(name.to_string()), sc),
+            Type::Shared(ref mut inner) => {
+                let inner = match Arc::get_mut(inner) {
+                    Some(inner) => mem::replace(inner, Type::None),
+                    None => (**inner).clone(),
+                };
+                Type::Shared(Arc::new(inner.into_owned()))
+            }
         }
+    }
 
-        if self.consume(b"$") {
-            if self.consume(b"TSS") {
-                let mut guard_num: i32 = self.consume_digit().ok_or(Error::new("missing digit".to_owned()))? as i32;
-                while !self.consume(b"@") {
-                    guard_num = guard_num * 10 + self.consume_digit().ok_or(Error::new("missing digit".to_owned()))? as i32;
-                }
-                let name = self.read_nested_name()?;
-                let scope = self.read_scope()?;
-                self.expect(b"4HA")?;
-                return Ok(ParseResult {
-                    symbol: Symbol { name, scope },
-                    symbol_type: Type::ThreadSafeStaticGuard(guard_num),
-                });
+    /// The type a `Type::Shared` back-reference refers to, or this type
+    /// for other types. The methods below look through back-references.
+    pub fn resolve(&self) -> &Type<'a> {
+        let mut t = self;
+        while let Type::Shared(ref inner) = *t {
+            t = inner;
+        }
+        t
+    }
+
+    /// Whether this is a function or member function type. Pointers to
+    /// functions are pointer types.
+    pub fn is_function(&self) -> bool {
+        matches!(*self.resolve(), Type::MemberFunction(..) | Type::NonMemberFunction(..))
+    }
+
+    /// The parameter types of a function type as mangled, i.e. including
+    /// `void` and `...`, or `None` for other types. See `signature()` for
+    /// the parameters without them.
+    pub fn params(&self) -> Option<&[Type<'a>]> {
+        match *self.resolve() {
+            Type::MemberFunction(_, _, ref params, _, _)
+            | Type::NonMemberFunction(_, ref params, _, _) => Some(&params.types),
+            _ => None,
+        }
+    }
+
+    /// The return type of a function type, or `None` for other types.
+    /// Constructors and destructors have the return type `Type::None`.
+    pub fn return_type(&self) -> Option<&Type<'a>> {
+        match *self.resolve() {
+            Type::MemberFunction(_, _, _, _, ref ret)
+            | Type::NonMemberFunction(_, _, _, ref ret) => Some(ret),
+            _ => None,
+        }
+    }
+
+    /// The type a pointer or reference refers to, or `None` for other
+    /// types.
+    pub fn pointee(&self) -> Option<&Type<'a>> {
+        match *self.resolve() {
+            Type::Ptr(ref inner, _) | Type::Ref(ref inner, _) | Type::RValueRef(ref inner, _) => Some(inner),
+            _ => None,
+        }
+    }
+
+    /// The qualifiers of the type, e.g. `const` or `__ptr64`, and those of
+    /// `this` for member functions. Empty for types without qualifiers.
+    pub fn storage_class(&self) -> StorageClass {
+        match *self.resolve() {
+            Type::MemberFunction(_, _, _, sc, _)
+            | Type::MemberFunctionPointer(_, _, _, _, sc, _)
+            | Type::NonMemberFunction(_, _, sc, _)
+            | Type::CXXVBTable(_, sc)
+            | Type::CXXVFTable(_, sc)
+            | Type::Ptr(_, sc)
+            | Type::Ref(_, sc)
+            | Type::RValueRef(_, sc)
+            | Type::Array(_, _, sc)
+            | Type::Struct(_, sc)
+            | Type::Typedef(_, sc)
+            | Type::Union(_, sc)
+            | Type::Class(_, sc)
+            | Type::Enum(_, _, sc)
+            | Type::Void(sc)
+            | Type::Bool(sc)
+            | Type::Char(sc)
+            | Type::Schar(sc)
+            | Type::Uchar(sc)
+            | Type::Short(sc)
+            | Type::Ushort(sc)
+            | Type::Int(sc)
+            | Type::Uint(sc)
+            | Type::Long(sc)
+            | Type::Ulong(sc)
+            | Type::Int64(sc)
+            | Type::Uint64(sc)
+            | Type::Wchar(sc)
+            | Type::Char16(sc)
+            | Type::Char32(sc)
+            | Type::Float(sc)
+            | Type::Double(sc)
+            | Type::Ldouble(sc)
+            | Type::Extension(_, sc) => sc,
+            Type::None
+            | Type::TemplateParameterWithIndex(_)
+            | Type::ThreadSafeStaticGuard(_)
+            | Type::Constant(_)
+            | Type::ConstantString(_)
+            | Type::VarArgs
+            | Type::EmptyParameterPack
+            | Type::Nullptr
+            | Type::Shared(_) => StorageClass::empty(),
+        }
+    }
+
+    // The qualifiers of the type for storage_class(), to change them.
+    fn storage_class_mut(&mut self) -> Option<&mut StorageClass> {
+        match *self {
+            Type::MemberFunction(_, _, _, ref mut sc, _)
+            | Type::MemberFunctionPointer(_, _, _, _, ref mut sc, _)
+            | Type::NonMemberFunction(_, _, ref mut sc, _)
+            | Type::CXXVBTable(_, ref mut sc)
+            | Type::CXXVFTable(_, ref mut sc)
+            | Type::Ptr(_, ref mut sc)
+            | Type::Ref(_, ref mut sc)
+            | Type::RValueRef(_, ref mut sc)
+            | Type::Array(_, _, ref mut sc)
+            | Type::Struct(_, ref mut sc)
+            | Type::Typedef(_, ref mut sc)
+            | Type::Union(_, ref mut sc)
+            | Type::Class(_, ref mut sc)
+            | Type::Enum(_, _, ref mut sc)
+            | Type::Void(ref mut sc)
+            | Type::Bool(ref mut sc)
+            | Type::Char(ref mut sc)
+            | Type::Schar(ref mut sc)
+            | Type::Uchar(ref mut sc)
+            | Type::Short(ref mut sc)
+            | Type::Ushort(ref mut sc)
+            | Type::Int(ref mut sc)
+            | Type::Uint(ref mut sc)
+            | Type::Long(ref mut sc)
+            | Type::Ulong(ref mut sc)
+            | Type::Int64(ref mut sc)
+            | Type::Uint64(ref mut sc)
+            | Type::Wchar(ref mut sc)
+            | Type::Char16(ref mut sc)
+            | Type::Char32(ref mut sc)
+            | Type::Float(ref mut sc)
+            | Type::Double(ref mut sc)
+            | Type::Ldouble(ref mut sc)
+            | Type::Extension(_, ref mut sc) => Some(sc),
+            Type::None
+            | Type::TemplateParameterWithIndex(_)
+            | Type::ThreadSafeStaticGuard(_)
+            | Type::Constant(_)
+            | Type::ConstantString(_)
+            | Type::VarArgs
+            | Type::EmptyParameterPack
+            | Type::Nullptr
+            | Type::Shared(_) => None,
+        }
+    }
+
+    /// The signature of a function or member function pointer type, or
+    /// `None` for other types.
+    pub fn signature(&self) -> Option<Signature<'_, 'a>> {
+        let (func_class, calling_conv, params, this_quals, ret) = match *self.resolve() {
+            Type::MemberFunction(func_class, calling_conv, ref params, sc, ref ret)
+            | Type::MemberFunctionPointer(_, func_class, calling_conv, ref params, sc, ref ret) => {
+                (Some(func_class), calling_conv, params, sc, ret)
             }
-            let name = self.read_template_name()?;
-            return Ok(ParseResult {
-                symbol: Symbol { name, scope: NameSequence{ names: Vec::new() } },
-                symbol_type: Type::None,
-            });
+            Type::NonMemberFunction(calling_conv, ref params, sc, ref ret) => (None, calling_conv, params, sc, ret),
+            _ => return None,
+        };
+        let mut parameters = &params.types[..];
+        let variadic = parameters.last() == Some(&Type::VarArgs);
+        if variadic {
+            parameters = &parameters[..parameters.len() - 1];
         }
+        if let [Type::Void(sc)] = *parameters {
+            if sc.is_empty() {
+                parameters = &[];
+            }
+        }
+        Some(Signature {
+            return_type: match **ret {
+                Type::None => None,
+                ref ret => Some(ret),
+            },
+            parameters,
+            variadic,
+            calling_conv,
+            this_quals,
+            func_class,
+        })
+    }
+}
 
-        // What follows is a main symbol name. This may include
-        // namespaces or class names.
-        let symbol = self.read_name(true)?;
+impl<'a> Type<'a> {
+    // The position of the variant in the declaration, which orders types
+    // of different variants.
+    fn variant_index(&self) -> u8 {
+        match *self {
+            Type::None => 0,
+            Type::MemberFunction(..) => 1,
+            Type::MemberFunctionPointer(..) => 2,
+            Type::NonMemberFunction(..) => 3,
+            Type::CXXVBTable(..) => 4,
+            Type::CXXVFTable(..) => 5,
+            Type::TemplateParameterWithIndex(_) => 6,
+            Type::ThreadSafeStaticGuard(_) => 7,
+            Type::Constant(_) => 8,
+            Type::ConstantString(_) => 9,
+            Type::Ptr(..) => 10,
+            Type::Ref(..) => 11,
+            Type::RValueRef(..) => 12,
+            Type::Array(..) => 13,
+            Type::Struct(..) => 14,
+            Type::Union(..) => 15,
+            Type::Class(..) => 16,
+            Type::Enum(..) => 17,
+            Type::Typedef(..) => 18,
+            Type::Void(_) => 19,
+            Type::Bool(_) => 20,
+            Type::Char(_) => 21,
+            Type::Schar(_) => 22,
+            Type::Uchar(_) => 23,
+            Type::Short(_) => 24,
+            Type::Ushort(_) => 25,
+            Type::Int(_) => 26,
+            Type::Uint(_) => 27,
+            Type::Long(_) => 28,
+            Type::Ulong(_) => 29,
+            Type::Int64(_) => 30,
+            Type::Uint64(_) => 31,
+            Type::Wchar(_) => 32,
+            Type::Char16(_) => 33,
+            Type::Char32(_) => 34,
+            Type::Float(_) => 35,
+            Type::Double(_) => 36,
+            Type::Ldouble(_) => 37,
+            Type::VarArgs => 38,
+            Type::EmptyParameterPack => 39,
+            Type::Nullptr => 40,
+            Type::Extension(..) => 41,
+            Type::Shared(_) => 42,
+        }
+    }
+}
 
-        if let Ok(c) = self.get() {
-            let symbol_type = match c {
-                b'0'...b'5' => {
-                    // Read a variable.
-                    self.read_var_type(StorageClass::empty())?
-                }
-                b'6' => {
-                    let access_class = self.read_qualifier();
-                    let scope = self.read_scope()?;
-                    Type::CXXVFTable(scope, access_class)
-                }
-                b'7' => {
-                    let access_class = self.read_qualifier();
-                    let scope = self.read_scope()?;
-                    Type::CXXVBTable(scope, access_class)
-                }
-                b'9' => {
-                    // extern "C" names have their class and type omitted.
-                    Type::None
-                }
-                b'Y' => {
-                    // Read a non-member function.
-                    let calling_conv = self.read_calling_conv()?;
-                    let storage_class = self.read_storage_class_for_return()?;
-                    let return_type = self.read_var_type(storage_class)?;
-                    let params = self.read_func_params()?;
-                    Type::NonMemberFunction(calling_conv, params, StorageClass::empty(), Box::new(return_type))
-                }
-                b'_' => {
-                    // Read an encoded string.
-                    let char_bytes = match self.get()? {
-                        b'0' => 1, // char
-                        b'1' => 2, // wchar_t
-                        _ => {
-                            return Err(Error::new("unknown string character type".to_owned()));
-                        },
-                    };
-                    self.read_encoded_string(char_bytes)?
-                }
-                c => {
-                    // Read a member function.
-                    let func_class = self.read_func_class(c)?;
-                    let access_class;
-                    if func_class.contains(FuncClass::STATIC) {
-                        access_class = StorageClass::empty();
-                    } else {
-                        let _is_64bit_ptr = self.expect(b"E");
-                        let restrict = if self.consume(b"I") {
-                            StorageClass::RESTRICT
-                        } else {
-                            StorageClass::empty()
-                        };
-                        let ref_qualifiers = match self.peek() {
-                            Some(b'G') => {
-                                self.expect(b"G").unwrap();
-                                StorageClass::LVALUE_QUAL
-                            },
-                            Some(b'H') => {
-                                self.expect(b"H").unwrap();
-                                StorageClass::RVALUE_QUAL
-                            },
-                            _ => StorageClass::empty(),
-                        };
-                        access_class = self.read_qualifier() | restrict | ref_qualifiers;
-                    }
+impl<'a> PartialEq for Type<'a> {
+    fn eq(&self, other: &Type<'a>) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
 
-                    let calling_conv = self.read_calling_conv()?;
-                    let storage_class_for_return = self.read_storage_class_for_return()?;
-                    let return_type = self.read_func_return_type(storage_class_for_return)?;
-                    let params = self.read_func_params()?;
-                    Type::MemberFunction(func_class, calling_conv, params, access_class, Box::new(return_type))
-                }
-            };
-            Ok(ParseResult {
-                symbol,
-                symbol_type,
-            })
-        } else {
-            Ok(ParseResult {
-                symbol,
-                symbol_type: Type::None,
-            })
-        }
+impl<'a> Eq for Type<'a> {}
+
+impl<'a> PartialOrd for Type<'a> {
+    fn partial_cmp(&self, other: &Type<'a>) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
     }
+}
 
-    fn peek(&self) -> Option<u8> {
-        self.input.first().cloned()
+impl<'a> Ord for Type<'a> {
+    fn cmp(&self, other: &Type<'a>) -> cmp::Ordering {
+        let (a, b) = (self.resolve(), other.resolve());
+        // Back-references can share a type many times over, which would
+        // take exponential time to compare by value.
+        if ptr::eq(a, b) {
+            return cmp::Ordering::Equal;
+        }
+        match (a, b) {
+            (Type::MemberFunction(a0, a1, a2, a3, a4), Type::MemberFunction(b0, b1, b2, b3, b4)) => {
+                (a0, a1, a2, a3, a4).cmp(&(b0, b1, b2, b3, b4))
+            }
+            (
+                Type::MemberFunctionPointer(a0, a1, a2, a3, a4, a5),
+                Type::MemberFunctionPointer(b0, b1, b2, b3, b4, b5),
+            ) => (a0, a1, a2, a3, a4, a5).cmp(&(b0, b1, b2, b3, b4, b5)),
+            (Type::NonMemberFunction(a0, a1, a2, a3), Type::NonMemberFunction(b0, b1, b2, b3)) => {
+                (a0, a1, a2, a3).cmp(&(b0, b1, b2, b3))
+            }
+            (Type::CXXVBTable(a0, a1), Type::CXXVBTable(b0, b1))
+            | (Type::CXXVFTable(a0, a1), Type::CXXVFTable(b0, b1)) => (a0, a1).cmp(&(b0, b1)),
+            (Type::TemplateParameterWithIndex(a), Type::TemplateParameterWithIndex(b))
+            | (Type::ThreadSafeStaticGuard(a), Type::ThreadSafeStaticGuard(b))
+            | (Type::Constant(a), Type::Constant(b)) => a.cmp(b),
+            (Type::ConstantString(a), Type::ConstantString(b)) => a.cmp(b),
+            (Type::Ptr(a0, a1), Type::Ptr(b0, b1))
+            | (Type::Ref(a0, a1), Type::Ref(b0, b1))
+            | (Type::RValueRef(a0, a1), Type::RValueRef(b0, b1)) => (a0, a1).cmp(&(b0, b1)),
+            (Type::Array(a0, a1, a2), Type::Array(b0, b1, b2)) => (a0, a1, a2).cmp(&(b0, b1, b2)),
+            (Type::Struct(a0, a1), Type::Struct(b0, b1))
+            | (Type::Union(a0, a1), Type::Union(b0, b1))
+            | (Type::Class(a0, a1), Type::Class(b0, b1))
+            | (Type::Typedef(a0, a1), Type::Typedef(b0, b1)) => (a0, a1).cmp(&(b0, b1)),
+            (Type::Enum(a0, a1, a2), Type::Enum(b0, b1, b2)) => (a0, a1, a2).cmp(&(b0, b1, b2)),
+            (Type::Extension(a0, a1), Type::Extension(b0, b1)) => (a0, a1).cmp(&(b0, b1)),
+            (a, b) => match a.variant_index().cmp(&b.variant_index()) {
+                cmp::Ordering::Equal => a.storage_class().cmp(&b.storage_class()),
+                ordering => ordering,
+            },
+        }
     }
+}
 
-    fn get(&mut self) -> Result<u8> {
-        match self.peek() {
-            Some(first) => {
-                self.trim(1);
-                Ok(first)
+impl<'a> Hash for Type<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let t = self.resolve();
+        t.variant_index().hash(state);
+        match *t {
+            Type::MemberFunction(ref a0, ref a1, ref a2, ref a3, ref a4) => (a0, a1, a2, a3, a4).hash(state),
+            Type::MemberFunctionPointer(ref a0, ref a1, ref a2, ref a3, ref a4, ref a5) => {
+                (a0, a1, a2, a3, a4, a5).hash(state)
+            }
+            Type::NonMemberFunction(ref a0, ref a1, ref a2, ref a3) => (a0, a1, a2, a3).hash(state),
+            Type::CXXVBTable(ref a0, ref a1) | Type::CXXVFTable(ref a0, ref a1) => (a0, a1).hash(state),
+            Type::TemplateParameterWithIndex(a) | Type::ThreadSafeStaticGuard(a) | Type::Constant(a) => {
+                a.hash(state)
+            }
+            Type::ConstantString(ref a) => a.hash(state),
+            Type::Ptr(ref a0, ref a1) | Type::Ref(ref a0, ref a1) | Type::RValueRef(ref a0, ref a1) => {
+                (a0, a1).hash(state)
             }
-            None => {panic!("Unexpected end of input");}// Err(Error::new("unexpected end of input".to_owned())),
+            Type::Array(ref a0, ref a1, ref a2) => (a0, a1, a2).hash(state),
+            Type::Struct(ref a0, ref a1)
+            | Type::Union(ref a0, ref a1)
+            | Type::Class(ref a0, ref a1)
+            | Type::Typedef(ref a0, ref a1) => (a0, a1).hash(state),
+            Type::Enum(ref a0, ref a1, ref a2) => (a0, a1, a2).hash(state),
+            Type::Extension(ref a0, ref a1) => (a0, a1).hash(state),
+            // The remaining variants have no fields or just qualifiers.
+            _ => t.storage_class().hash(state),
         }
     }
+}
 
-    fn consume(&mut self, s: &[u8]) -> bool {
-        if self.input.starts_with(s) {
-            self.trim(s.len());
-            true
-        } else {
-            false
+// Types built by hand can chain pointers and arrays far more deeply than
+// the parser allows. Dropping them must not recurse: the wrapped types are
+// taken out of the chain and dropped one by one.
+impl<'a> Drop for Type<'a> {
+    fn drop(&mut self) {
+        let mut next = match take_wrapped_type(self) {
+            Some(t) => t,
+            None => return,
+        };
+        while let Some(t) = take_wrapped_type(&mut next) {
+            next = t;
         }
     }
+}
 
-    fn trim(&mut self, len: usize) {
-        self.input = &self.input[len..]
+// Replaces the type wrapped by a pointer, reference or array with
+// `Type::None` and returns it.
+fn take_wrapped_type<'a>(t: &mut Type<'a>) -> Option<Type<'a>> {
+    match *t {
+        Type::Ptr(ref mut inner, _)
+        | Type::Ref(ref mut inner, _)
+        | Type::RValueRef(ref mut inner, _)
+        | Type::Array(_, ref mut inner, _) => Some(mem::replace(&mut **inner, Type::None)),
+        _ => None,
     }
+}
 
-    fn expect(&mut self, s: &[u8]) -> Result<()> {
-        if !self.consume(s) {
-            return Err(Error::new(format!(
-                "{} expected, but got {}",
-                str::from_utf8(s)?,
-                str::from_utf8(self.input)?
-            )));
-        }
-        Ok(())
+// Helpers for taking the children out of a `Type`, which can't be moved
+// out of as it implements `Drop`. They leave cheap placeholders behind.
+fn take_type<'a>(t: &mut Type<'a>) -> Type<'a> {
+    mem::replace(t, Type::None)
+}
+
+fn take_symbol<'a>(symbol: &mut Symbol<'a>) -> Symbol<'a> {
+    mem::replace(symbol, Symbol::new(Name::AnonymousNamespace, Vec::new()))
+}
+
+fn take_params<'a>(params: &mut Params<'a>) -> Params<'a> {
+    Params {
+        types: mem::take(&mut params.types),
     }
+}
 
-    fn consume_digit(&mut self) -> Option<u8> {
-        match self.peek() {
-            Some(first) => {
-                if char::from(first).is_digit(10) {
-                    self.trim(1);
-                    Some(first - b'0')
-                } else {
-                    None
-                }
-            }
-            None => None,
-        }
+fn take_names<'a>(names: &mut NameSequence<'a>) -> NameSequence<'a> {
+    NameSequence {
+        names: mem::take(&mut names.names),
     }
+}
 
-    fn consume_hex_digit(&mut self) -> bool {
-        match self.peek() {
-            Some(first) => {
-                if char::from(first).is_digit(16) {
-                    self.trim(1);
-                    true
-                } else {
-                    false
-                }
-            },
-            None => false,
-        }
+/// The access specifier of a member function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Access {
+    Public,
+    Protected,
+    Private,
+}
+
+/// The parts of a function type, see `Type::signature()` and
+/// `ParseResult::signature()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Signature<'b, 'a> {
+    return_type: Option<&'b Type<'a>>,
+    parameters: &'b [Type<'a>],
+    variadic: bool,
+    calling_conv: CallingConv,
+    this_quals: StorageClass,
+    func_class: Option<FuncClass>,
+}
+
+impl<'b, 'a> Signature<'b, 'a> {
+    /// The return type, or `None` for constructors and destructors.
+    pub fn return_type(&self) -> Option<&'b Type<'a>> {
+        self.return_type
     }
 
-    fn read_encoded_string(&mut self, char_bytes: i32) -> Result<Type<'a>> {
-        let byte_length = self.read_number()?; // including null terminator
-        let _crc = self.read_number()?;
-        let bytes = min(byte_length, char_bytes * 32);
+    /// The parameter types, without the `...` of variadic functions. Empty
+    /// for `(void)`.
+    pub fn parameters(&self) -> &'b [Type<'a>] {
+        self.parameters
+    }
 
-        let mut collected = vec!();
-        for _i in 0..bytes {
-            let c = self.get()?;
-            let byte: u8 = match c {
-                b'0'...b'9' | b'a'...b'z' | b'A'...b'Z' | b'_' | b'$' => {
-                    c
-                }
-                b'?' => {
-                    let c = self.get()?;
-                    match c {
-                        b'A'...b'Z' => {
-                            c - b'A' + 0xe1
-                        }
-                        b'a'...b'z' => {
-                            c - b'A' + 0xc1
-                        }
-                        b'0'...b'9' => {
-                            let v = &[b',', b'/', b'\\', b':', b'.',
-                                      b' ', b'\n', b'\t', b'\'', b'-'];
-                            v[(c - b'0') as usize]
-                        }
-                        b'$' => {
-                            let high = self.get()? - b'A';
-                            let low = self.get()? - b'A';
-                            high << 4 | low
-                        }
-                        _ => {
-                            return Err(Error::new(format!(
-                                "unknown escaped encoded string character {}",
-                                char::from(c))));
-                        }
-                    }
-                }
-                _ => {
-                    return Err(Error::new(format!(
-                        "unknown escaped encoded string character {}",
-                        char::from(c))));
-                }
-            };
-            collected.push(byte);
-        }
+    /// Whether the parameters end in `...`.
+    pub fn is_variadic(&self) -> bool {
+        self.variadic
+    }
 
-        Ok(Type::ConstantString(collected))
+    pub fn calling_conv(&self) -> CallingConv {
+        self.calling_conv
     }
 
-    // Sometimes numbers are encoded in mangled symbols. For example,
-    // "int (*x)[20]" is a valid C type (x is a pointer to an array of
-    // length 20), so we need some way to embed numbers as part of symbols.
-    // This function parses it.
-    //
-    // <number>               ::= [?] <non-negative integer>
-    //
-    // <non-negative integer> ::= <decimal digit> # when 1 <= Number <= 10
-    //                        ::= <hex digit>+ @  # when Numbrer == 0 or >= 10
-    //
-    // <hex-digit>            ::= [A-P]           # A = 0, B = 1, ...
-    fn read_number(&mut self) -> Result<i32> {
-        let neg = self.consume(b"?");
+    /// The qualifiers of the `this` pointer of member functions, e.g.
+    /// `const`. Empty for other functions.
+    pub fn this_quals(&self) -> StorageClass {
+        self.this_quals
+    }
 
-        if let Some(digit) = self.consume_digit() {
-            let ret = digit + 1;
-            return Ok(if neg { -(ret as i32) } else { ret as i32 });
+    /// The access specifier of member functions, or `None` for other
+    /// functions.
+    pub fn access(&self) -> Option<Access> {
+        let func_class = self.func_class?;
+        if func_class.contains(FuncClass::PRIVATE) {
+            Some(Access::Private)
+        } else if func_class.contains(FuncClass::PROTECTED) {
+            Some(Access::Protected)
+        } else if func_class.contains(FuncClass::PUBLIC) {
+            Some(Access::Public)
+        } else {
+            None
         }
+    }
 
-        let orig = self.input;
-        let mut i = 0;
-        let mut ret = 0;
-        for c in self.input {
-            match *c {
-                b'@' => {
-                    self.trim(i + 1);
-                    return Ok(if neg { -(ret as i32) } else { ret as i32 });
-                }
-                b'A'...b'P' => {
-                    ret = (ret << 4) + ((c - b'A') as i32);
-                    i += 1;
-                }
-                _ => {
-                    return Err(Error::new(format!("bad number: {}", str::from_utf8(orig)?)));
-                }
-            }
-        }
-        Err(Error::new(format!("bad number: {}", str::from_utf8(orig)?)))
+    /// Whether this is a static member function.
+    pub fn is_static(&self) -> bool {
+        self.func_class.is_some_and(|func_class| func_class.contains(FuncClass::STATIC))
     }
 
-    // Read until the next b'@'.
-    fn read_string(&mut self) -> Result<&'a [u8]> {
-        if let Some(pos) = self.input.iter().position(|&x| x == b'@') {
-            let ret = &self.input[0..pos];
-            self.trim(pos + 1);
-            Ok(ret)
-        } else {
-            let error = format!("read_string: missing b'@': {}", str::from_utf8(self.input)?);
-            Err(Error::new(error))
+    /// Whether this is a virtual member function.
+    pub fn is_virtual(&self) -> bool {
+        self.func_class.is_some_and(|func_class| func_class.contains(FuncClass::VIRTUAL))
+    }
+}
+
+/// What kind of entity a symbol names.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum SymbolKind {
+    /// A free or member function.
+    Function,
+    /// An adjustor or vcall thunk of a virtual member function.
+    Thunk,
+    /// A variable at namespace scope.
+    GlobalVariable,
+    /// A static data member of a class.
+    StaticMemberVariable,
+    /// A static variable inside a function.
+    LocalStaticVariable,
+    /// A virtual function table, `` `vftable' ``.
+    VTable,
+    /// A virtual base table, `` `vbtable' ``.
+    VBTable,
+    /// The guard variable of a local static variable.
+    Guard,
+    /// A string literal, `` `string' ``.
+    StringLiteral,
+    /// RTTI data of a class, such as its `` `RTTI Type Descriptor' ``.
+    Rtti,
+    /// Anything else, e.g. `extern "C"` names.
+    Other,
+}
+
+/// How a variable is stored, see `ParseResult::variable_kind`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VariableKind {
+    /// A variable at namespace scope.
+    Global,
+    /// A static data member of a class, with its access specifier.
+    StaticMember(Access),
+    /// A static variable inside a function.
+    LocalStatic,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ParseResult<'a> {
+    pub symbol: Symbol<'a>,
+    pub symbol_type: Type<'a>,
+    pub symbol_kind: SymbolKind,
+    /// The storage of a variable, `None` for other symbols.
+    pub variable_kind: Option<VariableKind>,
+    /// How much an adjustor thunk adds to `this` before calling the
+    /// function, `None` for other symbols.
+    pub thunk_adjustment: Option<i32>,
+    /// The names that `Name::Backref`s refer to. Only filled in for the
+    /// outermost symbol; the back-references of nested symbols
+    /// (`Name::ParsedName`) refer to the table of the outermost one.
+    pub names: Vec<Name<'a>>,
+}
+
+impl<'a> ParseResult<'a> {
+    /// What kind of entity the symbol names, e.g. to filter symbol tables.
+    pub fn kind(&self) -> SymbolKind {
+        self.symbol_kind
+    }
+
+    /// Whether the symbol is a global, static member or local static
+    /// variable, and the access of static members.
+    pub fn variable_kind(&self) -> Option<VariableKind> {
+        self.variable_kind
+    }
+
+    /// Looks up `name` in `names` if it is a `Name::Backref`, e.g. in a
+    /// `Visitor`. Other names are returned as they are.
+    pub fn resolve_name<'b>(&'b self, name: &'b Name<'a>) -> &'b Name<'a> {
+        resolve_name(&self.names, name)
+    }
+
+    /// The unqualified name of the symbol, e.g. `push_back` or
+    /// `operator+`.
+    pub fn function_name<O: Into<DemangleOptions>>(&self, flags: O) -> Result<String> {
+        let flags = flags.into();
+        let this = simplified(self, flags);
+        serialize_part(flags, &this.names, |serializer| {
+            serializer.write_unqualified_name(&this.symbol.name, &this.symbol.scope, Some(&this.symbol_type))
+        })
+    }
+
+    /// The namespaces and classes the symbol is declared in, e.g.
+    /// `std::vector<int,class std::allocator<int> >`. Empty for symbols in
+    /// the global namespace.
+    pub fn qualified_scope<O: Into<DemangleOptions>>(&self, flags: O) -> Result<String> {
+        let flags = flags.into();
+        let this = simplified(self, flags);
+        serialize_part(flags, &this.names, |serializer| serializer.write_scope(&this.symbol.scope))
+    }
+
+    /// The parameter types of a function, one string each. Empty for
+    /// functions without parameters and for symbols that are not functions.
+    pub fn parameter_strings<O: Into<DemangleOptions>>(&self, flags: O) -> Result<Vec<String>> {
+        let flags = flags.into();
+        let this = simplified(self, flags);
+        let params = this.symbol_type.params().unwrap_or(&[]);
+        match *params {
+            [Type::Void(sc)] if sc.is_empty() => Ok(Vec::new()),
+            _ => params.iter().map(|t| this.type_string(t, flags)).collect(),
         }
     }
 
-    // First 10 strings can be referenced by special names ?0, ?1, ..., ?9.
-    // Memorize it.
-    fn memorize_name(&mut self, n: &Name<'a>) {
-        // TODO: the contains check does an equality check on the Name enum, which
-        // might do unexpected things in subtle cases. It's not a pure string equality check.
-        // println!("memorize name {:?}", n);
-        if self.memorized_names.len() < 10 && !self.memorized_names.contains(n) {
-            self.memorized_names.push(n.clone());
+    // Writes a type that is part of this symbol, which has been simplified
+    // for `flags` already.
+    fn type_string(&self, t: &Type, flags: DemangleOptions) -> Result<String> {
+        serialize_part(flags, &self.names, |serializer| {
+            serializer.write_pre(t)?;
+            serializer.write_post(t)
+        })
+    }
+
+    /// The signature of a function symbol, or `None` for symbols that are
+    /// not functions.
+    pub fn signature(&self) -> Option<Signature<'_, 'a>> {
+        self.symbol_type.signature()
+    }
+
+    /// The template arguments of the symbol itself, e.g. `int` for
+    /// `f<int>`, or `None` if it isn't a template.
+    pub fn template_args(&self) -> Option<&[Type<'a>]> {
+        self.resolve_name(&self.symbol.name).template_args()
+    }
+
+    /// The namespaces, classes and functions the symbol is declared in,
+    /// outermost first.
+    pub fn scope_components(&self) -> ScopeComponents<'_, 'a> {
+        self.scope_components_of(&self.symbol)
+    }
+
+    /// Like `scope_components()`, but for a symbol that is part of this
+    /// one, e.g. the name of a class in a parameter type.
+    pub fn scope_components_of<'b>(&'b self, symbol: &'b Symbol<'a>) -> ScopeComponents<'b, 'a> {
+        ScopeComponents {
+            names: &self.names,
+            scope: symbol.scope.names.iter().rev(),
         }
     }
-    fn memorize_type(&mut self, t: &Type<'a>) {
-        // TODO: the contains check does an equality check on the Type enum, which
-        // might do unexpected things in subtle cases. It's not a pure string equality check.
-        if self.memorized_types.len() < 10 && !self.memorized_types.contains(t) {
-            self.memorized_types.push(t.clone());
+
+    /// Calls `f` on every scope in the symbol, including the scopes of
+    /// types and template arguments, to drop, rewrite or add names before
+    /// serializing it. The names of a scope are innermost first, as in
+    /// `NameSequence`.
+    ///
+    /// ```
+    /// use msvc_demangler::{parse, serialize, DemangleOptions, Name};
+    ///
+    /// let mut parsed = parse("?f@__1@std@@YAXV?$vector@H@12@@Z").unwrap();
+    /// parsed.rewrite_scopes(|names| names.retain(|name| *name != Name::NonTemplate("__1".into())));
+    /// assert_eq!(
+    ///     serialize(&parsed, DemangleOptions::cxxfilt()).unwrap(),
+    ///     "std::f(std::vector<int>)",
+    /// );
+    /// ```
+    pub fn rewrite_scopes<F: FnMut(&mut Vec<Name<'a>>)>(&mut self, mut f: F) {
+        for name in &mut self.names {
+            rewrite_name_scopes(name, &mut f);
         }
+        rewrite_symbol_scopes(&mut self.symbol, &mut f);
+        rewrite_type_scopes(&mut self.symbol_type, &mut f);
     }
 
-    fn read_template_name(&mut self) -> Result<Name<'a>> {
-        // Templates have their own context for backreferences.
-        let saved_memorized_names = mem::replace(&mut self.memorized_names, vec![]);
-        let saved_memorized_types = mem::replace(&mut self.memorized_types, vec![]);
-        let name = self.read_unqualified_name(false)?; // how does wine deal with ??$?DM@std@@YA?AV?$complex@M@0@ABMABV10@@Z
-        let template_params = self.read_params()?;
-        let _ = mem::replace(&mut self.memorized_names, saved_memorized_names);
-        let _ = mem::replace(&mut self.memorized_types, saved_memorized_types);
-        Ok(Name::Template(Box::new(name), template_params))
+    /// All template instances in the symbol, including those in scopes,
+    /// types and template arguments, as pairs of the name of the template
+    /// and its arguments. Back-referenced instances are only listed once.
+    pub fn template_instances(&self) -> Vec<(&Name<'a>, &[Type<'a>])> {
+        let mut finder = TemplateFinder {
+            names: &self.names,
+            seen: vec![false; self.names.len()],
+            instances: Vec::new(),
+        };
+        finder.symbol(self);
+        finder.instances
     }
 
-    fn read_nested_name(&mut self) -> Result<Name<'a>> {
-        let orig = self.input;
-        let name = if let Some(i) = self.consume_digit() {
-            let i = i as usize;
-            if i >= self.memorized_names.len() {
-                return Err(Error::new(format!(
-                    "name reference too large: {}",
-                    str::from_utf8(orig)?
-                )));
+    /// The arguments of every instance of the template `name` in the
+    /// symbol, e.g. all `T` of `nsTArray<T>` for `"nsTArray"`.
+    pub fn template_args_of(&self, name: &str) -> Vec<&[Type<'a>]> {
+        self.template_instances()
+            .into_iter()
+            .filter(|&(template, _)| *template == Name::NonTemplate(Cow::Borrowed(name)))
+            .map(|(_, args)| args)
+            .collect()
+    }
+
+    /// Whether the symbol is a constructor, destructor, table or RTTI data
+    /// of a class, and if so, the name of the class, e.g. `ns::C`.
+    pub fn class_member<O: Into<DemangleOptions>>(&self, flags: O) -> Result<Option<(String, ClassMember)>> {
+        let flags = flags.into();
+        let mut name = self.resolve_name(&self.symbol.name);
+        while let Name::Template(ref inner, _) = *name {
+            name = self.resolve_name(inner);
+        }
+        let member = match *name {
+            Name::Operator(Operator::Ctor)
+            | Name::Operator(Operator::DefaultCtorClosure)
+            | Name::Operator(Operator::CopyCtorClosure) => ClassMember::Constructor,
+            Name::Operator(Operator::Dtor) | Name::Operator(Operator::VBaseDtor) => ClassMember::Destructor,
+            Name::Operator(Operator::ScalarDeletingDtor) | Name::Operator(Operator::VectorDeletingDtor) => {
+                ClassMember::DeletingDestructor
             }
-            // println!("reading memorized name in position {}", i);
-            // println!(
-            //    "current list of memorized_names: {:#?}",
-            //    self.memorized_names
-            // );
-            self.memorized_names[i].clone()
-        } else if self.consume(b"?") {
-            match self.peek() {
-                Some(b'?') => {
-                    let name = Name::ParsedName(Box::new(self.parse()?));
-                    // println!("parsed name: {}", str::from_utf8(self.input)?);
-                    name
-                },
-                _ => {
-                    if self.consume(b"$") {
-                        let name = self.read_template_name()?;
-                        self.memorize_name(&name);
-                        name
-                    } else if self.consume(b"A") {
-                        // Anonymous namespace.
-                        if self.consume(b"0x") {
-                            while self.consume_hex_digit() {
-                            }
-                        }
-                        self.expect(b"@")?;
-                        Name::AnonymousNamespace
-                    } else {
-                        let discriminator = self.read_number()?;
-                        Name::Discriminator(discriminator)
+            Name::Operator(Operator::VFTable) | Name::Operator(Operator::LocalVFTable) => ClassMember::VFTable,
+            Name::Operator(Operator::VBTable) => ClassMember::VBTable,
+            Name::Operator(Operator::RTTITypeDescriptor) => {
+                // The class is the type of the symbol.
+                let this = simplified(self, flags);
+                return match this.symbol_type {
+                    Type::Class(ref symbol, _)
+                    | Type::Struct(ref symbol, _)
+                    | Type::Union(ref symbol, _)
+                    | Type::Typedef(ref symbol, _) => {
+                        let class = serialize_part(flags, &this.names, |serializer| {
+                            serializer.write_name(symbol, None, SpanKind::TypeName)
+                        })?;
+                        Ok(Some((class, ClassMember::Rtti)))
                     }
-                }
+                    _ => Ok(None),
+                };
             }
-        } else {
-            // Non-template functions or classes.
-            let name = self.read_string()?;
-            let name = Name::NonTemplate(name);
-            self.memorize_name(&name);
-            name
+            Name::Operator(ref op) if op.is_rtti() => ClassMember::Rtti,
+            _ => return Ok(None),
         };
-        Ok(name)
+        if self.symbol.scope.names.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some((self.qualified_scope(flags)?, member)))
     }
 
-    fn read_unqualified_name(&mut self, function: bool) -> Result<Name<'a>> {
-        let orig = self.input;
-        let name = if let Some(i) = self.consume_digit() {
-            let i = i as usize;
-            if i >= self.memorized_names.len() {
-                return Err(Error::new(format!(
-                    "name reference too large: {}",
-                    str::from_utf8(orig)?
-                )));
-            }
-            // println!("reading memorized name in position {}", i);
-            // println!(
-            //    "current list of memorized_names: {:#?}",
-            //    self.memorized_names
-            // );
-            self.memorized_names[i].clone()
-        } else if self.consume(b"?$") {
-            let name = self.read_template_name()?;
-            if !function {
-                self.memorize_name(&name);
-            }
-            name
-        } else if self.consume(b"?") {
-            // Overloaded operator.
-            self.read_operator()?
-        } else {
-            // Non-template functions or classes.
-            let name = self.read_string()?;
-            let name = Name::NonTemplate(name);
-            self.memorize_name(&name);
-            name
+    /// The class that RTTI data, a vftable or a vbtable is for, or `None`
+    /// for other symbols. The class of an `` `RTTI Type Descriptor' `` is
+    /// the type of the symbol; that of the others is the innermost name of
+    /// the scope, which is copied.
+    pub fn rtti_target_class(&self) -> Option<Cow<'_, Symbol<'a>>> {
+        let op = match self.symbol.name {
+            Name::Operator(ref op) => op,
+            _ => return None,
         };
-        Ok(name)
+        match *op {
+            Operator::RTTITypeDescriptor => match self.symbol_type {
+                Type::Class(ref symbol, _) | Type::Struct(ref symbol, _) | Type::Union(ref symbol, _) => {
+                    Some(Cow::Borrowed(symbol))
+                }
+                _ => None,
+            },
+            Operator::VFTable | Operator::VBTable | Operator::LocalVFTable => self.scope_class(),
+            _ if op.is_rtti() => self.scope_class(),
+            _ => None,
+        }
     }
 
-    fn read_scope(&mut self) -> Result<NameSequence<'a>> {
-        let mut names = Vec::new();
-        while !self.consume(b"@") {
-            // println!("read_name iteration on {}", str::from_utf8(self.input)?);
-            let name = self.read_nested_name()?;
-            names.push(name);
-        }
-        Ok(NameSequence { names })
+    // The innermost name of the scope of the symbol as a symbol.
+    fn scope_class(&self) -> Option<Cow<'_, Symbol<'a>>> {
+        let (name, scope) = self.symbol.scope.names.split_first()?;
+        Some(Cow::Owned(Symbol::new(name.clone(), scope.to_vec())))
     }
 
-    // Parses a name in the form of A@B@C@@ which represents C::B::A.
-    fn read_name(&mut self, function: bool) -> Result<Symbol<'a>> {
-        // println!("read_name on {}", str::from_utf8(self.input)?);
-        let name = self.read_unqualified_name(function)?;
+    /// The return type of a function, or `None` for constructors,
+    /// destructors and symbols that are not functions.
+    pub fn return_type_string<O: Into<DemangleOptions>>(&self, flags: O) -> Result<Option<String>> {
+        let flags = flags.into();
+        let this = simplified(self, flags);
+        match this.symbol_type.return_type() {
+            Some(&Type::None) | None => Ok(None),
+            Some(t) => this.type_string(t, flags).map(Some),
+        }
+    }
 
-        Ok(Symbol{name, scope: self.read_scope()? })
+    /// Copies the identifiers borrowed from the mangled symbol, so that the
+    /// result can be kept after the symbol is gone, e.g. in a cache.
+    pub fn into_owned(self) -> ParseResult<'static> {
+        ParseResult {
+            symbol: self.symbol.into_owned(),
+            symbol_type: self.symbol_type.into_owned(),
+            symbol_kind: self.symbol_kind,
+            variable_kind: self.variable_kind,
+            thunk_adjustment: self.thunk_adjustment,
+            names: self.names.into_iter().map(Name::into_owned).collect(),
+        }
     }
+}
 
-    fn read_func_type(&mut self) -> Result<Type<'a>> {
-        let calling_conv = self.read_calling_conv()?;
-        let return_type = self.read_var_type(StorageClass::empty())?;
-        let params = self.read_func_params()?;
-        return Ok(Type::NonMemberFunction(calling_conv, params,
-                                          StorageClass::empty(),
-                                          Box::new(return_type)));
+/// What a constructor, destructor, table or RTTI data is to its class, see
+/// `ParseResult::class_member()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum ClassMember {
+    /// A constructor, or a default or copy constructor closure.
+    Constructor,
+    /// A destructor or a vbase destructor.
+    Destructor,
+    /// A scalar or vector deleting destructor.
+    DeletingDestructor,
+    /// A vftable, including local vftables.
+    VFTable,
+    VBTable,
+    /// RTTI data such as the `` `RTTI Complete Object Locator' ``.
+    Rtti,
+}
+
+/// A part in which two symbols differ, see `diff()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Difference {
+    /// What kind of entity the symbols name, e.g. a function and a variable.
+    Kind,
+    /// The namespaces and classes the symbols are declared in.
+    Scope,
+    /// The unqualified names, without template arguments.
+    Name,
+    /// The number of template arguments of the names.
+    TemplateArgCount,
+    /// Template argument `n` of the names.
+    TemplateArg(usize),
+    /// The return types of functions.
+    ReturnType,
+    /// The number of parameters of functions.
+    ParameterCount,
+    /// Parameter `n` of functions.
+    Parameter(usize),
+    /// Whether functions take `...`.
+    Variadic,
+    CallingConv,
+    /// The qualifiers of `this` in member functions, e.g. `const`.
+    ThisQualifiers,
+    /// The access specifiers of member functions.
+    Access,
+    /// Whether member functions are static or virtual.
+    Storage,
+    /// The types of variables and other symbols that aren't functions.
+    Type,
+}
+
+/// The parts in which the symbols `a` and `b` differ, in the order of
+/// `Difference`; empty if they are the same. Parts are compared as they
+/// are written with `DemangleOptions::new()`, so that back-references
+/// don't matter.
+///
+/// ```
+/// use msvc_demangler::{diff, parse, Difference};
+///
+/// let a = parse("?f@ns@@YAXHPAD@Z").unwrap();
+/// let b = parse("?f@ns@@YAXHPBD@Z").unwrap();
+/// assert_eq!(diff(&a, &b).unwrap(), vec![Difference::Parameter(1)]);
+/// ```
+pub fn diff(a: &ParseResult, b: &ParseResult) -> Result<Vec<Difference>> {
+    let flags = DemangleOptions::new();
+    let mut differences = Vec::new();
+    if a.kind() != b.kind() {
+        differences.push(Difference::Kind);
+    }
+    if a.qualified_scope(flags)? != b.qualified_scope(flags)? {
+        differences.push(Difference::Scope);
     }
+    if unqualified_template_name(a)? != unqualified_template_name(b)? {
+        differences.push(Difference::Name);
+    }
+    let args = |p: &ParseResult| -> Result<Vec<String>> {
+        p.template_args().unwrap_or_default().iter().map(|t| p.type_string(t, flags)).collect()
+    };
+    diff_lists(&args(a)?, &args(b)?, Difference::TemplateArgCount, Difference::TemplateArg, &mut differences);
 
-    fn read_operator(&mut self) -> Result<Name<'a>> {
-        Ok(Name::Operator(self.read_operator_name()?))
+    match (a.signature(), b.signature()) {
+        (Some(sa), Some(sb)) => {
+            let return_type = |p: &ParseResult, s: &Signature| s.return_type().map(|t| p.type_string(t, flags)).transpose();
+            if return_type(a, &sa)? != return_type(b, &sb)? {
+                differences.push(Difference::ReturnType);
+            }
+            let params = |p: &ParseResult, s: &Signature| -> Result<Vec<String>> {
+                s.parameters().iter().map(|t| p.type_string(t, flags)).collect()
+            };
+            diff_lists(
+                &params(a, &sa)?,
+                &params(b, &sb)?,
+                Difference::ParameterCount,
+                Difference::Parameter,
+                &mut differences,
+            );
+            if sa.is_variadic() != sb.is_variadic() {
+                differences.push(Difference::Variadic);
+            }
+            if sa.calling_conv() != sb.calling_conv() {
+                differences.push(Difference::CallingConv);
+            }
+            if sa.this_quals() != sb.this_quals() {
+                differences.push(Difference::ThisQualifiers);
+            }
+            if sa.access() != sb.access() {
+                differences.push(Difference::Access);
+            }
+            if (sa.is_static(), sa.is_virtual()) != (sb.is_static(), sb.is_virtual()) {
+                differences.push(Difference::Storage);
+            }
+        }
+        (None, None) => {
+            if a.type_string(&a.symbol_type, flags)? != b.type_string(&b.symbol_type, flags)? {
+                differences.push(Difference::Type);
+            }
+        }
+        _ => differences.push(Difference::Type),
     }
+    Ok(differences)
+}
 
-    fn read_operator_name(&mut self) -> Result<Operator> {
-        let orig = self.input;
+// The unqualified name of a symbol without its template arguments.
+fn unqualified_template_name(p: &ParseResult) -> Result<String> {
+    let name = match *p.resolve_name(&p.symbol.name) {
+        Name::Template(ref name, _) => name,
+        ref name => name,
+    };
+    serialize_part(DemangleOptions::new(), &p.names, |serializer| {
+        serializer.write_unqualified_name(name, &p.symbol.scope, Some(&p.symbol_type))
+    })
+}
 
-        Ok(match self.get()? {
-            b'0' => Operator::Ctor,
-            b'1' => Operator::Dtor,
-            b'2' => Operator::New,
-            b'3' => Operator::Delete,
-            b'4' => Operator::Equal,
-            b'5' => Operator::RShift,
-            b'6' => Operator::LShift,
-            b'7' => Operator::Bang,
-            b'8' => Operator::EqualEqual,
-            b'9' => Operator::BangEqual,
-            b'A' => Operator::Subscript,
-            b'B' => Operator::Conversion, // TODO
-            b'C' => Operator::Arrow,
-            b'D' => Operator::Star,
-            b'E' => Operator::PlusPlus,
-            b'F' => Operator::MinusMinus,
-            b'G' => Operator::Minus,
-            b'H' => Operator::Plus,
-            b'I' => Operator::Amp,
-            b'J' => Operator::ArrowStar,
-            b'K' => Operator::Slash,
-            b'L' => Operator::Percent,
-            b'M' => Operator::Less,
-            b'N' => Operator::LessEqual,
-            b'O' => Operator::Greater,
-            b'P' => Operator::GreaterEqual,
-            b'Q' => Operator::Comma,
-            b'R' => Operator::Call,
-            b'S' => Operator::Tilde,
-            b'T' => Operator::Caret,
-            b'U' => Operator::Pipe,
-            b'V' => Operator::AmpAmp,
-            b'W' => Operator::PipePipe,
-            b'X' => Operator::StarEqual,
-            b'Y' => Operator::PlusEqual,
-            b'Z' => Operator::MinusEqual,
-            b'_' => match self.get()? {
-                b'0' => Operator::SlashEqual,
-                b'1' => Operator::PercentEqual,
-                b'2' => Operator::GreaterGreaterEqual,
-                b'3' => Operator::LessLessEqual,
-                b'4' => Operator::AmpEqual,
-                b'5' => Operator::PipeEqual,
-                b'6' => Operator::CaretEqual,
-                b'7' => Operator::VFTable,
-                b'8' => Operator::VBTable,
-                b'9' => Operator::VCall,
-                b'A' => Operator::Typeof,
-                b'B' => Operator::LocalStaticGuard,
-                b'C' => Operator::String,
-                b'D' => Operator::VBaseDtor,
-                b'E' => Operator::VectorDeletingDtor,
-                b'F' => Operator::DefaultCtorClosure,
-                b'G' => Operator::ScalarDeletingDtor,
-                b'H' => Operator::VectorCtorIterator,
-                b'I' => Operator::VectorDtorIterator,
-                b'J' => Operator::VectorVBaseCtorIterator,
-                b'K' => Operator::VirtualDisplacementMap,
-                b'L' => Operator::EHVectorCtorIterator,
-                b'M' => Operator::EHVectorDtorIterator,
-                b'N' => Operator::EHVectorVBaseCtorIterator,
-                b'O' => Operator::CopyCtorClosure,
-                b'S' => Operator::LocalVFTable,
-                b'T' => Operator::LocalVFTableCtorClosure,
-                b'U' => Operator::ArrayNew,
-                b'V' => Operator::ArrayDelete,
-                b'X' => Operator::PlacementDeleteClosure,
-                b'Y' => Operator::PlacementArrayDeleteClosure,
-                b'_' => if self.consume(b"L") {
-                    Operator::CoroutineAwait
-                } else if self.consume(b"K") {
-                    Operator::LiteralOperatorName // TODO: read <source-name>, that's the operator name
-                } else {
-                    return Err(Error::new(format!(
-                        "unknown operator name: {}",
-                        str::from_utf8(orig)?
-                    )));
-                },
-                _ => {
-                    return Err(Error::new(format!(
-                        "unknown operator name: {}",
-                        str::from_utf8(orig)?
-                    )))
-                }
-            },
-            _ => {
-                return Err(Error::new(format!(
-                    "unknown operator name: {}",
-                    str::from_utf8(orig)?
-                )))
-            }
-        })
+// Adds the differences between the lists `a` and `b` to `differences`: `count`
+// if their lengths differ and `item(i)` for every differing item `i`.
+fn diff_lists(
+    a: &[String],
+    b: &[String],
+    count: Difference,
+    item: fn(usize) -> Difference,
+    differences: &mut Vec<Difference>,
+) {
+    if a.len() != b.len() {
+        differences.push(count);
+    }
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        if x != y {
+            differences.push(item(i));
+        }
     }
+}
 
-    fn read_func_class(&mut self, c: u8) -> Result<FuncClass> {
-        // TODO: need to figure out how to wrap up the adjustment.
-        let mut read_thunk = |func_class| -> Result<FuncClass> {
-            let _adjustment = self.read_number()?;
-            Ok(func_class | FuncClass::THUNK)
-        };
+/// Callbacks for walking a parsed symbol with `walk()`.
+///
+/// Every method by default descends into the children of the node through
+/// the matching `walk_*` function. An implementation that overrides one
+/// should call that function itself to keep descending.
+pub trait Visitor {
+    /// Called for every name, including namespaces and template names.
+    fn visit_name(&mut self, name: &Name) {
+        walk_name(self, name);
+    }
 
-        Ok(match c {
-            b'A' => FuncClass::PRIVATE,
-            b'B' => FuncClass::PRIVATE | FuncClass::FAR,
-            b'C' => FuncClass::PRIVATE | FuncClass::STATIC,
-            b'D' => FuncClass::PRIVATE | FuncClass::STATIC,
-            b'E' => FuncClass::PRIVATE | FuncClass::VIRTUAL,
-            b'F' => FuncClass::PRIVATE | FuncClass::VIRTUAL,
-            b'G' => read_thunk(FuncClass::PRIVATE | FuncClass::VIRTUAL)?,
-            b'H' => read_thunk(FuncClass::PRIVATE | FuncClass::VIRTUAL | FuncClass::FAR)?,
-            b'I' => FuncClass::PROTECTED,
-            b'J' => FuncClass::PROTECTED | FuncClass::FAR,
-            b'K' => FuncClass::PROTECTED | FuncClass::STATIC,
-            b'L' => FuncClass::PROTECTED | FuncClass::STATIC | FuncClass::FAR,
-            b'M' => FuncClass::PROTECTED | FuncClass::VIRTUAL,
-            b'N' => FuncClass::PROTECTED | FuncClass::VIRTUAL | FuncClass::FAR,
-            b'O' => read_thunk(FuncClass::PROTECTED | FuncClass::VIRTUAL)?,
-            b'P' => read_thunk(FuncClass::PROTECTED | FuncClass::VIRTUAL | FuncClass::FAR)?,
-            b'Q' => FuncClass::PUBLIC,
-            b'R' => FuncClass::PUBLIC | FuncClass::FAR,
-            b'S' => FuncClass::PUBLIC | FuncClass::STATIC,
-            b'T' => FuncClass::PUBLIC | FuncClass::STATIC | FuncClass::FAR,
-            b'U' => FuncClass::PUBLIC | FuncClass::VIRTUAL,
-            b'V' => FuncClass::PUBLIC | FuncClass::VIRTUAL | FuncClass::FAR,
-            b'W' => read_thunk(FuncClass::PUBLIC | FuncClass::VIRTUAL)?,
-            b'X' => read_thunk(FuncClass::PUBLIC | FuncClass::VIRTUAL | FuncClass::FAR)?,
-            b'Y' => FuncClass::GLOBAL,
-            b'Z' => FuncClass::GLOBAL | FuncClass::FAR,
-            _ => {
-                return Err(Error::new(format!(
-                    "unknown func class: {}",
-                    str::from_utf8(&[c])?
-                )))
-            }
-        })
+    /// Called for every type, including function and template arguments.
+    fn visit_type(&mut self, t: &Type) {
+        walk_type(self, t);
     }
 
-    fn read_qualifier(&mut self) -> StorageClass {
-        let access_class = match self.peek() {
-            Some(b'A') => StorageClass::empty(),
-            Some(b'B') => StorageClass::CONST,
-            Some(b'C') => StorageClass::VOLATILE,
-            Some(b'D') => StorageClass::CONST | StorageClass::VOLATILE,
-            _ => return StorageClass::empty(),
-        };
-        self.trim(1);
-        access_class
+    /// Called for every function parameter and template argument list.
+    fn visit_params(&mut self, params: &Params) {
+        walk_params(self, params);
     }
+}
 
-    fn read_calling_conv(&mut self) -> Result<CallingConv> {
-        let orig = self.input;
+/// Visits the name and the type of a parsed symbol.
+///
+/// The names in `ParseResult::names` are visited once each, before the
+/// symbol, and a `Name::Backref` to them is visited as it is.
+pub fn walk<V: Visitor + ?Sized>(visitor: &mut V, parse_result: &ParseResult) {
+    for name in &parse_result.names {
+        visitor.visit_name(name);
+    }
+    walk_symbol(visitor, &parse_result.symbol);
+    visitor.visit_type(&parse_result.symbol_type);
+}
 
-        Ok(match self.get()? {
-            b'A' => CallingConv::Cdecl,
-            b'B' => CallingConv::Cdecl,
-            b'C' => CallingConv::Pascal,
-            b'E' => CallingConv::Thiscall,
-            b'G' => CallingConv::Stdcall,
-            b'I' => CallingConv::Fastcall,
-            _ => {
-                return Err(Error::new(format!(
-                    "unknown calling conv: {}",
-                    str::from_utf8(orig)?
-                )))
-            }
-        })
+fn walk_symbol<V: Visitor + ?Sized>(visitor: &mut V, symbol: &Symbol) {
+    for name in symbol.scope.names.iter().rev() {
+        visitor.visit_name(name);
     }
+    visitor.visit_name(&symbol.name);
+}
 
-    // <return-type> ::= <type>
-    //               ::= @ # structors (they have no declared return type)
-    fn read_func_return_type(&mut self, storage_class: StorageClass) -> Result<Type<'a>> {
-        if self.consume(b"@") {
-            Ok(Type::None)
-        } else {
-            self.read_var_type(storage_class)
+/// Visits the children of `name`.
+pub fn walk_name<V: Visitor + ?Sized>(visitor: &mut V, name: &Name) {
+    match *name {
+        Name::Template(ref name, ref params) => {
+            visitor.visit_name(name);
+            visitor.visit_params(params);
         }
+        Name::ParsedName(ref parse_result) => walk(visitor, parse_result),
+        _ => {}
     }
+}
 
-    fn read_storage_class(&mut self) -> StorageClass {
-        let storage_class = match self.peek() {
-            Some(b'A') => StorageClass::empty(),
-            Some(b'B') => StorageClass::CONST,
-            Some(b'C') => StorageClass::VOLATILE,
-            Some(b'D') => StorageClass::CONST | StorageClass::VOLATILE,
-            Some(b'E') => StorageClass::FAR,
-            Some(b'F') => StorageClass::CONST | StorageClass::FAR,
-            Some(b'G') => StorageClass::VOLATILE | StorageClass::FAR,
-            Some(b'H') => StorageClass::CONST | StorageClass::VOLATILE | StorageClass::FAR,
-            _ => return StorageClass::empty(),
-        };
-        self.trim(1);
-        storage_class
+/// Visits the children of `t`.
+pub fn walk_type<V: Visitor + ?Sized>(visitor: &mut V, t: &Type) {
+    match *t {
+        Type::MemberFunction(_, _, ref params, _, ref ret)
+        | Type::NonMemberFunction(_, ref params, _, ref ret) => {
+            visitor.visit_type(ret);
+            visitor.visit_params(params);
+        }
+        Type::MemberFunctionPointer(ref symbol, _, _, ref params, _, ref ret) => {
+            walk_symbol(visitor, symbol);
+            visitor.visit_type(ret);
+            visitor.visit_params(params);
+        }
+        Type::CXXVBTable(ref names, _) | Type::CXXVFTable(ref names, _) => {
+            for name in names.names.iter().rev() {
+                visitor.visit_name(name);
+            }
+        }
+        Type::Ptr(ref inner, _)
+        | Type::Ref(ref inner, _)
+        | Type::RValueRef(ref inner, _)
+        | Type::Array(_, ref inner, _) => visitor.visit_type(inner),
+        Type::Struct(ref symbol, _)
+        | Type::Union(ref symbol, _)
+        | Type::Class(ref symbol, _)
+        | Type::Typedef(ref symbol, _) => walk_symbol(visitor, symbol),
+        Type::Enum(ref symbol, ref underlying, _) => {
+            walk_symbol(visitor, symbol);
+            visitor.visit_type(underlying);
+        }
+        Type::Shared(ref inner) => visitor.visit_type(inner),
+        _ => {}
     }
+}
 
-    fn read_storage_class_for_return(&mut self) -> Result<StorageClass> {
-        if !self.consume(b"?") {
-            return Ok(StorageClass::empty());
-        }
-        let orig = self.input;
+/// Visits the types in `params`.
+pub fn walk_params<V: Visitor + ?Sized>(visitor: &mut V, params: &Params) {
+    for t in &params.types {
+        visitor.visit_type(t);
+    }
+}
 
-        Ok(match self.get()? {
-            b'A' => StorageClass::empty(),
-            b'B' => StorageClass::CONST,
-            b'C' => StorageClass::VOLATILE,
-            b'D' => StorageClass::CONST | StorageClass::VOLATILE,
-            _ => {
-                return Err(Error::new(format!(
-                    "unknown storage class: {}",
-                    str::from_utf8(orig)?
-                )))
+// The entry of `names` that `name` refers to if it is a Name::Backref.
+fn resolve_name<'b, 'c>(names: &'b [Name<'c>], name: &'b Name<'c>) -> &'b Name<'c> {
+    match *name {
+        Name::Backref(i) => names.get(i).unwrap_or(name),
+        _ => name,
+    }
+}
+
+// The kind of a symbol named `name` that would be of kind `kind` by its
+// type: some special names have a kind of their own.
+fn special_symbol_kind(name: &Name, kind: SymbolKind) -> SymbolKind {
+    match *name {
+        Name::Operator(Operator::LocalStaticGuard) => SymbolKind::Guard,
+        Name::Operator(ref op) if op.is_rtti() => SymbolKind::Rtti,
+        _ => kind,
+    }
+}
+
+/// A step of `parse_events()`.
+///
+/// Qualified names are written as their components, outermost first, each
+/// but the last followed by `EnterScope`; a `LeaveScope` for every
+/// `EnterScope` follows the last component. Types with parts start with one
+/// of the `Begin*` events, which is followed by the parts and `EndType`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event<'b> {
+    /// The start of a symbol, including the symbols nested in names such
+    /// as `` `f'::`2'::x ``. Followed by the name and the type of the
+    /// symbol and `EndSymbol`.
+    BeginSymbol(SymbolKind),
+    EndSymbol,
+    /// The name before this is a namespace or class containing what follows.
+    EnterScope,
+    LeaveScope,
+    /// A namespace, class, function or template name.
+    Identifier(&'b str),
+    /// An operator or special name such as `` `vftable' ``.
+    OperatorName(&'b Operator<'b>),
+    AnonymousNamespace,
+    Discriminator(i32),
+    /// Template arguments of the name before this, as types and constants.
+    BeginTemplateArgs,
+    EndTemplateArgs,
+    /// A built-in type such as `int`, with its qualifiers.
+    PrimitiveType(&'static str, StorageClass),
+    /// A type read by `Extensions::unknown_type()`.
+    ExtensionType(&'b str, StorageClass),
+    /// Followed by the type pointed to.
+    BeginPointer(StorageClass),
+    BeginReference(StorageClass),
+    BeginRValueReference(StorageClass),
+    /// Followed by the element type.
+    BeginArray(i32, StorageClass),
+    /// `class`, `struct`, `union`, `enum` or, for `Type::Typedef`,
+    /// `typedef`, followed by the name of the type and, for enums, the
+    /// underlying type.
+    BeginUserType(&'static str, StorageClass),
+    /// Followed by the return type and the parameters. The storage class
+    /// qualifies `this` in member functions.
+    BeginFunction(Option<FuncClass>, CallingConv, StorageClass),
+    /// Followed by the name of the class, the return type and the
+    /// parameters.
+    BeginMemberFunctionPointer(FuncClass, CallingConv, StorageClass),
+    /// A vftable or vbtable, followed by the names of the classes it is for.
+    BeginTable(StorageClass),
+    EndType,
+    BeginParams,
+    EndParams,
+    Constant(i32),
+    StringLiteral(&'b [u8]),
+    TemplateParameter(i32),
+    ThreadSafeStaticGuard(i32),
+}
+
+/// Parses `input` and passes the parts of the symbol to `callback` in
+/// order, for consumers that would otherwise copy the parsed tree into their
+/// own data structures.
+///
+/// This is not a streaming parser: the symbol is parsed into the same tree
+/// as `parse()` returns, which is then walked to pass on its parts. Only
+/// the parameters of function symbols, which make up most of the long
+/// names, are each passed on once they are read and then dropped instead of
+/// being kept in the tree. The name of a function comes first, so the
+/// target of a conversion operator isn't known yet and is only passed on
+/// as the return type. Back-referenced names and types are replayed in full
+/// wherever they are used.
+///
+/// If parsing fails, `callback` may already have been passed the start of
+/// the symbol.
+pub fn parse_events<O, F>(input: &str, options: O, mut callback: F) -> Result<()>
+where
+    O: Into<ParseOptions>,
+    F: FnMut(Event),
+{
+    let options = options.into();
+    let mut state = ParserState::new(input, options);
+    state.parse_events(&mut callback)?;
+    check_trailing(input, input.len() - state.input.len(), options)?;
+    Ok(())
+}
+
+/// A part of the scope of a symbol, see `ParseResult::scope_components()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScopeComponent<'b, 'a> {
+    /// A namespace or a class. Mangled names don't tell them apart.
+    Named(&'b str),
+    /// A class template instance: the template name and its arguments.
+    Template(&'b Name<'a>, &'b [Type<'a>]),
+    AnonymousNamespace,
+    /// The function a local class or static variable is declared in.
+    Function(&'b ParseResult<'a>),
+    /// The number of a block in a function, `` `2' `` in `` `f'::`2'::x ``.
+    Discriminator(i32),
+    /// A name that doesn't belong in a scope.
+    Other(&'b Name<'a>),
+}
+
+/// An iterator over the parts of the scope of a symbol, see
+/// `ParseResult::scope_components()`.
+#[derive(Clone, Debug)]
+pub struct ScopeComponents<'b, 'a> {
+    names: &'b [Name<'a>],
+    scope: iter::Rev<slice::Iter<'b, Name<'a>>>,
+}
+
+impl<'b, 'a> Iterator for ScopeComponents<'b, 'a> {
+    type Item = ScopeComponent<'b, 'a>;
+
+    fn next(&mut self) -> Option<ScopeComponent<'b, 'a>> {
+        let name = resolve_name(self.names, self.scope.next()?);
+        Some(match *name {
+            Name::NonTemplate(ref name) => ScopeComponent::Named(name),
+            Name::Template(ref template, _) => {
+                ScopeComponent::Template(template, name.template_args().unwrap_or_default())
             }
+            Name::AnonymousNamespace => ScopeComponent::AnonymousNamespace,
+            Name::ParsedName(ref parse_result) => ScopeComponent::Function(parse_result),
+            Name::Discriminator(n) => ScopeComponent::Discriminator(n),
+            Name::Operator(_) | Name::Backref(_) => ScopeComponent::Other(name),
         })
     }
 
-    fn read_member_function_pointer(&mut self, read_qualifiers: bool) -> Result<Type<'a>> {
-        let symbol = self.read_name(true)?;
-        let _is_64bit_ptr = self.consume(b"E");
-        let (access_class, func_class) = if read_qualifiers {
-            (self.read_qualifier(), FuncClass::empty())
-        } else {
-            let c = self.get()?;
-            (StorageClass::empty(), self.read_func_class(c)?)
-        };
-        let calling_conv = self.read_calling_conv()?;
-        let storage_class_for_return = self.read_storage_class_for_return()?;
-        let return_type = self.read_func_return_type(storage_class_for_return)?;
-        let params = self.read_func_params()?;
-        Ok(Type::MemberFunctionPointer(
-            symbol,
-            func_class,
-            calling_conv,
-            params,
-            access_class,
-            Box::new(return_type),
-        ))
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.scope.size_hint()
     }
+}
 
-    // Reads a variable type.
-    fn read_var_type(&mut self, mut sc: StorageClass) -> Result<Type<'a>> {
-        // println!("read_var_type on {}", str::from_utf8(self.input)?);
-        if self.consume(b"W4") {
-            let name = self.read_name(false)?;
-            return Ok(Type::Enum(name, sc));
-        }
+impl<'b, 'a> ExactSizeIterator for ScopeComponents<'b, 'a> {}
 
-        if self.consume(b"A6") {
-            let func_type = self.read_func_type()?;
-            return Ok(Type::Ref(Box::new(func_type), sc));
-        }
+// The parts of ParseResult::rewrite_scopes().
+fn rewrite_symbol_scopes<'a>(symbol: &mut Symbol<'a>, f: &mut dyn FnMut(&mut Vec<Name<'a>>)) {
+    rewrite_scope(&mut symbol.scope, f);
+    rewrite_name_scopes(&mut symbol.name, f);
+}
 
-        if self.consume(b"P6") {
-            let func_type = self.read_func_type()?;
-            return Ok(Type::Ptr(Box::new(func_type), sc));
-        }
+fn rewrite_scope<'a>(scope: &mut NameSequence<'a>, f: &mut dyn FnMut(&mut Vec<Name<'a>>)) {
+    f(&mut scope.names);
+    for name in &mut scope.names {
+        rewrite_name_scopes(name, f);
+    }
+}
 
-        if self.consume(b"P8") {
-            return self.read_member_function_pointer(true);
+fn rewrite_name_scopes<'a>(name: &mut Name<'a>, f: &mut dyn FnMut(&mut Vec<Name<'a>>)) {
+    match *name {
+        Name::Template(ref mut name, ref mut params) => {
+            rewrite_name_scopes(name, f);
+            for t in &mut params.types {
+                rewrite_type_scopes(t, f);
+            }
+        }
+        Name::ParsedName(ref mut parse_result) => {
+            rewrite_symbol_scopes(&mut parse_result.symbol, f);
+            rewrite_type_scopes(&mut parse_result.symbol_type, f);
         }
+        _ => {}
+    }
+}
 
-        if self.consume(b"$") {
-            if self.consume(b"0") {
-                let n = self.read_number()?;
-                return Ok(Type::Constant(n));
-            }
-            if self.consume(b"D") {
-                let n = self.read_number()?;
-                return Ok(Type::TemplateParameterWithIndex(n));
-            }
-            if self.consume(b"$BY") {
-                return Ok(self.read_array()?);
-            }
-            if self.consume(b"$Q") {
-                return Ok(Type::RValueRef(Box::new(self.read_pointee()?), sc))
+fn rewrite_type_scopes<'a>(t: &mut Type<'a>, f: &mut dyn FnMut(&mut Vec<Name<'a>>)) {
+    match *t {
+        Type::MemberFunction(_, _, ref mut params, _, ref mut ret)
+        | Type::NonMemberFunction(_, ref mut params, _, ref mut ret) => {
+            rewrite_type_scopes(ret, f);
+            for t in &mut params.types {
+                rewrite_type_scopes(t, f);
             }
-            if self.consume(b"$V") {
-                return Ok(Type::EmptyParameterPack);
-            }
-            if self.consume(b"$T") {
-                return Ok(Type::Nullptr);
-            }
-            if self.consume(b"$A6") {
-                return self.read_func_type();
-            }
-            // These next cases can fallthrough, so be careful adding new ones!
-            if self.consume(b"$C") {
-                sc = self.read_qualifier();
-            } else if let Some(x) = self.peek() {
-                match x {
-                    // Inheritance specifiers, which we don't need to remember.
-                    b'1' | b'H' | b'I' | b'J' => {
-                        self.trim(1);
-                        self.expect(b"?")?;
-                        return self.read_member_function_pointer(false);
-                    },
-                    _ => {},
-                };
+        }
+        Type::MemberFunctionPointer(ref mut symbol, _, _, ref mut params, _, ref mut ret) => {
+            rewrite_symbol_scopes(symbol, f);
+            rewrite_type_scopes(ret, f);
+            for t in &mut params.types {
+                rewrite_type_scopes(t, f);
             }
         }
-
-        if self.consume(b"?") {
-            let n = self.read_number()?;
-            return Ok(Type::TemplateParameterWithIndex(-n));
+        Type::CXXVBTable(ref mut names, _) | Type::CXXVFTable(ref mut names, _) => rewrite_scope(names, f),
+        Type::Ptr(ref mut inner, _)
+        | Type::Ref(ref mut inner, _)
+        | Type::RValueRef(ref mut inner, _)
+        | Type::Array(_, ref mut inner, _) => rewrite_type_scopes(inner, f),
+        Type::Struct(ref mut symbol, _)
+        | Type::Union(ref mut symbol, _)
+        | Type::Class(ref mut symbol, _)
+        | Type::Typedef(ref mut symbol, _) => rewrite_symbol_scopes(symbol, f),
+        Type::Enum(ref mut symbol, ref mut underlying, _) => {
+            rewrite_symbol_scopes(symbol, f);
+            rewrite_type_scopes(underlying, f);
         }
+        _ => {}
+    }
+}
 
-        if let Some(n) = self.consume_digit() {
-            if n as usize >= self.memorized_types.len() {
-                // println!("current memorized types: {:?}", self.memorized_types);
-                return Err(Error::new(format!("invalid backreference: {}", n)));
-            }
-
-            return Ok(self.memorized_types[n as usize].clone());
-        }
+// A rewrite of the names and types in a symbol, see rewrite(). The hooks
+// are called on a name or type before its parts are rewritten. `table`
+// holds the entries of ParseResult::names that the name or type may refer
+// to.
+trait Rewrite<'a> {
+    // Rewrites `name`, which is declared in the scope `enclosing`
+    // (innermost first), or `&[]` for the entries of ParseResult::names.
+    fn name(&mut self, _name: &mut Name<'a>, _enclosing: &[Name<'a>], _table: &[Name<'a>]) {}
 
-        let orig = self.input;
+    fn type_(&mut self, _t: &mut Type<'a>, _table: &[Name<'a>]) {}
+}
 
-        Ok(match self.get()? {
-            b'T' => Type::Union(self.read_name(false)?, sc),
-            b'U' => Type::Struct(self.read_name(false)?, sc),
-            b'V' => Type::Class(self.read_name(false)?, sc),
-            b'A' => Type::Ref(Box::new(self.read_pointee()?), sc),
-            b'B' => Type::Ref(Box::new(self.read_pointee()?), StorageClass::VOLATILE),
-            b'P' => Type::Ptr(Box::new(self.read_pointee()?), sc),
-            b'Q' => Type::Ptr(Box::new(self.read_pointee()?), StorageClass::CONST),
-            b'R' => Type::Ptr(Box::new(self.read_pointee()?), StorageClass::VOLATILE),
-            b'S' => Type::Ptr(
-                Box::new(self.read_pointee()?),
-                StorageClass::CONST | StorageClass::VOLATILE,
-            ),
-            b'Y' => self.read_array()?,
-            b'X' => Type::Void(sc),
-            b'D' => Type::Char(sc),
-            b'C' => Type::Schar(sc),
-            b'E' => Type::Uchar(sc),
-            b'F' => Type::Short(sc),
-            b'G' => Type::Ushort(sc),
-            b'H' => Type::Int(sc),
-            b'I' => Type::Uint(sc),
-            b'J' => Type::Long(sc),
-            b'K' => Type::Ulong(sc),
-            b'M' => Type::Float(sc),
-            b'N' => Type::Double(sc),
-            b'O' => Type::Ldouble(sc),
-            b'_' => match self.get()? {
-                b'N' => Type::Bool(sc),
-                b'J' => Type::Int64(sc),
-                b'K' => Type::Uint64(sc),
-                b'W' => Type::Wchar(sc),
-                b'S' => Type::Char16(sc),
-                b'U' => Type::Char32(sc),
-                _ => {
-                    return Err(Error::new(format!(
-                        "unknown primitive type: {}",
-                        str::from_utf8(orig)?
-                    )))
-                }
-            },
-            _ => {
-                return Err(Error::new(format!(
-                    "unknown primitive type: {}",
-                    str::from_utf8(orig)?
-                )))
-            }
-        })
+// Rewrites the entries of ParseResult::names, in order, and then the
+// symbol. An entry only refers to those before it.
+fn rewrite<'a, R: Rewrite<'a>>(parse_result: &mut ParseResult<'a>, r: &mut R) {
+    let mut names = mem::take(&mut parse_result.names);
+    for i in 0..names.len() {
+        let (table, rest) = names.split_at_mut(i);
+        rewrite_name_parts(r, &mut rest[0], &[], table);
     }
+    rewrite_parse_result_parts(r, parse_result, &names);
+    parse_result.names = names;
+}
 
-    fn read_pointee(&mut self) -> Result<Type<'a>> {
-        let _is_64bit_ptr = self.expect(b"E");
-        let storage_class = self.read_storage_class();
-        self.read_var_type(storage_class)
+fn rewrite_parse_result_parts<'a, R: Rewrite<'a>>(r: &mut R, parse_result: &mut ParseResult<'a>, table: &[Name<'a>]) {
+    rewrite_symbol_parts(r, &mut parse_result.symbol, table);
+    rewrite_type_parts(r, &mut parse_result.symbol_type, table);
+}
+
+fn rewrite_symbol_parts<'a, R: Rewrite<'a>>(r: &mut R, symbol: &mut Symbol<'a>, table: &[Name<'a>]) {
+    rewrite_scope_parts(r, &mut symbol.scope, table);
+    rewrite_name_parts(r, &mut symbol.name, &symbol.scope.names, table);
+}
+
+fn rewrite_scope_parts<'a, R: Rewrite<'a>>(r: &mut R, scope: &mut NameSequence<'a>, table: &[Name<'a>]) {
+    for i in (0..scope.names.len()).rev() {
+        let (name, enclosing) = scope.names[i..].split_first_mut().unwrap();
+        rewrite_name_parts(r, name, enclosing, table);
     }
+}
 
-    fn read_array(&mut self) -> Result<Type<'a>> {
-        let dimension = self.read_number()?;
-        if dimension <= 0 {
-            return Err(Error::new(format!(
-                "invalid array dimension: {}",
-                dimension
-            )));
+fn rewrite_name_parts<'a, R: Rewrite<'a>>(r: &mut R, name: &mut Name<'a>, enclosing: &[Name<'a>], table: &[Name<'a>]) {
+    r.name(name, enclosing, table);
+    match *name {
+        Name::Template(_, ref mut params) => {
+            for t in &mut params.types {
+                rewrite_type_parts(r, t, table);
+            }
         }
-        let (array, _) = self.read_nested_array(dimension)?;
-        Ok(array)
+        Name::ParsedName(ref mut parse_result) => rewrite_parse_result_parts(r, parse_result, table),
+        _ => {}
     }
+}
 
-    fn read_nested_array(&mut self, dimension: i32) -> Result<(Type<'a>, StorageClass)> {
-        if dimension > 0 {
-            let len = self.read_number()?;
-            let (inner_array, storage_class) = self.read_nested_array(dimension - 1)?;
-            Ok((
-                Type::Array(len, Box::new(inner_array), storage_class),
-                storage_class,
-            ))
-        } else {
-            let storage_class = if self.consume(b"$$C") {
-                if self.consume(b"B") {
-                    StorageClass::CONST
-                } else if self.consume(b"C") || self.consume(b"D") {
-                    StorageClass::CONST | StorageClass::VOLATILE
-                } else if !self.consume(b"A") {
-                    return Err(Error::new(format!(
-                        "unknown storage class: {}",
-                        str::from_utf8(self.input)?
-                    )));
-                } else {
-                    StorageClass::empty()
-                }
-            } else {
-                StorageClass::empty()
-            };
-
-            Ok((self.read_var_type(StorageClass::empty())?, storage_class))
+fn rewrite_type_parts<'a, R: Rewrite<'a>>(r: &mut R, t: &mut Type<'a>, table: &[Name<'a>]) {
+    r.type_(t, table);
+    match *t {
+        Type::MemberFunction(_, _, ref mut params, _, ref mut ret)
+        | Type::NonMemberFunction(_, ref mut params, _, ref mut ret) => {
+            rewrite_type_parts(r, ret, table);
+            for t in &mut params.types {
+                rewrite_type_parts(r, t, table);
+            }
+        }
+        Type::MemberFunctionPointer(ref mut symbol, _, _, ref mut params, _, ref mut ret) => {
+            rewrite_symbol_parts(r, symbol, table);
+            rewrite_type_parts(r, ret, table);
+            for t in &mut params.types {
+                rewrite_type_parts(r, t, table);
+            }
+        }
+        Type::CXXVBTable(ref mut names, _) | Type::CXXVFTable(ref mut names, _) => {
+            rewrite_scope_parts(r, names, table)
         }
+        Type::Ptr(ref mut inner, _)
+        | Type::Ref(ref mut inner, _)
+        | Type::RValueRef(ref mut inner, _)
+        | Type::Array(_, ref mut inner, _) => rewrite_type_parts(r, inner, table),
+        Type::Struct(ref mut symbol, _)
+        | Type::Union(ref mut symbol, _)
+        | Type::Class(ref mut symbol, _)
+        | Type::Typedef(ref mut symbol, _) => rewrite_symbol_parts(r, symbol, table),
+        Type::Enum(ref mut symbol, ref mut underlying, _) => {
+            rewrite_symbol_parts(r, symbol, table);
+            rewrite_type_parts(r, underlying, table);
+        }
+        _ => {}
     }
+}
 
-    // Reads a function or a template parameters.
-    fn read_params(&mut self) -> Result<Params<'a>> {
-        // println!("read_params on {}", str::from_utf8(self.input)?);
-        // Within the same parameter list, you can backreference the first 10 types.
-        // let mut backref: Vec<Type<'a>> = Vec::with_capacity(10);
+/// A rewrite of a parsed symbol, run by a `Pipeline` between parsing and
+/// serializing. Closures taking a `&mut ParseResult` are passes, too.
+pub trait Pass {
+    fn run(&self, parse_result: &mut ParseResult);
+}
 
-        let mut params: Vec<Type<'a>> = Vec::new();
+impl<F: Fn(&mut ParseResult)> Pass for F {
+    fn run(&self, parse_result: &mut ParseResult) {
+        self(parse_result)
+    }
+}
 
-        while !self.input.starts_with(b"@") && !self.input.starts_with(b"Z")
-            && !self.input.is_empty()
-        {
-            if let Some(n) = self.consume_digit() {
-                if n as usize >= self.memorized_types.len() {
-                    return Err(Error::new(format!("invalid backreference: {}", n)));
-                }
-                // println!("reading a type from memorized_types[{}]. full list: {:#?}", n, self.memorized_types);
-                params.push(self.memorized_types[n as usize].clone());
-                continue;
-            }
+/// A sequence of passes that rewrite a parsed symbol before it is
+/// serialized, for output that `DemangleOptions` can't describe. The
+/// simplifications `StdTypedefs`, `ElideDefaultTemplateArgs`,
+/// `ElideTemplateArgs` and `StripScopes` are passes, too.
+///
+/// ```
+/// use msvc_demangler::{DemangleOptions, Pipeline, StdTypedefs, StripScopes};
+///
+/// let pipeline = Pipeline::new().pass(StripScopes::new(&["__1"])).pass(StdTypedefs);
+/// assert_eq!(
+///     pipeline
+///         .demangle(
+///             "?f@__1@std@@YAXAEBV?$basic_string@DU?$char_traits@D@__1@std@@V?$allocator@D@23@@12@@Z",
+///             DemangleOptions::cxxfilt(),
+///         )
+///         .unwrap(),
+///     "std::f(std::string const&)",
+/// );
+/// ```
+#[derive(Default)]
+pub struct Pipeline {
+    passes: Vec<Box<dyn Pass>>,
+}
 
-            let len = self.input.len();
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
 
-            let param_type = self.read_var_type(StorageClass::empty())?;
+    /// Adds `pass` to run after the passes added so far.
+    pub fn pass<P: Pass + 'static>(mut self, pass: P) -> Pipeline {
+        self.passes.push(Box::new(pass));
+        self
+    }
 
-            // Single-letter types are ignored for backreferences because
-            // memorizing them doesn't save anything.
-            if len - self.input.len() > 1 {
-                self.memorize_type(&param_type);
-            }
-            params.push(param_type);
+    /// Runs the passes on `parse_result`, in order.
+    pub fn run(&self, parse_result: &mut ParseResult) {
+        for pass in &self.passes {
+            pass.run(parse_result);
         }
+    }
 
-        if self.consume(b"Z") {
-            params.push(Type::VarArgs);
-        } else if self.input.is_empty() {
-            // this is needed to handle the weird standalone template manglings
-        } else {
-            self.expect(b"@")?;
+    /// Like `demangle()`, but runs the passes on the parsed symbol before
+    /// serializing it.
+    pub fn demangle<O: Into<DemangleOptions>>(&self, input: &str, flags: O) -> Result<String> {
+        let flags = flags.into();
+        let mut parse_result = parse_with_options(input, flags)?;
+        self.run(&mut parse_result);
+        simplify(&mut parse_result, flags);
+        let limit = output_limit(flags, input.len());
+        let mut s = String::with_capacity(output_capacity(&parse_result, limit));
+        serialize_to_limited(&parse_result, flags, limit, &mut s)?;
+        Ok(s)
+    }
+}
+
+/// A pass that writes standard library typedefs such as `std::string` in
+/// place of the template instances they stand for, like
+/// `DemangleOptions::STD_TYPEDEFS`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdTypedefs;
+
+impl<'a> Rewrite<'a> for StdTypedefs {
+    fn name(&mut self, name: &mut Name<'a>, enclosing: &[Name<'a>], table: &[Name<'a>]) {
+        if let Some(typedef) = std_typedef(table, name, enclosing) {
+            *name = Name::NonTemplate(Cow::Borrowed(typedef));
         }
-        Ok(Params { types: params })
     }
 
-    // Reads a function parameters.
-    fn read_func_params(&mut self) -> Result<Params<'a>> {
-        let params = if self.consume(b"X") {
-            Params {
-                types: vec![Type::Void(StorageClass::empty())],
+    fn type_(&mut self, t: &mut Type<'a>, table: &[Name<'a>]) {
+        let is_typedef = match *t {
+            Type::Class(ref symbol, _) | Type::Struct(ref symbol, _) => {
+                std_typedef(table, &symbol.name, &symbol.scope.names).is_some()
             }
-        } else {
-            self.read_params()?
+            _ => false,
         };
+        if is_typedef {
+            let typedef = match *t {
+                Type::Class(ref mut symbol, sc) | Type::Struct(ref mut symbol, sc) => {
+                    Type::Typedef(Box::new(take_symbol(symbol)), sc)
+                }
+                _ => return,
+            };
+            // The name is rewritten by name() next.
+            *t = typedef;
+        }
+    }
+}
 
-        self.expect(b"Z")?;
-
-        Ok(params)
+impl Pass for StdTypedefs {
+    fn run(&self, parse_result: &mut ParseResult) {
+        rewrite(parse_result, &mut StdTypedefs);
     }
+}
 
+/// A pass that leaves out trailing template arguments of standard library
+/// templates that are equal to their defaults, like
+/// `DemangleOptions::NO_DEFAULT_TEMPLATE_ARGS`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ElideDefaultTemplateArgs;
+
+impl<'a> Rewrite<'a> for ElideDefaultTemplateArgs {
+    fn name(&mut self, name: &mut Name<'a>, enclosing: &[Name<'a>], table: &[Name<'a>]) {
+        // The entries of the names table are visited without their scope,
+        // so templates are elided where they are referred to from `std`,
+        // copying a table entry instead of changing it for all its uses.
+        if !is_std_scope(enclosing) {
+            return;
+        }
+        let (keep, len) = match *resolve_name(table, name) {
+            Name::Template(ref template, ref params) => {
+                let len = match params.types.last() {
+                    Some(&Type::EmptyParameterPack) => params.types.len() - 1,
+                    _ => params.types.len(),
+                };
+                (non_default_template_args(table, template, &params.types[..len]), len)
+            }
+            _ => return,
+        };
+        if keep == len {
+            return;
+        }
+        if let Name::Backref(_) = *name {
+            *name = resolve_name(table, name).clone();
+        }
+        if let Name::Template(_, ref mut params) = *name {
+            params.types.drain(keep..len);
+        }
+    }
 }
 
-pub fn demangle<'a>(input: &'a str, flags: DemangleFlags) -> Result<String> {
-    serialize(&parse(input)?, flags)
+impl Pass for ElideDefaultTemplateArgs {
+    fn run(&self, parse_result: &mut ParseResult) {
+        rewrite(parse_result, &mut ElideDefaultTemplateArgs);
+    }
 }
 
-pub fn parse<'a>(input: &'a str) -> Result<ParseResult> {
-    let mut state = ParserState {
-        input: input.as_bytes(),
-        memorized_names: Vec::with_capacity(10),
-        memorized_types: Vec::with_capacity(10),
-    };
-    state.parse()
+/// A pass that leaves out all template arguments, writing `Foo<>` for
+/// `Foo<int>`, like `DemangleOptions::NO_TEMPLATE_ARGS`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ElideTemplateArgs;
+
+impl<'a> Rewrite<'a> for ElideTemplateArgs {
+    fn name(&mut self, name: &mut Name<'a>, _enclosing: &[Name<'a>], _table: &[Name<'a>]) {
+        if let Name::Template(_, ref mut params) = *name {
+            params.types.clear();
+        }
+    }
 }
 
-pub fn serialize(input: &ParseResult, flags: DemangleFlags) -> Result<String> {
-    let mut s = Vec::new();
-    {
-        let mut serializer = Serializer { flags, w: &mut s };
-        serializer.serialize(&input).unwrap();
+impl Pass for ElideTemplateArgs {
+    fn run(&self, parse_result: &mut ParseResult) {
+        rewrite(parse_result, &mut ElideTemplateArgs);
     }
-    Ok(String::from_utf8(s)?)
+}
 
+/// A pass that drops the namespaces or classes with the given names from
+/// all scopes, e.g. inline namespaces such as `__1` of libc++.
+#[derive(Clone, Debug, Default)]
+pub struct StripScopes {
+    names: Vec<String>,
 }
 
-// Converts an AST to a string.
-//
-// Converting an AST representing a C++ type to a string is tricky due
-// to the bad grammar of the C++ declaration inherited from C. You have
-// to construct a string from inside to outside. For example, if a type
-// X is a pointer to a function returning int, the order you create a
-// string becomes something like this:
-//
-//   (1) X is a pointer: *X
-//   (2) (1) is a function returning int: int (*X)()
-//
-// So you cannot construct a result just by appending strings to a result.
-//
-// To deal with this, we split the function into two. write_pre() writes
-// the "first half" of type declaration, and write_post() writes the
-// "second half". For example, write_pre() writes a return type for a
-// function and write_post() writes an parameter list.
-struct Serializer<'a> {
-    flags: DemangleFlags,
-    w: &'a mut Vec<u8>,
+impl StripScopes {
+    pub fn new<S: AsRef<str>>(names: &[S]) -> StripScopes {
+        StripScopes {
+            names: names.iter().map(|name| name.as_ref().to_owned()).collect(),
+        }
+    }
 }
 
-impl<'a> Serializer<'a> {
-    fn serialize(&mut self, parse_result: &ParseResult) -> SerializeResult<()> {
-        self.write_pre(&parse_result.symbol_type)?;
-        self.write_name(&parse_result.symbol)?;
-        self.write_post(&parse_result.symbol_type)?;
-        Ok(())
+impl Pass for StripScopes {
+    fn run(&self, parse_result: &mut ParseResult) {
+        parse_result.rewrite_scopes(|names| {
+            names.retain(|name| match *name {
+                Name::NonTemplate(ref name) => !self.names.iter().any(|strip| strip == name),
+                _ => true,
+            })
+        });
     }
+}
 
-    fn write_calling_conv(&mut self, calling_conv: CallingConv) -> SerializeResult<()> {
-        if let Some(&b' ') = self.w.last() {
-        } else {
-            write!(self.w, " ")?;
-        }
-        match calling_conv {
-            CallingConv::Cdecl => {
-                write!(self.w, "__cdecl ")?;
-            },
-            CallingConv::Pascal => {
-            },
-            CallingConv::Thiscall => {
-                write!(self.w, "__thiscall ")?;
-            },
-            CallingConv::Stdcall => {
-                write!(self.w, "__stdcall ")?;
-            },
-            CallingConv::Fastcall => {
-                write!(self.w, "__fastcall ")?;
-            },
-            CallingConv::_Regcall => {
-                write!(self.w, "__regcall ")?;
-            },
-        };
+// Runs the passes of the simplifications that `flags` asks for on
+// `parse_result`. Typedefs go first, as they are recognized by all of their
+// template arguments.
+fn simplify(parse_result: &mut ParseResult, flags: DemangleOptions) {
+    if flags.contains(DemangleOptions::STD_TYPEDEFS) {
+        StdTypedefs.run(parse_result);
+    }
+    if flags.contains(DemangleOptions::NO_TEMPLATE_ARGS) {
+        ElideTemplateArgs.run(parse_result);
+    } else if flags.contains(DemangleOptions::NO_DEFAULT_TEMPLATE_ARGS) {
+        ElideDefaultTemplateArgs.run(parse_result);
+    }
+}
 
-        Ok(())
+// Like simplify(), but copies `parse_result` only if `flags` asks for any
+// simplification.
+fn simplified<'b, 'a>(parse_result: &'b ParseResult<'a>, flags: DemangleOptions) -> Cow<'b, ParseResult<'a>> {
+    if flags.contains(DemangleOptions::STD_TYPEDEFS)
+        || flags.contains(DemangleOptions::NO_TEMPLATE_ARGS)
+        || flags.contains(DemangleOptions::NO_DEFAULT_TEMPLATE_ARGS)
+    {
+        let mut parse_result = parse_result.clone();
+        simplify(&mut parse_result, flags);
+        Cow::Owned(parse_result)
+    } else {
+        Cow::Borrowed(parse_result)
     }
+}
 
-    // Write the "first half" of a given type.
-    fn write_pre(&mut self, t: &Type) -> SerializeResult<()> {
-        let storage_class = match t {
-            &Type::None => return Ok(()),
-            &Type::MemberFunction(func_class, calling_conv, _, _, ref inner) => {
-                if func_class.contains(FuncClass::THUNK) {
-                    write!(self.w, "[thunk]:")?
-                }
-                if func_class.contains(FuncClass::PRIVATE) {
-                    write!(self.w, "private: ")?
-                }
-                if func_class.contains(FuncClass::PROTECTED) {
-                    write!(self.w, "protected: ")?
-                }
-                if func_class.contains(FuncClass::PUBLIC) {
-                    write!(self.w, "public: ")?
-                }
-                if func_class.contains(FuncClass::STATIC) {
-                    write!(self.w, "static ")?
-                }
-                if func_class.contains(FuncClass::VIRTUAL) {
-                    write!(self.w, "virtual ")?;
-                }
-                self.write_pre(inner)?;
-                self.write_calling_conv(calling_conv)?;
-                return Ok(());
-            }
-            &Type::MemberFunctionPointer(ref symbol, _, calling_conv, _, _, ref inner) => {
-                self.write_pre(inner)?;
-                self.write_calling_conv(calling_conv)?;
-                if self.flags == DemangleFlags::LotsOfWhitespace {
-                    self.write_space()?;
-                }
-                write!(self.w, "(")?;
-                if self.flags == DemangleFlags::LotsOfWhitespace {
-                    self.write_space()?;
-                }
-                self.write_name(symbol)?;
-                write!(self.w, "::*)")?;
-                return Ok(());
-            }
-            &Type::NonMemberFunction(calling_conv, _, _, ref inner) => {
-                self.write_pre(inner)?;
-                self.write_calling_conv(calling_conv)?;
-                return Ok(());
+// Collects the template instances in a symbol for
+// ParseResult::template_instances().
+struct TemplateFinder<'b, 'a> {
+    names: &'b [Name<'a>],
+    // Which entries of `names` were visited already.
+    seen: Vec<bool>,
+    instances: Vec<(&'b Name<'a>, &'b [Type<'a>])>,
+}
+
+impl<'b, 'a> TemplateFinder<'b, 'a> {
+    fn symbol(&mut self, parse_result: &'b ParseResult<'a>) {
+        self.names(&parse_result.symbol.scope.names);
+        self.name(&parse_result.symbol.name);
+        self.type_(&parse_result.symbol_type);
+    }
+
+    // Visits the names of a scope, outermost first.
+    fn names(&mut self, names: &'b [Name<'a>]) {
+        for name in names.iter().rev() {
+            self.name(name);
+        }
+    }
+
+    fn name(&mut self, name: &'b Name<'a>) {
+        match *name {
+            Name::Template(ref template, _) => {
+                let args = name.template_args().unwrap_or_default();
+                self.instances.push((template, args));
+                self.name(template);
+                self.types(args);
             }
-            &Type::CXXVBTable(_, sc) => sc,
-            &Type::CXXVFTable(_, sc) => sc,
-            &Type::TemplateParameterWithIndex(n) => {
-                write!(self.w, "`template-parameter{}'", n)?;
-                return Ok(());
+            Name::ParsedName(ref parse_result) => self.symbol(parse_result),
+            Name::Backref(i) if i < self.seen.len() && !self.seen[i] => {
+                self.seen[i] = true;
+                self.name(&self.names[i]);
             }
-            &Type::ThreadSafeStaticGuard(num) => {
-                write!(self.w, "TSS{}", num)?;
-                return Ok(());
+            _ => {}
+        }
+    }
+
+    fn types(&mut self, types: &'b [Type<'a>]) {
+        for t in types {
+            self.type_(t);
+        }
+    }
+
+    fn type_(&mut self, t: &'b Type<'a>) {
+        match *t {
+            Type::MemberFunction(_, _, ref params, _, ref ret)
+            | Type::NonMemberFunction(_, ref params, _, ref ret) => {
+                self.type_(ret);
+                self.types(&params.types);
             }
-            &Type::Constant(n) => {
-                write!(self.w, "{}", n)?;
-                return Ok(());
+            Type::MemberFunctionPointer(ref symbol, _, _, ref params, _, ref ret) => {
+                self.names(&symbol.scope.names);
+                self.name(&symbol.name);
+                self.type_(ret);
+                self.types(&params.types);
             }
-            &Type::ConstantString(_) => {
-                // We have no idea what the original encoding of the string is,
-                // and undname doesn't even try to display anything.
-                //match str::from_utf8(s).ok() {
-                //  Some(ref s) => write!(self.w, "{}", s)?,
-                //  None => {},
-                //}
-                return Ok(());
+            Type::CXXVBTable(ref names, _) | Type::CXXVFTable(ref names, _) => self.names(&names.names),
+            Type::Ptr(ref inner, _)
+            | Type::Ref(ref inner, _)
+            | Type::RValueRef(ref inner, _)
+            | Type::Array(_, ref inner, _) => self.type_(inner),
+            Type::Struct(ref symbol, _)
+            | Type::Union(ref symbol, _)
+            | Type::Class(ref symbol, _)
+            | Type::Typedef(ref symbol, _) => {
+                self.names(&symbol.scope.names);
+                self.name(&symbol.name);
             }
-            &Type::VarArgs => {
-                write!(self.w, "...")?;
-                return Ok(());
+            Type::Enum(ref symbol, ref underlying, _) => {
+                self.names(&symbol.scope.names);
+                self.name(&symbol.name);
+                self.type_(underlying);
             }
-            &Type::Ptr(ref inner, storage_class) |
-            &Type::Ref(ref inner, storage_class) |
-            &Type::RValueRef(ref inner, storage_class)=> {
-                self.write_pre(inner)?;
+            _ => {}
+        }
+    }
+}
 
-                // "[]" and "()" (for function parameters) take precedence over "*",
-                // so "int *x(int)" means "x is a function returning int *". We need
-                // parentheses to supercede the default precedence. (e.g. we want to
-                // emit something like "int (*x)(int)".)
-                match inner.as_ref() {
-                    &Type::MemberFunction(_, _, _, _, _)
-                    | &Type::NonMemberFunction(_, _, _, _)
-                    | &Type::Array(_, _, _) => {
-                        if self.flags == DemangleFlags::LotsOfWhitespace {
-                            self.write_space()?;
-                        }
-                        write!(self.w, "(")?;
-                    }
-                    _ => {}
-                }
+// A part of a parse result that `EventEmitter` has yet to pass on.
+#[derive(Clone, Copy)]
+enum EmitStep<'b> {
+    Event(Event<'b>),
+    Symbol(&'b ParseResult<'b>),
+    QualifiedName(&'b Symbol<'b>),
+    Name(&'b Name<'b>),
+    Type(&'b Type<'b>),
+    Params(&'b Params<'b>),
+}
 
-                match t {
-                    &Type::Ptr(_, _) => {
-                        if self.flags == DemangleFlags::LotsOfWhitespace {
-                            self.write_space()?;
-                        }
-                        write!(self.w, "*")?
-                    }
-                    &Type::Ref(_, _) => {
-                        if self.flags == DemangleFlags::LotsOfWhitespace {
-                            self.write_space()?;
-                        }
-                        write!(self.w, "&")?
+// Passes the parts of parse results on as events. The parts still to be
+// passed on are kept on a stack rather than by recursing, so that
+// arbitrarily deep types don't run out of stack.
+struct EventEmitter<'b, F> {
+    names: &'b [Name<'b>],
+    callback: F,
+}
+
+impl<'b, F: FnMut(Event)> EventEmitter<'b, F> {
+    fn emit(&mut self, event: Event) {
+        (self.callback)(event);
+    }
+
+    fn symbol(&mut self, parse_result: &ParseResult) {
+        self.run(EmitStep::Symbol(parse_result));
+    }
+
+    fn qualified_name(&mut self, symbol: &Symbol) {
+        self.run(EmitStep::QualifiedName(symbol));
+    }
+
+    fn type_(&mut self, t: &Type) {
+        self.run(EmitStep::Type(t));
+    }
+
+    fn run<'s>(&mut self, first: EmitStep<'s>)
+    where
+        'b: 's,
+    {
+        let names: &'s [Name<'s>] = self.names;
+        let mut stack = vec![first];
+        while let Some(step) = stack.pop() {
+            // The steps are pushed in order and then reversed, so that they
+            // are popped in order.
+            let len = stack.len();
+            match step {
+                EmitStep::Event(event) => self.emit(event),
+                EmitStep::Symbol(parse_result) => stack.extend(&[
+                    EmitStep::Event(Event::BeginSymbol(parse_result.symbol_kind)),
+                    EmitStep::QualifiedName(&parse_result.symbol),
+                    EmitStep::Type(&parse_result.symbol_type),
+                    EmitStep::Event(Event::EndSymbol),
+                ]),
+                EmitStep::QualifiedName(symbol) => {
+                    // The names of the scope, outermost first, each followed
+                    // by EnterScope.
+                    for name in symbol.scope.names.iter().rev() {
+                        stack.extend(&[EmitStep::Name(name), EmitStep::Event(Event::EnterScope)]);
                     }
-                    &Type::RValueRef(_, _) => {
-                        if self.flags == DemangleFlags::LotsOfWhitespace {
-                            self.write_space()?;
-                        }
-                        write!(self.w, "&&")?
+                    stack.push(EmitStep::Name(&symbol.name));
+                    for _ in &symbol.scope.names {
+                        stack.push(EmitStep::Event(Event::LeaveScope));
                     }
-                    _ => {}
                 }
-
-                storage_class
-            }
-            &Type::Array(_len, ref inner, storage_class) => {
-                self.write_pre(inner)?;
-                storage_class
-            }
-            &Type::Struct(ref names, sc) => {
-                self.write_class(names, "struct")?;
-                sc
-            }
-            &Type::Union(ref names, sc) => {
-                self.write_class(names, "union")?;
-                sc
-            }
-            &Type::Class(ref names, sc) => {
-                self.write_class(names, "class")?;
-                sc
-            }
-            &Type::Enum(ref names, sc) => {
-                self.write_class(names, "enum")?;
-                sc
-            }
-            &Type::Void(sc) => {
-                write!(self.w, "void")?;
-                sc
-            }
-            &Type::Bool(sc) => {
-                write!(self.w, "bool")?;
-                sc
-            }
-            &Type::Char(sc) => {
-                write!(self.w, "char")?;
-                sc
-            }
-            &Type::Schar(sc) => {
-                write!(self.w, "signed char")?;
-                sc
-            }
-            &Type::Uchar(sc) => {
-                write!(self.w, "unsigned char")?;
-                sc
-            }
-            &Type::Short(sc) => {
-                write!(self.w, "short")?;
-                sc
-            }
-            &Type::Ushort(sc) => {
-                write!(self.w, "unsigned short")?;
-                sc
-            }
-            &Type::Int(sc) => {
-                write!(self.w, "int")?;
-                sc
-            }
-            &Type::Uint(sc) => {
-                write!(self.w, "unsigned int")?;
-                sc
+                EmitStep::Name(name) => Self::name_steps(names, name, &mut stack),
+                EmitStep::Type(t) => Self::type_steps(t, &mut stack),
+                EmitStep::Params(params) => {
+                    stack.push(EmitStep::Event(Event::BeginParams));
+                    stack.extend(params.types.iter().map(EmitStep::Type));
+                    stack.push(EmitStep::Event(Event::EndParams));
+                }
             }
-            &Type::Long(sc) => {
-                write!(self.w, "long")?;
-                sc
+            stack[len..].reverse();
+        }
+    }
+
+    fn name_steps<'s>(names: &'s [Name<'s>], name: &'s Name<'s>, stack: &mut Vec<EmitStep<'s>>) {
+        let event = match *name {
+            Name::Operator(ref op) => Event::OperatorName(op),
+            Name::NonTemplate(ref name) => Event::Identifier(name),
+            Name::Template(ref name, ref params) => {
+                stack.extend(&[EmitStep::Name(name), EmitStep::Event(Event::BeginTemplateArgs)]);
+                stack.extend(params.types.iter().map(EmitStep::Type));
+                stack.push(EmitStep::Event(Event::EndTemplateArgs));
+                return;
             }
-            &Type::Ulong(sc) => {
-                write!(self.w, "unsigned long")?;
-                sc
+            Name::Discriminator(n) => Event::Discriminator(n),
+            Name::ParsedName(ref parse_result) => {
+                stack.push(EmitStep::Symbol(parse_result));
+                return;
             }
-            &Type::Int64(sc) => {
-                write!(self.w, "int64_t")?;
-                sc
+            Name::AnonymousNamespace => Event::AnonymousNamespace,
+            Name::Backref(_) => {
+                let resolved = resolve_name(names, name);
+                if resolved != name {
+                    stack.push(EmitStep::Name(resolved));
+                }
+                return;
             }
-            &Type::Uint64(sc) => {
-                write!(self.w, "uint64_t")?;
-                sc
+        };
+        stack.push(EmitStep::Event(event));
+    }
+
+    fn type_steps<'s>(t: &'s Type<'s>, stack: &mut Vec<EmitStep<'s>>) {
+        let event = match *t {
+            Type::Shared(ref inner) => {
+                stack.push(EmitStep::Type(inner));
+                return;
             }
-            &Type::Wchar(sc) => {
-                write!(self.w, "wchar_t")?;
-                sc
+            Type::None | Type::EmptyParameterPack => return,
+            Type::MemberFunction(func_class, calling_conv, ref params, sc, ref ret) => {
+                stack.extend(&[
+                    EmitStep::Event(Event::BeginFunction(Some(func_class), calling_conv, sc)),
+                    EmitStep::Type(ret),
+                    EmitStep::Params(params),
+                    EmitStep::Event(Event::EndType),
+                ]);
+                return;
             }
-            &Type::Float(sc) => {
-                write!(self.w, "float")?;
-                sc
+            Type::NonMemberFunction(calling_conv, ref params, sc, ref ret) => {
+                stack.extend(&[
+                    EmitStep::Event(Event::BeginFunction(None, calling_conv, sc)),
+                    EmitStep::Type(ret),
+                    EmitStep::Params(params),
+                    EmitStep::Event(Event::EndType),
+                ]);
+                return;
             }
-            &Type::Double(sc) => {
-                write!(self.w, "double")?;
-                sc
+            Type::MemberFunctionPointer(ref symbol, func_class, calling_conv, ref params, sc, ref ret) => {
+                stack.extend(&[
+                    EmitStep::Event(Event::BeginMemberFunctionPointer(func_class, calling_conv, sc)),
+                    EmitStep::QualifiedName(symbol),
+                    EmitStep::Type(ret),
+                    EmitStep::Params(params),
+                    EmitStep::Event(Event::EndType),
+                ]);
+                return;
             }
-            &Type::Ldouble(sc) => {
-                write!(self.w, "long double")?;
-                sc
+            Type::CXXVBTable(ref names, sc) | Type::CXXVFTable(ref names, sc) => {
+                stack.push(EmitStep::Event(Event::BeginTable(sc)));
+                stack.extend(names.names.iter().rev().map(EmitStep::Name));
+                stack.push(EmitStep::Event(Event::EndType));
+                return;
             }
-            &Type::Char16(sc) => {
-                write!(self.w, "char16_t")?;
-                sc
-            },
-            &Type::Char32(sc) => {
-                write!(self.w, "char32_t")?;
-                sc
-            },
-            &Type::Nullptr => {
-                write!(self.w, "std::nullptr_t")?;
-                return Ok(());
+            Type::Ptr(ref inner, sc) => return Self::wrapped(Event::BeginPointer(sc), inner, stack),
+            Type::Ref(ref inner, sc) => return Self::wrapped(Event::BeginReference(sc), inner, stack),
+            Type::RValueRef(ref inner, sc) => return Self::wrapped(Event::BeginRValueReference(sc), inner, stack),
+            Type::Array(len, ref inner, sc) => return Self::wrapped(Event::BeginArray(len, sc), inner, stack),
+            Type::Struct(ref symbol, sc) => return Self::user_type("struct", symbol, sc, stack),
+            Type::Union(ref symbol, sc) => return Self::user_type("union", symbol, sc, stack),
+            Type::Class(ref symbol, sc) => return Self::user_type("class", symbol, sc, stack),
+            Type::Typedef(ref symbol, sc) => return Self::user_type("typedef", symbol, sc, stack),
+            Type::Enum(ref symbol, ref underlying, sc) => {
+                stack.extend(&[
+                    EmitStep::Event(Event::BeginUserType("enum", sc)),
+                    EmitStep::QualifiedName(symbol),
+                    EmitStep::Type(underlying),
+                    EmitStep::Event(Event::EndType),
+                ]);
+                return;
             }
-            &Type::EmptyParameterPack => {
-                return Ok(())
-            },
+            Type::TemplateParameterWithIndex(n) => Event::TemplateParameter(n),
+            Type::ThreadSafeStaticGuard(n) => Event::ThreadSafeStaticGuard(n),
+            Type::Constant(n) => Event::Constant(n),
+            Type::ConstantString(ref s) => Event::StringLiteral(s),
+            Type::Void(sc) => Event::PrimitiveType("void", sc),
+            Type::Bool(sc) => Event::PrimitiveType("bool", sc),
+            Type::Char(sc) => Event::PrimitiveType("char", sc),
+            Type::Schar(sc) => Event::PrimitiveType("signed char", sc),
+            Type::Uchar(sc) => Event::PrimitiveType("unsigned char", sc),
+            Type::Short(sc) => Event::PrimitiveType("short", sc),
+            Type::Ushort(sc) => Event::PrimitiveType("unsigned short", sc),
+            Type::Int(sc) => Event::PrimitiveType("int", sc),
+            Type::Uint(sc) => Event::PrimitiveType("unsigned int", sc),
+            Type::Long(sc) => Event::PrimitiveType("long", sc),
+            Type::Ulong(sc) => Event::PrimitiveType("unsigned long", sc),
+            Type::Int64(sc) => Event::PrimitiveType("__int64", sc),
+            Type::Uint64(sc) => Event::PrimitiveType("unsigned __int64", sc),
+            Type::Wchar(sc) => Event::PrimitiveType("wchar_t", sc),
+            Type::Char16(sc) => Event::PrimitiveType("char16_t", sc),
+            Type::Char32(sc) => Event::PrimitiveType("char32_t", sc),
+            Type::Float(sc) => Event::PrimitiveType("float", sc),
+            Type::Double(sc) => Event::PrimitiveType("double", sc),
+            Type::Ldouble(sc) => Event::PrimitiveType("long double", sc),
+            Type::VarArgs => Event::PrimitiveType("...", StorageClass::empty()),
+            Type::Nullptr => Event::PrimitiveType("std::nullptr_t", StorageClass::empty()),
+            Type::Extension(ref name, sc) => Event::ExtensionType(name, sc),
         };
+        stack.push(EmitStep::Event(event));
+    }
 
-        if storage_class.contains(StorageClass::CONST) {
-            self.write_space()?;
-            write!(self.w, "const")?;
-        }
-        if storage_class.contains(StorageClass::VOLATILE) {
-            self.write_space()?;
-            write!(self.w, "volatile")?;
-        }
-
-        Ok(())
+    // `begin`, then `inner` and EndType.
+    fn wrapped<'s>(begin: Event<'s>, inner: &'s Type<'s>, stack: &mut Vec<EmitStep<'s>>) {
+        stack.extend(&[EmitStep::Event(begin), EmitStep::Type(inner), EmitStep::Event(Event::EndType)]);
     }
 
-    fn write_memfn_qualifiers(&mut self, sc: StorageClass) -> SerializeResult<()> {
-        let mut write_one_qual = |flag, s| -> SerializeResult<()> {
-            if sc.contains(flag) {
-                self.w.write(s)?;
-                if self.flags == DemangleFlags::LotsOfWhitespace {
-                    self.write_space()?;
-                }
-            }
+    fn user_type<'s>(keyword: &'static str, symbol: &'s Symbol<'s>, sc: StorageClass, stack: &mut Vec<EmitStep<'s>>) {
+        stack.extend(&[
+            EmitStep::Event(Event::BeginUserType(keyword, sc)),
+            EmitStep::QualifiedName(symbol),
+            EmitStep::Event(Event::EndType),
+        ]);
+    }
+}
 
-            Ok(())
-        };
+// A step of `ParserState::read_var_type()`: a type, or a pointer, reference
+// or array wrapping the type that follows, which is read with the given
+// storage class.
+enum VarTypeStep<'a> {
+    Type(Type<'a>),
+    Wrapper(Wrapper<'a>, StorageClass),
+}
 
-        // TODO: DemangleFlags::LessWhitespace means we run all these together.
-        write_one_qual(StorageClass::CONST, b"const")?;
-        // __restrict is different than `restrict`, keep the underscores!
-        write_one_qual(StorageClass::RESTRICT, b"__restrict")?;
-        // TODO: undname prints ref-qualifiers tightly to previous qualifiers.
-        write_one_qual(StorageClass::LVALUE_QUAL, b"&")?;
-        write_one_qual(StorageClass::RVALUE_QUAL, b"&&")?;
+enum Wrapper<'a> {
+    // Made with e.g. Type::Ptr, with the pointer's own storage class.
+    Pointer(fn(Box<Type<'a>>, StorageClass) -> Type<'a>, StorageClass),
+    // The lengths of the dimensions, outermost first, and the storage class
+    // of the elements.
+    Array(Vec<i32>, StorageClass),
+}
 
-        Ok(())
+impl<'a> Wrapper<'a> {
+    fn wrap(self, inner: Type<'a>) -> Type<'a> {
+        match self {
+            Wrapper::Pointer(make, sc) => make(Box::new(inner), sc),
+            Wrapper::Array(lens, sc) => lens
+                .into_iter()
+                .rev()
+                .fold(inner, |inner, len| Type::Array(len, Box::new(inner), sc)),
+        }
     }
+}
 
-    // Write the "second half" of a given type.
-    fn write_post(&mut self, t: &Type) -> SerializeResult<()> {
-        match t {
-            &Type::MemberFunction(_, _, ref params, sc, ref return_type)
-            | &Type::NonMemberFunction(_, ref params, sc, ref return_type) => {
-                write!(self.w, "(")?;
-                self.write_types(&params.types)?;
-                write!(self.w, ")")?;
+// Demangler class takes the main role in demangling symbols.
+// It has a set of functions to parse mangled symbols into Type instnaces.
+// It also has a set of functions to cnovert Type instances to strings.
+struct ParserState<'a, 'h> {
+    // Mangled symbol. read_* functions shorten this string
+    // as they parse it.
+    input: &'a [u8],
 
-                self.write_post(return_type)?;
+    // The complete mangled symbol. Identifiers are sliced out of this, so
+    // that they don't need to be validated as UTF-8 again.
+    source: &'a str,
 
-                self.write_memfn_qualifiers(sc)?;
-            }
-            &Type::MemberFunctionPointer(_, _, _, ref params, sc, ref return_type) => {
-                write!(self.w, "(")?;
-                self.write_types(&params.types)?;
-                write!(self.w, ")")?;
+    // The first 10 names in a mangled name can be back-referenced by
+    // special name @[0-9]. This is a storage for the first 10 names, along
+    // with how many levels deep each is, see `measured()`.
+    memorized_names: Vec<(Name<'a>, usize)>,
+
+    // The template names that can be back-referenced, which the AST refers
+    // to by Name::Backref instead of copying them, and the index of each.
+    names: Vec<Name<'a>>,
+    name_ids: HashMap<Name<'a>, usize>,
+
+    // The types that can be back-referenced, along with the number of
+    // nodes each is made of and how many levels deep it is. Back-references
+    // share them as Type::Shared.
+    memorized_types: Vec<(Arc<Type<'a>>, usize, usize)>,
+
+    // Whether template names are moved to `names`. Without a ParseResult
+    // to hold the table, they are copied like other names.
+    intern: bool,
+
+    // Whether parameter lists must be terminated, see
+    // `DemangleOptions::STRICT`.
+    strict: bool,
+
+    // The current and the maximum nesting level of types and names, and
+    // the deepest level reached, see `measured()`.
+    depth: usize,
+    max_depth: usize,
+    deepest: usize,
+
+    // The number of types and names read so far, and the maximum.
+    nodes: usize,
+    max_nodes: usize,
+
+    // The hooks for unknown codes, if any.
+    extensions: Option<&'h mut dyn Extensions>,
+
+    // The grammar productions parsed so far if they are recorded, which
+    // only `parse_traced()` asks for, and how deeply nested the current one
+    // is.
+    #[cfg(feature = "trace")]
+    trace: Option<Vec<trace::TraceStep>>,
+    #[cfg(feature = "trace")]
+    trace_depth: usize,
+}
 
-                self.write_post(return_type)?;
+impl<'a, 'h> ParserState<'a, 'h> {
+    fn new(input: &'a str, options: ParseOptions) -> ParserState<'a, 'h> {
+        ParserState {
+            input: input.as_bytes(),
+            source: input,
+            memorized_names: Vec::with_capacity(10),
+            names: Vec::new(),
+            name_ids: HashMap::new(),
+            memorized_types: Vec::with_capacity(10),
+            intern: true,
+            strict: options.strict,
+            depth: 0,
+            max_depth: depth_limit(options.max_depth),
+            deepest: 0,
+            nodes: 0,
+            max_nodes: options.max_nodes.unwrap_or(usize::MAX),
+            extensions: None,
+            #[cfg(feature = "trace")]
+            trace: None,
+            #[cfg(feature = "trace")]
+            trace_depth: 0,
+        }
+    }
 
-                if sc.contains(StorageClass::CONST) {
-                    write!(self.w, "const")?;
-                    if self.flags == DemangleFlags::LotsOfWhitespace {
-                        self.write_space()?;
-                    }
-                }
-            }
-            &Type::CXXVBTable(ref names, _sc) => {
-                self.write_scope(names)?;
-                write!(self.w, "{}", "\'}")?; // the rest of the "operator"
-            }
-            &Type::Ptr(ref inner, _sc) | &Type::Ref(ref inner, _sc) => {
-                match inner.as_ref() {
-                    &Type::MemberFunction(_, _, _, _, _)
-                    | &Type::NonMemberFunction(_, _, _, _)
-                    | &Type::Array(_, _, _) => {
-                        write!(self.w, ")")?;
-                    }
-                    _ => {}
-                }
-                self.write_post(inner)?;
-            }
-            &Type::Array(len, ref inner, _sc) => {
-                write!(self.w, "[{}]", len)?;
-                self.write_post(inner)?;
-            },
-            &Type::CXXVFTable(ref names, _) => if !names.names.is_empty() {
-                write!(self.w, "{{for ")?;
-                for name in &names.names {
-                    write!(self.w, "`")?;
-                    self.write_one_name(name)?;
-                    write!(self.w, "'")?;
-                }
-                self.w.write(b"}")?;
-            },
-            _ => {}
-        }
-        Ok(())
+    // Runs `f` one nesting level deeper, failing if that is too deep.
+    fn nested<T, F: FnOnce(&mut Self) -> Result<T>>(&mut self, f: F) -> Result<T> {
+        self.enter()?;
+        let result = f(self);
+        self.depth -= 1;
+        result
     }
 
-    // Write a function or template parameter list.
-    fn write_types(&mut self, types: &[Type]) -> SerializeResult<()> {
-        for param in types.iter().take(types.len() - 1) {
-            self.write_pre(param)?;
-            self.write_post(param)?;
-            write!(self.w, ",")?;
-        }
-        if let Some(param) = types.last() {
-            self.write_pre(param)?;
-            self.write_post(param)?;
+    // Goes one nesting level deeper, failing if that is too deep. The caller
+    // restores `depth` when leaving the level.
+    fn enter(&mut self) -> Result<()> {
+        self.add_nodes(1)?;
+        if self.depth >= self.max_depth {
+            return Err(Error::limit_exceeded("symbol is nested too deeply".to_owned()));
         }
+        self.depth += 1;
+        self.deepest = max(self.deepest, self.depth);
         Ok(())
     }
 
-    fn write_class(&mut self, names: &Symbol, s: &str) -> SerializeResult<()> {
-        write!(self.w, "{}", s)?;
-        write!(self.w, " ")?;
-        self.write_name(names)?;
-        Ok(())
+    // Runs `f` and returns what it read along with how many levels below
+    // the current one it went. Back-references to names and types take
+    // that many levels again wherever they appear, as the serializer goes
+    // through them there.
+    fn measured<T, F: FnOnce(&mut Self) -> Result<T>>(&mut self, f: F) -> Result<(T, usize)> {
+        let deepest = mem::replace(&mut self.deepest, self.depth);
+        let result = f(self);
+        let height = self.deepest - self.depth;
+        self.deepest = max(self.deepest, deepest);
+        result.map(|t| (t, height))
     }
 
-    fn write_space_pre(&mut self) -> SerializeResult<()> {
-        if let Some(&c) = self.w.last() {
-            match self.flags {
-                DemangleFlags::LessWhitespace => {
-                    if char::from(c).is_ascii_alphabetic() {
-                        write!(self.w, " ")?;
-                    }
-                }
-                DemangleFlags::LotsOfWhitespace => {
-                    if char::from(c).is_ascii_alphabetic() || c == b'&' || c == b'>' {
-                        write!(self.w, " ")?;
-                    }
-                }
-            }
+    // Accounts for a back-reference to something `height` levels deep,
+    // placed at `depth`.
+    fn reach(&mut self, depth: usize, height: usize) -> Result<()> {
+        if depth + height > self.max_depth {
+            return Err(Error::limit_exceeded("symbol is nested too deeply".to_owned()));
         }
+        self.deepest = max(self.deepest, depth + height);
         Ok(())
     }
-    fn write_space(&mut self) -> SerializeResult<()> {
-        if let Some(&c) = self.w.last() {
-            match self.flags {
-                DemangleFlags::LessWhitespace => {
-                    if char::from(c).is_ascii_alphabetic() {
-                        write!(self.w, " ")?;
-                    }
-                }
-                DemangleFlags::LotsOfWhitespace => {
-                    if char::from(c).is_ascii_alphabetic() || c == b'*' || c == b'&' || c == b'>' {
-                        write!(self.w, " ")?;
-                    }
-                }
-            }
-        }
-        Ok(())
+
+    // Runs `f`, which parses the grammar production `production`, and
+    // records it in the trace if there is one.
+    fn traced<T, F: FnOnce(&mut Self) -> Result<T>>(&mut self, production: &'static str, f: F) -> Result<T> {
+        let step = self.trace_begin(production);
+        let result = f(self);
+        self.trace_end(step, result.is_ok());
+        result
     }
 
-    fn write_operator_name(&mut self, op: &Operator) -> SerializeResult<()> {
-        let s = match op {
-            &Operator::Ctor => "ctor",
-            &Operator::Dtor => "dtor",
-            &Operator::New => "operator new",
-            &Operator::Delete => "operator delete",
-            &Operator::Equal => "operator=",
-            &Operator::RShift => "operator>>",
-            &Operator::LShift => "operator<<",
-            &Operator::Bang => "operator!",
-            &Operator::EqualEqual => "operator==",
-            &Operator::BangEqual => "operator!=",
-            &Operator::Subscript => "operator[]",
-            &Operator::Conversion => "operatorcast",
-            &Operator::Arrow => "operator->",
-            &Operator::Star => "operator*",
-            &Operator::PlusPlus => "operator++",
-            &Operator::MinusMinus => "operator--",
-            &Operator::Minus => "operator-",
-            &Operator::Plus => "operator+",
-            &Operator::Amp => "operator&",
-            &Operator::ArrowStar => "operator->*",
-            &Operator::Slash => "operator/",
-            &Operator::Percent => "operator%",
-            &Operator::Less => "operator<",
-            &Operator::LessEqual => "operator<=",
-            &Operator::Greater => "operator>",
-            &Operator::GreaterEqual => "operator>=",
-            &Operator::Comma => "operator,",
-            &Operator::Call => "operator()",
-            &Operator::Tilde => "operator~",
-            &Operator::Caret => "operator^",
-            &Operator::Pipe => "operator|",
-            &Operator::AmpAmp => "operator&&",
-            &Operator::PipePipe => "operator||",
-            &Operator::StarEqual => "operator*=",
-            &Operator::PlusEqual => "operator+=",
-            &Operator::MinusEqual => "operator-=",
-            &Operator::SlashEqual => "operator/=",
-            &Operator::PercentEqual => "operator%=",
-            &Operator::GreaterGreaterEqual => "operator>>=",
-            &Operator::LessLessEqual => "operator<<=",
-            &Operator::AmpEqual => "operator&=",
-            &Operator::PipeEqual => "operator|=",
-            &Operator::CaretEqual => "operator^=",
-
-            &Operator::VFTable => "`vftable'",
-            &Operator::VBTable => "`vbtable'",
-            &Operator::VCall => "`vcall'",
-            &Operator::Typeof => "`typeof'",
-            &Operator::LocalStaticGuard => "`local static guard'",
-            &Operator::String => "`string'",
-            &Operator::VBaseDtor => "`vbase destructor'",
-            &Operator::VectorDeletingDtor => "`vector deleting destructor'",
-            &Operator::DefaultCtorClosure => "`default constructor closure'",
-            &Operator::ScalarDeletingDtor => "`scalar deleting destructor'",
-            &Operator::VectorCtorIterator => "`vector constructor iterator'",
-            &Operator::VectorDtorIterator => "`vector destructor iterator'",
-            &Operator::VectorVBaseCtorIterator => "`vector vbase constructor iterator'",
-            &Operator::VirtualDisplacementMap => "`virual displacement map'",
-            &Operator::EHVectorCtorIterator => "`eh vector constructor iterator'",
-            &Operator::EHVectorDtorIterator => "`eh vector destructor iterator'",
-            &Operator::EHVectorVBaseCtorIterator => "`eh vector vbase constructor iterator'",
-            &Operator::CopyCtorClosure => "`copy constructor closure",
-
-            &Operator::LocalVFTable => "`local vftable'",
-            &Operator::LocalVFTableCtorClosure => "`local vftable constructor closure'",
-            &Operator::ArrayNew => "operator new[]",
-            &Operator::ArrayDelete => "operator delete[]",
-            &Operator::PlacementDeleteClosure => "`placement delete closure'",
-            &Operator::PlacementArrayDeleteClosure => "`placement delete[] closure'",
-
-            &Operator::CoroutineAwait => " co_await",
-            &Operator::LiteralOperatorName => " CXXLiteralOperatorName",
+    // Records the start of the grammar production `production` in the
+    // trace if there is one, returning the step to pass to `trace_end()`.
+    #[cfg(not(feature = "trace"))]
+    fn trace_begin(&mut self, production: &'static str) -> usize {
+        let _ = production;
+        0
+    }
+
+    #[cfg(feature = "trace")]
+    fn trace_begin(&mut self, production: &'static str) -> usize {
+        let start = self.source.len() - self.input.len();
+        let trace = match self.trace {
+            Some(ref mut trace) => trace,
+            None => return 0,
         };
-        write!(self.w, "{}", s)?;
-        Ok(())
+        trace.push(trace::TraceStep {
+            production,
+            range: start..start,
+            depth: self.trace_depth,
+            ok: false,
+        });
+        self.trace_depth += 1;
+        trace.len() - 1
     }
 
-    fn write_one_name(&mut self, name: &Name) -> SerializeResult<()> {
-        match name {
-            &Name::Operator(ref op) => {
-                if self.flags == DemangleFlags::LotsOfWhitespace {
-                    self.write_space()?;
-                }
-                self.write_operator_name(op)?;
-                //panic!("only the last name should be an operator");
-            }
-            &Name::NonTemplate(ref name) => {
-                self.w.write(name)?;
-            }
-            &Name::Template(ref name, ref params) => {
-                self.write_one_name(name)?;
-                self.write_tmpl_params(&params)?;
-            }
-            &Name::Discriminator(ref val) => {
-                write!(self.w, "`{}'", val)?;
-            }
-            &Name::ParsedName(ref val) => {
-                write!(self.w, "`{}'", serialize(val, self.flags).unwrap())?;
-            }
-            &Name::AnonymousNamespace => {
-                write!(self.w, "`anonymous namespace`")?;
+    // Records the end of the production begun as `step`.
+    #[cfg(not(feature = "trace"))]
+    fn trace_end(&mut self, step: usize, ok: bool) {
+        let _ = (step, ok);
+    }
+
+    #[cfg(feature = "trace")]
+    fn trace_end(&mut self, step: usize, ok: bool) {
+        let end = self.source.len() - self.input.len();
+        if let Some(ref mut trace) = self.trace {
+            self.trace_depth -= 1;
+            let step = &mut trace[step];
+            step.range.end = end;
+            step.ok = ok;
+        }
+    }
+
+    // Lets the extension hook `hook` read the unknown code at the start of
+    // `orig`, a suffix of the input. Returns what it read, consuming the
+    // bytes it used.
+    fn extension<F>(&mut self, orig: &'a [u8], hook: F) -> Option<String>
+    where
+        F: FnOnce(&mut dyn Extensions, &str) -> Option<(String, usize)>,
+    {
+        let input = self.source.get(self.source.len() - orig.len()..)?;
+        let extensions = self.extensions.as_mut()?;
+        match hook(&mut **extensions, input) {
+            Some((name, len)) if len > 0 && len <= orig.len() => {
+                self.input = &orig[len..];
+                Some(name)
             }
+            _ => None,
         }
-        Ok(())
     }
 
-    fn write_scope(&mut self, names: &NameSequence) -> SerializeResult<()> {
-        // Print out namespaces or outer class names.
-        let mut i = names.names.iter().rev();
-        if let Some(name) = i.next() {
-            self.write_one_name(&name)?;
+    // Reads the unknown type at the start of `orig` with the extension
+    // hooks, if they know it.
+    fn unknown_type(&mut self, orig: &'a [u8], sc: StorageClass) -> Result<Type<'a>> {
+        match self.extension(orig, |extensions, input| extensions.unknown_type(input)) {
+            Some(name) => Ok(Type::Extension(Cow::Owned(name), sc)),
+            None => Err(Error::new(format!("unknown primitive type: {}", String::from_utf8_lossy(orig)))),
+        }
+    }
 
+    // Counts `n` more nodes, failing if there are too many now.
+    fn add_nodes(&mut self, n: usize) -> Result<()> {
+        self.nodes = self.nodes.saturating_add(n);
+        if self.nodes > self.max_nodes {
+            return Err(Error::limit_exceeded("symbol has too many nodes".to_owned()));
         }
-        for name in i {
-            write!(self.w, "::")?;
-            self.write_one_name(&name)?;
+        Ok(())
+    }
+
+    fn parse(&mut self) -> Result<ParseResult<'a>> {
+        self.traced("symbol", |this| this.parse_untraced(&mut None))
+    }
 
+    // Like parse(), but passes the symbol to `callback` as events, see
+    // `parse_events()`. The parameters of a function are passed on as they
+    // are read and left out of the returned result; for other symbols, the
+    // result is passed on once it is read.
+    fn parse_events(&mut self, callback: &mut dyn FnMut(Event)) -> Result<()> {
+        let mut events = Some(callback);
+        let parse_result = self.traced("symbol", |this| this.parse_untraced(&mut events))?;
+        if let Some(callback) = events {
+            let mut emitter = EventEmitter {
+                names: &self.names,
+                callback,
+            };
+            emitter.symbol(&parse_result);
         }
         Ok(())
     }
 
-    // Write a name read by read_name().
-    fn write_name(&mut self, names: &Symbol) -> SerializeResult<()> {
-        self.write_space_pre()?;
+    // `events` is taken by the function symbols that stream their parts,
+    // see `parse_events()`.
+    fn parse_untraced(&mut self, events: &mut Option<&mut dyn FnMut(Event)>) -> Result<ParseResult<'a>> {
+        // MSVC-style mangled symbols must start with b'?'.
+        if !self.consume(b"?") {
+            return Err(Error::new("does not start with b'?'".to_owned()));
+        }
 
-        self.write_scope(&names.scope)?;
+        if self.consume(b"$") {
+            if self.consume(b"TSS") {
+                let mut guard_num: i32 = self.consume_digit().ok_or(Error::new("missing digit".to_owned()))? as i32;
+                while !self.consume(b"@") {
+                    let digit = self.consume_digit().ok_or(Error::new("missing digit".to_owned()))? as i32;
+                    guard_num = guard_num
+                        .checked_mul(10)
+                        .and_then(|n| n.checked_add(digit))
+                        .ok_or(Error::new("guard number too large".to_owned()))?;
+                }
+                let name = self.read_nested_name()?;
+                let scope = self.read_scope()?;
+                self.expect(b"4HA")?;
+                return Ok(ParseResult {
+                    symbol: Symbol { name, scope },
+                    symbol_type: Type::ThreadSafeStaticGuard(guard_num),
+                    symbol_kind: SymbolKind::Guard,
+                    variable_kind: None,
+                    thunk_adjustment: None,
+                    names: Vec::new(),
+                });
+            }
+            let name = self.read_template_name()?;
+            return Ok(ParseResult {
+                symbol: Symbol { name, scope: NameSequence{ names: Vec::new() } },
+                symbol_type: Type::None,
+                symbol_kind: SymbolKind::Other,
+                variable_kind: None,
+                thunk_adjustment: None,
+                names: Vec::new(),
+            });
+        }
 
-        if !names.scope.names.is_empty() {
-            write!(self.w, "::")?;
+        // The RTTI type descriptor of a class has a type instead of a scope.
+        if self.consume(b"?_R0") {
+            let storage_class = self.read_storage_class_for_return()?;
+            let symbol_type = self.read_var_type(storage_class)?;
+            self.expect(b"@8")?;
+            return Ok(ParseResult {
+                symbol: Symbol::new(Name::Operator(Operator::RTTITypeDescriptor), Vec::new()),
+                symbol_type,
+                symbol_kind: SymbolKind::Rtti,
+                variable_kind: None,
+                thunk_adjustment: None,
+                names: Vec::new(),
+            });
         }
 
-        match &names.name {
-            &Name::Operator(ref op) => {
-                match op {
-                    &Operator::Ctor => {
-                        let prev = names.scope.names.iter().nth(0).expect(
-                            "If there's a ctor, there should be another name in this sequence",
-                        );
-                        self.write_one_name(prev)?;
-                    }
-                    &Operator::Dtor => {
-                        let prev = names.scope.names.iter().nth(0).expect(
-                            "If there's a dtor, there should be another name in this sequence",
-                        );
-                        write!(self.w, "~")?;
-                        self.write_one_name(prev)?;
-                    }
-                    &Operator::VBTable => {
-                        write!(self.w, "{}", "`vbtable'{for `")?;
-                        // The rest will be written by write_post of the
-                        // symbol type.
-                    }
-                    _ => {
-                        if self.flags == DemangleFlags::LotsOfWhitespace {
-                            self.write_space()?;
-                        }
-                        // Print out an overloaded operator.
-                        self.write_operator_name(op)?;
+        // What follows is a main symbol name. This may include
+        // namespaces or class names.
+        let symbol = self.read_name(true)?;
+        self.parse_symbol_type(symbol, events)
+    }
+
+    // Like parse(), but skips the type of the symbol unless the name is
+    // made of it, as for conversion operators.
+    fn parse_name(&mut self) -> Result<ParseResult<'a>> {
+        // The names of RTTI descriptors are followed by the type or offsets
+        // they describe, which read_name() doesn't know about.
+        if self.input.starts_with(b"?$") || self.input.starts_with(b"??_R") {
+            return self.parse();
+        }
+        if !self.consume(b"?") {
+            return Err(Error::new("does not start with b'?'".to_owned()));
+        }
+        let symbol = self.read_name(true)?;
+        // Conversion operators are named by their return type, and special
+        // names such as vftables are written depending on their type.
+        if symbol.name.is_conversion_operator() || symbol.name.is_special_name() {
+            return self.parse_symbol_type(symbol, &mut None);
+        }
+        Ok(ParseResult {
+            symbol,
+            symbol_type: Type::None,
+            symbol_kind: SymbolKind::Other,
+            variable_kind: None,
+            thunk_adjustment: None,
+            names: Vec::new(),
+        })
+    }
+
+    // Reads what follows the name of a symbol: the type of a variable or
+    // function, or what kind of table or string it is.
+    fn parse_symbol_type(
+        &mut self,
+        mut symbol: Symbol<'a>,
+        events: &mut Option<&mut dyn FnMut(Event)>,
+    ) -> Result<ParseResult<'a>> {
+        if let Ok(c) = self.get() {
+            let mut symbol_kind = SymbolKind::Function;
+            let mut variable_kind = None;
+            let mut thunk_adjustment = None;
+            let symbol_type = match c {
+                b'0'..=b'5' => {
+                    // Read a variable.
+                    let kind = match c {
+                        b'0' => VariableKind::StaticMember(Access::Private),
+                        b'1' => VariableKind::StaticMember(Access::Protected),
+                        b'2' => VariableKind::StaticMember(Access::Public),
+                        b'4' => VariableKind::LocalStatic,
+                        _ => VariableKind::Global,
+                    };
+                    symbol_kind = match kind {
+                        VariableKind::StaticMember(_) => SymbolKind::StaticMemberVariable,
+                        VariableKind::LocalStatic => SymbolKind::LocalStaticVariable,
+                        VariableKind::Global => SymbolKind::GlobalVariable,
+                    };
+                    variable_kind = Some(kind);
+                    let var_type = self.read_var_type(StorageClass::empty())?;
+                    // Skip the qualifiers of the variable itself, e.g. "EA"
+                    // for a 64-bit pointer.
+                    while self.consume(b"E") || self.consume(b"F") || self.consume(b"I") {}
+                    self.read_qualifier();
+                    var_type
+                }
+                b'6' => {
+                    symbol_kind = SymbolKind::VTable;
+                    let access_class = self.read_qualifier();
+                    let scope = self.read_scope()?;
+                    Type::CXXVFTable(scope, access_class)
+                }
+                b'7' => {
+                    symbol_kind = SymbolKind::VBTable;
+                    let access_class = self.read_qualifier();
+                    let scope = self.read_scope()?;
+                    Type::CXXVBTable(scope, access_class)
+                }
+                b'8' => {
+                    // RTTI data, whose type is implied by the name.
+                    symbol_kind = SymbolKind::Rtti;
+                    Type::None
+                }
+                b'9' => {
+                    // extern "C" names have their class and type omitted.
+                    symbol_kind = SymbolKind::Other;
+                    Type::None
+                }
+                b'Y' => {
+                    // Read a non-member function.
+                    let calling_conv = self.read_calling_conv()?;
+                    let storage_class = self.read_storage_class_for_return()?;
+                    let begin = Event::BeginFunction(None, calling_conv, StorageClass::empty());
+                    let mut events = self.begin_function(events, &symbol, symbol_kind, begin);
+                    let return_type = self.read_var_type(storage_class)?;
+                    let params = self.read_symbol_params(&mut events, &return_type)?;
+                    Type::NonMemberFunction(calling_conv, params, StorageClass::empty(), Box::new(return_type))
+                }
+                b'_' => {
+                    // Read an encoded string.
+                    symbol_kind = SymbolKind::StringLiteral;
+                    let char_bytes = match self.get()? {
+                        b'0' => 1, // char
+                        b'1' => 2, // wchar_t
+                        _ => {
+                            return Err(Error::new("unknown string character type".to_owned()));
+                        },
+                    };
+                    self.read_encoded_string(char_bytes)?
+                }
+                c => {
+                    // Read a member function.
+                    let (func_class, adjustment) = self.read_func_class(c)?;
+                    if func_class.contains(FuncClass::THUNK) {
+                        symbol_kind = SymbolKind::Thunk;
                     }
+                    thunk_adjustment = adjustment;
+                    let access_class = if func_class.contains(FuncClass::STATIC) {
+                        StorageClass::empty()
+                    } else {
+                        let ptr64 = if self.consume(b"E") {
+                            StorageClass::PTR64
+                        } else {
+                            StorageClass::empty()
+                        };
+                        let restrict = if self.consume(b"I") {
+                            StorageClass::RESTRICT
+                        } else {
+                            StorageClass::empty()
+                        };
+                        let ref_qualifiers = match self.peek() {
+                            Some(b'G') => {
+                                self.expect(b"G")?;
+                                StorageClass::LVALUE_QUAL
+                            },
+                            Some(b'H') => {
+                                self.expect(b"H")?;
+                                StorageClass::RVALUE_QUAL
+                            },
+                            _ => StorageClass::empty(),
+                        };
+                        self.read_qualifier() | ptr64 | restrict | ref_qualifiers
+                    };
+
+                    let calling_conv = self.read_calling_conv()?;
+                    let storage_class_for_return = self.read_storage_class_for_return()?;
+                    let begin = Event::BeginFunction(Some(func_class), calling_conv, access_class);
+                    let mut events = self.begin_function(events, &symbol, symbol_kind, begin);
+                    let return_type = self.read_func_return_type(storage_class_for_return)?;
+                    let params = self.read_symbol_params(&mut events, &return_type)?;
+                    Type::MemberFunction(func_class, calling_conv, params, access_class, Box::new(return_type))
                 }
+            };
+            if let Some(target) = symbol_type.return_type() {
+                self.set_conversion_target(&mut symbol.name, target);
             }
-            &Name::NonTemplate(ref name) => {
-                self.w.write(name)?;
-            }
-            &Name::Template(ref name, ref params) => {
-                self.write_one_name(name)?;
-                self.write_tmpl_params(&params)?;
-            }
-            &Name::Discriminator(ref val) => {
-                write!(self.w, "`{}'", val)?;
-            }
-            &Name::ParsedName(ref val) => {
-                write!(self.w, "{}", serialize(val, self.flags).unwrap())?;
-            }
-            &Name::AnonymousNamespace => {
-                panic!("not supposed to be here");
-            }
+            Ok(ParseResult {
+                symbol_kind: special_symbol_kind(&symbol.name, symbol_kind),
+                symbol,
+                symbol_type,
+                variable_kind,
+                thunk_adjustment,
+                names: Vec::new(),
+            })
+        } else {
+            Ok(ParseResult {
+                symbol,
+                symbol_type: Type::None,
+                symbol_kind: SymbolKind::Other,
+                variable_kind: None,
+                thunk_adjustment: None,
+                names: Vec::new(),
+            })
         }
-        Ok(())
     }
 
-    fn write_tmpl_params<'b>(&mut self, params: &Params<'b>) -> SerializeResult<()> {
-        let types = if let Some(&Type::EmptyParameterPack) = params.types.last() {
-            &params.types[0..params.types.len()-1]
-        } else {
-            &params.types
+    // Starts streaming the function symbol `symbol` to `events`, if there,
+    // with the events before its return type. Takes the callback out of
+    // `events` to mark the symbol as streamed.
+    fn begin_function<'e>(
+        &self,
+        events: &mut Option<&'e mut dyn FnMut(Event)>,
+        symbol: &Symbol<'a>,
+        symbol_kind: SymbolKind,
+        begin: Event,
+    ) -> Option<&'e mut dyn FnMut(Event)> {
+        let callback = events.take()?;
+        let mut emitter = EventEmitter {
+            names: &self.names,
+            callback: &mut *callback,
         };
+        emitter.emit(Event::BeginSymbol(special_symbol_kind(&symbol.name, symbol_kind)));
+        emitter.qualified_name(symbol);
+        emitter.emit(begin);
+        Some(callback)
+    }
 
-        write!(self.w, "<")?;
-        if !types.is_empty() {
-            self.write_types(types)?;
-            if let Some(&b'>') = self.w.last() {
-                write!(self.w, " ")?;
+    // Reads the parameters of a function symbol. If the symbol is
+    // streamed, they are passed on after `return_type` as they are read
+    // instead, followed by the end of the symbol.
+    fn read_symbol_params(
+        &mut self,
+        events: &mut Option<&mut dyn FnMut(Event)>,
+        return_type: &Type<'a>,
+    ) -> Result<Params<'a>> {
+        let callback = match *events {
+            Some(ref mut callback) => callback,
+            None => return self.read_func_params(),
+        };
+        let mut emitter = EventEmitter {
+            names: &self.names,
+            callback: &mut **callback,
+        };
+        emitter.type_(return_type);
+        emitter.emit(Event::BeginParams);
+        self.read_func_params_with(|this, param| {
+            let mut emitter = EventEmitter {
+                names: &this.names,
+                callback: &mut **callback,
+            };
+            emitter.type_(&param);
+        })?;
+        for event in &[Event::EndParams, Event::EndType, Event::EndSymbol] {
+            callback(*event);
+        }
+        Ok(Params { types: Vec::new() })
+    }
+
+    // Records `target` as the type converted to if `name` is a conversion
+    // operator.
+    fn set_conversion_target(&mut self, name: &mut Name<'a>, target: &Type<'a>) {
+        match *name {
+            Name::Operator(Operator::Conversion(ref mut to)) => *to = Some(Box::new(target.clone())),
+            Name::Template(ref mut name, _) => self.set_conversion_target(name, target),
+            Name::Backref(i) if i < self.names.len() => {
+                let mut name = mem::replace(&mut self.names[i], Name::AnonymousNamespace);
+                self.set_conversion_target(&mut name, target);
+                self.names[i] = name;
             }
+            _ => {}
         }
-        write!(self.w, ">")?;
-        Ok(())
     }
-}
 
-// grammar from MicrosoftMangle.cpp:
+    fn peek(&self) -> Option<u8> {
+        self.input.first().cloned()
+    }
 
-// <mangled-name> ::= ? <name> <type-encoding>
-// <name> ::= <unscoped-name> {[<named-scope>]+ | [<nested-name>]}? @
-// <unqualified-name> ::= <operator-name>
-//                    ::= <ctor-dtor-name>
-//                    ::= <source-name>
-//                    ::= <template-name>
-// <operator-name> ::= ???
-//                 ::= ?B # cast, the target type is encoded as the return type.
-// <source-name> ::= <identifier> @
-//
-// mangleNestedName: calls into mangle, which is responsible for <mangled-name>, and into mangleUnqualifiedName
-// <postfix> ::= <unqualified-name> [<postfix>]
-//           ::= <substitution> [<postfix>]
-//
-// <template-name> ::= <unscoped-template-name> <template-args>
-//                 ::= <substitution>
-// <unscoped-template-name> ::= ?$ <unqualified-name>
-// <type-encoding> ::= <function-class> <function-type>
-//                 ::= <storage-class> <variable-type>
-// <function-class>  ::= <member-function> E? # E designates a 64-bit 'this'
-//                                            # pointer. in 64-bit mode *all*
-//                                            # 'this' pointers are 64-bit.
-//                   ::= <global-function>
-// <function-type> ::= <this-cvr-qualifiers> <calling-convention>
-//                     <return-type> <argument-list> <throw-spec>
-// <member-function> ::= A # private: near
-//                   ::= B # private: far
-//                   ::= C # private: static near
-//                   ::= D # private: static far
-//                   ::= E # private: near
-//                   ::= F # private: far
-//                   ::= I # near
-//                   ::= J # far
-//                   ::= K # static near
-//                   ::= L # static far
-//                   ::= M # near
-//                   ::= N # far
-//                   ::= Q # near
-//                   ::= R # far
-//                   ::= S # static near
-//                   ::= T # static far
-//                   ::= U # near
-//                   ::= V # far
-// <global-function> ::= Y # global near
-//                   ::= Z # global far
-// <storage-class> ::= 0  # private static member
-//                 ::= 1  # protected static member
-//                 ::= 2  # public static member
-//                 ::= 3  # global
-//                 ::= 4  # static local
+    fn get(&mut self) -> Result<u8> {
+        match self.peek() {
+            Some(first) => {
+                self.trim(1);
+                Ok(first)
+            }
+            None => Err(Error::new("unexpected end of input".to_owned())),
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    fn expect_with_flags(input: &str, reference: &str, flags: ::DemangleFlags) {
-        let demangled: ::Result<_> = ::demangle(input, flags);
-        let reference: ::Result<_> = Ok(reference.to_owned());
-        assert_eq!(demangled, reference);
+    fn consume(&mut self, s: &[u8]) -> bool {
+        if self.input.starts_with(s) {
+            self.trim(s.len());
+            true
+        } else {
+            false
+        }
     }
 
-    // For cases where undname demangles differently/better than we do.
-    fn expect_undname_failure(input: &str, reference: &str) {
-        let demangled: ::Result<_> = ::demangle(input, ::DemangleFlags::LotsOfWhitespace);
-        let reference: ::Result<_> = Ok(reference.to_owned());
-        assert_ne!(demangled, reference);
+    fn trim(&mut self, len: usize) {
+        self.input = &self.input[len..]
     }
-    // std::basic_filebuf<char,struct std::char_traits<char> >::basic_filebuf<char,struct std::char_traits<char> >
-    // std::basic_filebuf<char,struct std::char_traits<char> >::"operator ctor"
-    // "operator ctor" = ?0
 
-    #[test]
-    fn other_tests() {
-        let expect = |input, reference| {
-            expect_with_flags(input, reference, ::DemangleFlags::LotsOfWhitespace);
-        };
+    fn expect(&mut self, s: &[u8]) -> Result<()> {
+        if !self.consume(s) {
+            return Err(Error::new(format!(
+                "{} expected, but got {}",
+                String::from_utf8_lossy(s),
+                String::from_utf8_lossy(self.input)
+            )));
+        }
+        Ok(())
+    }
 
-        expect("?f@@YAHQBH@Z", "int __cdecl f(int const * const)");
-        expect("?f@@YA_WQB_W@Z", "wchar_t __cdecl f(wchar_t const * const)");
-        expect("?f@@YA_UQB_U@Z", "char32_t __cdecl f(char32_t const * const)");
-        expect("?f@@YA_SQB_S@Z", "char16_t __cdecl f(char16_t const * const)");
-        expect("?g@@YAHQAY0EA@$$CBH@Z", "int __cdecl g(int const (* const)[64])");
-        expect(
-            "??0Klass@std@@AEAA@AEBV01@@Z",
-            "private: __cdecl std::Klass::Klass(class std::Klass const &)",
-        );
-        expect("??0?$Klass@V?$Mass@_N@@@std@@QEAA@AEBV01@@Z",
-               "public: __cdecl std::Klass<class Mass<bool> >::Klass<class Mass<bool> >(class std::Klass<class Mass<bool> > const &)");
-        expect("??$load@M@UnsharedOps@js@@SAMV?$SharedMem@PAM@@@Z",
-               "public: static float __cdecl js::UnsharedOps::load<float>(class SharedMem<float *>)");
+    fn consume_digit(&mut self) -> Option<u8> {
+        match self.peek() {
+            Some(first) => {
+                if first.is_ascii_digit() {
+                    self.trim(1);
+                    Some(first - b'0')
+                } else {
+                    None
+                }
+            }
+            None => None,
+        }
+    }
 
-        expect("?cached@?1??GetLong@BinaryPath@mozilla@@SA?AW4nsresult@@QA_W@Z@4_NA",
-               "bool `public: static enum nsresult __cdecl mozilla::BinaryPath::GetLong(wchar_t * const)\'::`2\'::cached");
-        expect("??0?$A@_K@B@@QAE@$$QAV01@@Z",
-               "public: __thiscall B::A<uint64_t>::A<uint64_t>(class B::A<uint64_t> &&)");
-        expect("??_7nsI@@6B@",
-               "const nsI::`vftable\'");
-        expect(
-            "??_7W@?A@@6B@",
-            "const `anonymous namespace`::W::`vftable'",
-        );
-        expect(
-            "??_7?$RunnableMethodImpl@PEAVLazyIdleThread@mozilla@@P812@EAAXXZ$0A@$0A@$$V@detail@mozilla@@6BnsIRunnable@@@",
-            "const mozilla::detail::RunnableMethodImpl<class mozilla::LazyIdleThread *,void __cdecl (mozilla::LazyIdleThread::*)(void),0,0>::`vftable\'{for `nsIRunnable\'}",
-        );
-        expect_undname_failure(
-            "??_7?$RunnableMethodImpl@PEAVLazyIdleThread@mozilla@@P812@EAAXXZ$0A@$0A@$$V@detail@mozilla@@6BnsIRunnable@@@",
-            "const mozilla::detail::RunnableMethodImpl<class mozilla::LazyIdleThread * __ptr64,void __cdecl (mozilla::LazyIdleThread::*)(void) __ptr64,0,0>::`vftable\'{for `nsIRunnable\'}",
-        );
-        expect("??1?$ns@$$CBVtxXP@@@@QAE@XZ",
-               "public: __thiscall ns<class txXP const>::~ns<class txXP const>(void)");
-        /* XXX: undname prints void (__thiscall*)(void *) for the parameter type. */
-        expect(
-            "??_I@YGXPAXIIP6EX0@Z@Z",
-            "void __stdcall `vector destructor iterator'(void *,unsigned int,unsigned int,void __thiscall (*)(void *))",
-        );
-        expect(
-            "??_GnsWindowsShellService@@EAEPAXI@Z",
-            "private: virtual void * __thiscall nsWindowsShellService::`scalar deleting destructor'(unsigned int)",
-        );
-        expect(
-            "??1?$nsAutoPtr@$$CBVtxXPathNode@@@@QAE@XZ",
-            "public: __thiscall nsAutoPtr<class txXPathNode const>::~nsAutoPtr<class txXPathNode const>(void)",
-        );
-        expect(
-            "??_EPrintfTarget@mozilla@@MAEPAXI@Z",
-            "protected: virtual void * __thiscall mozilla::PrintfTarget::`vector deleting destructor'(unsigned int)",
-        );
-        expect(
-            "??_GDynamicFrameEventFilter@?A0xcdaa5fa8@@AAEPAXI@Z",
-            "private: void * __thiscall `anonymous namespace`::DynamicFrameEventFilter::`scalar deleting destructor\'(unsigned int)",
-        );
-        /* XXX: undname tacks on `adjustor{16}` to the name. */
-        expect(
-            "?Release@ContentSignatureVerifier@@WBA@AGKXZ",
-            "[thunk]:public: virtual unsigned long __stdcall ContentSignatureVerifier::Release(void)",
-        );
-        expect(
-            "??$new_@VWatchpointMap@js@@$$V@?$MallocProvider@UZone@JS@@@js@@QAEPAVWatchpointMap@1@XZ",
-            "public: class js::WatchpointMap * __thiscall js::MallocProvider<struct JS::Zone>::new_<class js::WatchpointMap>(void)",
-        );
-        expect(
-            "??$templ_fun_with_ty_pack@$$V@@YAXXZ",
-            "void __cdecl templ_fun_with_ty_pack<>(void)",
-        );
-        expect(
-            "??4?$RefPtr@VnsRange@@@@QAEAAV0@$$T@Z",
-            "public: class RefPtr<class nsRange> & __thiscall RefPtr<class nsRange>::operator=(std::nullptr_t)",
-        );
-        expect(
-            "??1?$function@$$A6AXXZ@std@@QAE@XZ",
-            "public: __thiscall std::function<void __cdecl (void)>::~function<void __cdecl (void)>(void)",
-        );
-        expect_undname_failure(
-            "??1?$function@$$A6AXXZ@std@@QAE@XZ",
-            "public: __thiscall std::function<void __cdecl(void)>::~function<void __cdecl(void)>(void)",
-        );
-        // Not great (`operatorcast`, space at the end), but at least make sure we don't regress.
-        expect(
-            "??B?$function@$$A6AXXZ@std@@QBE_NXZ",
-            "public: bool __thiscall std::function<void __cdecl (void)>::operatorcast(void)const ",
-        );
-        expect_undname_failure(
-            "??B?$function@$$A6AXXZ@std@@QBE_NXZ",
-            "public: __thiscall std::function<void __cdecl(void)>::operator bool(void)const",
-        );
-        expect(
-            "??$?RA6AXXZ$$V@SkOnce@@QAEXA6AXXZ@Z",
-            "public: void __thiscall SkOnce::operator()<void __cdecl (&)(void)>(void __cdecl (&)(void))",
-        );
-        expect_undname_failure(
-            "??$?RA6AXXZ$$V@SkOnce@@QAEXA6AXXZ@Z",
-            "public: void __thiscall SkOnce::operator()<void (__cdecl&)(void)>(void (__cdecl&)(void))",
-        );
-        expect(
-            "?foo@A@PR19361@@QIHAEXXZ",
-            "public: void __thiscall PR19361::A::foo(void)__restrict && ",
-        );
-        expect_undname_failure(
-            "?foo@A@PR19361@@QIHAEXXZ",
-            "public: void __thiscall PR19361::A::foo(void) __restrict&& ",
-        );
-        expect(
-            "??$GenericCreateConstructor@$1?construct@SetObject@js@@CA_NPEAUJSContext@@IPEATValue@JS@@@Z$0A@$0A@$0A@@js@@YAPEAVJSObject@@PEAUJSContext@@W4JSProtoKey@@@Z",
-            "class JSObject * __cdecl js::GenericCreateConstructor<bool __cdecl (js::SetObject::construct::*)(struct JSContext *,unsigned int,union JS::Value *),0,0,0>(struct JSContext *,enum JSProtoKey)",
-        );
-        expect_undname_failure(
-            "??$GenericCreateConstructor@$1?construct@SetObject@js@@CA_NPEAUJSContext@@IPEATValue@JS@@@Z$0A@$0A@$0A@@js@@YAPEAVJSObject@@PEAUJSContext@@W4JSProtoKey@@@Z",
-            "class JSObject * __ptr64 __cdecl js::GenericCreateConstructor<&private: static bool __cdecl (js::SetObject::construct::*)(struct JSContext * __ptr64,unsigned int,union JS::Value * __ptr64),0,0,0>(struct JSContext * __ptr64,enum JSProtoKey)",
-        );
-        expect(
-            "??$emplace_hint@AEBUpiecewise_construct_t@std@@V?$tuple@AEBH@2@V?$tuple@$$V@2@@?$_Tree@V?$_Tmap_traits@HUPayload@RtpUtility@webrtc@@U?$less@H@std@@V?$allocator@U?$pair@$$CBHUPayload@RtpUtility@webrtc@@@std@@@5@$0A@@std@@@std@@QEAA?AV?$_Tree_iterator@V?$_Tree_val@U?$_Tree_simple_types@U?$pair@$$CBHUPayload@RtpUtility@webrtc@@@std@@@std@@@std@@@1@V?$_Tree_const_iterator@V?$_Tree_val@U?$_Tree_simple_types@U?$pair@$$CBHUPayload@RtpUtility@webrtc@@@std@@@std@@@std@@@1@AEBUpiecewise_construct_t@1@$$QEAV?$tuple@AEBH@1@$$QEAV?$tuple@$$V@1@@Z",
-            "public: class std::_Tree_iterator<class std::_Tree_val<struct std::_Tree_simple_types<struct std::pair<int const,struct webrtc::RtpUtility::Payload> > > > __cdecl std::_Tree<class std::_Tmap_traits<int,struct webrtc::RtpUtility::Payload,struct std::less<int>,class std::allocator<struct std::pair<int const,struct webrtc::RtpUtility::Payload> >,0> >::emplace_hint<struct std::piecewise_construct_t const &,class std::tuple<int const &>,class std::tuple<> >(class std::_Tree_const_iterator<class std::_Tree_val<struct std::_Tree_simple_types<struct std::pair<int const,struct webrtc::RtpUtility::Payload> > > >,struct std::piecewise_construct_t const &,class std::tuple<int const &> &&,class std::tuple<> &&)",
-        );
-        expect(
-            "?_OptionsStorage@?1??__local_stdio_scanf_options@@9@9",
-            "`__local_stdio_scanf_options'::`2'::_OptionsStorage",
-        );
+    fn consume_hex_digit(&mut self) -> bool {
+        match self.peek() {
+            Some(first) if first.is_ascii_hexdigit() => {
+                self.trim(1);
+                true
+            }
+            _ => false,
+        }
     }
 
-    #[test]
-    fn test_strings() {
-        let expect = |input, reference| {
-            expect_with_flags(input, reference, ::DemangleFlags::LotsOfWhitespace);
-        };
+    fn read_encoded_string(&mut self, char_bytes: i32) -> Result<Type<'a>> {
+        self.traced("string literal", |this| this.read_encoded_string_untraced(char_bytes))
+    }
 
-        // Test symbols extracted from clang's test/CodeGenCXX/mangle-ms-string-literals.cpp.
-        // Even though we don't print the encoded strings, these tests
-        // exhaustively cover all the cases we'll run into.
+    fn read_encoded_string_untraced(&mut self, char_bytes: i32) -> Result<Type<'a>> {
+        let byte_length = self.read_number()?; // including null terminator
+        let _crc = self.read_number()?;
+        let bytes = min(byte_length, char_bytes * 32);
 
-        // Single-byte characters.
-        expect("??_C@_01CNACBAHC@?$PP?$AA@", "`string'");
-        expect("??_C@_01DEBJCBDD@?$PO?$AA@", "`string'");
-        expect("??_C@_01BPDEHCPA@?$PN?$AA@", "`string'");
-        expect("??_C@_01GCPEDLB@?$PM?$AA@", "`string'");
-        expect("??_C@_01EJGONFHG@?$PL?$AA@", "`string'");
-        expect("??_C@_01FAHFOEDH@?z?$AA@", "`string'");
-        expect("??_C@_01HLFILHPE@?y?$AA@", "`string'");
-        expect("??_C@_01GCEDIGLF@?x?$AA@", "`string'");
-        expect("??_C@_01OFNLJKHK@?w?$AA@", "`string'");
-        expect("??_C@_01PMMAKLDL@?v?$AA@", "`string'");
-        expect("??_C@_01NHONPIPI@?u?$AA@", "`string'");
-        expect("??_C@_01MOPGMJLJ@?t?$AA@", "`string'");
-        expect("??_C@_01IBLHFPHO@?s?$AA@", "`string'");
-        expect("??_C@_01JIKMGODP@?r?$AA@", "`string'");
-        expect("??_C@_01LDIBDNPM@?q?$AA@", "`string'");
-        expect("??_C@_01KKJKAMLN@?p?$AA@", "`string'");
-        expect("??_C@_01GHMAACCD@?o?$AA@", "`string'");
-        expect("??_C@_01HONLDDGC@?n?$AA@", "`string'");
-        expect("??_C@_01FFPGGAKB@?m?$AA@", "`string'");
-        expect("??_C@_01EMONFBOA@?l?$AA@", "`string'");
-        expect("??_C@_01DKMMHCH@?k?$AA@", "`string'");
-        expect("??_C@_01BKLHPGGG@?j?$AA@", "`string'");
-        expect("??_C@_01DBJKKFKF@?i?$AA@", "`string'");
-        expect("??_C@_01CIIBJEOE@?h?$AA@", "`string'");
-        expect("??_C@_01KPBJIICL@?g?$AA@", "`string'");
-        expect("??_C@_01LGACLJGK@?f?$AA@", "`string'");
-        expect("??_C@_01JNCPOKKJ@?e?$AA@", "`string'");
-        expect("??_C@_01IEDENLOI@?d?$AA@", "`string'");
-        expect("??_C@_01MLHFENCP@?c?$AA@", "`string'");
-        expect("??_C@_01NCGOHMGO@?b?$AA@", "`string'");
-        expect("??_C@_01PJEDCPKN@?a?$AA@", "`string'");
-        expect("??_C@_01OAFIBOOM@?$OA?$AA@", "`string'");
-        expect("??_C@_01LIIGDENA@?$NP?$AA@", "`string'");
-        expect("??_C@_01KBJNAFJB@?$NO?$AA@", "`string'");
-        expect("??_C@_01IKLAFGFC@?$NN?$AA@", "`string'");
-        expect("??_C@_01JDKLGHBD@?$NM?$AA@", "`string'");
+        let mut collected = vec!();
+        for _i in 0..bytes {
+            let c = self.get()?;
+            let byte: u8 = match c {
+                b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'_' | b'$' => {
+                    c
+                }
+                b'?' => {
+                    let c = self.get()?;
+                    match c {
+                        b'A'..=b'Z' => {
+                            c - b'A' + 0xe1
+                        }
+                        b'a'..=b'z' => {
+                            c - b'A' + 0xc1
+                        }
+                        b'0'..=b'9' => {
+                            let v = b",/\\:. \n\t'-";
+                            v[(c - b'0') as usize]
+                        }
+                        b'$' => match (self.get()?, self.get()?) {
+                            (high @ b'A'..=b'P', low @ b'A'..=b'P') => (high - b'A') << 4 | (low - b'A'),
+                            _ => {
+                                return Err(Error::new(
+                                    "bad escaped encoded string character".to_owned(),
+                                ))
+                            }
+                        },
+                        _ => {
+                            return Err(Error::new(format!(
+                                "unknown escaped encoded string character {}",
+                                char::from(c))));
+                        }
+                    }
+                }
+                _ => {
+                    return Err(Error::new(format!(
+                        "unknown escaped encoded string character {}",
+                        char::from(c))));
+                }
+            };
+            collected.push(byte);
+        }
+
+        Ok(Type::ConstantString(collected))
+    }
+
+    // Sometimes numbers are encoded in mangled symbols. For example,
+    // "int (*x)[20]" is a valid C type (x is a pointer to an array of
+    // length 20), so we need some way to embed numbers as part of symbols.
+    // This function parses it.
+    //
+    // <number>               ::= [?] <non-negative integer>
+    //
+    // <non-negative integer> ::= <decimal digit> # when 1 <= Number <= 10
+    //                        ::= <hex digit>+ @  # when Numbrer == 0 or >= 10
+    //
+    // <hex-digit>            ::= [A-P]           # A = 0, B = 1, ...
+    fn read_number(&mut self) -> Result<i32> {
+        self.traced("number", |this| this.read_number_untraced())
+    }
+
+    fn read_number_untraced(&mut self) -> Result<i32> {
+        let neg = self.consume(b"?");
+
+        if let Some(digit) = self.consume_digit() {
+            let ret = digit + 1;
+            return Ok(if neg { -(ret as i32) } else { ret as i32 });
+        }
+
+        let len = self.input.iter().position(|c| !matches!(c, b'A'..=b'P')).unwrap_or(self.input.len());
+        if self.input.get(len) != Some(&b'@') {
+            return Err(Error::new(format!("bad number: {}", String::from_utf8_lossy(self.input))));
+        }
+        // Larger numbers silently wrap around.
+        let ret = self.input[..len].iter().fold(0i32, |ret, c| (ret << 4) | (c - b'A') as i32);
+        self.trim(len + 1);
+        Ok(if neg { ret.wrapping_neg() } else { ret })
+    }
+
+    // Read until the next b'@'.
+    fn read_string(&mut self) -> Result<&'a str> {
+        self.traced("identifier", |this| this.read_string_untraced())
+    }
+
+    fn read_string_untraced(&mut self) -> Result<&'a str> {
+        if let Some(pos) = memchr::memchr(b'@', self.input) {
+            // `input` is always a suffix of `source`, and b'@' can't be part
+            // of a multi-byte character, so this is on character boundaries.
+            let start = self.source.len() - self.input.len();
+            let ret = &self.source[start..start + pos];
+            self.trim(pos + 1);
+            Ok(ret)
+        } else {
+            let error = format!("read_string: missing b'@': {}", String::from_utf8_lossy(self.input));
+            Err(Error::new(error))
+        }
+    }
+
+    // First 10 strings can be referenced by special names ?0, ?1, ..., ?9.
+    // Memorize it. Template names are moved to `names` and replaced by a
+    // Name::Backref, which is returned.
+    fn memorize_name(&mut self, n: Name<'a>, height: usize) -> Name<'a> {
+        let n = match n {
+            Name::Template(..) if self.intern => self.intern_name(n),
+            n => n,
+        };
+        // TODO: the contains check does an equality check on the Name enum, which
+        // might do unexpected things in subtle cases. It's not a pure string equality check.
+        if self.memorized_names.len() < 10 && !self.memorized_names.iter().any(|m| m.0 == n) {
+            self.memorized_names.push((n.clone(), height));
+        }
+        n
+    }
+
+    // Returns a Name::Backref to `n` in `names`, adding it if it isn't
+    // there yet. Equal names get the same index, so back-references can be
+    // compared by their index.
+    fn intern_name(&mut self, n: Name<'a>) -> Name<'a> {
+        if let Some(&i) = self.name_ids.get(&n) {
+            return Name::Backref(i);
+        }
+        let i = self.names.len();
+        self.name_ids.insert(n.clone(), i);
+        self.names.push(n);
+        Name::Backref(i)
+    }
+    fn memorize_type(&mut self, t: &Type<'a>, nodes: usize, height: usize) {
+        // TODO: the contains check does an equality check on the Type enum, which
+        // might do unexpected things in subtle cases. It's not a pure string equality check.
+        if self.memorized_types.len() < 10 && !self.memorized_types.iter().any(|m| *m.0 == *t) {
+            self.memorized_types.push((Arc::new(t.clone()), nodes, height));
+        }
+    }
+
+    // A back-reference to the memorized type `n`, placed below `depth`,
+    // whose nodes and levels count again even though they are shared
+    // rather than copied.
+    fn memorized_type(&mut self, n: usize, depth: usize) -> Result<Type<'a>> {
+        let (nodes, height) = match self.memorized_types.get(n) {
+            Some(&(_, nodes, height)) => (nodes, height),
+            None => return Err(Error::new(format!("invalid backreference: {}", n))),
+        };
+        self.add_nodes(nodes)?;
+        self.reach(depth, height)?;
+        Ok(Type::Shared(Arc::clone(&self.memorized_types[n].0)))
+    }
+
+    fn read_template_name(&mut self) -> Result<Name<'a>> {
+        self.traced("template name", |this| this.nested(ParserState::read_template_name_unchecked))
+    }
+
+    fn read_template_name_unchecked(&mut self) -> Result<Name<'a>> {
+        // Templates have their own context for backreferences.
+        let saved_memorized_names = mem::take(&mut self.memorized_names);
+        let saved_memorized_types = mem::take(&mut self.memorized_types);
+        // This may be an operator, as in ??$?DM@std@@YA?AV?$complex@M@0@ABMABV10@@Z,
+        // which is "std::operator*<float>".
+        let name = self.read_unqualified_name(false)?;
+        let template_params = self.read_params()?;
+        let _ = mem::replace(&mut self.memorized_names, saved_memorized_names);
+        let _ = mem::replace(&mut self.memorized_types, saved_memorized_types);
+        Ok(Name::Template(Box::new(name), template_params))
+    }
+
+    fn read_nested_name(&mut self) -> Result<Name<'a>> {
+        self.traced("scope component", |this| this.read_nested_name_untraced())
+    }
+
+    fn read_nested_name_untraced(&mut self) -> Result<Name<'a>> {
+        let orig = self.input;
+        let name = if let Some(i) = self.consume_digit() {
+            let i = i as usize;
+            if i >= self.memorized_names.len() {
+                return Err(Error::new(format!(
+                    "name reference too large: {}",
+                    String::from_utf8_lossy(orig)
+                )));
+            }
+            let (ref name, height) = self.memorized_names[i];
+            let name = name.clone();
+            self.reach(self.depth, height)?;
+            name
+        } else if self.consume(b"?") {
+            match self.peek() {
+                Some(b'?') => {
+                    Name::ParsedName(Box::new(self.nested(ParserState::parse)?))
+                },
+                _ => {
+                    if self.consume(b"$") {
+                        let (name, height) = self.measured(ParserState::read_template_name)?;
+                        self.memorize_name(name, height)
+                    } else if self.consume(b"A") {
+                        // Anonymous namespace.
+                        if self.consume(b"0x") {
+                            while self.consume_hex_digit() {
+                            }
+                        }
+                        self.expect(b"@")?;
+                        Name::AnonymousNamespace
+                    } else {
+                        let discriminator = self.read_number()?;
+                        Name::Discriminator(discriminator)
+                    }
+                }
+            }
+        } else {
+            // Non-template functions or classes.
+            let name = self.read_string()?;
+            self.memorize_name(Name::NonTemplate(Cow::Borrowed(name)), 0)
+        };
+        Ok(name)
+    }
+
+    fn read_unqualified_name(&mut self, function: bool) -> Result<Name<'a>> {
+        self.traced("unqualified name", |this| this.read_unqualified_name_untraced(function))
+    }
+
+    fn read_unqualified_name_untraced(&mut self, function: bool) -> Result<Name<'a>> {
+        let orig = self.input;
+        let name = if let Some(i) = self.consume_digit() {
+            let i = i as usize;
+            if i >= self.memorized_names.len() {
+                return Err(Error::new(format!(
+                    "name reference too large: {}",
+                    String::from_utf8_lossy(orig)
+                )));
+            }
+            let (ref name, height) = self.memorized_names[i];
+            let name = name.clone();
+            self.reach(self.depth, height)?;
+            name
+        } else if self.consume(b"?$") {
+            let (name, height) = self.measured(ParserState::read_template_name)?;
+            if function {
+                name
+            } else {
+                self.memorize_name(name, height)
+            }
+        } else if self.consume(b"?") {
+            // Overloaded operator.
+            self.read_operator()?
+        } else {
+            // Non-template functions or classes.
+            let name = self.read_string()?;
+            self.memorize_name(Name::NonTemplate(Cow::Borrowed(name)), 0)
+        };
+        Ok(name)
+    }
+
+    fn read_scope(&mut self) -> Result<NameSequence<'a>> {
+        self.traced("scope", |this| this.read_scope_untraced())
+    }
+
+    fn read_scope_untraced(&mut self) -> Result<NameSequence<'a>> {
+        let mut names = Vec::new();
+        while !self.consume(b"@") {
+            let name = self.read_nested_name()?;
+            names.push(name);
+        }
+        Ok(NameSequence { names })
+    }
+
+    // Parses a name in the form of A@B@C@@ which represents C::B::A.
+    fn read_name(&mut self, function: bool) -> Result<Symbol<'a>> {
+        self.traced("name", |this| this.read_name_untraced(function))
+    }
+
+    fn read_name_untraced(&mut self, function: bool) -> Result<Symbol<'a>> {
+        let name = self.read_unqualified_name(function)?;
+
+        Ok(Symbol{name, scope: self.read_scope()? })
+    }
+
+    fn read_func_type(&mut self) -> Result<Type<'a>> {
+        let calling_conv = self.read_calling_conv()?;
+        let return_type = self.read_var_type(StorageClass::empty())?;
+        let params = self.read_func_params()?;
+        Ok(Type::NonMemberFunction(calling_conv, params,
+                                   StorageClass::empty(),
+                                   Box::new(return_type)))
+    }
+
+    fn read_operator(&mut self) -> Result<Name<'a>> {
+        self.traced("operator", |this| this.read_operator_untraced())
+    }
+
+    fn read_operator_untraced(&mut self) -> Result<Name<'a>> {
+        let orig = self.input;
+        match self.read_operator_name() {
+            Ok(op) => Ok(Name::Operator(op)),
+            Err(err) => match self.extension(orig, |extensions, input| extensions.unknown_operator(input)) {
+                Some(name) => Ok(Name::NonTemplate(Cow::Owned(name))),
+                None => Err(err),
+            },
+        }
+    }
+
+    fn read_operator_name(&mut self) -> Result<Operator<'a>> {
+        let orig = self.input;
+
+        Ok(match self.get()? {
+            b'0' => Operator::Ctor,
+            b'1' => Operator::Dtor,
+            b'2' => Operator::New,
+            b'3' => Operator::Delete,
+            b'4' => Operator::Equal,
+            b'5' => Operator::RShift,
+            b'6' => Operator::LShift,
+            b'7' => Operator::Bang,
+            b'8' => Operator::EqualEqual,
+            b'9' => Operator::BangEqual,
+            b'A' => Operator::Subscript,
+            b'B' => Operator::Conversion(None),
+            b'C' => Operator::Arrow,
+            b'D' => Operator::Star,
+            b'E' => Operator::PlusPlus,
+            b'F' => Operator::MinusMinus,
+            b'G' => Operator::Minus,
+            b'H' => Operator::Plus,
+            b'I' => Operator::Amp,
+            b'J' => Operator::ArrowStar,
+            b'K' => Operator::Slash,
+            b'L' => Operator::Percent,
+            b'M' => Operator::Less,
+            b'N' => Operator::LessEqual,
+            b'O' => Operator::Greater,
+            b'P' => Operator::GreaterEqual,
+            b'Q' => Operator::Comma,
+            b'R' => Operator::Call,
+            b'S' => Operator::Tilde,
+            b'T' => Operator::Caret,
+            b'U' => Operator::Pipe,
+            b'V' => Operator::AmpAmp,
+            b'W' => Operator::PipePipe,
+            b'X' => Operator::StarEqual,
+            b'Y' => Operator::PlusEqual,
+            b'Z' => Operator::MinusEqual,
+            b'_' => match self.get()? {
+                b'0' => Operator::SlashEqual,
+                b'1' => Operator::PercentEqual,
+                b'2' => Operator::GreaterGreaterEqual,
+                b'3' => Operator::LessLessEqual,
+                b'4' => Operator::AmpEqual,
+                b'5' => Operator::PipeEqual,
+                b'6' => Operator::CaretEqual,
+                b'7' => Operator::VFTable,
+                b'8' => Operator::VBTable,
+                b'9' => Operator::VCall,
+                b'A' => Operator::Typeof,
+                b'B' => Operator::LocalStaticGuard,
+                b'C' => Operator::String,
+                b'D' => Operator::VBaseDtor,
+                b'E' => Operator::VectorDeletingDtor,
+                b'F' => Operator::DefaultCtorClosure,
+                b'G' => Operator::ScalarDeletingDtor,
+                b'H' => Operator::VectorCtorIterator,
+                b'I' => Operator::VectorDtorIterator,
+                b'J' => Operator::VectorVBaseCtorIterator,
+                b'K' => Operator::VirtualDisplacementMap,
+                b'L' => Operator::EHVectorCtorIterator,
+                b'M' => Operator::EHVectorDtorIterator,
+                b'N' => Operator::EHVectorVBaseCtorIterator,
+                b'O' => Operator::CopyCtorClosure,
+                b'S' => Operator::LocalVFTable,
+                b'T' => Operator::LocalVFTableCtorClosure,
+                b'U' => Operator::ArrayNew,
+                b'V' => Operator::ArrayDelete,
+                b'X' => Operator::PlacementDeleteClosure,
+                b'Y' => Operator::PlacementArrayDeleteClosure,
+                // The operator that follows is read once more, but not a
+                // third time, so that `_P_P...` can't nest without bound.
+                b'P' if !self.input.starts_with(b"_P") => {
+                    Operator::UdtReturning(Box::new(self.read_operator_name()?))
+                }
+                b'R' => match self.get()? {
+                    b'0' => Operator::RTTITypeDescriptor,
+                    b'1' => Operator::RTTIBaseClassDescriptor(
+                        self.read_number()?,
+                        self.read_number()?,
+                        self.read_number()?,
+                        self.read_number()?,
+                    ),
+                    b'2' => Operator::RTTIBaseClassArray,
+                    b'3' => Operator::RTTIClassHierarchyDescriptor,
+                    b'4' => Operator::RTTICompleteObjectLocator,
+                    _ => {
+                        return Err(Error::new(format!(
+                            "unknown operator name: {}",
+                            String::from_utf8_lossy(orig)
+                        )))
+                    }
+                },
+                b'_' => if self.consume(b"L") {
+                    Operator::CoroutineAwait
+                } else if self.consume(b"K") {
+                    Operator::LiteralOperator(Cow::Borrowed(self.read_string()?))
+                } else {
+                    return Err(Error::new(format!(
+                        "unknown operator name: {}",
+                        String::from_utf8_lossy(orig)
+                    )));
+                },
+                _ => {
+                    return Err(Error::new(format!(
+                        "unknown operator name: {}",
+                        String::from_utf8_lossy(orig)
+                    )))
+                }
+            },
+            _ => {
+                return Err(Error::new(format!(
+                    "unknown operator name: {}",
+                    String::from_utf8_lossy(orig)
+                )))
+            }
+        })
+    }
+
+    // Reads a function class, along with the `this` adjustment of thunks.
+    fn read_func_class(&mut self, c: u8) -> Result<(FuncClass, Option<i32>)> {
+        self.traced("function class", |this| this.read_func_class_untraced(c))
+    }
+
+    fn read_func_class_untraced(&mut self, c: u8) -> Result<(FuncClass, Option<i32>)> {
+        let mut adjustment = None;
+        let mut read_thunk = |func_class| -> Result<FuncClass> {
+            adjustment = Some(self.read_number()?);
+            Ok(func_class | FuncClass::THUNK)
+        };
+
+        let func_class = match c {
+            b'A' => FuncClass::PRIVATE,
+            b'B' => FuncClass::PRIVATE | FuncClass::FAR,
+            b'C' => FuncClass::PRIVATE | FuncClass::STATIC,
+            b'D' => FuncClass::PRIVATE | FuncClass::STATIC,
+            b'E' => FuncClass::PRIVATE | FuncClass::VIRTUAL,
+            b'F' => FuncClass::PRIVATE | FuncClass::VIRTUAL,
+            b'G' => read_thunk(FuncClass::PRIVATE | FuncClass::VIRTUAL)?,
+            b'H' => read_thunk(FuncClass::PRIVATE | FuncClass::VIRTUAL | FuncClass::FAR)?,
+            b'I' => FuncClass::PROTECTED,
+            b'J' => FuncClass::PROTECTED | FuncClass::FAR,
+            b'K' => FuncClass::PROTECTED | FuncClass::STATIC,
+            b'L' => FuncClass::PROTECTED | FuncClass::STATIC | FuncClass::FAR,
+            b'M' => FuncClass::PROTECTED | FuncClass::VIRTUAL,
+            b'N' => FuncClass::PROTECTED | FuncClass::VIRTUAL | FuncClass::FAR,
+            b'O' => read_thunk(FuncClass::PROTECTED | FuncClass::VIRTUAL)?,
+            b'P' => read_thunk(FuncClass::PROTECTED | FuncClass::VIRTUAL | FuncClass::FAR)?,
+            b'Q' => FuncClass::PUBLIC,
+            b'R' => FuncClass::PUBLIC | FuncClass::FAR,
+            b'S' => FuncClass::PUBLIC | FuncClass::STATIC,
+            b'T' => FuncClass::PUBLIC | FuncClass::STATIC | FuncClass::FAR,
+            b'U' => FuncClass::PUBLIC | FuncClass::VIRTUAL,
+            b'V' => FuncClass::PUBLIC | FuncClass::VIRTUAL | FuncClass::FAR,
+            b'W' => read_thunk(FuncClass::PUBLIC | FuncClass::VIRTUAL)?,
+            b'X' => read_thunk(FuncClass::PUBLIC | FuncClass::VIRTUAL | FuncClass::FAR)?,
+            b'Y' => FuncClass::GLOBAL,
+            b'Z' => FuncClass::GLOBAL | FuncClass::FAR,
+            _ => {
+                return Err(Error::new(format!(
+                    "unknown func class: {}",
+                    String::from_utf8_lossy(&[c])
+                )))
+            }
+        };
+        Ok((func_class, adjustment))
+    }
+
+    fn read_qualifier(&mut self) -> StorageClass {
+        let access_class = match self.peek() {
+            Some(b'A') => StorageClass::empty(),
+            Some(b'B') => StorageClass::CONST,
+            Some(b'C') => StorageClass::VOLATILE,
+            Some(b'D') => StorageClass::CONST | StorageClass::VOLATILE,
+            _ => return StorageClass::empty(),
+        };
+        self.trim(1);
+        access_class
+    }
+
+    fn read_calling_conv(&mut self) -> Result<CallingConv> {
+        self.traced("calling convention", |this| this.read_calling_conv_untraced())
+    }
+
+    fn read_calling_conv_untraced(&mut self) -> Result<CallingConv> {
+        let orig = self.input;
+
+        Ok(match self.get()? {
+            b'A' => CallingConv::Cdecl,
+            b'B' => CallingConv::Cdecl,
+            b'C' => CallingConv::Pascal,
+            b'E' => CallingConv::Thiscall,
+            b'G' => CallingConv::Stdcall,
+            b'I' => CallingConv::Fastcall,
+            c => {
+                if let Some(calling_conv) = self.extensions.as_mut().and_then(|ext| ext.unknown_calling_conv(c)) {
+                    return Ok(calling_conv);
+                }
+                return Err(Error::new(format!(
+                    "unknown calling conv: {}",
+                    String::from_utf8_lossy(orig)
+                )))
+            }
+        })
+    }
+
+    // <return-type> ::= <type>
+    //               ::= @ # structors (they have no declared return type)
+    fn read_func_return_type(&mut self, storage_class: StorageClass) -> Result<Type<'a>> {
+        if self.consume(b"@") {
+            Ok(Type::None)
+        } else {
+            self.read_var_type(storage_class)
+        }
+    }
+
+    fn read_storage_class(&mut self) -> StorageClass {
+        let storage_class = match self.peek() {
+            Some(b'A') => StorageClass::empty(),
+            Some(b'B') => StorageClass::CONST,
+            Some(b'C') => StorageClass::VOLATILE,
+            Some(b'D') => StorageClass::CONST | StorageClass::VOLATILE,
+            Some(b'E') => StorageClass::FAR,
+            Some(b'F') => StorageClass::CONST | StorageClass::FAR,
+            Some(b'G') => StorageClass::VOLATILE | StorageClass::FAR,
+            Some(b'H') => StorageClass::CONST | StorageClass::VOLATILE | StorageClass::FAR,
+            _ => return StorageClass::empty(),
+        };
+        self.trim(1);
+        storage_class
+    }
+
+    fn read_storage_class_for_return(&mut self) -> Result<StorageClass> {
+        if !self.consume(b"?") {
+            return Ok(StorageClass::empty());
+        }
+        let orig = self.input;
+
+        Ok(match self.get()? {
+            b'A' => StorageClass::empty(),
+            b'B' => StorageClass::CONST,
+            b'C' => StorageClass::VOLATILE,
+            b'D' => StorageClass::CONST | StorageClass::VOLATILE,
+            _ => {
+                return Err(Error::new(format!(
+                    "unknown storage class: {}",
+                    String::from_utf8_lossy(orig)
+                )))
+            }
+        })
+    }
+
+    fn read_member_function_pointer(&mut self, read_qualifiers: bool) -> Result<Type<'a>> {
+        let symbol = self.read_name(true)?;
+        let ptr64 = if self.consume(b"E") {
+            StorageClass::PTR64
+        } else {
+            StorageClass::empty()
+        };
+        let (access_class, func_class) = if read_qualifiers {
+            (self.read_qualifier() | ptr64, FuncClass::empty())
+        } else {
+            let c = self.get()?;
+            (ptr64, self.read_func_class(c)?.0)
+        };
+        let calling_conv = self.read_calling_conv()?;
+        let storage_class_for_return = self.read_storage_class_for_return()?;
+        let return_type = self.read_func_return_type(storage_class_for_return)?;
+        let params = self.read_func_params()?;
+        Ok(Type::MemberFunctionPointer(
+            Box::new(symbol),
+            func_class,
+            calling_conv,
+            params,
+            access_class,
+            Box::new(return_type),
+        ))
+    }
+
+    // Reads a variable type. Rather than recursing into the type they wrap,
+    // pointers, references and arrays are kept on a stack until it has been
+    // read.
+    fn read_var_type(&mut self, mut sc: StorageClass) -> Result<Type<'a>> {
+        let depth = self.depth;
+        let mut wrappers = Vec::new();
+        let result = loop {
+            let step = self.trace_begin("type");
+            match self.enter().and_then(|()| self.read_var_type_step(sc)) {
+                Ok(VarTypeStep::Wrapper(wrapper, inner_sc)) => {
+                    wrappers.push((wrapper, step));
+                    sc = inner_sc;
+                }
+                Ok(VarTypeStep::Type(t)) => {
+                    self.trace_end(step, true);
+                    break Ok(t);
+                }
+                Err(err) => {
+                    self.trace_end(step, false);
+                    break Err(err);
+                }
+            }
+        };
+        self.depth = depth;
+        let ok = result.is_ok();
+        wrappers.into_iter().rev().fold(result, |result, (wrapper, step)| {
+            self.trace_end(step, ok);
+            result.map(|t| wrapper.wrap(t))
+        })
+    }
+
+    // Reads a variable type, or the pointer, reference or array that wraps
+    // the type that follows it.
+    fn read_var_type_step(&mut self, mut sc: StorageClass) -> Result<VarTypeStep<'a>> {
+        if self.consume(b"W") {
+            let underlying = match self.get()? {
+                b'0' => Type::Char(StorageClass::empty()),
+                b'1' => Type::Uchar(StorageClass::empty()),
+                b'2' => Type::Short(StorageClass::empty()),
+                b'3' => Type::Ushort(StorageClass::empty()),
+                b'4' => Type::Int(StorageClass::empty()),
+                b'5' => Type::Uint(StorageClass::empty()),
+                b'6' => Type::Long(StorageClass::empty()),
+                b'7' => Type::Ulong(StorageClass::empty()),
+                c => {
+                    return Err(Error::new(format!(
+                        "unknown enum underlying type: {}",
+                        c as char
+                    )))
+                }
+            };
+            let name = self.read_name(false)?;
+            return Ok(VarTypeStep::Type(Type::Enum(Box::new(name), Box::new(underlying), sc)));
+        }
+
+        if self.consume(b"A6") {
+            let func_type = self.read_func_type()?;
+            return Ok(VarTypeStep::Type(Type::Ref(Box::new(func_type), sc)));
+        }
+
+        if self.consume(b"P6") {
+            let func_type = self.read_func_type()?;
+            return Ok(VarTypeStep::Type(Type::Ptr(Box::new(func_type), sc)));
+        }
+
+        if self.consume(b"P8") {
+            return self.read_member_function_pointer(true).map(VarTypeStep::Type);
+        }
+
+        if self.consume(b"$") {
+            if self.consume(b"0") {
+                let n = self.read_number()?;
+                return Ok(VarTypeStep::Type(Type::Constant(n)));
+            }
+            if self.consume(b"D") {
+                let n = self.read_number()?;
+                return Ok(VarTypeStep::Type(Type::TemplateParameterWithIndex(n)));
+            }
+            if self.consume(b"$BY") {
+                return self.read_array();
+            }
+            if self.consume(b"$Q") {
+                return Ok(self.read_pointer(Type::RValueRef, sc));
+            }
+            if self.consume(b"$V") {
+                return Ok(VarTypeStep::Type(Type::EmptyParameterPack));
+            }
+            if self.consume(b"$T") {
+                return Ok(VarTypeStep::Type(Type::Nullptr));
+            }
+            if self.consume(b"$A6") {
+                return self.read_func_type().map(VarTypeStep::Type);
+            }
+            // These next cases can fallthrough, so be careful adding new ones!
+            if self.consume(b"$C") {
+                sc = self.read_qualifier();
+            } else if let Some(b'1') | Some(b'H') | Some(b'I') | Some(b'J') = self.peek() {
+                // Inheritance specifiers, which we don't need to remember.
+                self.trim(1);
+                self.expect(b"?")?;
+                return self.read_member_function_pointer(false).map(VarTypeStep::Type);
+            }
+        }
+
+        if self.consume(b"?") {
+            let n = self.read_number()?;
+            let n = n.checked_neg().ok_or_else(|| Error::new(format!("bad template parameter index: {}", n)))?;
+            return Ok(VarTypeStep::Type(Type::TemplateParameterWithIndex(n)));
+        }
+
+        if let Some(n) = self.consume_digit() {
+            // The type takes the place of the level entered for this step.
+            return self.memorized_type(n as usize, self.depth - 1).map(VarTypeStep::Type);
+        }
+
+        let orig = self.input;
+
+        Ok(VarTypeStep::Type(match self.get()? {
+            b'T' => Type::Union(Box::new(self.read_name(false)?), sc),
+            b'U' => Type::Struct(Box::new(self.read_name(false)?), sc),
+            b'V' => Type::Class(Box::new(self.read_name(false)?), sc),
+            b'A' => return Ok(self.read_pointer(Type::Ref, sc)),
+            b'B' => return Ok(self.read_pointer(Type::Ref, StorageClass::VOLATILE)),
+            b'P' => return Ok(self.read_pointer(Type::Ptr, sc)),
+            b'Q' => return Ok(self.read_pointer(Type::Ptr, StorageClass::CONST)),
+            b'R' => return Ok(self.read_pointer(Type::Ptr, StorageClass::VOLATILE)),
+            b'S' => return Ok(self.read_pointer(Type::Ptr, StorageClass::CONST | StorageClass::VOLATILE)),
+            b'Y' => return self.read_array(),
+            b'X' => Type::Void(sc),
+            b'D' => Type::Char(sc),
+            b'C' => Type::Schar(sc),
+            b'E' => Type::Uchar(sc),
+            b'F' => Type::Short(sc),
+            b'G' => Type::Ushort(sc),
+            b'H' => Type::Int(sc),
+            b'I' => Type::Uint(sc),
+            b'J' => Type::Long(sc),
+            b'K' => Type::Ulong(sc),
+            b'M' => Type::Float(sc),
+            b'N' => Type::Double(sc),
+            b'O' => Type::Ldouble(sc),
+            b'_' => match self.get()? {
+                b'N' => Type::Bool(sc),
+                b'J' => Type::Int64(sc),
+                b'K' => Type::Uint64(sc),
+                b'W' => Type::Wchar(sc),
+                b'S' => Type::Char16(sc),
+                b'U' => Type::Char32(sc),
+                _ => return self.unknown_type(orig, sc).map(VarTypeStep::Type),
+            },
+            _ => return self.unknown_type(orig, sc).map(VarTypeStep::Type),
+        }))
+    }
+
+    // Reads the storage class of the pointee of a pointer or reference type,
+    // which is wrapped using `make` (e.g. Type::Ptr) with the pointer's own
+    // storage class.
+    fn read_pointer(
+        &mut self,
+        make: fn(Box<Type<'a>>, StorageClass) -> Type<'a>,
+        sc: StorageClass,
+    ) -> VarTypeStep<'a> {
+        let ptr64 = if self.consume(b"E") {
+            StorageClass::PTR64
+        } else {
+            StorageClass::empty()
+        };
+        let storage_class = self.read_storage_class();
+        VarTypeStep::Wrapper(Wrapper::Pointer(make, sc | ptr64), storage_class)
+    }
+
+    // Reads the dimensions of an array type, each one nesting level deeper,
+    // and the storage class of its elements.
+    fn read_array(&mut self) -> Result<VarTypeStep<'a>> {
+        let dimension = self.read_number()?;
+        if dimension <= 0 {
+            return Err(Error::new(format!(
+                "invalid array dimension: {}",
+                dimension
+            )));
+        }
+        let mut lens = Vec::new();
+        for _ in 0..dimension {
+            lens.push(self.read_number()?);
+            self.enter()?;
+        }
+        let storage_class = if self.consume(b"$$C") {
+            if self.consume(b"B") {
+                StorageClass::CONST
+            } else if self.consume(b"C") || self.consume(b"D") {
+                StorageClass::CONST | StorageClass::VOLATILE
+            } else if !self.consume(b"A") {
+                return Err(Error::new(format!(
+                    "unknown storage class: {}",
+                    String::from_utf8_lossy(self.input)
+                )));
+            } else {
+                StorageClass::empty()
+            }
+        } else {
+            StorageClass::empty()
+        };
+        Ok(VarTypeStep::Wrapper(Wrapper::Array(lens, storage_class), StorageClass::empty()))
+    }
+
+    // Reads a function or a template parameters.
+    fn read_params(&mut self) -> Result<Params<'a>> {
+        let mut types = Vec::new();
+        self.read_params_with(&mut |_: &mut Self, t| types.push(t))?;
+        Ok(Params { types })
+    }
+
+    // Reads parameters, passing each to `param` as it is read.
+    fn read_params_with<F: FnMut(&mut Self, Type<'a>)>(&mut self, param: &mut F) -> Result<()> {
+        self.traced("parameters", |this| this.read_params_untraced(param))
+    }
+
+    fn read_params_untraced<F: FnMut(&mut Self, Type<'a>)>(&mut self, param: &mut F) -> Result<()> {
+        // Within the same parameter list, you can backreference the first 10 types.
+        while !self.input.starts_with(b"@") && !self.input.starts_with(b"Z")
+            && !self.input.is_empty()
+        {
+            if let Some(n) = self.consume_digit() {
+                let t = self.memorized_type(n as usize, self.depth)?;
+                param(self, t);
+                continue;
+            }
+
+            let len = self.input.len();
+            let nodes = self.nodes;
+
+            let (param_type, height) = self.measured(|this| this.read_var_type(StorageClass::empty()))?;
+
+            // Single-letter types are ignored for backreferences because
+            // memorizing them doesn't save anything.
+            if len - self.input.len() > 1 {
+                self.memorize_type(&param_type, self.nodes - nodes, height);
+            }
+            param(self, param_type);
+        }
+
+        if self.consume(b"Z") {
+            param(self, Type::VarArgs);
+        } else if self.input.is_empty() && !self.strict {
+            // this is needed to handle the weird standalone template manglings
+        } else {
+            self.expect(b"@")?;
+        }
+        Ok(())
+    }
+
+    // Reads a function parameters.
+    fn read_func_params(&mut self) -> Result<Params<'a>> {
+        let mut types = Vec::new();
+        self.read_func_params_with(|_, t| types.push(t))?;
+        Ok(Params { types })
+    }
+
+    // Like read_func_params(), but passes each parameter to `param` as it
+    // is read.
+    fn read_func_params_with<F: FnMut(&mut Self, Type<'a>)>(&mut self, mut param: F) -> Result<()> {
+        if self.consume(b"X") {
+            param(self, Type::Void(StorageClass::empty()));
+        } else {
+            self.read_params_with(&mut param)?;
+        }
+
+        self.expect(b"Z")?;
+
+        Ok(())
+    }
+
+}
+
+/// Cheaply checks whether `input` looks like an MSVC-mangled name, without
+/// parsing it. This can be used to route symbols from mixed sources; a
+/// `true` result doesn't guarantee that `demangle()` succeeds.
+pub fn is_msvc_mangled(input: &[u8]) -> bool {
+    // The shortest names look like "?x@@3HA" or "?f@@9".
+    if input.len() < 5 || input[0] != b'?' || !input.contains(&b'@') {
+        return false;
+    }
+    // Mangled names never contain spaces or the punctuation of C++
+    // declarations, unlike demangled ones.
+    input.iter().all(|&c| {
+        !c.is_ascii_whitespace()
+            && !c.is_ascii_control()
+            && !b"(),*&:;'\"\\".contains(&c)
+    })
+}
+
+pub fn demangle<O: Into<DemangleOptions>>(input: &str, flags: O) -> Result<String> {
+    let flags = flags.into();
+    let mut parse_result = parse_with_options(input, flags)?;
+    simplify(&mut parse_result, flags);
+    let limit = output_limit(flags, input.len());
+    let mut s = String::with_capacity(output_capacity(&parse_result, limit));
+    serialize_to_limited(&parse_result, flags, limit, &mut s)?;
+    Ok(s)
+}
+
+/// Like `demangle()`, but writes the demangled name to `out`, replacing
+/// its contents, and leaves `out` empty on errors.
+///
+/// `demangle()` allocates a new `String` for every name. When demangling
+/// many names, reusing one `String` for all of them saves most of those
+/// allocations, as its capacity grows to the longest name and stays there.
+/// Parsing still allocates the tree of every name.
+pub fn demangle_into<O: Into<DemangleOptions>>(input: &str, flags: O, out: &mut String) -> Result<()> {
+    let flags = flags.into();
+    let limit = output_limit(flags, input.len());
+    out.clear();
+    let result = parse_with_options(input, flags).and_then(|mut parse_result| {
+        simplify(&mut parse_result, flags);
+        out.reserve(output_capacity(&parse_result, limit));
+        serialize_to_limited(&parse_result, flags, limit, out)
+    });
+    if result.is_err() {
+        out.clear();
+    }
+    result
+}
+
+/// Like `demangle()`, but takes the raw bytes of a name, e.g. as read from
+/// an object file. Fails on invalid UTF-8 unless `DemangleOptions::LOSSY`
+/// is given, in which case invalid sequences are written as U+FFFD. They
+/// can only be part of identifiers, so the rest of the name is demangled
+/// as usual.
+pub fn demangle_bytes<O: Into<DemangleOptions>>(input: &[u8], flags: O) -> Result<String> {
+    let flags = flags.into();
+    if flags.flags.contains(OptionFlags::LOSSY) {
+        demangle(&String::from_utf8_lossy(input), flags)
+    } else {
+        demangle(str::from_utf8(input)?, flags)
+    }
+}
+
+/// Demangles many names with the same options, like `demangle_into()`, but
+/// also reuses the tables of the parser from one name to the next.
+///
+/// ```
+/// let mut demangler = msvc_demangler::Demangler::new(msvc_demangler::DemangleOptions::new());
+/// for symbol in &["?x@@3HA", "?f@@YAXXZ"] {
+///     println!("{}", demangler.demangle(symbol).unwrap_or(symbol));
+/// }
+/// ```
+pub struct Demangler {
+    flags: DemangleOptions,
+    buffers: ParserBuffers,
+    output: String,
+}
+
+impl Demangler {
+    /// Creates a demangler which demangles names according to `flags`.
+    pub fn new<O: Into<DemangleOptions>>(flags: O) -> Demangler {
+        Demangler {
+            flags: flags.into(),
+            buffers: ParserBuffers::default(),
+            output: String::new(),
+        }
+    }
+
+    /// The options this demangler was created with.
+    pub fn options(&self) -> DemangleOptions {
+        self.flags
+    }
+
+    /// Demangles `input`. The result borrows the output buffer of the
+    /// demangler, which is overwritten by the next call.
+    pub fn demangle(&mut self, input: &str) -> Result<&str> {
+        self.output.clear();
+        let (mut parse_result, len) = parse_prefix_with_buffers(input, self.flags.into(), &mut self.buffers)?;
+        if let Err(err) = check_trailing(input, len, self.flags.into()) {
+            self.buffers.names = recycle(parse_result.names);
+            return Err(err);
+        }
+        simplify(&mut parse_result, self.flags);
+        let limit = output_limit(self.flags, input.len());
+        self.output.reserve(output_capacity(&parse_result, limit));
+        let result = serialize_to_limited(&parse_result, self.flags, limit, &mut self.output);
+        self.buffers.names = recycle(parse_result.names);
+        match result {
+            Ok(()) => Ok(&self.output),
+            Err(err) => {
+                self.output.clear();
+                Err(err)
+            }
+        }
+    }
+}
+
+// The fewest names demangle_batch() hands to a thread of its own.
+const MIN_BATCH_PER_THREAD: usize = 256;
+
+/// Demangles all of `inputs`, e.g. the symbol table of a PDB file, spread
+/// over the available CPU cores. The results are in the order of `inputs`.
+pub fn demangle_batch<O: Into<DemangleOptions>>(inputs: &[&str], flags: O) -> Vec<Result<String>> {
+    let flags = flags.into();
+    let demangle_chunk = |chunk: &[&str]| {
+        let mut demangler = Demangler::new(flags);
+        chunk
+            .iter()
+            .map(|input| demangler.demangle(input).map(str::to_owned))
+            .collect::<Vec<_>>()
+    };
+    let threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(inputs.len() / MIN_BATCH_PER_THREAD);
+    if threads <= 1 {
+        return demangle_chunk(inputs);
+    }
+    thread::scope(|scope| {
+        let handles: Vec<_> = inputs
+            .chunks(inputs.len().div_ceil(threads))
+            .map(|chunk| scope.spawn(move || demangle_chunk(chunk)))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_else(|err| panic::resume_unwind(err)))
+            .collect()
+    })
+}
+
+/// Adds `demangled()` to iterators over names, e.g. the lines of `nm` or
+/// `dumpbin` output.
+///
+/// ```
+/// use msvc_demangler::{DemangleExt, DemangleOptions};
+///
+/// let text = "?x@@3HA\nmain\n";
+/// for (input, result) in text.lines().demangled(DemangleOptions::new()) {
+///     println!("{}", result.as_ref().map_or(input, |s| s.as_str()));
+/// }
+/// ```
+pub trait DemangleExt<'s>: Iterator<Item = &'s str> + Sized {
+    /// Demangles every name with the options `flags`, yielding each name
+    /// along with the result of demangling it. A `Demangler` is reused for
+    /// all of the names.
+    fn demangled<O: Into<DemangleOptions>>(self, flags: O) -> Demangled<Self> {
+        Demangled {
+            iter: self,
+            demangler: Demangler::new(flags),
+        }
+    }
+}
+
+impl<'s, I: Iterator<Item = &'s str>> DemangleExt<'s> for I {}
+
+/// The iterator returned by `DemangleExt::demangled()`.
+pub struct Demangled<I> {
+    iter: I,
+    demangler: Demangler,
+}
+
+impl<'s, I: Iterator<Item = &'s str>> Iterator for Demangled<I> {
+    type Item = (&'s str, Result<String>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let input = self.iter.next()?;
+        Some((input, self.demangler.demangle(input).map(str::to_owned)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+impl<'s, I: ExactSizeIterator<Item = &'s str>> ExactSizeIterator for Demangled<I> {}
+
+/// Demangles `input` if it is a valid MSVC-mangled name and returns it
+/// unchanged otherwise.
+pub fn try_demangle<O: Into<DemangleOptions>>(input: &str, flags: O) -> Cow<'_, str> {
+    match demangle(input, flags) {
+        Ok(demangled) => Cow::Owned(demangled),
+        Err(_) => Cow::Borrowed(input),
+    }
+}
+
+/// Replaces every MSVC-mangled name in `text`, e.g. a log line, linker
+/// map or disassembly listing, with its demangled form, like piping the
+/// text through c++filt. Everything else is left untouched.
+pub fn demangle_text<O: Into<DemangleOptions>>(text: &str, flags: O) -> String {
+    // Bytes that can precede a mangled name only as part of a longer word.
+    fn is_name_byte(c: u8) -> bool {
+        c.is_ascii_alphanumeric() || b"?@$_<>".contains(&c) || !c.is_ascii()
+    }
+    // Bytes that can be part of a mangled name, including "<lambda_1>",
+    // "<unnamed-type-x>" and "<unnamed-tag>".
+    fn in_name(c: u8) -> bool {
+        is_name_byte(c) || c == b'-'
+    }
+
+    let flags = flags.into();
+    let bytes = text.as_bytes();
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'?' || (i > 0 && is_name_byte(bytes[i - 1])) {
+            i += 1;
+            continue;
+        }
+        let end = bytes[i..].iter().position(|&c| !in_name(c)).map_or(bytes.len(), |n| i + n);
+        match demangle_prefix(&text[i..end], flags) {
+            Ok((demangled, len)) => {
+                out.push_str(&text[copied..i]);
+                out.push_str(&demangled);
+                i += len;
+                copied = i;
+            }
+            Err(_) => i = end,
+        }
+    }
+    out.push_str(&text[copied..]);
+    out
+}
+
+/// Whether two mangled names refer to the same entity, e.g. one spelled
+/// with back-references and one without. Names that fail to parse are
+/// never equivalent.
+pub fn equivalent(a: &str, b: &str) -> bool {
+    match (parse(a), parse(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// A canonical string for the entity that `input` names. Equivalent names
+/// (see `equivalent()`) have the same normalized form, which doesn't change
+/// between versions of this crate, so it can serve as a lookup key.
+pub fn normalize(input: &str) -> Result<String> {
+    demangle(input, DemangleOptions::new().format_profile(FormatProfile::V1))
+}
+
+/// A 64-bit hash of the normalized form of `input` (see `normalize()`),
+/// e.g. to deduplicate functions in a symbol server. Equivalent names have
+/// the same hash, which doesn't depend on the platform, the options used
+/// elsewhere or the version of this crate.
+pub fn stable_hash(input: &str) -> Result<u64> {
+    // 64-bit FNV-1a.
+    let normalized = normalize(input)?;
+    Ok(normalized.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    }))
+}
+
+/// Like `stable_hash()`, but 128 bits wide, for sets of names large enough
+/// that 64-bit hashes would collide.
+pub fn stable_hash_128(input: &str) -> Result<u128> {
+    // 128-bit FNV-1a.
+    let normalized = normalize(input)?;
+    Ok(normalized.bytes().fold(0x6c62_272e_07bb_0142_62b8_2175_6295_c58d, |hash, b| {
+        (hash ^ u128::from(b)).wrapping_mul(0x0000_0000_0100_0000_0000_0000_0000_013b)
+    }))
+}
+
+/// Whether two mangled names refer to instances of the same template, or
+/// of members of the same class template, e.g. `Foo<int>::bar(int)` and
+/// `Foo<double>::bar(int)`, see `normalize_ignoring_template_args()`.
+/// Names that fail to parse never match.
+pub fn equivalent_ignoring_template_args(a: &str, b: &str) -> bool {
+    match (normalize_ignoring_template_args(a), normalize_ignoring_template_args(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// A key that all instances of a template have in common, e.g.
+/// `Foo<>::bar(int)` for `Foo<int>::bar(int)`: the qualified name and,
+/// for functions, the parameter list and `const`/`volatile` of `this`,
+/// with all template arguments left out. Overloads thus keep apart. Like
+/// `normalize()`, the format is stable.
+pub fn normalize_ignoring_template_args(input: &str) -> Result<String> {
+    let parsed = parse(input)?;
+    let flags = DemangleOptions::new().format_profile(FormatProfile::V1) | DemangleOptions::NO_TEMPLATE_ARGS;
+    let name = parsed.function_name(flags)?;
+    let scope = parsed.qualified_scope(flags)?;
+    let mut key = if scope.is_empty() {
+        name
+    } else {
+        format!("{}::{}", scope, name)
+    };
+    if let Some(signature) = parsed.signature() {
+        key.push('(');
+        key.push_str(&parsed.parameter_strings(flags)?.join(","));
+        key.push(')');
+        let quals = signature.this_quals();
+        if quals.contains(StorageClass::CONST) {
+            key.push_str(" const");
+        }
+        if quals.contains(StorageClass::VOLATILE) {
+            key.push_str(" volatile");
+        }
+    }
+    Ok(key)
+}
+
+/// Hooks for codes the parser doesn't know, such as extensions of other
+/// compilers, see `parse_with_extensions()`. Each hook gets the rest of
+/// the mangled name, starting with the unknown code, and returns how to
+/// write what it stands for along with the number of bytes it takes up, or
+/// `None` to fail as usual.
+pub trait Extensions {
+    /// Called for an unknown type code, e.g. `_$` in `?x@@3_$A`.
+    fn unknown_type(&mut self, input: &str) -> Option<(String, usize)> {
+        let _ = input;
+        None
+    }
+
+    /// Called for an unknown operator code following `?`. The operator is
+    /// written like an identifier.
+    fn unknown_operator(&mut self, input: &str) -> Option<(String, usize)> {
+        let _ = input;
+        None
+    }
+
+    /// Called for an unknown calling convention code, which is treated as
+    /// the returned calling convention.
+    fn unknown_calling_conv(&mut self, code: u8) -> Option<CallingConv> {
+        let _ = code;
+        None
+    }
+}
+
+/// Like `parse_with_options()`, but calls the hooks of `extensions` for
+/// unknown codes.
+pub fn parse_with_extensions<'a, O: Into<ParseOptions>>(
+    input: &'a str,
+    options: O,
+    extensions: &mut dyn Extensions,
+) -> Result<ParseResult<'a>> {
+    let options = options.into();
+    let mut state = ParserState::new(input, options);
+    state.extensions = Some(extensions);
+    let mut parse_result = state.parse()?;
+    check_trailing(input, input.len() - state.input.len(), options)?;
+    parse_result.names = state.names;
+    Ok(parse_result)
+}
+
+/// Like `demangle()`, but calls the hooks of `extensions` for unknown
+/// codes.
+pub fn demangle_with_extensions<O: Into<DemangleOptions>>(
+    input: &str,
+    flags: O,
+    extensions: &mut dyn Extensions,
+) -> Result<String> {
+    let flags = flags.into();
+    let mut parse_result = parse_with_extensions(input, flags, extensions)?;
+    simplify(&mut parse_result, flags);
+    let limit = output_limit(flags, input.len());
+    let mut s = String::with_capacity(output_capacity(&parse_result, limit));
+    serialize_to_limited(&parse_result, flags, limit, &mut s)?;
+    Ok(s)
+}
+
+pub fn parse(input: &str) -> Result<ParseResult<'_>> {
+    parse_with_options(input, DemangleOptions::new())
+}
+
+/// Like `parse()`, but with the limits and strictness of `options`, which
+/// are either `ParseOptions` or `DemangleOptions`.
+pub fn parse_with_options<O: Into<ParseOptions>>(input: &str, options: O) -> Result<ParseResult<'_>> {
+    parse_with_trailing(input, options).map(|(parse_result, _)| parse_result)
+}
+
+/// Like `parse_with_options()`, but for input that may not be UTF-8, e.g.
+/// names read from a binary. Invalid UTF-8 is an error unless
+/// `ParseOptions::allow_non_utf8()` is set; then it is replaced with
+/// U+FFFD, and the result is a copy rather than borrowed from `input`.
+pub fn parse_bytes<O: Into<ParseOptions>>(input: &[u8], options: O) -> Result<ParseResult<'_>> {
+    let options = options.into();
+    match str::from_utf8(input) {
+        Ok(input) => parse_with_options(input, options),
+        Err(err) if !options.allow_non_utf8 => Err(err.into()),
+        Err(_) => {
+            let input = String::from_utf8_lossy(input);
+            parse_with_options(&input, options).map(ParseResult::into_owned)
+        }
+    }
+}
+
+/// Like `parse_with_options()`, but also returns the input following the
+/// mangled name, which `parse_with_options()` ignores. With
+/// `DemangleOptions::STRICT`, it is always empty, as trailing input is an
+/// error.
+pub fn parse_with_trailing<O: Into<ParseOptions>>(input: &str, options: O) -> Result<(ParseResult<'_>, &str)> {
+    let options = options.into();
+    let (parse_result, len) = parse_prefix_with_options(input, options)?;
+    Ok((parse_result, check_trailing(input, len, options)?))
+}
+
+// Returns the input following the first `len` bytes, failing if there is
+// any in strict mode.
+fn check_trailing(input: &str, len: usize, options: ParseOptions) -> Result<&str> {
+    let trailing = &input[len..];
+    if !trailing.is_empty() && options.strict {
+        return Err(Error::new(format!("unconsumed input at offset {}: {:?}", len, trailing)));
+    }
+    Ok(trailing)
+}
+
+/// Parses a mangled name followed by arbitrary data, such as the `$`
+/// decorations of import thunks. Returns the parsed name and the number of
+/// bytes of `input` it takes up.
+pub fn parse_prefix(input: &str) -> Result<(ParseResult<'_>, usize)> {
+    parse_prefix_with_options(input, DemangleOptions::new())
+}
+
+/// Like `parse_prefix()`, but with the limits of `options`.
+pub fn parse_prefix_with_options<O: Into<ParseOptions>>(
+    input: &str,
+    options: O,
+) -> Result<(ParseResult<'_>, usize)> {
+    parse_prefix_with_buffers(input, options.into(), &mut ParserBuffers::default())
+}
+
+// The tables of the parser, kept empty between symbols so that their
+// memory can be reused by a Demangler.
+#[derive(Default)]
+struct ParserBuffers {
+    memorized_names: Vec<(Name<'static>, usize)>,
+    memorized_types: Vec<(Arc<Type<'static>>, usize, usize)>,
+    names: Vec<Name<'static>>,
+}
+
+// Clears `v` and returns it with the element type of another lifetime,
+// keeping its memory.
+fn recycle<T, U>(mut v: Vec<T>) -> Vec<U> {
+    v.clear();
+    v.into_iter().map(|_| unreachable!()).collect()
+}
+
+fn parse_prefix_with_buffers<'a>(
+    input: &'a str,
+    options: ParseOptions,
+    buffers: &mut ParserBuffers,
+) -> Result<(ParseResult<'a>, usize)> {
+    let mut state = ParserState {
+        input: input.as_bytes(),
+        source: input,
+        memorized_names: recycle(mem::take(&mut buffers.memorized_names)),
+        names: recycle(mem::take(&mut buffers.names)),
+        name_ids: HashMap::new(),
+        memorized_types: recycle(mem::take(&mut buffers.memorized_types)),
+        intern: true,
+        strict: options.strict,
+        depth: 0,
+        max_depth: depth_limit(options.max_depth),
+        deepest: 0,
+        nodes: 0,
+        max_nodes: options.max_nodes.unwrap_or(usize::MAX),
+        extensions: None,
+        #[cfg(feature = "trace")]
+        trace: None,
+        #[cfg(feature = "trace")]
+        trace_depth: 0,
+    };
+    state.memorized_names.reserve(10);
+    state.memorized_types.reserve(10);
+    let result = state.parse();
+    buffers.memorized_names = recycle(state.memorized_names);
+    buffers.memorized_types = recycle(state.memorized_types);
+    let mut parse_result = result?;
+    parse_result.names = state.names;
+    Ok((parse_result, input.len() - state.input.len()))
+}
+
+/// Parses a mangled type on its own, such as the type fragments in PDB
+/// records, e.g. `PEAVFoo@@` for `class Foo *`. RTTI type descriptor names
+/// like `.?AVFoo@@` are accepted as well. The returned type doesn't refer
+/// to a names table, so it can be serialized with `serialize_type()`.
+pub fn parse_type(input: &str) -> Result<Type<'_>> {
+    parse_type_with_options(input, DemangleOptions::new())
+}
+
+/// Like `parse_type()`, but with the limits of `options`.
+pub fn parse_type_with_options<O: Into<ParseOptions>>(input: &str, options: O) -> Result<Type<'_>> {
+    let fragment = input.strip_prefix(".?A").unwrap_or(input);
+    let mut state = ParserState::new(fragment, options.into());
+    state.intern = false;
+    let t = state.read_var_type(StorageClass::empty())?;
+    if !state.input.is_empty() {
+        return Err(Error::new(format!(
+            "unexpected data after type: {}",
+            String::from_utf8_lossy(state.input)
+        )));
+    }
+    Ok(t)
+}
+
+/// Writes a type returned by `parse_type()`, e.g. `class Foo *`.
+pub fn serialize_type<O: Into<DemangleOptions>>(t: &Type, flags: O) -> Result<String> {
+    let flags = flags.into();
+    let parse_result = ParseResult {
+        symbol: Symbol::new(Name::AnonymousNamespace, Vec::new()),
+        symbol_type: t.clone(),
+        symbol_kind: SymbolKind::Other,
+        variable_kind: None,
+        thunk_adjustment: None,
+        names: Vec::new(),
+    };
+    let parse_result = simplified(&parse_result, flags);
+    let t = &parse_result.symbol_type;
+    serialize_part(flags, &[], |serializer| {
+        serializer.write_pre(t)?;
+        serializer.write_post(t)
+    })
+}
+
+/// Demangles the mangled name at the start of `input`, ignoring whatever
+/// follows it. Returns the demangled name and the number of bytes of
+/// `input` that were used.
+pub fn demangle_prefix<O: Into<DemangleOptions>>(input: &str, flags: O) -> Result<(String, usize)> {
+    let flags = flags.into();
+    let (mut parse_result, len) = parse_prefix_with_options(input, flags)?;
+    simplify(&mut parse_result, flags);
+    let limit = output_limit(flags, len);
+    let mut s = String::with_capacity(output_capacity(&parse_result, limit));
+    serialize_to_limited(&parse_result, flags, limit, &mut s)?;
+    Ok((s, len))
+}
+
+/// Just the qualified name of the symbol in `input`, e.g.
+/// `ns::Foo<int>::bar`, like `demangle()` with `DemangleOptions::NAME_ONLY`
+/// but faster: the parameters and return type are neither parsed nor
+/// written, except for special names such as vftables and RTTI
+/// descriptors. Add `DemangleOptions::NO_TEMPLATE_ARGS` to get
+/// `ns::Foo<>::bar`.
+pub fn demangle_qualified_name<O: Into<DemangleOptions>>(input: &str, flags: O) -> Result<String> {
+    // NAME_ONLY also applies to the functions that local names are nested
+    // in, e.g. "`f'::`2'::x".
+    let flags = flags.into() | DemangleOptions::NAME_ONLY;
+    let mut state = ParserState::new(input, flags.into());
+    let mut parse_result = state.parse_name()?;
+    parse_result.names = state.names;
+    simplify(&mut parse_result, flags);
+    let mut s = String::new();
+    serialize_to_limited(&parse_result, flags, flags.max_output, &mut s)?;
+    Ok(s)
+}
+
+/// The result of `demangle_lossy()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LossyDemangling<'a> {
+    /// The demangled form of the part of the input that could be parsed.
+    pub demangled: String,
+    /// The rest of the input, empty if all of it was demangled.
+    pub remainder: &'a str,
+    /// Why the remainder could not be demangled.
+    pub error: Option<Error>,
+}
+
+impl<'a> fmt::Display for LossyDemangling<'a> {
+    /// Writes the demangled part followed by the remainder, if any, e.g.
+    /// `ns::Foo::bar [unparsed: QAEX_$@Z]`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.demangled)?;
+        if !self.remainder.is_empty() {
+            write!(f, " [unparsed: {}]", self.remainder)?;
+        }
+        Ok(())
+    }
+}
+
+/// Like `demangle()`, but if the type of the symbol cannot be parsed, e.g.
+/// because it uses an unknown code or was cut off, still returns the
+/// qualified name, along with the unparsed remainder of `input`. Fails only
+/// if not even the name can be parsed.
+pub fn demangle_lossy<O: Into<DemangleOptions>>(input: &str, flags: O) -> Result<LossyDemangling<'_>> {
+    let flags = flags.into();
+    let error = match demangle(input, flags) {
+        Ok(demangled) => {
+            return Ok(LossyDemangling {
+                demangled,
+                remainder: "",
+                error: None,
+            })
+        }
+        Err(err) => err,
+    };
+    let mut state = ParserState::new(input, flags.into());
+    if input.starts_with("?$") || !state.consume(b"?") {
+        return Err(error);
+    }
+    let symbol = state.read_name(true).map_err(|_| error.clone())?;
+    let mut parse_result = ParseResult {
+        symbol,
+        symbol_type: Type::None,
+        symbol_kind: SymbolKind::Other,
+        variable_kind: None,
+        thunk_adjustment: None,
+        names: mem::take(&mut state.names),
+    };
+    simplify(&mut parse_result, flags);
+    let demangled = serialize_part(flags, &parse_result.names, |serializer| {
+        serializer.write_name(&parse_result.symbol, None, SpanKind::SymbolName)
+    })?;
+    Ok(LossyDemangling {
+        demangled,
+        remainder: input.get(input.len() - state.input.len()..).unwrap_or_default(),
+        error: Some(error),
+    })
+}
+
+pub fn serialize<O: Into<DemangleOptions>>(input: &ParseResult, flags: O) -> Result<String> {
+    let flags = flags.into();
+    let input = simplified(input, flags);
+    let mut s = String::with_capacity(output_capacity(&input, flags.max_output));
+    serialize_to_limited(&input, flags, flags.max_output, &mut s)?;
+    Ok(s)
+}
+
+/// Writes the demangled form of `input` to `w`, without building an
+/// intermediate string.
+pub fn serialize_to<W: fmt::Write, O: Into<DemangleOptions>>(
+    input: &ParseResult,
+    flags: O,
+    w: &mut W,
+) -> Result<()> {
+    let flags = flags.into();
+    serialize_to_limited(&simplified(input, flags), flags, flags.max_output, w)
+}
+
+// Like serialize_to(), but fails if the output would be longer than
+// `limit`.
+fn serialize_to_limited<W: fmt::Write>(
+    input: &ParseResult,
+    flags: DemangleOptions,
+    limit: Option<usize>,
+    w: &mut W,
+) -> Result<()> {
+    let mut name_writer = DefaultNameWriter;
+    let mut serializer = Serializer::new(flags, &input.names, w, &mut name_writer);
+    serializer.w.limit = limit;
+    serializer.serialize_all(input)?;
+    Ok(())
+}
+
+// A guess at the length of the demangled form of `input`, from its
+// identifiers and the number of its other nodes, so that the output can be
+// allocated once. Back-referenced names count as often as they are used,
+// which can add up to a lot, so counting stops at `cap`.
+fn estimated_len(input: &ParseResult, cap: usize) -> usize {
+    struct Estimate<'b, 'a: 'b> {
+        names: &'b [Name<'a>],
+        len: usize,
+        cap: usize,
+    }
+
+    impl<'b, 'a> Visitor for Estimate<'b, 'a> {
+        fn visit_name(&mut self, name: &Name) {
+            if self.len >= self.cap {
+                return;
+            }
+            match *name {
+                Name::NonTemplate(ref name) => self.len += name.len() + 2,
+                Name::Operator(_) => self.len += 12,
+                Name::Template(..) => self.len += 2,
+                Name::Backref(i) => {
+                    if let Some(name) = self.names.get(i) {
+                        self.visit_name(name);
+                    }
+                    return;
+                }
+                _ => self.len += 8,
+            }
+            walk_name(self, name);
+        }
+
+        fn visit_type(&mut self, mut t: &Type) {
+            if self.len >= self.cap {
+                return;
+            }
+            // Chains of pointers and arrays can be long, see
+            // `Serializer::write_pre()`.
+            while let Type::Ptr(ref inner, _)
+            | Type::Ref(ref inner, _)
+            | Type::RValueRef(ref inner, _)
+            | Type::Array(_, ref inner, _) = *t
+            {
+                self.len += 2;
+                t = inner;
+            }
+            self.len += match *t {
+                // Access specifiers and the qualifiers of `this`.
+                Type::MemberFunction(..) | Type::MemberFunctionPointer(..) => 28,
+                Type::NonMemberFunction(..) => 16,
+                _ => 6,
+            };
+            walk_type(self, t);
+        }
+    }
+
+    let mut estimate = Estimate {
+        names: &input.names,
+        len: 0,
+        cap,
+    };
+    walk_symbol(&mut estimate, &input.symbol);
+    estimate.visit_type(&input.symbol_type);
+    min(estimate.len, cap)
+}
+
+// The capacity to allocate for the demangled form of `input`. Longer
+// names are rare enough to grow their buffer as they are written.
+fn output_capacity(input: &ParseResult, limit: Option<usize>) -> usize {
+    estimated_len(input, min(limit.unwrap_or(usize::MAX), MAX_OUTPUT_CAPACITY))
+}
+
+const MAX_OUTPUT_CAPACITY: usize = 4096;
+
+// The longest output allowed for a mangled name of `len` bytes.
+fn output_limit(flags: DemangleOptions, len: usize) -> Option<usize> {
+    match (flags.max_expansion.map(|factor| len.saturating_mul(factor)), flags.max_output) {
+        (Some(expansion), Some(max)) => Some(expansion.min(max)),
+        (expansion, max) => expansion.or(max),
+    }
+}
+
+// Writes a part of a symbol with `f`.
+fn serialize_part<F>(flags: DemangleOptions, names: &[Name], f: F) -> Result<String>
+where
+    F: FnOnce(&mut Serializer) -> SerializeResult<()>,
+{
+    let mut s = String::new();
+    let mut name_writer = DefaultNameWriter;
+    {
+        let mut serializer = Serializer::new(flags, names, &mut s, &mut name_writer);
+        f(&mut serializer)?;
+        serializer.w.finish().map_err(SerializeError::from)?;
+    }
+    Ok(s)
+}
+
+/// Hooks to customize how parts of a symbol are written, e.g. to shorten
+/// namespaces, substitute typedef names or anonymize identifiers.
+///
+/// The serializer calls these for every node of the respective kind. The
+/// default implementations produce the usual output, so implementors only
+/// need to override the hooks they are interested in.
+pub trait NameWriter {
+    /// Writes a single identifier, e.g. a namespace, class or function name.
+    fn write_identifier(&mut self, w: &mut dyn fmt::Write, identifier: &str) -> fmt::Result {
+        w.write_str(identifier)
+    }
+
+    /// Called for `Type::Struct`, `Type::Union`, `Type::Class`,
+    /// `Type::Enum` and `Type::Typedef` types. Either write a replacement for the keyword and
+    /// name of the type and return `Ok(true)`, or return `Ok(false)` to
+    /// have it written as usual. Qualifiers such as `const` are written
+    /// afterwards either way.
+    fn write_type_name(&mut self, w: &mut dyn fmt::Write, ty: &Type) -> result::Result<bool, fmt::Error> {
+        let _ = (w, ty);
+        Ok(false)
+    }
+}
+
+struct DefaultNameWriter;
+
+impl NameWriter for DefaultNameWriter {}
+
+/// Like `serialize()`, but calls the hooks of `name_writer` while doing so.
+pub fn serialize_with_name_writer<O: Into<DemangleOptions>>(
+    input: &ParseResult,
+    flags: O,
+    name_writer: &mut dyn NameWriter,
+) -> Result<String> {
+    let flags = flags.into();
+    let input = simplified(input, flags);
+    let mut s = String::new();
+    {
+        let mut serializer = Serializer::new(flags, &input.names, &mut s, name_writer);
+        serializer.serialize_all(&input)?;
+    }
+    Ok(s)
+}
+
+/// Like `serialize_to()`, but writes to an `io::Write` such as a file.
+pub fn serialize_to_writer<W: io::Write, O: Into<DemangleOptions>>(
+    input: &ParseResult,
+    flags: O,
+    w: &mut W,
+) -> Result<()> {
+    // Adapts an io::Write to fmt::Write, holding on to the actual error.
+    struct Adapter<'a, W: 'a> {
+        w: &'a mut W,
+        error: Option<io::Error>,
+    }
+
+    impl<'a, W: io::Write> fmt::Write for Adapter<'a, W> {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            self.w.write_all(s.as_bytes()).map_err(|e| {
+                self.error = Some(e);
+                fmt::Error
+            })
+        }
+    }
+
+    let mut adapter = Adapter { w, error: None };
+    serialize_to(input, flags, &mut adapter).map_err(|e| match adapter.error.take() {
+        Some(io_error) => Error::new(format!("{:?}", io_error)),
+        None => e,
+    })
+}
+
+/// The kind of AST node a `Span` of the output belongs to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SpanKind {
+    /// The (possibly qualified) name of the symbol itself.
+    SymbolName,
+    /// The (possibly qualified) name of a class, struct, union or enum used
+    /// in a type.
+    TypeName,
+    /// A single identifier, e.g. a namespace, class or function name.
+    Identifier,
+    /// An operator or special name such as `operator+` or `` `vftable' ``.
+    Operator,
+    /// The return type of the function, or the leading part of it for
+    /// return types such as function pointers that surround the name.
+    ReturnType,
+    /// A single function parameter.
+    Parameter,
+    /// A single template argument.
+    TemplateArgument,
+    /// A calling convention such as `__cdecl`.
+    CallingConvention,
+}
+
+/// A range of bytes in the demangled output, along with what it represents.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub range: ops::Range<usize>,
+    pub kind: SpanKind,
+}
+
+/// Like `demangle()`, but also returns the spans of the output string
+/// that correspond to names, parameters and other interesting parts of
+/// the symbol. See `serialize_with_spans()`.
+pub fn demangle_with_spans<O: Into<DemangleOptions>>(
+    input: &str,
+    flags: O,
+) -> Result<(String, Vec<Span>)> {
+    let flags = flags.into();
+    let mut parse_result = parse_with_options(input, flags)?;
+    simplify(&mut parse_result, flags);
+    serialize_with_spans_limited(&parse_result, flags, output_limit(flags, input.len()))
+}
+
+/// Like `serialize()`, but also returns the spans of the output string
+/// that correspond to names, parameters and other interesting parts of
+/// the symbol.
+///
+/// Spans can nest (a `Parameter` may contain a `TypeName`, which contains
+/// `Identifier`s) and are sorted by their start; an enclosing span comes
+/// before the spans it contains.
+pub fn serialize_with_spans<O: Into<DemangleOptions>>(
+    input: &ParseResult,
+    flags: O,
+) -> Result<(String, Vec<Span>)> {
+    let flags = flags.into();
+    serialize_with_spans_limited(&simplified(input, flags), flags, flags.max_output)
+}
+
+fn serialize_with_spans_limited(
+    input: &ParseResult,
+    flags: DemangleOptions,
+    limit: Option<usize>,
+) -> Result<(String, Vec<Span>)> {
+    let mut s = String::with_capacity(output_capacity(input, limit));
+    let mut name_writer = DefaultNameWriter;
+    let mut spans = {
+        let mut serializer = Serializer::new(flags, &input.names, &mut s, &mut name_writer);
+        serializer.w.limit = limit;
+        serializer.spans = Some(Vec::new());
+        serializer.serialize_all(input)?;
+        serializer.spans.take().unwrap_or_default()
+    };
+    // Spans are recorded when a node is finished, so inner nodes come first.
+    spans.reverse();
+    spans.sort_by(|a, b| {
+        a.range
+            .start
+            .cmp(&b.range.start)
+            .then(b.range.end.cmp(&a.range.end))
+    });
+    Ok((s, spans))
+}
+
+/// Like `demangle()`, but writes HTML, see `serialize_html()`.
+pub fn demangle_html<O: Into<DemangleOptions>>(input: &str, flags: O) -> Result<String> {
+    let flags = flags.into();
+    let mut parse_result = parse_with_options(input, flags)?;
+    simplify(&mut parse_result, flags);
+    let (s, spans) = serialize_with_spans_limited(&parse_result, flags, output_limit(flags, input.len()))?;
+    Ok(html_from_spans(&s, &spans))
+}
+
+/// Like `serialize()`, but writes HTML for web pages, with the parts of
+/// the name wrapped in `<span>`s of these classes:
+///
+/// * `name`: the qualified name of the symbol.
+/// * `type`: the name of a class, struct, union or enum.
+/// * `template-arg`: a template argument.
+/// * `keyword`: a built-in type, qualifier, calling convention or other
+///   keyword, e.g. `unsigned`, `const`, `__cdecl` or `public`.
+///
+/// Spans nest like those of `serialize_with_spans()`, e.g. a `type` may
+/// contain `template-arg`s. `&`, `<`, `>` and `"` are escaped.
+///
+/// ```
+/// let html = msvc_demangler::demangle_html("?x@@3PEAVFoo@@EA", msvc_demangler::DemangleOptions::new());
+/// assert_eq!(
+///     html.unwrap(),
+///     "<span class=\"keyword\">class</span> <span class=\"type\">Foo</span> *<span class=\"name\">x</span>"
+/// );
+/// ```
+pub fn serialize_html<O: Into<DemangleOptions>>(input: &ParseResult, flags: O) -> Result<String> {
+    let (s, spans) = serialize_with_spans(input, flags)?;
+    Ok(html_from_spans(&s, &spans))
+}
+
+// Marks up `s` with the spans of serialize_with_spans(). Keywords have no
+// spans of their own; they are the words outside of identifiers and
+// operators, as everything the input names is in one of those.
+fn html_from_spans(s: &str, spans: &[Span]) -> String {
+    let mut classes: Vec<(ops::Range<usize>, &str)> = spans
+        .iter()
+        .filter_map(|span| match span.kind {
+            SpanKind::SymbolName => Some((span.range.clone(), "name")),
+            SpanKind::TypeName => Some((span.range.clone(), "type")),
+            SpanKind::TemplateArgument => Some((span.range.clone(), "template-arg")),
+            _ => None,
+        })
+        .collect();
+    let named = |i: usize| {
+        spans.iter().any(|span| {
+            (span.kind == SpanKind::Identifier || span.kind == SpanKind::Operator) && span.range.contains(&i)
+        })
+    };
+    let boundary = |i: usize| classes.iter().any(|(range, _)| range.start == i || range.end == i);
+    let mut keywords = Vec::new();
+    let mut start = None;
+    for (i, c) in s.char_indices().chain(Some((s.len(), ' '))) {
+        let word = c == '_' || c.is_ascii_alphabetic() || (start.is_some() && c.is_ascii_digit());
+        if let Some(begin) = start {
+            if !word || boundary(i) {
+                keywords.push((begin..i, "keyword"));
+                start = None;
+            }
+        }
+        if start.is_none() && word && !named(i) && !c.is_ascii_digit() {
+            start = Some(i);
+        }
+    }
+    classes.extend(keywords);
+    classes.sort_by(|a, b| a.0.start.cmp(&b.0.start).then(b.0.end.cmp(&a.0.end)));
+
+    let mut html = String::with_capacity(s.len() * 2);
+    let mut open: Vec<usize> = Vec::new();
+    let mut next = 0;
+    for (i, c) in s.char_indices() {
+        while open.last() == Some(&i) {
+            html.push_str("</span>");
+            open.pop();
+        }
+        while next < classes.len() && classes[next].0.start == i {
+            let (ref range, class) = classes[next];
+            html.push_str("<span class=\"");
+            html.push_str(class);
+            html.push_str("\">");
+            open.push(range.end);
+            next += 1;
+        }
+        push_html_escaped(&mut html, c);
+    }
+    for _ in open {
+        html.push_str("</span>");
+    }
+    html
+}
+
+fn push_html_escaped(html: &mut String, c: char) {
+    match c {
+        '&' => html.push_str("&amp;"),
+        '<' => html.push_str("&lt;"),
+        '>' => html.push_str("&gt;"),
+        '"' => html.push_str("&quot;"),
+        _ => html.push(c),
+    }
+}
+
+// Whether `enclosing` (innermost first) is the namespace `std` itself.
+fn is_std_scope(enclosing: &[Name]) -> bool {
+    enclosing == [Name::NonTemplate(Cow::Borrowed("std"))]
+}
+
+// If `name`, declared in the scope `enclosing` (innermost first), is the
+// template `std::<template_name><...>`, returns its template arguments.
+fn std_template_args<'b, 'c>(
+    names: &'b [Name<'c>],
+    name: &'b Name<'c>,
+    enclosing: &[Name],
+    template_name: &str,
+) -> Option<&'b [Type<'c>]> {
+    if !is_std_scope(enclosing) {
+        return None;
+    }
+    match *resolve_name(names, name) {
+        Name::Template(ref name, ref params) if **name == Name::NonTemplate(Cow::Borrowed(template_name)) => {
+            Some(&params.types)
+        }
+        _ => None,
+    }
+}
+
+// Like std_template_args(), but for an unqualified class or struct type.
+fn std_template_type_args<'b, 'c>(
+    names: &'b [Name<'c>],
+    t: &'b Type<'c>,
+    template_name: &str,
+) -> Option<&'b [Type<'c>]> {
+    match *t {
+        Type::Class(ref symbol, sc) | Type::Struct(ref symbol, sc) if sc.is_empty() => {
+            std_template_args(names, &symbol.name, &symbol.scope.names, template_name)
+        }
+        _ => None,
+    }
+}
+
+// Standard library templates parameterized by a character type which have
+// typedefs for their instances with the default traits (and allocator):
+// the template name, whether it takes an allocator, and the typedefs for
+// char, wchar_t, char16_t and char32_t.
+static STD_CHAR_TYPEDEFS: &[(&str, bool, [Option<&str>; 4])] = &[
+    ("basic_string", true, [Some("string"), Some("wstring"), Some("u16string"), Some("u32string")]),
+    ("basic_string_view", false, [Some("string_view"), Some("wstring_view"), Some("u16string_view"), Some("u32string_view")]),
+    ("basic_ios", false, [Some("ios"), Some("wios"), None, None]),
+    ("basic_streambuf", false, [Some("streambuf"), Some("wstreambuf"), None, None]),
+    ("basic_istream", false, [Some("istream"), Some("wistream"), None, None]),
+    ("basic_ostream", false, [Some("ostream"), Some("wostream"), None, None]),
+    ("basic_iostream", false, [Some("iostream"), Some("wiostream"), None, None]),
+    ("basic_filebuf", false, [Some("filebuf"), Some("wfilebuf"), None, None]),
+    ("basic_ifstream", false, [Some("ifstream"), Some("wifstream"), None, None]),
+    ("basic_ofstream", false, [Some("ofstream"), Some("wofstream"), None, None]),
+    ("basic_fstream", false, [Some("fstream"), Some("wfstream"), None, None]),
+    ("basic_stringbuf", true, [Some("stringbuf"), Some("wstringbuf"), None, None]),
+    ("basic_istringstream", true, [Some("istringstream"), Some("wistringstream"), None, None]),
+    ("basic_ostringstream", true, [Some("ostringstream"), Some("wostringstream"), None, None]),
+    ("basic_stringstream", true, [Some("stringstream"), Some("wstringstream"), None, None]),
+];
+
+// The name of the standard library typedef for the template instance
+// `name` declared in `enclosing`, e.g. "string" for
+// std::basic_string<char,std::char_traits<char>,std::allocator<char> >.
+fn std_typedef(names: &[Name], name: &Name, enclosing: &[Name]) -> Option<&'static str> {
+    let (args, allocator, typedefs) = STD_CHAR_TYPEDEFS
+        .iter()
+        .find_map(|&(template_name, allocator, ref typedefs)| {
+            std_template_args(names, name, enclosing, template_name).map(|args| (args, allocator, typedefs))
+        })?;
+    let typedef = match args.first() {
+        Some(&Type::Char(sc)) if sc.is_empty() => typedefs[0],
+        Some(&Type::Wchar(sc)) if sc.is_empty() => typedefs[1],
+        Some(&Type::Char16(sc)) if sc.is_empty() => typedefs[2],
+        Some(&Type::Char32(sc)) if sc.is_empty() => typedefs[3],
+        _ => return None,
+    };
+    let char_type = &args[0..1];
+    let expected_len = if allocator { 3 } else { 2 };
+    if args.len() == expected_len
+        && std_template_type_args(names, &args[1], "char_traits") == Some(char_type)
+        && (!allocator || std_template_type_args(names, &args[2], "allocator") == Some(char_type))
+    {
+        typedef
+    } else {
+        None
+    }
+}
+
+// The default of a standard library template argument, in terms of the
+// preceding arguments.
+enum StdDefaultArg {
+    // std::<name><args[i]>, e.g. std::allocator<T>.
+    Unary(&'static str, usize),
+    // std::allocator<std::pair<args[0] const,args[i]> >.
+    PairAllocator(usize),
+}
+
+// Standard library templates with defaulted trailing arguments: the
+// template name, the number of arguments without a default, and the
+// defaults of the remaining arguments.
+static STD_DEFAULT_ARGS: &[(&str, usize, &[StdDefaultArg])] = &[
+    ("vector", 1, &[StdDefaultArg::Unary("allocator", 0)]),
+    ("deque", 1, &[StdDefaultArg::Unary("allocator", 0)]),
+    ("list", 1, &[StdDefaultArg::Unary("allocator", 0)]),
+    ("forward_list", 1, &[StdDefaultArg::Unary("allocator", 0)]),
+    ("set", 1, &[StdDefaultArg::Unary("less", 0), StdDefaultArg::Unary("allocator", 0)]),
+    ("multiset", 1, &[StdDefaultArg::Unary("less", 0), StdDefaultArg::Unary("allocator", 0)]),
+    ("map", 2, &[StdDefaultArg::Unary("less", 0), StdDefaultArg::PairAllocator(1)]),
+    ("multimap", 2, &[StdDefaultArg::Unary("less", 0), StdDefaultArg::PairAllocator(1)]),
+    (
+        "unordered_set",
+        1,
+        &[
+            StdDefaultArg::Unary("hash", 0),
+            StdDefaultArg::Unary("equal_to", 0),
+            StdDefaultArg::Unary("allocator", 0),
+        ],
+    ),
+    (
+        "unordered_multiset",
+        1,
+        &[
+            StdDefaultArg::Unary("hash", 0),
+            StdDefaultArg::Unary("equal_to", 0),
+            StdDefaultArg::Unary("allocator", 0),
+        ],
+    ),
+    (
+        "unordered_map",
+        2,
+        &[
+            StdDefaultArg::Unary("hash", 0),
+            StdDefaultArg::Unary("equal_to", 0),
+            StdDefaultArg::PairAllocator(1),
+        ],
+    ),
+    (
+        "unordered_multimap",
+        2,
+        &[
+            StdDefaultArg::Unary("hash", 0),
+            StdDefaultArg::Unary("equal_to", 0),
+            StdDefaultArg::PairAllocator(1),
+        ],
+    ),
+    ("unique_ptr", 1, &[StdDefaultArg::Unary("default_delete", 0)]),
+    (
+        "basic_string",
+        1,
+        &[StdDefaultArg::Unary("char_traits", 0), StdDefaultArg::Unary("allocator", 0)],
+    ),
+    ("basic_string_view", 1, &[StdDefaultArg::Unary("char_traits", 0)]),
+    ("basic_ostream", 1, &[StdDefaultArg::Unary("char_traits", 0)]),
+    ("basic_istream", 1, &[StdDefaultArg::Unary("char_traits", 0)]),
+    ("basic_iostream", 1, &[StdDefaultArg::Unary("char_traits", 0)]),
+];
+
+impl StdDefaultArg {
+    fn matches(&self, names: &[Name], t: &Type, args: &[Type]) -> bool {
+        match *self {
+            StdDefaultArg::Unary(name, i) => std_template_type_args(names, t, name) == Some(&args[i..=i]),
+            StdDefaultArg::PairAllocator(i) => match std_template_type_args(names, t, "allocator") {
+                Some([pair]) => match std_template_type_args(names, pair, "pair") {
+                    Some([key, value]) => *value == args[i] && is_const_of(key, &args[0]),
+                    _ => false,
+                },
+                _ => false,
+            },
+        }
+    }
+}
+
+// Whether `t` is the type `of` with `const` added.
+fn is_const_of(t: &Type, of: &Type) -> bool {
+    let mut of = of.resolve().clone();
+    match of.storage_class_mut() {
+        Some(sc) if !sc.contains(StorageClass::CONST) => sc.insert(StorageClass::CONST),
+        _ => return false,
+    }
+    *t == of
+}
+
+// The number of template arguments of the template `name` to write,
+// leaving out trailing arguments which are equal to their defaults.
+fn non_default_template_args(names: &[Name], name: &Name, args: &[Type]) -> usize {
+    let (required, defaults) = match STD_DEFAULT_ARGS
+        .iter()
+        .find(|&&(template_name, _, _)| *name == Name::NonTemplate(Cow::Borrowed(template_name)))
+    {
+        Some(&(_, required, defaults)) => (required, defaults),
+        None => return args.len(),
+    };
+    if args.len() != required + defaults.len() {
+        return args.len();
+    }
+    let mut len = args.len();
+    while len > required && defaults[len - required - 1].matches(names, &args[len - 1], args) {
+        len -= 1;
+    }
+    len
+}
+
+// Whether a space is needed between `c` and a following identifier or
+// keyword.
+fn is_ident_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+// The sink the serializer writes to. Spacing decisions depend on what
+// was written last, so this keeps track of that instead of reading the
+// output back. A trailing space is held back until something follows it,
+// which allows the serializer to take it back again.
+struct Output<'a> {
+    w: &'a mut dyn fmt::Write,
+    len: usize,
+    // The number of bytes written since the last newline.
+    column: usize,
+    last: Option<u8>,
+    // The last byte written that isn't part of a run of `*` and `&`.
+    last_non_declarator: Option<u8>,
+    pending_space: bool,
+    max_length: Option<usize>,
+    // Whether writing stopped because `max_length` was reached.
+    truncated: bool,
+    // The length at which writing fails, see DemangleOptions::max_output()
+    // and max_expansion().
+    limit: Option<usize>,
+    limit_exceeded: bool,
+}
+
+impl<'a> Output<'a> {
+    fn new(w: &'a mut dyn fmt::Write, max_length: Option<usize>, limit: Option<usize>) -> Output<'a> {
+        Output {
+            w,
+            len: 0,
+            column: 0,
+            last: None,
+            last_non_declarator: None,
+            pending_space: false,
+            max_length,
+            truncated: false,
+            limit,
+            limit_exceeded: false,
+        }
+    }
+
+    // The number of bytes written so far, including a held back space.
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    // The number of bytes written so far, not counting a held back space.
+    fn len_without_trailing_space(&self) -> usize {
+        if self.pending_space {
+            self.len - 1
+        } else {
+            self.len
+        }
+    }
+
+    fn last(&self) -> Option<u8> {
+        if self.pending_space {
+            Some(b' ')
+        } else {
+            self.last
+        }
+    }
+
+    fn last_non_declarator(&self) -> Option<u8> {
+        if self.pending_space {
+            Some(b' ')
+        } else {
+            self.last_non_declarator
+        }
+    }
+
+    // Takes back a trailing space, returning whether there was one.
+    fn pop_space(&mut self) -> bool {
+        let popped = self.pending_space;
+        if popped {
+            self.pending_space = false;
+            self.len -= 1;
+            self.column -= 1;
+        }
+        popped
+    }
+
+    fn finish(&mut self) -> fmt::Result {
+        if self.pending_space {
+            self.pending_space = false;
+            self.w.write_str(" ")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Write for Output<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.is_empty() {
+            return Ok(());
+        }
+        if let Some(max_length) = self.max_length {
+            if self.len + s.len() > max_length {
+                // Write what still fits and make the serializer bail out.
+                let mut end = max_length - self.len;
+                while !s.is_char_boundary(end) {
+                    end -= 1;
+                }
+                self.finish()?;
+                self.w.write_str(&s[..end])?;
+                self.w.write_str("...")?;
+                self.truncated = true;
+                return Err(fmt::Error);
+            }
+        }
+        if let Some(limit) = self.limit {
+            if self.len + s.len() > limit {
+                self.limit_exceeded = true;
+                return Err(fmt::Error);
+            }
+        }
+        self.finish()?;
+        self.len += s.len();
+        match s.rfind('\n') {
+            Some(pos) => self.column = s.len() - pos - 1,
+            None => self.column += s.len(),
+        }
+        let s = match s.strip_suffix(' ') {
+            Some(s) => {
+                self.pending_space = true;
+                s
+            }
+            None => s,
+        };
+        self.w.write_str(s)?;
+        if let Some(&c) = s.as_bytes().last() {
+            self.last = Some(c);
+        }
+        if let Some(&c) = s.as_bytes().iter().rev().find(|&&b| b != b'*' && b != b'&') {
+            self.last_non_declarator = Some(c);
+        }
+        Ok(())
+    }
+}
+
+// Converts an AST to a string.
+//
+// Converting an AST representing a C++ type to a string is tricky due
+// to the bad grammar of the C++ declaration inherited from C. You have
+// to construct a string from inside to outside. For example, if a type
+// X is a pointer to a function returning int, the order you create a
+// string becomes something like this:
+//
+//   (1) X is a pointer: *X
+//   (2) (1) is a function returning int: int (*X)()
+//
+// So you cannot construct a result just by appending strings to a result.
+//
+// To deal with this, we split the function into two. write_pre() writes
+// the "first half" of type declaration, and write_post() writes the
+// "second half". For example, write_pre() writes a return type for a
+// function and write_post() writes an parameter list.
+struct Serializer<'a> {
+    flags: DemangleOptions,
+    // The names that Name::Backrefs refer to.
+    names: &'a [Name<'a>],
+    w: Output<'a>,
+    name_writer: &'a mut dyn NameWriter,
+    // Only collected for serialize_with_spans().
+    spans: Option<Vec<Span>>,
+    // The number of template argument lists we are currently in.
+    template_depth: usize,
+    // The number of lists being spread over multiple lines.
+    indent: usize,
+    // The current nesting level of types and names, and the number of
+    // levels written recursively, see MAX_RECURSIVE_DEPTH.
+    depth: usize,
+    recursion: usize,
+    // The `this` adjustment of the thunk being written, until it is
+    // written before its parameters.
+    thunk_adjustment: Option<i32>,
+}
+
+impl<'a> Serializer<'a> {
+    // Serialize a complete symbol.
+    fn serialize_all(&mut self, parse_result: &ParseResult) -> SerializeResult<()> {
+        match self.serialize_unchecked(parse_result) {
+            // The output was cut off at the length limit.
+            Err(_) if self.w.truncated => Ok(()),
+            Err(_) if self.w.limit_exceeded => Err(SerializeError::output_limit_exceeded(
+                "demangled symbol is too long".to_owned(),
+            )),
+            result => {
+                result?;
+                self.w.finish()?;
+                Ok(())
+            }
+        }
+    }
+
+    fn new(
+        flags: DemangleOptions,
+        names: &'a [Name<'a>],
+        w: &'a mut dyn fmt::Write,
+        name_writer: &'a mut dyn NameWriter,
+    ) -> Serializer<'a> {
+        Serializer {
+            flags,
+            names,
+            w: Output::new(w, flags.max_length, flags.max_output),
+            name_writer,
+            spans: None,
+            template_depth: 0,
+            indent: 0,
+            depth: 0,
+            recursion: 0,
+            thunk_adjustment: None,
+        }
+    }
+
+    // Whether to write the output of `FormatProfile::V1`, i.e. of
+    // msvc-demangler 0.5.1, where it differs from the current format.
+    fn v1(&self) -> bool {
+        self.flags.format_profile == Some(FormatProfile::V1)
+    }
+
+    // The name that Name::Backref(i) refers to.
+    fn backref(&self, i: usize) -> SerializeResult<&'a Name<'a>> {
+        self.names
+            .get(i)
+            .ok_or_else(|| SerializeError::new(format!("invalid name back-reference: {}", i)))
+    }
+
+    // Runs `f` one nesting level deeper, failing if that is too deep.
+    fn nested<F: FnOnce(&mut Self) -> SerializeResult<()>>(&mut self, f: F) -> SerializeResult<()> {
+        self.enter()?;
+        let result = self.recursive(f);
+        self.depth -= 1;
+        result
+    }
+
+    // Runs `f`, which recurses into the serializer, failing if the
+    // serializer is MAX_RECURSIVE_DEPTH levels deep already.
+    fn recursive<F: FnOnce(&mut Self) -> SerializeResult<()>>(&mut self, f: F) -> SerializeResult<()> {
+        if self.recursion >= MAX_RECURSIVE_DEPTH {
+            return Err(SerializeError::limit_exceeded("symbol is nested too deeply".to_owned()));
+        }
+        self.recursion += 1;
+        let result = f(self);
+        self.recursion -= 1;
+        result
+    }
+
+    // Goes one nesting level deeper, failing if that is too deep. The caller
+    // restores `depth` when leaving the level.
+    fn enter(&mut self) -> SerializeResult<()> {
+        if self.depth >= depth_limit(self.flags.max_depth) {
+            return Err(SerializeError::limit_exceeded("symbol is nested too deeply".to_owned()));
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    // Goes one nesting level deeper for the type `t`, like the parser does.
+    // The parser reads function types as part of the pointer or symbol
+    // they belong to, so they don't count as a level of their own, and
+    // symbols without a type have no level for it.
+    fn enter_type(&mut self, t: &Type) -> SerializeResult<()> {
+        if t.is_function() || *t == Type::None {
+            Ok(())
+        } else {
+            self.enter()
+        }
+    }
+
+    fn serialize(&mut self, parse_result: &ParseResult) -> SerializeResult<()> {
+        self.nested(|this| this.serialize_unchecked(parse_result))
+    }
+
+    fn serialize_unchecked(&mut self, parse_result: &ParseResult) -> SerializeResult<()> {
+        let symbol = &parse_result.symbol;
+        let is_special = match parse_result.symbol_type {
+            Type::ThreadSafeStaticGuard(_) => true,
+            _ => symbol.name.is_special_name(),
+        };
+        if is_special && !symbol.scope.names.is_empty() {
+            if self.flags.contains(DemangleOptions::DESCRIBE_SPECIAL_SYMS) {
+                // "vtable for ns::Foo", "guard variable for f()".
+                let mut name = &symbol.name;
+                while let Name::Template(ref inner, _) = *name {
+                    name = inner;
+                }
+                let what = match *name {
+                    Name::Operator(Operator::LocalStaticGuard) => "guard variable",
+                    Name::Operator(Operator::VFTable) => "vtable",
+                    Name::Operator(Operator::VBTable) => "vbtable",
+                    Name::Operator(ref op) => op.to_str().trim_matches(|c| c == '`' || c == '\''),
+                    // Thread-safe static guards are named "TSS0" and so on.
+                    _ => "guard variable",
+                };
+                write!(self.w, "{} for ", what)?;
+            }
+            if self.flags.contains(DemangleOptions::NO_SPECIAL_SYMS)
+                || self.flags.contains(DemangleOptions::DESCRIBE_SPECIAL_SYMS)
+            {
+                // Only write the class (or function) the symbol belongs to.
+                let start = self.w.len();
+                self.write_scope(&symbol.scope)?;
+                self.add_span(start, SpanKind::SymbolName);
+                return Ok(());
+            }
+        }
+        if self.flags.contains(DemangleOptions::NAME_ONLY) {
+            return self.write_name(&parse_result.symbol, Some(&parse_result.symbol_type), SpanKind::SymbolName);
+        }
+        // The target type of a conversion operator is encoded as its return
+        // type, but it is printed as part of the name ("operator bool").
+        // Version 1 wrote it as the return type of "operatorcast".
+        let return_type = (self.v1() || !parse_result.symbol.name.is_conversion_operator())
+            && !self.flags.contains(DemangleOptions::NO_RETURN_TYPE);
+        let compat = self.flags.contains(DemangleOptions::UNDNAME_COMPAT)
+            || self.flags.contains(DemangleOptions::LLVM_UNDNAME_COMPAT);
+        // "public: static int Foo::x", as for static member functions.
+        if let (true, Some(VariableKind::StaticMember(access))) = (compat, parse_result.variable_kind) {
+            if !self.flags.contains(DemangleOptions::NO_ACCESS_SPECIFIERS) {
+                match access {
+                    Access::Public => write!(self.w, "public: ")?,
+                    Access::Protected => write!(self.w, "protected: ")?,
+                    Access::Private => write!(self.w, "private: ")?,
+                }
+            }
+            if !self.flags.contains(DemangleOptions::NO_MEMBER_TYPE) {
+                write!(self.w, "static ")?;
+            }
+        }
+        self.write_func_pre(&parse_result.symbol_type, return_type)?;
+        self.write_name(
+            &parse_result.symbol,
+            Some(&parse_result.symbol_type),
+            SpanKind::SymbolName,
+        )?;
+        if compat {
+            self.thunk_adjustment = parse_result.thunk_adjustment;
+        }
+        let arguments = !self.flags.contains(DemangleOptions::NO_ARGUMENTS);
+        self.write_func_post(&parse_result.symbol_type, return_type, arguments)?;
+        Ok(())
+    }
+
+    // Record that the output from `start` up to the current position
+    // represents a node of the given kind.
+    fn add_span(&mut self, start: usize, kind: SpanKind) {
+        let end = self.w.len_without_trailing_space();
+        if let Some(ref mut spans) = self.spans {
+            if start < end {
+                spans.push(Span {
+                    range: start..end,
+                    kind,
+                });
+            }
+        }
+    }
+
+    // Strips the leading underscores from a Microsoft keyword if requested.
+    fn ms_keyword(&self, keyword: &'static str) -> &'static str {
+        if self.flags.contains(DemangleOptions::NO_LEADING_UNDERSCORES) {
+            keyword.trim_start_matches('_')
+        } else {
+            keyword
+        }
+    }
+
+    fn write_calling_conv(&mut self, calling_conv: CallingConv) -> SerializeResult<()> {
+        if self.flags.contains(DemangleOptions::NO_MS_KEYWORDS) {
+            return Ok(());
+        }
+        match self.w.last() {
+            None | Some(b' ') | Some(b'(') | Some(b'`') => {}
+            _ => write!(self.w, " ")?,
+        }
+        let start = self.w.len();
+        match calling_conv {
+            CallingConv::Cdecl => {
+                write!(self.w, "{} ", self.ms_keyword("__cdecl"))?;
+            },
+            CallingConv::Pascal => {
+            },
+            CallingConv::Thiscall => {
+                write!(self.w, "{} ", self.ms_keyword("__thiscall"))?;
+            },
+            CallingConv::Stdcall => {
+                write!(self.w, "{} ", self.ms_keyword("__stdcall"))?;
+            },
+            CallingConv::Fastcall => {
+                write!(self.w, "{} ", self.ms_keyword("__fastcall"))?;
+            },
+            CallingConv::_Regcall => {
+                write!(self.w, "{} ", self.ms_keyword("__regcall"))?;
+            },
+        };
+        self.add_span(start, SpanKind::CallingConvention);
+
+        Ok(())
+    }
+
+    // Write the "first half" of a function type, optionally leaving out
+    // the return type. Other types are passed on to write_pre().
+    fn write_func_pre(&mut self, t: &Type, return_type: bool) -> SerializeResult<()> {
+        match *t {
+            Type::MemberFunction(func_class, calling_conv, _, _, ref inner) => {
+                if func_class.contains(FuncClass::THUNK) {
+                    if self.flags.contains(DemangleOptions::LLVM_UNDNAME_COMPAT) {
+                        write!(self.w, "[thunk]: ")?
+                    } else {
+                        write!(self.w, "[thunk]:")?
+                    }
+                }
+                if !self.flags.contains(DemangleOptions::NO_ACCESS_SPECIFIERS) {
+                    if func_class.contains(FuncClass::PRIVATE) {
+                        write!(self.w, "private: ")?
+                    }
+                    if func_class.contains(FuncClass::PROTECTED) {
+                        write!(self.w, "protected: ")?
+                    }
+                    if func_class.contains(FuncClass::PUBLIC) {
+                        write!(self.w, "public: ")?
+                    }
+                }
+                if !self.flags.contains(DemangleOptions::NO_MEMBER_TYPE) {
+                    if func_class.contains(FuncClass::STATIC) {
+                        write!(self.w, "static ")?
+                    }
+                    if func_class.contains(FuncClass::VIRTUAL) {
+                        write!(self.w, "virtual ")?;
+                    }
+                }
+                if return_type {
+                    self.write_return_type_pre(inner)?;
+                }
+                self.write_calling_conv(calling_conv)?;
+            }
+            Type::NonMemberFunction(calling_conv, _, _, ref inner) => {
+                if return_type {
+                    self.write_return_type_pre(inner)?;
+                }
+                self.write_calling_conv(calling_conv)?;
+            }
+            _ => self.write_pre(t)?,
+        }
+        Ok(())
+    }
+
+    fn write_return_type_pre(&mut self, t: &Type) -> SerializeResult<()> {
+        let start = self.w.len();
+        self.write_pre(t)?;
+        self.add_span(start, SpanKind::ReturnType);
+        Ok(())
+    }
+
+    // Write the "first half" of a given type. Pointers, references and
+    // arrays are written after the type they wrap, so rather than recursing
+    // into it, they are kept on a stack until it has been written. The
+    // types of templates, functions and nested names are written
+    // recursively, see MAX_RECURSIVE_DEPTH.
+    fn write_pre(&mut self, t: &Type) -> SerializeResult<()> {
+        let depth = self.depth;
+        let result = self.recursive(|this| this.write_pre_wrapped(t));
+        self.depth = depth;
+        result
+    }
+
+    fn write_pre_wrapped(&mut self, mut t: &Type) -> SerializeResult<()> {
+        let mut wrappers = Vec::new();
+        loop {
+            t = t.resolve();
+            self.enter_type(t)?;
+            match self.wrapped_type(t) {
+                Some(inner) => {
+                    wrappers.push(t);
+                    t = inner;
+                }
+                None => break,
+            }
+        }
+        self.write_pre_unchecked(t)?;
+        for t in wrappers.into_iter().rev() {
+            self.write_wrapper_pre(t)?;
+        }
+        Ok(())
+    }
+
+    // The type that a pointer, reference or array wraps, unless it is a
+    // pointer to a function written in the style of undname, whose return
+    // type comes before the calling convention.
+    fn wrapped_type<'t, 'b>(&self, t: &'t Type<'b>) -> Option<&'t Type<'b>> {
+        match *t {
+            Type::Ptr(ref inner, _) | Type::Ref(ref inner, _) | Type::RValueRef(ref inner, _)
+                if !self.is_undname_function_pointee(inner) =>
+            {
+                Some(inner)
+            }
+            Type::Array(_, ref inner, _) => Some(inner),
+            _ => None,
+        }
+    }
+
+    fn is_undname_function_pointee(&self, inner: &Type) -> bool {
+        inner.is_function()
+            && (self.flags.contains(DemangleOptions::LLVM_UNDNAME_COMPAT)
+                || self.flags.contains(DemangleOptions::UNDNAME_COMPAT))
+    }
+
+    // Write the part of the "first half" of a pointer, reference or array
+    // that follows the type it wraps.
+    fn write_wrapper_pre(&mut self, t: &Type) -> SerializeResult<()> {
+        let storage_class = match *t {
+            Type::Ptr(ref inner, storage_class) |
+            Type::Ref(ref inner, storage_class) |
+            Type::RValueRef(ref inner, storage_class) => {
+                // "[]" and "()" (for function parameters) take precedence over "*",
+                // so "int *x(int)" means "x is a function returning int *". We need
+                // parentheses to supercede the default precedence. (e.g. we want to
+                // emit something like "int (*x)(int)".)
+                let mut space_before_declarator = !self.flags.contains(DemangleOptions::LESS_WHITESPACE)
+                    // c++filt attaches the declarator to the type: "char const*".
+                    && !self.flags.contains(DemangleOptions::GNU_STYLE);
+                if self.is_undname_function_pointee(inner) {
+                    // write_pre_unchecked() wrote "int (__cdecl" already.
+                    if self.flags.contains(DemangleOptions::UNDNAME_COMPAT) {
+                        space_before_declarator = false;
+                    }
+                } else if inner.is_function() || matches!(*inner.resolve(), Type::Array(..)) {
+                    if !self.flags.contains(DemangleOptions::LESS_WHITESPACE) {
+                        self.write_space()?;
+                    }
+                    write!(self.w, "(")?;
+                }
+
+                if space_before_declarator {
+                    self.write_space()?;
+                }
+                match *t {
+                    Type::Ptr(_, _) => write!(self.w, "*")?,
+                    Type::Ref(_, _) => write!(self.w, "&")?,
+                    Type::RValueRef(_, _) => write!(self.w, "&&")?,
+                    _ => {}
+                }
+
+                if self.flags.contains(DemangleOptions::UNDNAME_COMPAT)
+                    && !self.flags.contains(DemangleOptions::NO_MS_KEYWORDS)
+                    && storage_class.contains(StorageClass::PTR64)
+                {
+                    write!(self.w, " {}", self.ms_keyword("__ptr64"))?;
+                }
+
+                storage_class
+            }
+            Type::Array(_len, _, storage_class) => storage_class,
+            _ => return Ok(()),
+        };
+        self.write_cv(storage_class)
+    }
+
+    // Write the const and volatile qualifiers of a type.
+    fn write_cv(&mut self, storage_class: StorageClass) -> SerializeResult<()> {
+        if storage_class.contains(StorageClass::CONST) {
+            self.write_space()?;
+            write!(self.w, "const")?;
+        }
+        if storage_class.contains(StorageClass::VOLATILE) {
+            self.write_space()?;
+            write!(self.w, "volatile")?;
+        }
+        Ok(())
+    }
+
+    fn write_pre_unchecked(&mut self, t: &Type) -> SerializeResult<()> {
+        let storage_class = match *t {
+            Type::None => return Ok(()),
+            Type::Shared(ref inner) => return self.write_pre(inner),
+            Type::MemberFunction(..) | Type::NonMemberFunction(..) => {
+                return self.write_func_pre(t, true);
+            }
+            Type::MemberFunctionPointer(ref symbol, _, calling_conv, _, _, ref inner) => {
+                self.write_pre(inner)?;
+                if self.flags.contains(DemangleOptions::LLVM_UNDNAME_COMPAT) {
+                    // "void (__cdecl X::*)(void)"
+                    self.write_space()?;
+                    write!(self.w, "(")?;
+                    self.write_calling_conv(calling_conv)?;
+                    self.write_name(symbol, None, SpanKind::TypeName)?;
+                    write!(self.w, "::*)")?;
+                    return Ok(());
+                }
+                self.write_calling_conv(calling_conv)?;
+                if !self.flags.contains(DemangleOptions::LESS_WHITESPACE) {
+                    self.write_space()?;
+                }
+                write!(self.w, "(")?;
+                if !self.flags.contains(DemangleOptions::LESS_WHITESPACE) {
+                    self.write_space()?;
+                }
+                self.write_name(symbol, None, SpanKind::TypeName)?;
+                write!(self.w, "::*)")?;
+                return Ok(());
+            }
+            Type::CXXVBTable(_, sc) => sc,
+            Type::CXXVFTable(_, sc) => sc,
+            Type::TemplateParameterWithIndex(n) => {
+                write!(self.w, "`template-parameter{}'", n)?;
+                return Ok(());
+            }
+            Type::ThreadSafeStaticGuard(num) => {
+                write!(self.w, "TSS{}", num)?;
+                return Ok(());
+            }
+            Type::Constant(n) => {
+                match self.flags.hex_threshold {
+                    Some(threshold) if u64::from(n.unsigned_abs()) > threshold => {
+                        let sign = if n < 0 { "-" } else { "" };
+                        write!(self.w, "{}{:#x}", sign, n.unsigned_abs())?;
+                    }
+                    _ => write!(self.w, "{}", n)?,
+                }
+                return Ok(());
+            }
+            Type::ConstantString(_) => {
+                // We have no idea what the original encoding of the string is,
+                // and undname doesn't even try to display anything.
+                //match str::from_utf8(s).ok() {
+                //  Some(ref s) => write!(self.w, "{}", s)?,
+                //  None => {},
+                //}
+                return Ok(());
+            }
+            Type::VarArgs => {
+                write!(self.w, "...")?;
+                return Ok(());
+            }
+            // Other pointers, references and arrays are written by
+            // write_pre(), see wrapped_type().
+            Type::Ptr(ref inner, _) | Type::Ref(ref inner, _) | Type::RValueRef(ref inner, _) => {
+                if let Type::MemberFunction(_, calling_conv, _, _, ref ret)
+                | Type::NonMemberFunction(calling_conv, _, _, ref ret) = *inner.resolve()
+                {
+                    // "int (__cdecl *x)(int)", or "int (__cdecl*x)(int)"
+                    // for undname.
+                    self.write_pre(ret)?;
+                    self.write_space()?;
+                    write!(self.w, "(")?;
+                    self.write_calling_conv(calling_conv)?;
+                    if self.flags.contains(DemangleOptions::UNDNAME_COMPAT) {
+                        self.w.pop_space();
+                    }
+                }
+                return self.write_wrapper_pre(t);
+            }
+            Type::Array(..) => return self.write_wrapper_pre(t),
+            Type::Struct(ref names, sc) => {
+                self.write_class(t, names, "struct")?;
+                sc
+            }
+            Type::Union(ref names, sc) => {
+                self.write_class(t, names, "union")?;
+                sc
+            }
+            Type::Class(ref names, sc) => {
+                self.write_class(t, names, "class")?;
+                sc
+            }
+            Type::Typedef(ref names, sc) => {
+                let start = self.w.len();
+                if self.name_writer.write_type_name(&mut self.w, t)? {
+                    self.add_span(start, SpanKind::TypeName);
+                } else {
+                    self.write_name(names, None, SpanKind::TypeName)?;
+                }
+                sc
+            }
+            Type::Enum(ref names, ref underlying, sc) => {
+                self.write_class(t, names, "enum")?;
+                if self.flags.contains(DemangleOptions::ENUM_UNDERLYING_TYPES) {
+                    write!(self.w, " : ")?;
+                    self.write_pre(underlying)?;
+                }
+                sc
+            }
+            Type::Void(sc) => {
+                write!(self.w, "void")?;
+                sc
+            }
+            Type::Bool(sc) => {
+                write!(self.w, "bool")?;
+                sc
+            }
+            Type::Char(sc) => {
+                write!(self.w, "char")?;
+                sc
+            }
+            Type::Schar(sc) => {
+                write!(self.w, "signed char")?;
+                sc
+            }
+            Type::Uchar(sc) => {
+                write!(self.w, "unsigned char")?;
+                sc
+            }
+            Type::Short(sc) => {
+                write!(self.w, "short")?;
+                sc
+            }
+            Type::Ushort(sc) => {
+                write!(self.w, "unsigned short")?;
+                sc
+            }
+            Type::Int(sc) => {
+                write!(self.w, "int")?;
+                sc
+            }
+            Type::Uint(sc) => {
+                write!(self.w, "unsigned int")?;
+                sc
+            }
+            Type::Long(sc) => {
+                write!(self.w, "long")?;
+                sc
+            }
+            Type::Ulong(sc) => {
+                write!(self.w, "unsigned long")?;
+                sc
+            }
+            Type::Int64(sc) => {
+                match self.flags.int64_spelling.unwrap_or(Int64Spelling::Stdint) {
+                    Int64Spelling::Stdint => write!(self.w, "int64_t")?,
+                    Int64Spelling::Ms => write!(self.w, "{}", self.ms_keyword("__int64"))?,
+                    Int64Spelling::LongLong => write!(self.w, "long long")?,
+                }
+                sc
+            }
+            Type::Uint64(sc) => {
+                match self.flags.int64_spelling.unwrap_or(Int64Spelling::Stdint) {
+                    Int64Spelling::Stdint => write!(self.w, "uint64_t")?,
+                    Int64Spelling::Ms => write!(self.w, "unsigned {}", self.ms_keyword("__int64"))?,
+                    Int64Spelling::LongLong => write!(self.w, "unsigned long long")?,
+                }
+                sc
+            }
+            Type::Wchar(sc) => {
+                write!(self.w, "wchar_t")?;
+                sc
+            }
+            Type::Float(sc) => {
+                write!(self.w, "float")?;
+                sc
+            }
+            Type::Double(sc) => {
+                write!(self.w, "double")?;
+                sc
+            }
+            Type::Ldouble(sc) => {
+                write!(self.w, "long double")?;
+                sc
+            }
+            Type::Char16(sc) => {
+                write!(self.w, "char16_t")?;
+                sc
+            },
+            Type::Char32(sc) => {
+                write!(self.w, "char32_t")?;
+                sc
+            },
+            Type::Nullptr => {
+                write!(self.w, "std::nullptr_t")?;
+                return Ok(());
+            }
+            Type::Extension(ref name, sc) => {
+                write!(self.w, "{}", name)?;
+                sc
+            }
+            Type::EmptyParameterPack => {
+                return Ok(())
+            },
+        };
+
+        self.write_cv(storage_class)
+    }
+
+    fn write_memfn_qualifiers(&mut self, mut sc: StorageClass) -> SerializeResult<()> {
+        let no_cv = self.flags.contains(DemangleOptions::NO_CV_THISTYPE);
+        if self.flags.contains(DemangleOptions::NO_MS_KEYWORDS) {
+            sc.remove(StorageClass::PTR64 | StorageClass::RESTRICT);
+        }
+        if self.flags.contains(DemangleOptions::UNDNAME_COMPAT) {
+            return self.write_undname_memfn_qualifiers(sc, no_cv);
+        }
+        if self.flags.contains(DemangleOptions::LLVM_UNDNAME_COMPAT)
+            || self.flags.contains(DemangleOptions::GNU_STYLE)
+        {
+            return self.write_spaced_memfn_qualifiers(sc, no_cv);
+        }
+        let restrict = self.ms_keyword("__restrict");
+        let v1 = self.v1();
+        let mut write_one_qual = |flag, s| -> SerializeResult<()> {
+            if sc.contains(flag) {
+                self.w.write_str(s)?;
+                if !self.flags.contains(DemangleOptions::LESS_WHITESPACE) {
+                    self.write_space()?;
+                }
+            }
+
+            Ok(())
+        };
+
+        // TODO: DemangleOptions::LESS_WHITESPACE means we run all these together.
+        if !no_cv {
+            write_one_qual(StorageClass::CONST, "const")?;
+            // Version 1 left out `volatile`.
+            if !v1 {
+                write_one_qual(StorageClass::VOLATILE, "volatile")?;
+            }
+        }
+        // __restrict is different than `restrict`, keep the underscores
+        // unless asked not to.
+        write_one_qual(StorageClass::RESTRICT, restrict)?;
+        // TODO: undname prints ref-qualifiers tightly to previous qualifiers.
+        write_one_qual(StorageClass::LVALUE_QUAL, "&")?;
+        write_one_qual(StorageClass::RVALUE_QUAL, "&&")?;
+
+        Ok(())
+    }
+
+    // Write the "second half" of a function type, optionally leaving out
+    // the return type or the parameter list. Other types are passed on to
+    // write_post().
+    fn write_func_post(&mut self, t: &Type, return_type: bool, arguments: bool) -> SerializeResult<()> {
+        match *t {
+            Type::MemberFunction(_, _, ref params, sc, ref inner)
+            | Type::NonMemberFunction(_, ref params, sc, ref inner) => {
+                // undname writes "void __cdecl(void)" for function types.
+                if arguments
+                    && (self.flags.contains(DemangleOptions::UNDNAME_COMPAT)
+                        || self.flags.contains(DemangleOptions::LLVM_UNDNAME_COMPAT))
+                {
+                    self.w.pop_space();
+                }
+                // "Foo::Release`adjustor{4}' (void)"; llvm-undname leaves out
+                // the space.
+                if let Some(adjustment) = self.thunk_adjustment.take() {
+                    write!(self.w, "`adjustor{{{}}}'", adjustment)?;
+                    if self.flags.contains(DemangleOptions::UNDNAME_COMPAT) {
+                        write!(self.w, " ")?;
+                    }
+                }
+                if arguments {
+                    self.write_params(params)?;
+                }
+
+                if return_type {
+                    self.write_post(inner)?;
+                }
+
+                if arguments {
+                    self.write_memfn_qualifiers(sc)?;
+                }
+            }
+            _ => self.write_post(t)?,
+        }
+        Ok(())
+    }
+
+    // undname attaches cv- and ref-qualifiers to what precedes them, and
+    // separates the MS keywords with spaces: "(void)const __ptr64 __restrict&".
+    fn write_undname_memfn_qualifiers(&mut self, sc: StorageClass, no_cv: bool) -> SerializeResult<()> {
+        if !no_cv {
+            if sc.contains(StorageClass::CONST) {
+                write!(self.w, "const")?;
+            }
+            if sc.contains(StorageClass::VOLATILE) {
+                self.write_space()?;
+                write!(self.w, "volatile")?;
+            }
+        }
+        if sc.contains(StorageClass::PTR64) {
+            write!(self.w, " {}", self.ms_keyword("__ptr64"))?;
+        }
+        if sc.contains(StorageClass::RESTRICT) {
+            write!(self.w, " {}", self.ms_keyword("__restrict"))?;
+        }
+        if sc.contains(StorageClass::LVALUE_QUAL) {
+            write!(self.w, "&")?;
+        }
+        if sc.contains(StorageClass::RVALUE_QUAL) {
+            write!(self.w, "&&")?;
+        }
+        Ok(())
+    }
+
+    // llvm-undname and c++filt put a space before each qualifier:
+    // "(void) const &&".
+    fn write_spaced_memfn_qualifiers(&mut self, sc: StorageClass, no_cv: bool) -> SerializeResult<()> {
+        if !no_cv {
+            if sc.contains(StorageClass::CONST) {
+                write!(self.w, " const")?;
+            }
+            if sc.contains(StorageClass::VOLATILE) {
+                write!(self.w, " volatile")?;
+            }
+        }
+        if sc.contains(StorageClass::RESTRICT) {
+            write!(self.w, " {}", self.ms_keyword("__restrict"))?;
+        }
+        if sc.contains(StorageClass::LVALUE_QUAL) {
+            write!(self.w, " &")?;
+        }
+        if sc.contains(StorageClass::RVALUE_QUAL) {
+            write!(self.w, " &&")?;
+        }
+        Ok(())
+    }
+
+    // Write the "second half" of a given type. Like write_pre(), this goes
+    // through pointers, references and arrays without recursing.
+    fn write_post(&mut self, t: &Type) -> SerializeResult<()> {
+        let depth = self.depth;
+        let result = self.recursive(|this| this.write_post_wrapped(t));
+        self.depth = depth;
+        result
+    }
+
+    fn write_post_wrapped(&mut self, mut t: &Type) -> SerializeResult<()> {
+        loop {
+            t = t.resolve();
+            self.enter_type(t)?;
+            t = match *t {
+                Type::Ptr(ref inner, _sc) | Type::Ref(ref inner, _sc) => {
+                    if inner.is_function() || matches!(*inner.resolve(), Type::Array(..)) {
+                        write!(self.w, ")")?;
+                    }
+                    inner
+                }
+                Type::Array(len, ref inner, _sc) => {
+                    write!(self.w, "[{}]", len)?;
+                    inner
+                }
+                ref t => return self.write_post_unchecked(t),
+            };
+        }
+    }
+
+    fn write_post_unchecked(&mut self, t: &Type) -> SerializeResult<()> {
+        match *t {
+            Type::MemberFunction(..) | Type::NonMemberFunction(..) => {
+                self.write_func_post(t, true, true)?;
+            }
+            Type::MemberFunctionPointer(_, _, _, ref params, mut sc, ref return_type) => {
+                self.write_params(params)?;
+
+                self.write_post(return_type)?;
+
+                if self.flags.contains(DemangleOptions::NO_MS_KEYWORDS) {
+                    sc.remove(StorageClass::PTR64 | StorageClass::RESTRICT);
+                }
+                if self.flags.contains(DemangleOptions::UNDNAME_COMPAT) {
+                    self.write_undname_memfn_qualifiers(sc, false)?;
+                } else if self.flags.contains(DemangleOptions::LLVM_UNDNAME_COMPAT)
+                    || self.flags.contains(DemangleOptions::GNU_STYLE)
+                {
+                    self.write_spaced_memfn_qualifiers(sc, false)?;
+                } else if sc.contains(StorageClass::CONST) {
+                    write!(self.w, "const")?;
+                    if !self.flags.contains(DemangleOptions::LESS_WHITESPACE) {
+                        self.write_space()?;
+                    }
+                }
+            }
+            Type::CXXVBTable(ref names, _sc) => {
+                self.write_scope(names)?;
+                write!(self.w, "'}}")?; // the rest of the "operator"
+            }
+            Type::CXXVFTable(ref names, _) if !names.names.is_empty() => {
+                write!(self.w, "{{for ")?;
+                for name in &names.names {
+                    write!(self.w, "`")?;
+                    self.write_one_name(name)?;
+                    write!(self.w, "'")?;
+                }
+                write!(self.w, "}}")?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Write the parenthesized parameter list of a function.
+    fn write_params(&mut self, params: &Params) -> SerializeResult<()> {
+        write!(self.w, "(")?;
+        // c++filt writes "f()" rather than "f(void)".
+        let is_void = match params.types[..] {
+            [Type::Void(sc)] => sc.is_empty(),
+            _ => false,
+        };
+        if !(is_void && self.flags.contains(DemangleOptions::GNU_STYLE)) {
+            self.write_types(&params.types, SpanKind::Parameter)?;
+        }
+        write!(self.w, ")")?;
+        Ok(())
+    }
+
+    // Write a function or template parameter list.
+    fn write_types(&mut self, types: &[Type], kind: SpanKind) -> SerializeResult<()> {
+        let separator = if self.flags.contains(DemangleOptions::LLVM_UNDNAME_COMPAT)
+            || self.flags.contains(DemangleOptions::GNU_STYLE)
+        {
+            ", "
+        } else {
+            ","
+        };
+        // The closing bracket needs to fit as well.
+        let wrap = match self.flags.wrap_width {
+            Some(width) => self.w.column + self.flat_len(types, kind)? + 1 > width,
+            None => false,
+        };
+        if wrap {
+            self.indent += 1;
+        }
+        for (i, param) in types.iter().enumerate() {
+            if i > 0 {
+                if wrap {
+                    write!(self.w, ",")?;
+                } else {
+                    write!(self.w, "{}", separator)?;
+                }
+            }
+            if wrap {
+                self.write_newline()?;
+            }
+            let start = self.w.len();
+            self.write_pre(param)?;
+            self.write_post(param)?;
+            self.add_span(start, kind);
+        }
+        if wrap {
+            self.indent -= 1;
+            self.write_newline()?;
+        }
+        Ok(())
+    }
+
+    // The length of a parameter or template argument list when written on
+    // a single line.
+    fn flat_len(&mut self, types: &[Type], kind: SpanKind) -> SerializeResult<usize> {
+        let mut flags = self.flags;
+        flags.wrap_width = None;
+        flags.max_length = None;
+        let mut s = String::new();
+        {
+            let mut serializer = Serializer::new(flags, self.names, &mut s, &mut *self.name_writer);
+            serializer.template_depth = self.template_depth;
+            serializer.depth = self.depth;
+            serializer.recursion = self.recursion;
+            serializer.write_types(types, kind)?;
+            serializer.w.finish()?;
+        }
+        Ok(s.len())
+    }
+
+    fn write_newline(&mut self) -> SerializeResult<()> {
+        writeln!(self.w)?;
+        for _ in 0..self.indent {
+            write!(self.w, "    ")?;
+        }
+        Ok(())
+    }
+
+    fn write_class(&mut self, t: &Type, names: &Symbol, s: &str) -> SerializeResult<()> {
+        let start = self.w.len();
+        if self.name_writer.write_type_name(&mut self.w, t)? {
+            self.add_span(start, SpanKind::TypeName);
+            return Ok(());
+        }
+        if !self.flags.contains(DemangleOptions::NO_CLASS_KEYWORDS) {
+            write!(self.w, "{}", s)?;
+            write!(self.w, " ")?;
+        }
+        self.write_name(names, None, SpanKind::TypeName)?;
+        Ok(())
+    }
+
+    fn write_space_pre(&mut self) -> SerializeResult<()> {
+        if let Some(c) = self.w.last() {
+            if self.flags.contains(DemangleOptions::LESS_WHITESPACE) {
+                if is_ident_char(c) {
+                    write!(self.w, " ")?;
+                }
+            } else if self.flags.contains(DemangleOptions::LLVM_UNDNAME_COMPAT) {
+                if is_ident_char(c) || c == b'>' {
+                    write!(self.w, " ")?;
+                }
+            } else if self.flags.contains(DemangleOptions::GNU_STYLE) {
+                // "int* x", but "int (*x)(float)".
+                let declarator = (c == b'*' || c == b'&')
+                    && self.w.last_non_declarator() != Some(b'(');
+                if is_ident_char(c) || c == b'>' || declarator {
+                    write!(self.w, " ")?;
+                }
+            } else {
+                if is_ident_char(c) || c == b'&' || c == b'>' {
+                    write!(self.w, " ")?;
+                }
+            }
+        }
+        Ok(())
+    }
+    fn write_space(&mut self) -> SerializeResult<()> {
+        if let Some(c) = self.w.last() {
+            if self.flags.contains(DemangleOptions::LESS_WHITESPACE) {
+                if is_ident_char(c) {
+                    write!(self.w, " ")?;
+                }
+            } else if self.flags.contains(DemangleOptions::LLVM_UNDNAME_COMPAT) {
+                // "int const *const", not "int const * const".
+                if is_ident_char(c) || c == b'>' {
+                    write!(self.w, " ")?;
+                }
+            } else {
+                if is_ident_char(c) || c == b'*' || c == b'&' || c == b'>' {
+                    write!(self.w, " ")?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_operator_name(&mut self, op: &Operator) -> SerializeResult<()> {
+        let start = self.w.len();
+        self.write_operator(op)?;
+        self.add_span(start, SpanKind::Operator);
+        Ok(())
+    }
+
+    fn write_operator(&mut self, op: &Operator) -> SerializeResult<()> {
+        match *op {
+            // Version 1 wrote all conversion operators as "operatorcast".
+            Operator::Conversion(Some(ref target)) if !self.v1() => {
+                write!(self.w, "operator ")?;
+                self.write_pre(target)?;
+                self.write_post(target)?;
+            }
+            Operator::LiteralOperator(_) if self.v1() => {
+                write!(self.w, " CXXLiteralOperatorName")?;
+            }
+            Operator::LiteralOperator(ref suffix) => {
+                write!(self.w, "{}{}", op.to_str(), suffix)?;
+            }
+            Operator::RTTIBaseClassDescriptor(a, b, c, d) => {
+                write!(self.w, "`RTTI Base Class Descriptor at ({},{},{},{})'", a, b, c, d)?;
+            }
+            Operator::UdtReturning(ref op) => {
+                write!(self.w, "`udt returning'")?;
+                self.write_operator(op)?;
+            }
+            _ if self.flags.contains(DemangleOptions::LLVM_UNDNAME_COMPAT) => {
+                write!(self.w, "{}", op.to_llvm_str())?;
+            }
+            _ => {
+                write!(self.w, "{}", op.to_str())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Write an identifier, e.g. a namespace, class or function name.
+    fn write_identifier(&mut self, name: &str) -> SerializeResult<()> {
+        let start = self.w.len();
+        self.name_writer.write_identifier(&mut self.w, name)?;
+        self.add_span(start, SpanKind::Identifier);
+        Ok(())
+    }
+
+    fn write_one_name(&mut self, name: &Name) -> SerializeResult<()> {
+        match *name {
+            Name::Operator(ref op) => {
+                if !self.flags.contains(DemangleOptions::LESS_WHITESPACE) {
+                    self.write_space()?;
+                }
+                self.write_operator_name(op)?;
+                //panic!("only the last name should be an operator");
+            }
+            Name::NonTemplate(ref name) => {
+                self.write_identifier(name)?;
+            }
+            Name::Template(ref name, ref params) => {
+                self.write_one_name(name)?;
+                self.write_tmpl_params(params)?;
+            }
+            Name::Discriminator(ref val) => {
+                write!(self.w, "`{}'", val)?;
+            }
+            Name::ParsedName(ref val) => {
+                // c++filt writes "f()::x" for local names.
+                if self.flags.contains(DemangleOptions::GNU_STYLE) {
+                    self.serialize(val)?;
+                } else {
+                    write!(self.w, "`")?;
+                    self.serialize(val)?;
+                    write!(self.w, "'")?;
+                }
+            }
+            Name::AnonymousNamespace => {
+                let start = self.w.len();
+                if self.flags.contains(DemangleOptions::GNU_STYLE)
+                    || self.flags.contains(DemangleOptions::ANONYMOUS_NAMESPACE_PARENS)
+                {
+                    write!(self.w, "(anonymous namespace)")?;
+                } else if self.flags.contains(DemangleOptions::LLVM_UNDNAME_COMPAT) {
+                    write!(self.w, "`anonymous namespace'")?;
+                } else {
+                    write!(self.w, "`anonymous namespace`")?;
+                }
+                self.add_span(start, SpanKind::Identifier);
+            }
+            Name::Backref(i) => {
+                let name = self.backref(i)?;
+                self.write_one_name(name)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_scope(&mut self, names: &NameSequence) -> SerializeResult<()> {
+        // Print out namespaces or outer class names.
+        for i in (0..names.names.len()).rev() {
+            if i + 1 < names.names.len() {
+                write!(self.w, "::")?;
+            }
+            self.write_one_name(&names.names[i])?;
+        }
+        Ok(())
+    }
+
+    // Write a name read by read_name().
+    // `ty` is the type of the symbol, if known; it is needed to spell
+    // conversion operators.
+    fn write_name(&mut self, names: &Symbol, ty: Option<&Type>, kind: SpanKind) -> SerializeResult<()> {
+        // Version 1 ran guard variables into their scope: "TSS0`f'::`2'".
+        if !(self.v1() && matches!(ty, Some(Type::ThreadSafeStaticGuard(_)))) {
+            self.write_space_pre()?;
+        }
+        let start = self.w.len();
+
+        self.write_scope(&names.scope)?;
+
+        if !names.scope.names.is_empty() {
+            write!(self.w, "::")?;
+        }
+
+        // Version 1 read the suffix of a literal operator as the innermost
+        // scope: "_deg:: CXXLiteralOperatorName".
+        if let Name::Operator(Operator::LiteralOperator(ref suffix)) = names.name {
+            if self.v1() {
+                self.write_identifier(suffix)?;
+                write!(self.w, "::")?;
+            }
+        }
+
+        self.write_unqualified_name(&names.name, &names.scope, ty)?;
+        self.add_span(start, kind);
+        Ok(())
+    }
+
+    // Write the innermost name of a symbol. Constructors and destructors
+    // take their name from the enclosing class, and conversion operators
+    // from the return type of the function.
+    fn write_unqualified_name(
+        &mut self,
+        name: &Name,
+        scope: &NameSequence,
+        ty: Option<&Type>,
+    ) -> SerializeResult<()> {
+        if let (&Name::Operator(Operator::Conversion(None)), Some(target)) =
+            (name, ty.and_then(Type::return_type))
+        {
+            let op = Operator::Conversion(Some(Box::new(target.clone())));
+            return self.write_unqualified_name(&Name::Operator(op), scope, ty);
+        }
+
+        match *name {
+            Name::Operator(ref op) => {
+                match *op {
+                    Operator::Ctor => {
+                        let prev = scope.names.first().ok_or_else(|| {
+                            SerializeError::new("constructor without a class name".to_owned())
+                        })?;
+                        self.write_one_name(prev)?;
+                    }
+                    Operator::Dtor => {
+                        let prev = scope.names.first().ok_or_else(|| {
+                            SerializeError::new("destructor without a class name".to_owned())
+                        })?;
+                        write!(self.w, "~")?;
+                        self.write_one_name(prev)?;
+                    }
+                    Operator::VBTable => {
+                        write!(self.w, "`vbtable'{{for `")?;
+                        // The rest will be written by write_post of the
+                        // symbol type.
+                    }
+                    _ => {
+                        if !self.flags.contains(DemangleOptions::LESS_WHITESPACE) {
+                            self.write_space()?;
+                        }
+                        // Print out an overloaded operator.
+                        self.write_operator_name(op)?;
+                    }
+                }
+            }
+            Name::NonTemplate(ref name) => {
+                self.write_identifier(name)?;
+            }
+            Name::Template(ref name, ref params) => {
+                match **name {
+                    // Version 1 spelled templated constructors and
+                    // destructors "ctor<int>" and "dtor<int>".
+                    Name::Operator(ref op @ Operator::Ctor) | Name::Operator(ref op @ Operator::Dtor) if self.v1() => {
+                        self.write_operator_name(op)?;
+                    }
+                    _ => self.write_unqualified_name(name, scope, ty)?,
+                }
+                self.write_tmpl_params(params)?;
+            }
+            Name::Discriminator(ref val) => {
+                write!(self.w, "`{}'", val)?;
+            }
+            Name::ParsedName(ref val) => {
+                self.serialize(val)?;
+            }
+            Name::AnonymousNamespace => {
+                return Err(SerializeError::new(
+                    "anonymous namespace as a symbol name".to_owned(),
+                ));
+            }
+            Name::Backref(i) => {
+                let name = self.backref(i)?;
+                self.write_unqualified_name(name, scope, ty)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Write the template arguments of the template `name`.
+    fn write_tmpl_params<'b>(&mut self, params: &Params<'b>) -> SerializeResult<()> {
+        let types = if let Some(Type::EmptyParameterPack) = params.types.last() {
+            &params.types[0..params.types.len()-1]
+        } else {
+            &params.types
+        };
+
+        // "operator< <int>", not "operator<<int>" as version 1 and
+        // llvm-undname write.
+        if self.w.last() == Some(b'<')
+            && !self.v1()
+            && !self.flags.contains(DemangleOptions::LLVM_UNDNAME_COMPAT)
+        {
+            write!(self.w, " ")?;
+        }
+        write!(self.w, "<")?;
+        if !types.is_empty() {
+            if self.flags.max_template_depth.is_some_and(|max| self.template_depth >= max) {
+                write!(self.w, "...>")?;
+                return Ok(());
+            }
+            self.template_depth += 1;
+            // Counts as a level of recursion of its own.
+            self.recursive(|this| this.write_types(types, SpanKind::TemplateArgument))?;
+            self.template_depth -= 1;
+            // llvm-undname writes "A<B<int>>".
+            if self.w.last() == Some(b'>')
+                && !self.flags.contains(DemangleOptions::LLVM_UNDNAME_COMPAT)
+            {
+                write!(self.w, " ")?;
+            }
+        }
+        write!(self.w, ">")?;
+        Ok(())
+    }
+}
+
+// grammar from MicrosoftMangle.cpp:
+
+// <mangled-name> ::= ? <name> <type-encoding>
+// <name> ::= <unscoped-name> {[<named-scope>]+ | [<nested-name>]}? @
+// <unqualified-name> ::= <operator-name>
+//                    ::= <ctor-dtor-name>
+//                    ::= <source-name>
+//                    ::= <template-name>
+// <operator-name> ::= ???
+//                 ::= ?B # cast, the target type is encoded as the return type.
+// <source-name> ::= <identifier> @
+//
+// mangleNestedName: calls into mangle, which is responsible for <mangled-name>, and into mangleUnqualifiedName
+// <postfix> ::= <unqualified-name> [<postfix>]
+//           ::= <substitution> [<postfix>]
+//
+// <template-name> ::= <unscoped-template-name> <template-args>
+//                 ::= <substitution>
+// <unscoped-template-name> ::= ?$ <unqualified-name>
+// <type-encoding> ::= <function-class> <function-type>
+//                 ::= <storage-class> <variable-type>
+// <function-class>  ::= <member-function> E? # E designates a 64-bit 'this'
+//                                            # pointer. in 64-bit mode *all*
+//                                            # 'this' pointers are 64-bit.
+//                   ::= <global-function>
+// <function-type> ::= <this-cvr-qualifiers> <calling-convention>
+//                     <return-type> <argument-list> <throw-spec>
+// <member-function> ::= A # private: near
+//                   ::= B # private: far
+//                   ::= C # private: static near
+//                   ::= D # private: static far
+//                   ::= E # private: near
+//                   ::= F # private: far
+//                   ::= I # near
+//                   ::= J # far
+//                   ::= K # static near
+//                   ::= L # static far
+//                   ::= M # near
+//                   ::= N # far
+//                   ::= Q # near
+//                   ::= R # far
+//                   ::= S # static near
+//                   ::= T # static far
+//                   ::= U # near
+//                   ::= V # far
+// <global-function> ::= Y # global near
+//                   ::= Z # global far
+// <storage-class> ::= 0  # private static member
+//                 ::= 1  # protected static member
+//                 ::= 2  # public static member
+//                 ::= 3  # global
+//                 ::= 4  # static local
+
+#[cfg(test)]
+mod tests {
+    fn expect_with_flags(input: &str, reference: &str, flags: ::DemangleOptions) {
+        let demangled: ::Result<_> = ::demangle(input, flags);
+        let reference: ::Result<_> = Ok(reference.to_owned());
+        assert_eq!(demangled, reference);
+    }
+
+    // For cases where undname demangles differently/better than we do.
+    fn expect_undname_failure(input: &str, reference: &str) {
+        let demangled: ::Result<_> = ::demangle(input, ::DemangleOptions::new());
+        let reference: ::Result<_> = Ok(reference.to_owned());
+        assert_ne!(demangled, reference);
+    }
+    // std::basic_filebuf<char,struct std::char_traits<char> >::basic_filebuf<char,struct std::char_traits<char> >
+    // std::basic_filebuf<char,struct std::char_traits<char> >::"operator ctor"
+    // "operator ctor" = ?0
+
+    #[test]
+    fn other_tests() {
+        let expect = |input, reference| {
+            expect_with_flags(input, reference, ::DemangleOptions::new());
+        };
+
+        expect("?f@@YAHQBH@Z", "int __cdecl f(int const * const)");
+        expect("?f@@YA_WQB_W@Z", "wchar_t __cdecl f(wchar_t const * const)");
+        expect("?f@@YA_UQB_U@Z", "char32_t __cdecl f(char32_t const * const)");
+        expect("?f@@YA_SQB_S@Z", "char16_t __cdecl f(char16_t const * const)");
+        expect("?g@@YAHQAY0EA@$$CBH@Z", "int __cdecl g(int const (* const)[64])");
+        expect(
+            "??0Klass@std@@AEAA@AEBV01@@Z",
+            "private: __cdecl std::Klass::Klass(class std::Klass const &)",
+        );
+        expect("??0?$Klass@V?$Mass@_N@@@std@@QEAA@AEBV01@@Z",
+               "public: __cdecl std::Klass<class Mass<bool> >::Klass<class Mass<bool> >(class std::Klass<class Mass<bool> > const &)");
+        expect("??$load@M@UnsharedOps@js@@SAMV?$SharedMem@PAM@@@Z",
+               "public: static float __cdecl js::UnsharedOps::load<float>(class SharedMem<float *>)");
+
+        expect("?cached@?1??GetLong@BinaryPath@mozilla@@SA?AW4nsresult@@QA_W@Z@4_NA",
+               "bool `public: static enum nsresult __cdecl mozilla::BinaryPath::GetLong(wchar_t * const)\'::`2\'::cached");
+        expect("??0?$A@_K@B@@QAE@$$QAV01@@Z",
+               "public: __thiscall B::A<uint64_t>::A<uint64_t>(class B::A<uint64_t> &&)");
+        expect("??_7nsI@@6B@",
+               "const nsI::`vftable\'");
+        expect(
+            "??_7W@?A@@6B@",
+            "const `anonymous namespace`::W::`vftable'",
+        );
+        expect(
+            "??_7?$RunnableMethodImpl@PEAVLazyIdleThread@mozilla@@P812@EAAXXZ$0A@$0A@$$V@detail@mozilla@@6BnsIRunnable@@@",
+            "const mozilla::detail::RunnableMethodImpl<class mozilla::LazyIdleThread *,void __cdecl (mozilla::LazyIdleThread::*)(void),0,0>::`vftable\'{for `nsIRunnable\'}",
+        );
+        expect_undname_failure(
+            "??_7?$RunnableMethodImpl@PEAVLazyIdleThread@mozilla@@P812@EAAXXZ$0A@$0A@$$V@detail@mozilla@@6BnsIRunnable@@@",
+            "const mozilla::detail::RunnableMethodImpl<class mozilla::LazyIdleThread * __ptr64,void __cdecl (mozilla::LazyIdleThread::*)(void) __ptr64,0,0>::`vftable\'{for `nsIRunnable\'}",
+        );
+        expect("??1?$ns@$$CBVtxXP@@@@QAE@XZ",
+               "public: __thiscall ns<class txXP const>::~ns<class txXP const>(void)");
+        // undname prints void (__thiscall*)(void *) for the parameter type,
+        // see undname_compat_tests.
+        expect(
+            "??_I@YGXPAXIIP6EX0@Z@Z",
+            "void __stdcall `vector destructor iterator'(void *,unsigned int,unsigned int,void __thiscall (*)(void *))",
+        );
+        expect(
+            "??_GnsWindowsShellService@@EAEPAXI@Z",
+            "private: virtual void * __thiscall nsWindowsShellService::`scalar deleting destructor'(unsigned int)",
+        );
+        expect(
+            "??1?$nsAutoPtr@$$CBVtxXPathNode@@@@QAE@XZ",
+            "public: __thiscall nsAutoPtr<class txXPathNode const>::~nsAutoPtr<class txXPathNode const>(void)",
+        );
+        expect(
+            "??_EPrintfTarget@mozilla@@MAEPAXI@Z",
+            "protected: virtual void * __thiscall mozilla::PrintfTarget::`vector deleting destructor'(unsigned int)",
+        );
+        expect(
+            "??_GDynamicFrameEventFilter@?A0xcdaa5fa8@@AAEPAXI@Z",
+            "private: void * __thiscall `anonymous namespace`::DynamicFrameEventFilter::`scalar deleting destructor\'(unsigned int)",
+        );
+        // undname writes the adjustment of the thunk, see
+        // undname_compat_tests().
+        expect(
+            "?Release@ContentSignatureVerifier@@WBA@AGKXZ",
+            "[thunk]:public: virtual unsigned long __stdcall ContentSignatureVerifier::Release(void)",
+        );
+        expect(
+            "??$new_@VWatchpointMap@js@@$$V@?$MallocProvider@UZone@JS@@@js@@QAEPAVWatchpointMap@1@XZ",
+            "public: class js::WatchpointMap * __thiscall js::MallocProvider<struct JS::Zone>::new_<class js::WatchpointMap>(void)",
+        );
+        expect(
+            "??$templ_fun_with_ty_pack@$$V@@YAXXZ",
+            "void __cdecl templ_fun_with_ty_pack<>(void)",
+        );
+        expect(
+            "??4?$RefPtr@VnsRange@@@@QAEAAV0@$$T@Z",
+            "public: class RefPtr<class nsRange> & __thiscall RefPtr<class nsRange>::operator=(std::nullptr_t)",
+        );
+        expect(
+            "??1?$function@$$A6AXXZ@std@@QAE@XZ",
+            "public: __thiscall std::function<void __cdecl (void)>::~function<void __cdecl (void)>(void)",
+        );
+        expect_undname_failure(
+            "??1?$function@$$A6AXXZ@std@@QAE@XZ",
+            "public: __thiscall std::function<void __cdecl(void)>::~function<void __cdecl(void)>(void)",
+        );
+        // Not great (space at the end), but at least make sure we don't regress.
+        expect(
+            "??B?$function@$$A6AXXZ@std@@QBE_NXZ",
+            "public: __thiscall std::function<void __cdecl (void)>::operator bool(void)const ",
+        );
+        expect_undname_failure(
+            "??B?$function@$$A6AXXZ@std@@QBE_NXZ",
+            "public: __thiscall std::function<void __cdecl(void)>::operator bool(void)const",
+        );
+        expect(
+            "??$?RA6AXXZ$$V@SkOnce@@QAEXA6AXXZ@Z",
+            "public: void __thiscall SkOnce::operator()<void __cdecl (&)(void)>(void __cdecl (&)(void))",
+        );
+        expect_undname_failure(
+            "??$?RA6AXXZ$$V@SkOnce@@QAEXA6AXXZ@Z",
+            "public: void __thiscall SkOnce::operator()<void (__cdecl&)(void)>(void (__cdecl&)(void))",
+        );
+        expect(
+            "?foo@A@PR19361@@QIHAEXXZ",
+            "public: void __thiscall PR19361::A::foo(void)__restrict && ",
+        );
+        expect_undname_failure(
+            "?foo@A@PR19361@@QIHAEXXZ",
+            "public: void __thiscall PR19361::A::foo(void) __restrict&& ",
+        );
+        expect(
+            "??$GenericCreateConstructor@$1?construct@SetObject@js@@CA_NPEAUJSContext@@IPEATValue@JS@@@Z$0A@$0A@$0A@@js@@YAPEAVJSObject@@PEAUJSContext@@W4JSProtoKey@@@Z",
+            "class JSObject * __cdecl js::GenericCreateConstructor<bool __cdecl (js::SetObject::construct::*)(struct JSContext *,unsigned int,union JS::Value *),0,0,0>(struct JSContext *,enum JSProtoKey)",
+        );
+        expect_undname_failure(
+            "??$GenericCreateConstructor@$1?construct@SetObject@js@@CA_NPEAUJSContext@@IPEATValue@JS@@@Z$0A@$0A@$0A@@js@@YAPEAVJSObject@@PEAUJSContext@@W4JSProtoKey@@@Z",
+            "class JSObject * __ptr64 __cdecl js::GenericCreateConstructor<&private: static bool __cdecl (js::SetObject::construct::*)(struct JSContext * __ptr64,unsigned int,union JS::Value * __ptr64),0,0,0>(struct JSContext * __ptr64,enum JSProtoKey)",
+        );
+        expect(
+            "??$emplace_hint@AEBUpiecewise_construct_t@std@@V?$tuple@AEBH@2@V?$tuple@$$V@2@@?$_Tree@V?$_Tmap_traits@HUPayload@RtpUtility@webrtc@@U?$less@H@std@@V?$allocator@U?$pair@$$CBHUPayload@RtpUtility@webrtc@@@std@@@5@$0A@@std@@@std@@QEAA?AV?$_Tree_iterator@V?$_Tree_val@U?$_Tree_simple_types@U?$pair@$$CBHUPayload@RtpUtility@webrtc@@@std@@@std@@@std@@@1@V?$_Tree_const_iterator@V?$_Tree_val@U?$_Tree_simple_types@U?$pair@$$CBHUPayload@RtpUtility@webrtc@@@std@@@std@@@std@@@1@AEBUpiecewise_construct_t@1@$$QEAV?$tuple@AEBH@1@$$QEAV?$tuple@$$V@1@@Z",
+            "public: class std::_Tree_iterator<class std::_Tree_val<struct std::_Tree_simple_types<struct std::pair<int const,struct webrtc::RtpUtility::Payload> > > > __cdecl std::_Tree<class std::_Tmap_traits<int,struct webrtc::RtpUtility::Payload,struct std::less<int>,class std::allocator<struct std::pair<int const,struct webrtc::RtpUtility::Payload> >,0> >::emplace_hint<struct std::piecewise_construct_t const &,class std::tuple<int const &>,class std::tuple<> >(class std::_Tree_const_iterator<class std::_Tree_val<struct std::_Tree_simple_types<struct std::pair<int const,struct webrtc::RtpUtility::Payload> > > >,struct std::piecewise_construct_t const &,class std::tuple<int const &> &&,class std::tuple<> &&)",
+        );
+        expect(
+            "?_OptionsStorage@?1??__local_stdio_scanf_options@@9@9",
+            "`__local_stdio_scanf_options'::`2'::_OptionsStorage",
+        );
+        expect(
+            "??$?0H@Klass@@QAE@H@Z",
+            "public: __thiscall Klass::Klass<int>(int)",
+        );
+        expect(
+            "??$?0V?$A@H@@@?$A@H@@QAE@ABV0@@Z",
+            "public: __thiscall A<int>::A<int><class A<int> >(class A<int> const &)",
+        );
+        expect(
+            "??$?1H@Klass@@QAE@XZ",
+            "public: __thiscall Klass::~Klass<int>(void)",
+        );
+        expect(
+            "??$?BH@TemplateOps@@QAEHXZ",
+            "public: __thiscall TemplateOps::operator int<int>(void)",
+        );
+        expect(
+            "??$?DM@std@@YA?AV?$complex@M@0@ABMABV10@@Z",
+            "class std::complex<float> __cdecl std::operator*<float>(float const &,class std::complex<float> const &)",
+        );
+        expect(
+            "??$?HH@S@@QEAAAEAU0@H@Z",
+            "public: struct S & __cdecl S::operator+<int>(int)",
+        );
+        expect(
+            "??$?MH@@YA_NHH@Z",
+            "bool __cdecl operator< <int>(int,int)",
+        );
+        expect(
+            "??$?6H@std@@YAAAVostream@0@AAV10@H@Z",
+            "class std::ostream & __cdecl std::operator<< <int>(class std::ostream &,int)",
+        );
+    }
+
+    #[test]
+    fn malformed_input() {
+        // These used to panic.
+        let inputs = [
+            "?f@@YAX",
+            "??_C@_01BBODEMC@?$5C@",
+            "??_C@_01BCJDKCAM@?$M?$AA",
+            "??_C@_01BCJDKCAM@?$A?$AA@",
+            "?$TSS99999999999@f@@4HA",
+            "??$f@$0PPPPPPPPPPPPPPPPPPPP@@@YAXXZ",
+            "??$f@$0?IAAAAAAA@@@YAXXZ",
+            "?x@@3?IAAAAAAA@A",
+            "??$f@?IAAAAAAA@@@YAXXZ",
+        ];
+        for input in inputs.iter() {
+            let _ = ::demangle(input, ::DemangleOptions::new());
+        }
+        assert!(::demangle("?f@@YAX", ::DemangleOptions::new()).is_err());
+        assert!(::demangle("?$TSS99999999999@f@@4HA", ::DemangleOptions::new()).is_err());
+        assert!(::demangle("?x@@3?IAAAAAAA@A", ::DemangleOptions::new()).is_err());
+        assert!(::parse_type("?IAAAAAAA@").is_err());
+    }
+
+    #[test]
+    fn into_owned() {
+        let input = "??$f@V?$vector@HV?$allocator@H@std@@@std@@@?A0x1234@ns@@YAXPEAUS@?1??g@@YAXXZ@@Z".to_owned();
+        let parsed = ::parse(&input).unwrap();
+        let owned: ::ParseResult<'static> = parsed.clone().into_owned();
+        assert_eq!(owned, parsed);
+        drop(parsed);
+        drop(input);
+        assert_eq!(
+            ::serialize(&owned, ::DemangleOptions::new()),
+            Ok("void __cdecl ns::`anonymous namespace`::f<class std::vector<int,class std::allocator<int> > >(struct `void __cdecl g(void)'::`2'::S *)".to_owned())
+        );
+    }
+
+    #[test]
+    fn demangle_into() {
+        let mut out = "leftover".to_owned();
+        assert_eq!(::demangle_into("?x@@3HA", ::DemangleOptions::new(), &mut out), Ok(()));
+        assert_eq!(out, "int x");
+        assert_eq!(::demangle_into("?f@@YAXXZ", ::DemangleOptions::new(), &mut out), Ok(()));
+        assert_eq!(out, "void __cdecl f(void)");
+        assert!(::demangle_into("?f@@YAX", ::DemangleOptions::new(), &mut out).is_err());
+        assert_eq!(out, "");
+    }
+
+    #[test]
+    fn demangler() {
+        let mut demangler = ::Demangler::new(::DemangleOptions::new());
+        assert_eq!(demangler.demangle("?f@@YAXV?$A@H@@V?$B@V?$A@H@@@@V1@@Z"), Ok("void __cdecl f(class A<int>,class B<class A<int> >,class A<int>)"));
+        assert!(demangler.demangle("?f@@YAXV?$A@H@@").is_err());
+        // Nothing is left over from the previous names.
+        assert_eq!(demangler.demangle("?f@@YAXV?$B@H@@V1@@Z"), Ok("void __cdecl f(class B<int>,class B<int>)"));
+        assert!(demangler.demangle("?f@@YAXV2@@Z").is_err());
+    }
+
+    #[test]
+    fn demangle_batch() {
+        let names = ["?x@@3HA", "?f@@YAX", "?f@@YAXV?$A@H@@V?$B@V?$A@H@@@@V1@@Z"];
+        // Enough names to be spread over several threads.
+        let inputs: Vec<&str> = names.iter().cycle().take(2000).cloned().collect();
+        let results = ::demangle_batch(&inputs, ::DemangleOptions::new());
+        assert_eq!(results.len(), inputs.len());
+        for (input, result) in inputs.iter().zip(&results) {
+            assert_eq!(*result, ::demangle(input, ::DemangleOptions::new()));
+        }
+        assert_eq!(::demangle_batch(&[], ::DemangleOptions::new()), []);
+    }
+
+    #[test]
+    fn demangled() {
+        use DemangleExt;
+        let names = ["?x@@3HA", "main", "?f@@YAXV?$A@H@@V1@@Z"];
+        let results: Vec<_> = names.iter().cloned().demangled(::DemangleOptions::NAME_ONLY).collect();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0], ("?x@@3HA", Ok("x".to_owned())));
+        assert_eq!(results[1].0, "main");
+        assert!(results[1].1.is_err());
+        assert_eq!(results[2], ("?f@@YAXV?$A@H@@V1@@Z", Ok("f".to_owned())));
+        assert_eq!([].iter().cloned().demangled(::DemangleOptions::new()).len(), 0);
+    }
+
+    #[test]
+    fn long_identifiers() {
+        let name = "x".repeat(100_000);
+        let input = format!("?{}@@3HA", name);
+        assert_eq!(::demangle(&input, ::DemangleOptions::new()).unwrap(), format!("int {}", name));
+        expect_with_flags("?x@@3PAY0BAAA@HA", "int (*x)[4096]", ::DemangleOptions::new());
+        expect_with_flags("?x@@3PAY0BA@HA", "int (*x)[16]", ::DemangleOptions::new());
+        assert!(::demangle("?x@@3PAY0BAQ@HA", ::DemangleOptions::new()).is_err());
+        assert!(::demangle("?x@@3PAY0BA", ::DemangleOptions::new()).is_err());
+    }
+
+    #[test]
+    fn shared_backrefs() {
+        // Back-references share the memorized type, so nesting them costs
+        // time linear in the input rather than exponential.
+        let mut input = "PAH".to_owned();
+        for _ in 0..30 {
+            input = format!("V?$A@{}0@@", input);
+        }
+        let start = std::time::Instant::now();
+        let t = ::parse_type(&input).unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+        let params = match t {
+            ::Type::Class(ref symbol, _) => match symbol.name {
+                ::Name::Template(_, ref params) => params.types.clone(),
+                _ => panic!("{:?}", symbol),
+            },
+            _ => panic!("{:?}", t),
+        };
+        assert_eq!(params[1], ::Type::Shared(::std::sync::Arc::new(params[0].clone())));
+
+        expect_with_flags("?f@@YAXPAH0@Z", "void __cdecl f(int *,int *)", ::DemangleOptions::new());
+    }
+
+    #[test]
+    fn limits() {
+        // Every level doubles the length of the output: A<A<int>,A<int> >
+        // and so on.
+        let bomb = |levels| {
+            let mut t = "V?$A@H@@".to_owned();
+            for _ in 0..levels {
+                t = format!("V?$A@{}0@@", t);
+            }
+            format!("?x@@3{}A", t)
+        };
+        let flags = ::DemangleOptions::new().max_nodes(1000).max_expansion(100);
+        expect_with_flags(&bomb(2), "class A<class A<class A<int>,class A<int> >,class A<class A<int>,class A<int> > > x", flags);
+        let err = ::demangle(&bomb(60), ::DemangleOptions::new().max_expansion(100)).unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::OutputLimitExceeded);
+        let err = ::demangle(&bomb(60), ::DemangleOptions::new().max_nodes(1000)).unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::LimitExceeded);
+        let err = ::demangle_with_spans(&bomb(60), ::DemangleOptions::new().max_expansion(100)).unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::OutputLimitExceeded);
+
+        // max_output() caps the output of every function, whatever the
+        // length of the input.
+        let flags = ::DemangleOptions::new().max_output(100);
+        expect_with_flags(&bomb(2), "class A<class A<class A<int>,class A<int> >,class A<class A<int>,class A<int> > > x", flags);
+        let err = ::demangle(&bomb(3), flags).unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::OutputLimitExceeded);
+        let input = bomb(20);
+        let parsed = ::parse(&input).unwrap();
+        assert_eq!(::serialize(&parsed, flags).unwrap_err().kind(), ::ErrorKind::OutputLimitExceeded);
+        let err = ::demangle(&bomb(60), ::DemangleOptions::new().max_expansion(1000).max_output(1000)).unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::OutputLimitExceeded);
+        assert_eq!(::demangle(&"PA".repeat(200), ::DemangleOptions::new()).unwrap_err().kind(), ::ErrorKind::Invalid);
+        let nested = format!("?x@@3{}HA", "PA".repeat(200));
+        assert_eq!(::demangle(&nested, ::DemangleOptions::new()).unwrap_err().kind(), ::ErrorKind::LimitExceeded);
+    }
+
+    #[test]
+    fn parse_events() {
+        use ::Event::*;
+
+        let mut events = Vec::new();
+        let result = ::parse_events("?f@ns@@YAXPEBHV?$A@H@1@V21@@Z", ::DemangleOptions::new(), |event| {
+            events.push(format!("{:?}", event))
+        });
+        assert_eq!(result, Ok(()));
+        let class_a = [
+            BeginUserType("class", ::StorageClass::empty()),
+            Identifier("ns"),
+            EnterScope,
+            Identifier("A"),
+            BeginTemplateArgs,
+            PrimitiveType("int", ::StorageClass::empty()),
+            EndTemplateArgs,
+            LeaveScope,
+            EndType,
+        ];
+        let mut expected = vec![
+            BeginSymbol(::SymbolKind::Function),
+            Identifier("ns"),
+            EnterScope,
+            Identifier("f"),
+            LeaveScope,
+            BeginFunction(None, ::CallingConv::Cdecl, ::StorageClass::empty()),
+            PrimitiveType("void", ::StorageClass::empty()),
+            BeginParams,
+            BeginPointer(::StorageClass::PTR64),
+            PrimitiveType("int", ::StorageClass::CONST),
+            EndType,
+        ];
+        // The back-reference to A<int> is replayed.
+        expected.extend_from_slice(&class_a);
+        expected.extend_from_slice(&class_a);
+        expected.extend_from_slice(&[EndParams, EndType, EndSymbol]);
+        let expected: Vec<_> = expected.iter().map(|event| format!("{:?}", event)).collect();
+        assert_eq!(events, expected);
+        assert!(::parse_events("?f@@YA", ::DemangleOptions::new(), |_| {}).is_err());
+
+        // Parameters are passed on as they are read, before the error.
+        let mut events = Vec::new();
+        let result = ::parse_events("?f@@YAXHN", ::DemangleOptions::new(), |event| {
+            events.push(format!("{:?}", event))
+        });
+        assert!(result.is_err());
+        assert!(events.contains(&format!("{:?}", PrimitiveType("double", ::StorageClass::empty()))));
+
+        // Streamed or not, the events are those of the parsed tree.
+        for input in &[
+            "?foo@Bar@@UEBAPEAHH_N@Z",
+            "??0Bar@@IEAA@XZ",
+            "??_GBar@@UAEPAXI@Z",
+            "?f@?1??g@@YAXXZ@YAXU?$A@H@?1??g@@YAXXZ@U21@@Z",
+            "?x@@3PEAHEA",
+            "??_7Bar@@6B@",
+            "??_R0?AVBar@@@8",
+            "?$TSS0@?1??f@@YAXXZ@4HA",
+        ] {
+            let mut streamed = Vec::new();
+            ::parse_events(input, ::DemangleOptions::new(), |event| streamed.push(format!("{:?}", event))).unwrap();
+            let parsed = ::parse(input).unwrap();
+            let mut walked = Vec::new();
+            let mut emitter = ::EventEmitter {
+                names: &parsed.names,
+                callback: |event: ::Event| walked.push(format!("{:?}", event)),
+            };
+            emitter.symbol(&parsed);
+            assert_eq!(streamed, walked, "{}", input);
+        }
+    }
+
+    #[test]
+    fn hash_and_ord() {
+        use std::collections::HashSet;
+
+        let int = ::parse("?f@@YAXH@Z").unwrap();
+        let float = ::parse("?f@@YAXM@Z").unwrap();
+        let symbols: HashSet<_> = vec![int.clone(), float.clone(), ::parse("?f@@YAXH@Z").unwrap()]
+            .into_iter()
+            .collect();
+
+        assert_eq!(symbols.len(), 2);
+        let mut sorted: Vec<_> = symbols.into_iter().collect();
+        sorted.sort();
+        assert_eq!(sorted, [int, float]);
+
+        // Shared types are the same as the types they share.
+        let shared = ::parse("?f@@YAXVA@@0@Z").unwrap();
+        let spelled = ::parse("?f@@YAXVA@@VA@@@Z").unwrap();
+        assert_eq!(shared, spelled);
+        assert_eq!(shared.cmp(&spelled), ::std::cmp::Ordering::Equal);
+        let symbols: HashSet<_> = vec![shared, spelled].into_iter().collect();
+        assert_eq!(symbols.len(), 1);
+    }
+
+    #[test]
+    fn signature() {
+        let parsed = ::parse("?foo@Bar@@UEBAPEAHH_N@Z").unwrap();
+        let signature = parsed.signature().unwrap();
+        assert_eq!(signature.return_type(), Some(&::Type::Ptr(Box::new(::Type::Int(::StorageClass::empty())), ::StorageClass::PTR64)));
+        assert_eq!(signature.parameters(), [::Type::Int(::StorageClass::empty()), ::Type::Bool(::StorageClass::empty())]);
+        assert!(!signature.is_variadic());
+        assert_eq!(signature.calling_conv(), ::CallingConv::Cdecl);
+        assert_eq!(signature.this_quals(), ::StorageClass::CONST | ::StorageClass::PTR64);
+        assert_eq!(signature.access(), Some(::Access::Public));
+        assert!(signature.is_virtual());
+        assert!(!signature.is_static());
+
+        let parsed = ::parse("??0Bar@@IEAA@XZ").unwrap();
+        let signature = parsed.signature().unwrap();
+        assert_eq!(signature.return_type(), None);
+        assert_eq!(signature.parameters(), []);
+        assert_eq!(signature.access(), Some(::Access::Protected));
+
+        let parsed = ::parse("?v@@YGXHZZ").unwrap();
+        let signature = parsed.signature().unwrap();
+        assert_eq!(signature.parameters(), [::Type::Int(::StorageClass::empty())]);
+        assert!(signature.is_variadic());
+        assert_eq!(signature.calling_conv(), ::CallingConv::Stdcall);
+        assert_eq!(signature.access(), None);
+        assert!(!signature.is_static());
+
+        assert!(::parse("?s@Bar@@SAXXZ").unwrap().signature().unwrap().is_static());
+        assert_eq!(::parse("?x@@3HA").unwrap().signature(), None);
+    }
+
+    #[test]
+    fn template_args() {
+        let int = ::Type::Int(::StorageClass::empty());
+        let parsed = ::parse("??$f@H@@YAXXZ").unwrap();
+        assert_eq!(parsed.template_args(), Some(&[int.clone()][..]));
+        assert_eq!(::parse("?f@@YAXXZ").unwrap().template_args(), None);
+
+        let parsed = ::parse("?f@ns@@YAXPEAV?$B@VC@@@1@AEBV?$vector@HV?$allocator@H@std@@@std@@V21@@Z").unwrap();
+        let names: Vec<_> = parsed
+            .template_instances()
+            .into_iter()
+            .map(|(name, args)| (name.clone(), args.len()))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                (::Name::NonTemplate("B".into()), 1),
+                (::Name::NonTemplate("vector".into()), 2),
+                (::Name::NonTemplate("allocator".into()), 1),
+            ]
+        );
+        let args = parsed.template_args_of("allocator");
+        assert_eq!(args, [&[int][..]]);
+        assert!(parsed.template_args_of("list").is_empty());
+    }
+
+    #[test]
+    fn scope_components() {
+        use ::ScopeComponent::*;
+
+        let parsed = ::parse("?f@?$A@H@ns@?A0x1@@YAXXZ").unwrap();
+        let components: Vec<_> = parsed.scope_components().collect();
+        let int = [::Type::Int(::StorageClass::empty())];
+        assert_eq!(
+            components,
+            [AnonymousNamespace, Named("ns"), Template(&::Name::NonTemplate("A".into()), &int)]
+        );
+
+        let parsed = ::parse("?x@?1??g@@YAXXZ@4HA").unwrap();
+        let components: Vec<_> = parsed.scope_components().collect();
+        match components[..] {
+            [Function(function), Discriminator(2)] => {
+                assert_eq!(function.function_name(::DemangleOptions::new()), Ok("g".to_owned()));
+            }
+            _ => panic!("unexpected scope: {:?}", components),
+        }
+
+        let parsed = ::parse("?f@@YAXPEAVB@?$A@H@ns@@@Z").unwrap();
+        let params = parsed.signature().unwrap().parameters();
+        let symbol = match params[0] {
+            ::Type::Ptr(ref inner, _) => match **inner {
+                ::Type::Class(ref symbol, _) => symbol,
+                _ => panic!(),
+            },
+            _ => panic!(),
+        };
+        assert_eq!(parsed.scope_components_of(symbol).len(), 2);
+        assert_eq!(parsed.scope_components().len(), 0);
+    }
+
+    #[test]
+    fn rewrite_scopes() {
+        // Drop anonymous namespaces, also in the back-referenced B<...>.
+        let mut parsed = ::parse("?f@?A0x1@@YAXV?$B@VC@?A0x1@@@@V1@@Z").unwrap();
+        parsed.rewrite_scopes(|names| names.retain(|name| *name != ::Name::AnonymousNamespace));
+        assert_eq!(
+            ::serialize(&parsed, ::DemangleOptions::cxxfilt()),
+            Ok("f(B<C>, B<C>)".to_owned())
+        );
+
+        // Move C from ns to other::ns.
+        let mut parsed = ::parse("?f@ns@@YAXVC@1@@Z").unwrap();
+        parsed.rewrite_scopes(|names| {
+            if names.first() == Some(&::Name::NonTemplate("ns".into())) {
+                names.push(::Name::NonTemplate("other".into()));
+            }
+        });
+        assert_eq!(
+            ::serialize(&parsed, ::DemangleOptions::cxxfilt()),
+            Ok("other::ns::f(other::ns::C)".to_owned())
+        );
+    }
+
+    #[test]
+    fn parse_type() {
+        let t = ::parse_type("PEAVFoo@ns@@").unwrap();
+        assert_eq!(::serialize_type(&t, ::DemangleOptions::new()), Ok("class ns::Foo *".to_owned()));
+        let t = ::parse_type(".?AV?$vector@HV?$allocator@H@std@@@std@@").unwrap();
+        assert_eq!(
+            ::serialize_type(&t, ::DemangleOptions::cxxfilt()),
+            Ok("std::vector<int, std::allocator<int> >".to_owned())
+        );
+        let t = ::parse_type("P6AHH@Z").unwrap();
+        assert_eq!(::serialize_type(&t, ::DemangleOptions::cxxfilt()), Ok("int (*)(int)".to_owned()));
+        assert!(::parse_type("HH").is_err());
+        assert!(::parse_type("").is_err());
+    }
+
+    #[test]
+    fn ignoring_template_args() {
+        assert_eq!(
+            ::normalize_ignoring_template_args("?bar@?$Foo@H@ns@@QAEXH@Z"),
+            Ok("ns::Foo<>::bar(int)".to_owned())
+        );
+        assert_eq!(::normalize_ignoring_template_args("??$f@H@@YAXXZ"), Ok("f<>()".to_owned()));
+        assert_eq!(
+            ::normalize_ignoring_template_args("?f@?$Foo@H@@QBEXV?$vector@H@@ZZ"),
+            Ok("Foo<>::f(class vector<>,...) const".to_owned())
+        );
+        assert!(::equivalent_ignoring_template_args("?bar@?$Foo@H@@QAEXH@Z", "?bar@?$Foo@N@@QAEXH@Z"));
+        assert!(::equivalent_ignoring_template_args("?bar@?$Foo@H@@QAEXV?$vector@H@@@Z", "?bar@?$Foo@N@@QAEXV?$vector@N@@@Z"));
+        assert!(::equivalent_ignoring_template_args("?x@?$Foo@H@@2HA", "?x@?$Foo@N@@2NA"));
+        assert!(!::equivalent_ignoring_template_args("?bar@?$Foo@H@@QAEXH@Z", "?baz@?$Foo@H@@QAEXH@Z"));
+        assert!(!::equivalent_ignoring_template_args("?bar@?$Foo@H@@QAEXH@Z", "?bar@?$Bar@H@@QAEXH@Z"));
+        assert!(!::equivalent_ignoring_template_args("?bar@?$Foo@H@@QAEXH@Z", "?bar@?$Foo@N@@QAEXN@Z"));
+        assert!(!::equivalent_ignoring_template_args("?bar@?$Foo@H@@QAEXXZ", "?bar@?$Foo@N@@QBEXXZ"));
+        assert!(!::equivalent_ignoring_template_args("?f@@YAXH@Z", "?f@@YAXN@Z"));
+        assert!(!::equivalent_ignoring_template_args("garbage", "garbage"));
+    }
+
+    #[test]
+    fn from_undname_flags() {
+        let expect = |input, reference, undname_flags| {
+            expect_with_flags(input, reference, ::DemangleOptions::from_undname_flags(undname_flags));
+        };
+        expect("?bar@Foo@@QAEHH@Z", "public: int __thiscall Foo::bar(int)", 0);
+        expect("?bar@Foo@@QAEHH@Z", "public: int Foo::bar(int)", 0x2);
+        expect("?bar@Foo@@QAEHH@Z", "Foo::bar(int)", 0x286);
+        expect("?bar@Foo@@QAEHH@Z", "Foo::bar", 0x1000);
+        expect("?x@@3HA", "x", 0x1000);
+        expect("??Bfoo@@QAEHXZ", "foo::operator int", 0x1000);
+        expect("?f@@YAXVA@@@Z", "void __cdecl f(class A)", 0x20);
+        expect("?f@@YAXVA@@@Z", "void __cdecl f(A)", 0x8000);
+        expect("?f@@YAXVA@@@Z", "f", 0xffff_ffff);
+        expect("?bar@Foo@@QBEHXZ", "public: int __thiscall Foo::bar(void)", 0x60);
+    }
+
+    #[test]
+    fn breakpad() {
+        let expect = |input, reference| {
+            expect_with_flags(input, reference, ::DemangleOptions::breakpad());
+        };
+        expect("?bar@Foo@?A0x1@@QBEHHPBD@Z", "(anonymous namespace)::Foo::bar(int,char const *)");
+        expect("?f@@YAXVA@@@Z", "f(A)");
+        expect("?h@@YGXXZ", "h(void)");
+        expect("?g@@YAXP6AHH@Z@Z", "g(int (*)(int))");
+    }
+
+    #[test]
+    fn stable_hash() {
+        // The hashes must never change.
+        assert_eq!(::stable_hash("?x@@3HA"), Ok(0xfa1b_008f_42c1_dae6));
+        assert_eq!(::stable_hash_128("?x@@3HA"), Ok(0xdc02_2937_8683_d94f_7081_2f93_f9da_97ce));
+        let a = "?f@@YAXV?$A@H@@V?$B@V?$A@H@@@@V1@@Z";
+        let b = "?f@@YAXV?$A@H@@V?$B@V?$A@H@@@@V?$A@H@@@Z";
+        assert_eq!(::stable_hash(a), ::stable_hash(b));
+        assert_eq!(::stable_hash_128(a), ::stable_hash_128(b));
+        assert_ne!(::stable_hash(a), ::stable_hash("?x@@3HA"));
+        assert!(::stable_hash("garbage").is_err());
+    }
+
+    #[test]
+    fn demangle_qualified_name() {
+        let inputs = [
+            "?bar@?$Foo@H@ns@@QAEXH@Z",
+            "??$f@H@@YAXXZ",
+            "??Bfoo@@QAEHXZ",
+            "??0?$Foo@H@@QAE@XZ",
+            "??_7W@?A@@6B@",
+            "?x@@3HA",
+            "?$f@H",
+            "?f@@YAXV?$A@H@@V?$B@V?$A@H@@@@V1@@Z",
+            "??_R0?AVC@@@8",
+            "?x@?1??f@@YAXXZ@4HA",
+            "?$TSS0@?1??f@@YAXXZ@4HA",
+        ];
+        for input in &inputs {
+            for &flags in &[::DemangleOptions::new(), ::DemangleOptions::cxxfilt() | ::DemangleOptions::NO_TEMPLATE_ARGS] {
+                assert_eq!(
+                    ::demangle_qualified_name(input, flags),
+                    ::demangle(input, flags | ::DemangleOptions::NAME_ONLY),
+                    "{}",
+                    input
+                );
+            }
+        }
+        assert_eq!(
+            ::demangle_qualified_name("?bar@?$Foo@H@ns@@QAEXH@Z", ::DemangleOptions::new()),
+            Ok("ns::Foo<int>::bar".to_owned())
+        );
+        // The parameters aren't looked at.
+        assert_eq!(::demangle_qualified_name("?f@@YA", ::DemangleOptions::new()), Ok("f".to_owned()));
+        assert!(::demangle_qualified_name("f", ::DemangleOptions::new()).is_err());
+    }
+
+    #[test]
+    fn demangle_lossy() {
+        let lossy = ::demangle_lossy("?bar@Foo@ns@@QAEXH@Z", ::DemangleOptions::new()).unwrap();
+        assert_eq!(lossy.demangled, "public: void __thiscall ns::Foo::bar(int)");
+        assert_eq!(lossy.remainder, "");
+        assert_eq!(lossy.error, None);
+
+        // An unknown type code.
+        let lossy = ::demangle_lossy("?bar@Foo@ns@@QAEX_$@Z", ::DemangleOptions::new()).unwrap();
+        assert_eq!(lossy.demangled, "ns::Foo::bar");
+        assert_eq!(lossy.remainder, "QAEX_$@Z");
+        assert!(lossy.error.is_some());
+        assert_eq!(lossy.to_string(), "ns::Foo::bar [unparsed: QAEX_$@Z]");
+
+        // Cut off after the name.
+        let lossy = ::demangle_lossy("?f@?$A@H@@Y", ::DemangleOptions::cxxfilt()).unwrap();
+        assert_eq!(lossy.to_string(), "A<int>::f [unparsed: Y]");
+
+        assert!(::demangle_lossy("?f@?$A@H", ::DemangleOptions::new()).is_err());
+        assert!(::demangle_lossy("f", ::DemangleOptions::new()).is_err());
+    }
+
+    #[test]
+    fn extensions() {
+        struct Icc;
+
+        impl ::Extensions for Icc {
+            fn unknown_type(&mut self, input: &str) -> Option<(String, usize)> {
+                if input.starts_with("_$") {
+                    Some(("__m512".to_owned(), 2))
+                } else {
+                    None
+                }
+            }
+
+            fn unknown_operator(&mut self, input: &str) -> Option<(String, usize)> {
+                if input.starts_with("_?") {
+                    Some(("operator co_yield".to_owned(), 2))
+                } else {
+                    None
+                }
+            }
+
+            fn unknown_calling_conv(&mut self, code: u8) -> Option<::CallingConv> {
+                if code == b'Q' {
+                    Some(::CallingConv::Fastcall)
+                } else {
+                    None
+                }
+            }
+        }
+
+        let demangle = |input| ::demangle_with_extensions(input, ::DemangleOptions::cxxfilt(), &mut Icc);
+        assert_eq!(demangle("?f@@YAX_$@Z"), Ok("f(__m512)".to_owned()));
+        assert_eq!(demangle("?f@@YAXPB_$@Z"), Ok("f(__m512 const*)".to_owned()));
+        assert_eq!(demangle("??_?A@@QAEXXZ"), Ok("A::operator co_yield()".to_owned()));
+        assert_eq!(
+            ::demangle_with_extensions("?f@@YQXXZ", ::DemangleOptions::new(), &mut Icc),
+            Ok("void __fastcall f(void)".to_owned())
+        );
+        assert!(demangle("?f@@YAX_$B@Z").is_err());
+        assert!(demangle("?f@@YAX_Q@Z").is_err());
+        assert!(::demangle("?f@@YAX_$@Z", ::DemangleOptions::cxxfilt()).is_err());
+    }
+
+    #[test]
+    fn backrefs() {
+        // A<int> is spelled out in the template arguments of B and
+        // back-referenced by "1", but stored only once.
+        let parsed = ::parse("?f@@YAXV?$A@H@@V?$B@V?$A@H@@@@V1@@Z").unwrap();
+        assert_eq!(parsed.names.len(), 2);
+        let class_names: Vec<_> = match parsed.symbol_type {
+            ::Type::NonMemberFunction(_, ref params, _, _) => params
+                .types
+                .iter()
+                .map(|t| match *t {
+                    ::Type::Class(ref symbol, _) => symbol.name.clone(),
+                    _ => panic!("not a class: {:?}", t),
+                })
+                .collect(),
+            _ => panic!("not a function: {:?}", parsed.symbol_type),
+        };
+        assert_eq!(class_names, [::Name::Backref(0), ::Name::Backref(1), ::Name::Backref(0)]);
+        match *parsed.resolve_name(&class_names[1]) {
+            ::Name::Template(_, ref params) => {
+                assert_eq!(params.types, [::Type::class(::Symbol::new(::Name::Backref(0), Vec::new()))]);
+            }
+            ref name => panic!("not a template: {:?}", name),
+        }
+        expect_with_flags(
+            "?f@@YAXV?$A@H@@V?$B@V?$A@H@@@@V1@@Z",
+            "void __cdecl f(class A<int>,class B<class A<int> >,class A<int>)",
+            ::DemangleOptions::new(),
+        );
+    }
+
+    #[test]
+    fn equivalent() {
+        // The same function with and without a back-reference.
+        let a = "?f@ns@@YAXVFoo@1@@Z";
+        let b = "?f@ns@@YAXVFoo@ns@@@Z";
+        assert!(::equivalent(a, b));
+        assert_eq!(::normalize(a), ::normalize(b));
+        assert_eq!(::normalize(a), Ok("void __cdecl ns::f(class ns::Foo)".to_owned()));
+        // Anonymous namespaces get a different hash in every build.
+        assert!(::equivalent("?x@?A0x1234@@3HA", "?x@?A0xabcd@@3HA"));
+        // A parameter type repeated by a back-reference and spelled out.
+        assert!(::equivalent("?f@@YAXVA@@0@Z", "?f@@YAXVA@@VA@@@Z"));
+
+        assert!(!::equivalent("?f@@YAXH@Z", "?f@@YAXI@Z"));
+        assert!(!::equivalent("?f@@YAXXZ", "?g@@YAXXZ"));
+        assert!(!::equivalent("?f@@YAXXZ", "not mangled"));
+        assert!(!::equivalent("not mangled", "not mangled"));
+    }
+
+    #[test]
+    fn demangle_text() {
+        let flags = ::DemangleOptions::new();
+        assert_eq!(
+            ::demangle_text("call ?f@@YAXXZ ; (?x@@3HA)", flags),
+            "call void __cdecl f(void) ; (int x)"
+        );
+        assert_eq!(
+            ::demangle_text(" 0001:00000010 ?f@@YAXXZ$$J0 00401010 f foo.obj", flags),
+            " 0001:00000010 void __cdecl f(void)$$J0 00401010 f foo.obj"
+        );
+        assert_eq!(::demangle_text("what? ?not@mangled x?f@@YAXXZ", flags), "what? ?not@mangled x?f@@YAXXZ");
+        assert_eq!(::demangle_text("\u{e4}?f@@YAXXZ", flags), "\u{e4}?f@@YAXXZ");
+        assert_eq!(
+            ::demangle_text("?f@@YAXXZ,?g@@YAXXZ", ::DemangleOptions::cxxfilt()),
+            "f(),g()"
+        );
+        assert_eq!(
+            ::demangle_text("?x@<unnamed-type-x>@@3HA ?y@<unnamed-tag>@@3HA", flags),
+            "int <unnamed-type-x>::x int <unnamed-tag>::y"
+        );
+        assert_eq!(::demangle_text("a-?x@@3HA", flags), "a-int x");
+        assert_eq!(::demangle_text("", flags), "");
+    }
+
+    #[test]
+    fn demangle_prefix() {
+        let flags = ::DemangleOptions::new();
+        assert_eq!(
+            ::demangle_prefix("?f@@YAXXZ", flags),
+            Ok(("void __cdecl f(void)".to_owned(), 9))
+        );
+        assert_eq!(
+            ::demangle_prefix("?f@@YAXXZ$$J0", flags),
+            Ok(("void __cdecl f(void)".to_owned(), 9))
+        );
+        assert_eq!(::demangle_prefix("?x@@3HA garbage", flags), Ok(("int x".to_owned(), 7)));
+        assert_eq!(::demangle_prefix("?x@@3PEAHEA$", flags), Ok(("int *x".to_owned(), 11)));
+        assert!(::demangle_prefix("?f@@YA", flags).is_err());
+
+        let (parsed, len) = ::parse_prefix("??_7Foo@@6B@.data").unwrap();
+        assert_eq!(len, 12);
+        assert_eq!(parsed.kind(), ::SymbolKind::VTable);
+    }
+
+    #[test]
+    fn lossy() {
+        let lossy = ::DemangleOptions::LOSSY;
+        assert!(::demangle_bytes(b"?x\xe4@@3HA", ::DemangleOptions::new()).is_err());
+        assert_eq!(::demangle_bytes(b"?x@@3HA", ::DemangleOptions::new()).unwrap(), "int x");
+        assert_eq!(::demangle_bytes(b"?x\xe4@@3HA", lossy).unwrap(), "int x\u{fffd}");
+        assert_eq!(
+            ::demangle_bytes(b"?f@\xff\xfens@@YAXXZ", lossy).unwrap(),
+            "void __cdecl \u{fffd}\u{fffd}ns::f(void)"
+        );
+
+        // Errors about input cut in the middle of a character still have
+        // a message.
+        let err = ::demangle("?f@@\u{e4}AXXZ", ::DemangleOptions::new()).unwrap_err();
+        assert_eq!(err.to_string(), "unknown func class: \u{fffd}");
+    }
+
+    #[test]
+    fn strict() {
+        let strict = ::DemangleOptions::STRICT;
+        assert_eq!(::demangle("?x@@3HA$", ::DemangleOptions::new()).unwrap(), "int x");
+        assert_eq!(::demangle("?x@@3HA", strict).unwrap(), "int x");
+        let err = ::demangle("?x@@3HA$", strict).unwrap_err();
+        assert_eq!(err.to_string(), "unconsumed input at offset 7: \"$\"");
+        assert!(::Demangler::new(strict).demangle("?x@@3HA$").is_err());
+
+        let (parsed, trailing) = ::parse_with_trailing("??_7Foo@@6B@.data", ::DemangleOptions::new()).unwrap();
+        assert_eq!(parsed.kind(), ::SymbolKind::VTable);
+        assert_eq!(trailing, ".data");
+        assert_eq!(::parse_with_trailing("??_7Foo@@6B@", strict).unwrap().1, "");
+
+        // Unterminated parameter lists are only accepted when permissive.
+        assert_eq!(::demangle("?$f@H", ::DemangleOptions::new()).unwrap(), "f<int>");
+        assert!(::demangle("?$f@H", strict).is_err());
+        assert!(::demangle_prefix("?$f@H", strict).is_err());
+    }
+
+    #[test]
+    fn parse_options() {
+        let options = ::ParseOptions::new();
+        assert!(::parse_with_options("?x@@3HA$", options).is_ok());
+        assert!(::parse_with_options("?x@@3HA$", options.strict(true)).is_err());
+        assert_eq!(::parse_with_trailing("?x@@3HA$", options).unwrap().1, "$");
+
+        let deep = format!("?x@@3{}HA", "PA".repeat(10));
+        assert!(::parse_with_options(&deep, options).is_ok());
+        assert!(::parse_with_options(&deep, options.max_depth(5)).is_err());
+        assert!(::parse_with_options(&deep, options.max_nodes(5)).is_err());
+        assert!(::parse_type_with_options("PAPAH", options.max_depth(1)).is_err());
+
+        assert!(::parse_bytes(b"?x\xe4@@3HA", options).is_err());
+        let parsed = ::parse_bytes(b"?x\xe4@@3HA", options.allow_non_utf8(true)).unwrap();
+        assert_eq!(::serialize(&parsed, ::DemangleOptions::new()).unwrap(), "int x\u{fffd}");
+        assert_eq!(::parse_bytes(b"?x@@3HA", options).unwrap(), ::parse("?x@@3HA").unwrap());
+
+        // The settings of DemangleOptions that concern parsing carry over.
+        let flags = ::DemangleOptions::STRICT | ::DemangleOptions::LOSSY;
+        assert_eq!(::ParseOptions::from(flags.max_depth(5)), options.strict(true).allow_non_utf8(true).max_depth(5));
+        assert_eq!(::ParseOptions::from(::DemangleOptions::LESS_WHITESPACE), options);
+    }
+
+    #[test]
+    fn try_demangle() {
+        let flags = ::DemangleOptions::new();
+        assert_eq!(::try_demangle("?f@@YAXXZ", flags), "void __cdecl f(void)");
+        assert_eq!(::try_demangle("main", flags), "main");
+        assert!(match ::try_demangle("_ZN3foo3barEv", flags) {
+            ::std::borrow::Cow::Borrowed(s) => s == "_ZN3foo3barEv",
+            ::std::borrow::Cow::Owned(_) => false,
+        });
+    }
+
+    #[test]
+    fn is_msvc_mangled() {
+        assert!(::is_msvc_mangled(b"?f@@YAXXZ"));
+        assert!(::is_msvc_mangled(b"?x@@3HA"));
+        assert!(::is_msvc_mangled(b"??_7Foo@ns@@6B@"));
+        assert!(::is_msvc_mangled(b"??$f@H@@YAXXZ"));
+        assert!(::is_msvc_mangled("?f\u{e4}@@YAXXZ".as_bytes()));
+        assert!(!::is_msvc_mangled(b""));
+        assert!(!::is_msvc_mangled(b"?"));
+        assert!(!::is_msvc_mangled(b"_ZN3foo3barEv"));
+        assert!(!::is_msvc_mangled(b"_RNvC3foo3bar"));
+        assert!(!::is_msvc_mangled(b"_f@8"));
+        assert!(!::is_msvc_mangled(b"?what is this@"));
+        assert!(!::is_msvc_mangled(b"void __cdecl f(void)"));
+    }
+
+    #[test]
+    fn components() {
+        let flags = ::DemangleOptions::new();
+        let parsed = ::parse("?push_back@?$vector@HV?$allocator@H@std@@@std@@QEAAXAEBHPEAX@Z").unwrap();
+        assert_eq!(parsed.function_name(flags), Ok("push_back".to_owned()));
+        assert_eq!(
+            parsed.qualified_scope(flags),
+            Ok("std::vector<int,class std::allocator<int> >".to_owned())
+        );
+        assert_eq!(
+            parsed.parameter_strings(flags),
+            Ok(vec!["int const &".to_owned(), "void *".to_owned()])
+        );
+        assert_eq!(parsed.return_type_string(flags), Ok(Some("void".to_owned())));
+        assert_eq!(
+            parsed.qualified_scope(::DemangleOptions::cxxfilt() | ::DemangleOptions::NO_DEFAULT_TEMPLATE_ARGS),
+            Ok("std::vector<int>".to_owned())
+        );
+
+        let parsed = ::parse("??0Foo@ns@@QAE@XZ").unwrap();
+        assert_eq!(parsed.function_name(flags), Ok("Foo".to_owned()));
+        assert_eq!(parsed.qualified_scope(flags), Ok("ns::Foo".to_owned()));
+        assert_eq!(parsed.parameter_strings(flags), Ok(Vec::new()));
+        assert_eq!(parsed.return_type_string(flags), Ok(None));
+
+        let parsed = ::parse("??HFoo@@QAE?AV0@PAP6AHH@ZZZ").unwrap();
+        assert_eq!(parsed.function_name(flags), Ok("operator+".to_owned()));
+        assert_eq!(
+            parsed.parameter_strings(flags),
+            Ok(vec!["int __cdecl (* *)(int)".to_owned(), "...".to_owned()])
+        );
+        assert_eq!(parsed.return_type_string(flags), Ok(Some("class Foo".to_owned())));
+
+        let parsed = ::parse("?x@@3HA").unwrap();
+        assert_eq!(parsed.qualified_scope(flags), Ok(String::new()));
+        assert_eq!(parsed.return_type_string(flags), Ok(None));
+    }
+
+    #[test]
+    fn operators() {
+        let parsed = ::parse("??Bfoo@@QAEHXZ").unwrap();
+        assert_eq!(
+            parsed.symbol.name,
+            ::Name::Operator(::Operator::Conversion(Some(Box::new(::Type::Int(::StorageClass::empty())))))
+        );
+        let parsed = ::parse("??$?BH@TemplateOps@@QAEHXZ").unwrap();
+        match *parsed.resolve_name(&parsed.symbol.name) {
+            ::Name::Template(ref name, _) => assert_eq!(
+                **name,
+                ::Name::Operator(::Operator::Conversion(Some(Box::new(::Type::Int(::StorageClass::empty())))))
+            ),
+            ref name => panic!("not a template: {:?}", name),
+        }
+
+        let parsed = ::parse("??__K_deg@@YAHO@Z").unwrap();
+        assert_eq!(
+            parsed.symbol.name,
+            ::Name::Operator(::Operator::LiteralOperator("_deg".into()))
+        );
+        assert_eq!(
+            ::demangle("??__K_deg@@YAHO@Z", ::DemangleOptions::new()),
+            Ok("int __cdecl operator \"\"_deg(long double)".to_owned())
+        );
+
+        assert_eq!(::Operator::Plus.to_str(), "operator+");
+        assert_eq!(::Operator::VFTable.to_str(), "`vftable'");
+        assert_eq!(::Operator::Conversion(None).to_str(), "operatorcast");
+
+        let parsed = ::parse("??_PCC@@QAEPAVX@@XZ").unwrap();
+        assert_eq!(
+            parsed.symbol.name,
+            ::Name::Operator(::Operator::UdtReturning(Box::new(::Operator::Arrow)))
+        );
+        assert_eq!(
+            ::serialize(&parsed, ::DemangleOptions::new()),
+            Ok("public: class X * __thiscall C::`udt returning'operator->(void)".to_owned())
+        );
+        assert!(::parse("??_P_PCC@@QAEXXZ").is_err());
+        assert!(::parse("??_P").is_err());
+    }
+
+    #[test]
+    fn constructors() {
+        let flags = ::DemangleOptions::cxxfilt();
+        let foo = ::Symbol::new(::Name::identifier("Foo"), vec![::Name::identifier("ns")]);
+        assert_eq!(
+            ::serialize_type(&::Type::pointer(::Type::class(foo.clone())), flags),
+            Ok("ns::Foo*".to_owned())
+        );
+        let vector = ::Symbol::new(
+            ::Name::template(::Name::identifier("vector"), vec![::Type::Int(::StorageClass::empty())]),
+            vec![::Name::identifier("std")],
+        );
+        assert_eq!(
+            ::serialize_type(&::Type::reference(::Type::class(vector)), flags),
+            Ok("std::vector<int>&".to_owned())
+        );
+        let f = ::Type::function(vec![::Type::rvalue_reference(::Type::structure(foo))], ::Type::Void(::StorageClass::empty()));
+        assert_eq!(::serialize_type(&::Type::pointer(f), flags), Ok("void (*)(ns::Foo&&)".to_owned()));
+        assert_eq!(
+            ::Operator::conversion(::Type::Bool(::StorageClass::empty())),
+            ::Operator::Conversion(Some(Box::new(::Type::Bool(::StorageClass::empty()))))
+        );
+        assert_eq!(::Operator::literal("_km"), ::Operator::LiteralOperator("_km".into()));
+    }
+
+    #[test]
+    fn type_accessors() {
+        let parsed = ::parse("?f@@YAPBHHPAD@Z").unwrap();
+        let ty = &parsed.symbol_type;
+        assert!(ty.is_function());
+        assert_eq!(ty.params().map(<[::Type]>::len), Some(2));
+        let ret = ty.return_type().unwrap();
+        assert!(!ret.is_function());
+        assert_eq!(ret.pointee(), Some(&::Type::Int(::StorageClass::CONST)));
+        assert_eq!(ret.pointee().unwrap().storage_class(), ::StorageClass::CONST);
+
+        let parsed = ::parse("?f@C@@QBEXXZ").unwrap();
+        assert_eq!(parsed.symbol_type.storage_class(), ::StorageClass::CONST);
+        assert_eq!(parsed.symbol_type.params(), Some(&[::Type::Void(::StorageClass::empty())][..]));
+
+        let parsed = ::parse("?x@@3PAHA").unwrap();
+        assert!(!parsed.symbol_type.is_function());
+        assert_eq!(parsed.symbol_type.params(), None);
+        assert_eq!(parsed.symbol_type.return_type(), None);
+        assert_eq!(parsed.symbol_type.pointee(), Some(&::Type::Int(::StorageClass::empty())));
+        assert_eq!(::Type::VarArgs.storage_class(), ::StorageClass::empty());
+    }
+
+    #[test]
+    fn diff() {
+        let diff = |a, b| ::diff(&::parse(a).unwrap(), &::parse(b).unwrap()).unwrap();
+        assert_eq!(diff("?f@ns@@YAXHPAD@Z", "?f@ns@@YAXHPAD@Z"), vec![]);
+        assert_eq!(diff("?f@ns@@YAXHPAD@Z", "?f@ns@@YAXHPBD@Z"), vec![::Difference::Parameter(1)]);
+        assert_eq!(
+            diff("?f@ns@@YAXHPAD@Z", "?g@ms@@YAHH@Z"),
+            vec![
+                ::Difference::Scope,
+                ::Difference::Name,
+                ::Difference::ReturnType,
+                ::Difference::ParameterCount,
+            ]
+        );
+        assert_eq!(diff("?f@@YAXXZ", "?f@@YAXZZ"), vec![::Difference::Variadic]);
+        assert_eq!(diff("?f@@YAXXZ", "?f@@YGXXZ"), vec![::Difference::CallingConv]);
+        assert_eq!(diff("?f@C@@QAEXXZ", "?f@C@@QBEXXZ"), vec![::Difference::ThisQualifiers]);
+        assert_eq!(diff("?f@C@@QAEXXZ", "?f@C@@AAEXXZ"), vec![::Difference::Access]);
+        assert_eq!(diff("?f@C@@QAEXXZ", "?f@C@@UAEXXZ"), vec![::Difference::Storage]);
+        assert_eq!(
+            diff("??$f@HH@@YAXXZ", "??$f@HM@@YAXXZ"),
+            vec![::Difference::TemplateArg(1)]
+        );
+        assert_eq!(
+            diff("??$f@H@@YAXXZ", "??$f@HM@@YAXXZ"),
+            vec![::Difference::TemplateArgCount]
+        );
+        assert_eq!(diff("?x@@3HA", "?x@@3MA"), vec![::Difference::Type]);
+        assert_eq!(diff("?x@@3HA", "?x@@YAHXZ"), vec![::Difference::Kind, ::Difference::Type]);
+    }
+
+    #[test]
+    fn rtti() {
+        let expect = |input, reference| {
+            expect_with_flags(input, reference, ::DemangleOptions::new());
+        };
+
+        expect("??_R0?AVC@@@8", "class C `RTTI Type Descriptor'");
+        expect("??_R0?AUS@ns@@@8", "struct ns::S `RTTI Type Descriptor'");
+        expect("??_R0H@8", "int `RTTI Type Descriptor'");
+        expect("??_R1A@?0A@EA@C@@8", "C::`RTTI Base Class Descriptor at (0,-1,0,64)'");
+        expect("??_R2C@@8", "C::`RTTI Base Class Array'");
+        expect("??_R3C@@8", "C::`RTTI Class Hierarchy Descriptor'");
+        expect("??_R4C@@6B@", "const C::`RTTI Complete Object Locator'");
+        expect_with_flags(
+            "??_R3C@@8",
+            "RTTI Class Hierarchy Descriptor for C",
+            ::DemangleOptions::DESCRIBE_SPECIAL_SYMS,
+        );
+    }
+
+    #[test]
+    fn rtti_target_class() {
+        let class = |input| {
+            let parsed = ::parse(input).unwrap();
+            let class = parsed.rtti_target_class().map(|symbol| symbol.into_owned().into_owned());
+            class
+        };
+        let c = Some(::Symbol::new(::Name::identifier("C"), vec![::Name::identifier("ns")]));
+        assert_eq!(class("??_R0?AVC@ns@@@8"), c);
+        assert_eq!(class("??_R1A@?0A@EA@C@ns@@8"), c);
+        assert_eq!(class("??_R2C@ns@@8"), c);
+        assert_eq!(class("??_R3C@ns@@8"), c);
+        assert_eq!(class("??_R4C@ns@@6B@"), c);
+        assert_eq!(class("??_7C@ns@@6B@"), c);
+        assert_eq!(class("??_8C@ns@@7B@"), c);
+        assert_eq!(class("??_R0H@8"), None);
+        assert_eq!(class("??0C@ns@@QAE@XZ"), None);
+
+        let parsed = ::parse("??_R4?$vector@H@std@@6B@").unwrap();
+        let symbol = parsed.rtti_target_class().unwrap();
+        assert_eq!(parsed.template_args_of("vector").len(), 1);
+        assert_eq!(symbol.scope.names, [::Name::identifier("std")]);
+    }
+
+    #[test]
+    fn symbol_kind() {
+        let kind = |input| ::parse(input).unwrap().kind();
+        assert_eq!(kind("?f@@YAXXZ"), ::SymbolKind::Function);
+        assert_eq!(kind("?f@C@@QAEXXZ"), ::SymbolKind::Function);
+        assert_eq!(kind("?f@C@@W7EAAXXZ"), ::SymbolKind::Thunk);
+        assert_eq!(kind("?x@@3HA"), ::SymbolKind::GlobalVariable);
+        assert_eq!(kind("?x@ns@@3HA"), ::SymbolKind::GlobalVariable);
+        assert_eq!(kind("?x@C@@2HA"), ::SymbolKind::StaticMemberVariable);
+        assert_eq!(kind("?x@?1??f@@YAXXZ@4HA"), ::SymbolKind::LocalStaticVariable);
+        assert_eq!(kind("??_7C@@6B@"), ::SymbolKind::VTable);
+        assert_eq!(kind("??_8C@@7B@"), ::SymbolKind::VBTable);
+        assert_eq!(kind("?$TSS0@?1??f@@YAXXZ@4HA"), ::SymbolKind::Guard);
+        assert_eq!(kind("??_B?1??f@@YAXXZ@5HA"), ::SymbolKind::Guard);
+        assert_eq!(kind("??_C@_01CNACBAHC@?$PP?$AA@"), ::SymbolKind::StringLiteral);
+        assert_eq!(kind("?f@@9"), ::SymbolKind::Other);
+        assert_eq!(kind("??_R0?AVC@@@8"), ::SymbolKind::Rtti);
+        assert_eq!(kind("??_R2C@@8"), ::SymbolKind::Rtti);
+        assert_eq!(kind("??_R4C@@6B@"), ::SymbolKind::Rtti);
+    }
+
+    #[test]
+    fn variable_kind() {
+        let kind = |input| ::parse(input).unwrap().variable_kind();
+        assert_eq!(kind("?x@@3HA"), Some(::VariableKind::Global));
+        assert_eq!(kind("?x@C@@0HA"), Some(::VariableKind::StaticMember(::Access::Private)));
+        assert_eq!(kind("?x@C@@1HA"), Some(::VariableKind::StaticMember(::Access::Protected)));
+        assert_eq!(kind("?x@C@@2HA"), Some(::VariableKind::StaticMember(::Access::Public)));
+        assert_eq!(kind("?x@?1??f@@YAXXZ@4HA"), Some(::VariableKind::LocalStatic));
+        assert_eq!(kind("?f@@YAXXZ"), None);
+        assert_eq!(kind("??_7C@@6B@"), None);
+    }
+
+    #[test]
+    fn visitor() {
+        // Collects the names of all class types.
+        struct Classes<'a>(Vec<String>, &'a [::Name<'a>]);
+
+        impl<'a> ::Visitor for Classes<'a> {
+            fn visit_type(&mut self, t: &::Type) {
+                if let ::Type::Class(ref symbol, _) = *t {
+                    let s = ::serialize(
+                        &::ParseResult {
+                            symbol: (**symbol).clone(),
+                            symbol_type: ::Type::None,
+                            symbol_kind: ::SymbolKind::Other,
+                            variable_kind: None,
+                            thunk_adjustment: None,
+                            names: self.1.to_vec(),
+                        },
+                        ::DemangleOptions::new(),
+                    );
+                    self.0.push(s.unwrap());
+                }
+                ::walk_type(self, t);
+            }
+        }
+
+        let parsed = ::parse("?f@ns@@YA?AVA@@PEAV?$B@VC@@@1@AEBV?$vector@HV?$allocator@H@std@@@std@@@Z").unwrap();
+        let mut classes = Classes(Vec::new(), &parsed.names);
+        ::walk(&mut classes, &parsed);
+        // The template names in `parsed.names` are visited first, once
+        // each and in the order they were read, so a template argument
+        // comes before the template using it. The Name::Backrefs to them
+        // in the symbol are not followed.
+        assert_eq!(
+            classes.0,
+            [
+                "C",
+                "std::allocator<int>",
+                "A",
+                "ns::B<class C>",
+                "std::vector<int,class std::allocator<int> >",
+            ]
+        );
+    }
+
+    #[test]
+    fn max_depth() {
+        let nested = |depth| format!("?x@@3{}HA", "PA".repeat(depth));
+        let pointers = |depth: usize| format!("int {}*x", "* ".repeat(depth - 1));
+        expect_with_flags(&nested(100), &pointers(100), ::DemangleOptions::new());
+        assert!(::demangle(&nested(100_000), ::DemangleOptions::new()).is_err());
+        assert!(::demangle(&nested(10), ::DemangleOptions::new().max_depth(5)).is_err());
+        expect_with_flags(&nested(10), &pointers(10), ::DemangleOptions::new().max_depth(20));
+
+        let templates = |depth| format!("?x@@3{}H{}A", "V?$A@".repeat(depth), "@@".repeat(depth));
+        assert!(::demangle(&templates(100_000), ::DemangleOptions::new()).is_err());
+        // An array with 100000 (BIGKA@) dimensions.
+        let arrays = format!("?x@@3PAYBIGKA@{}HA", "0".repeat(100_000));
+        assert!(::demangle(&arrays, ::DemangleOptions::new()).is_err());
+    }
+
+    #[test]
+    fn max_recursive_depth() {
+        // Types built by hand stop the serializer at MAX_RECURSIVE_DEPTH
+        // levels as well, whatever max_depth() allows.
+        let mut t = ::Type::Int(::StorageClass::empty());
+        for _ in 0..150 {
+            let name = ::Name::Template(Box::new(::Name::NonTemplate("A".into())), ::Params { types: vec![t] });
+            t = ::Type::class(::Symbol::new(name, Vec::new()));
+        }
+        let err = ::serialize_type(&t, ::DemangleOptions::new().max_depth(10_000_000)).unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::LimitExceeded);
+    }
+
+    #[test]
+    fn parse_and_serialize_depth_agree() {
+        // Whatever parses within the depth limit can also be written
+        // within it, and vice versa.
+        let input = format!("?x@@3{}HA", "PEA".repeat(127));
+        assert!(::parse(&input).is_ok());
+        assert!(::demangle(&input, ::DemangleOptions::new()).is_ok());
+        let input = format!("?x@@3{}HA", "PEA".repeat(128));
+        assert_eq!(::parse(&input).unwrap_err().kind(), ::ErrorKind::LimitExceeded);
+        let err = ::demangle(&input, ::DemangleOptions::new()).unwrap_err();
+        assert_eq!(err.kind(), ::ErrorKind::LimitExceeded);
+
+        let input = "??$f@V?$A@V?$A@H@@@@@@YAXXZ";
+        let depth = (1..10)
+            .find(|&depth| ::parse_with_options(input, ::ParseOptions::new().max_depth(depth)).is_ok())
+            .unwrap();
+        assert!(::demangle(input, ::DemangleOptions::new().max_depth(depth)).is_ok());
+        assert!(::demangle(input, ::DemangleOptions::new().max_depth(depth - 1)).is_err());
+    }
+
+    #[test]
+    fn deep_chains() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::Hash;
+
+        struct Walker;
+        impl ::Visitor for Walker {}
+
+        // Parse results are cloned, compared, hashed, written and walked
+        // recursively, so whatever max_depth() allows, the parser stops at
+        // MAX_RECURSIVE_DEPTH levels, for chains of pointers and arrays as
+        // well.
+        let flags = ::DemangleOptions::new().max_depth(usize::MAX);
+        let shapes: [fn(usize) -> String; 5] = [
+            |n| format!("?x@@3{}HA", "PA".repeat(n)),
+            |n| format!("?x@@3{}HA", "PAY0BA@".repeat(n)),
+            |n| format!("?x@@3{}H{}A", "V?$A@".repeat(n), "@@".repeat(n)),
+            |n| format!("?x@@3{}X{}A", "P6A".repeat(n), "XZ".repeat(n)),
+            |n| (0..n).fold("?f@@YAXXZ".to_owned(), |inner, _| format!("?x@?1?{}@4HA", inner)),
+        ];
+        for shape in &shapes {
+            let input = shape(10_000);
+            let limit_exceeded = ::ErrorKind::LimitExceeded;
+            assert_eq!(::parse_with_options(&input, flags).unwrap_err().kind(), limit_exceeded);
+            assert_eq!(::demangle(&input, flags).unwrap_err().kind(), limit_exceeded);
+            assert_eq!(::parse_events(&input, flags, |_| ()).unwrap_err().kind(), limit_exceeded);
+
+            // Everything works on the deepest symbols that parse, in the
+            // stack of a thread spawned by std::thread.
+            let n = (0..::MAX_RECURSIVE_DEPTH).rev().find(|&n| ::parse_with_options(&shape(n), flags).is_ok()).unwrap();
+            assert!(n > ::MAX_RECURSIVE_DEPTH / 4);
+            let input = shape(n);
+            std::thread::spawn(move || {
+                let parsed = ::parse_with_options(&input, flags).unwrap();
+                let cloned = parsed.clone();
+                assert_eq!(cloned, parsed);
+                assert_eq!(cloned.symbol_type.cmp(&parsed.symbol_type), std::cmp::Ordering::Equal);
+                parsed.symbol_type.hash(&mut DefaultHasher::new());
+                assert!(!format!("{:?}", parsed).is_empty());
+                assert!(!parsed.dump().is_empty());
+                ::walk(&mut Walker, &parsed);
+                assert!(::parse_events(&input, flags, |_| ()).is_ok());
+                let mut rewritten = parsed.clone();
+                ::Pipeline::new().pass(::StdTypedefs).run(&mut rewritten);
+                assert_eq!(::serialize(&rewritten, flags), ::serialize(&parsed, flags));
+                assert!(::serialize(&parsed.into_owned(), flags).is_ok());
+            })
+            .join()
+            .unwrap();
+        }
+
+        // Types built by hand can chain pointers and arrays far more deeply
+        // than the parser allows, and neither passing them on as events nor
+        // dropping them recurses.
+        let mut t = ::Type::array(16, ::Type::Int(::StorageClass::empty()));
+        for _ in 0..100_000 {
+            t = ::Type::pointer(t);
+        }
+        let mut events = 0;
+        ::EventEmitter {
+            names: &[],
+            callback: |_: ::Event| events += 1,
+        }
+        .type_(&t);
+        assert_eq!(events, 2 * 100_001 + 1);
+        drop(t);
+    }
+
+    #[test]
+    fn test_strings() {
+        let expect = |input, reference| {
+            expect_with_flags(input, reference, ::DemangleOptions::new());
+        };
+
+        // Test symbols extracted from clang's test/CodeGenCXX/mangle-ms-string-literals.cpp.
+        // Even though we don't print the encoded strings, these tests
+        // exhaustively cover all the cases we'll run into.
+
+        // Single-byte characters.
+        expect("??_C@_01CNACBAHC@?$PP?$AA@", "`string'");
+        expect("??_C@_01DEBJCBDD@?$PO?$AA@", "`string'");
+        expect("??_C@_01BPDEHCPA@?$PN?$AA@", "`string'");
+        expect("??_C@_01GCPEDLB@?$PM?$AA@", "`string'");
+        expect("??_C@_01EJGONFHG@?$PL?$AA@", "`string'");
+        expect("??_C@_01FAHFOEDH@?z?$AA@", "`string'");
+        expect("??_C@_01HLFILHPE@?y?$AA@", "`string'");
+        expect("??_C@_01GCEDIGLF@?x?$AA@", "`string'");
+        expect("??_C@_01OFNLJKHK@?w?$AA@", "`string'");
+        expect("??_C@_01PMMAKLDL@?v?$AA@", "`string'");
+        expect("??_C@_01NHONPIPI@?u?$AA@", "`string'");
+        expect("??_C@_01MOPGMJLJ@?t?$AA@", "`string'");
+        expect("??_C@_01IBLHFPHO@?s?$AA@", "`string'");
+        expect("??_C@_01JIKMGODP@?r?$AA@", "`string'");
+        expect("??_C@_01LDIBDNPM@?q?$AA@", "`string'");
+        expect("??_C@_01KKJKAMLN@?p?$AA@", "`string'");
+        expect("??_C@_01GHMAACCD@?o?$AA@", "`string'");
+        expect("??_C@_01HONLDDGC@?n?$AA@", "`string'");
+        expect("??_C@_01FFPGGAKB@?m?$AA@", "`string'");
+        expect("??_C@_01EMONFBOA@?l?$AA@", "`string'");
+        expect("??_C@_01DKMMHCH@?k?$AA@", "`string'");
+        expect("??_C@_01BKLHPGGG@?j?$AA@", "`string'");
+        expect("??_C@_01DBJKKFKF@?i?$AA@", "`string'");
+        expect("??_C@_01CIIBJEOE@?h?$AA@", "`string'");
+        expect("??_C@_01KPBJIICL@?g?$AA@", "`string'");
+        expect("??_C@_01LGACLJGK@?f?$AA@", "`string'");
+        expect("??_C@_01JNCPOKKJ@?e?$AA@", "`string'");
+        expect("??_C@_01IEDENLOI@?d?$AA@", "`string'");
+        expect("??_C@_01MLHFENCP@?c?$AA@", "`string'");
+        expect("??_C@_01NCGOHMGO@?b?$AA@", "`string'");
+        expect("??_C@_01PJEDCPKN@?a?$AA@", "`string'");
+        expect("??_C@_01OAFIBOOM@?$OA?$AA@", "`string'");
+        expect("??_C@_01LIIGDENA@?$NP?$AA@", "`string'");
+        expect("??_C@_01KBJNAFJB@?$NO?$AA@", "`string'");
+        expect("??_C@_01IKLAFGFC@?$NN?$AA@", "`string'");
+        expect("??_C@_01JDKLGHBD@?$NM?$AA@", "`string'");
         expect("??_C@_01NMOKPBNE@?$NL?$AA@", "`string'");
         expect("??_C@_01MFPBMAJF@?Z?$AA@", "`string'");
         expect("??_C@_01OONMJDFG@?Y?$AA@", "`string'");
@@ -2311,278 +8803,994 @@ mod tests {
         expect("??_C@_01KHDNNMEB@?$AB?$AA@", "`string'");
         expect("??_C@_01LOCGONAA@?$AA?$AA@", "`string'");
 
-        // Wide characters.
-        expect("??_C@_13KDLDGPGJ@?$AA?7?$AA?$AA@", "`string'");
-        expect("??_C@_13LBAGMAIH@?$AA?6?$AA?$AA@", "`string'");
-        expect("??_C@_13JLKKHOC@?$AA?$AL?$AA?$AA@", "`string'");
-        expect("??_C@_13HOIJIPNN@?$AA?5?$AA?$AA@", "`string'");
-        expect("??_C@_13MGDFOILI@?$AA?$CB?$AA?$AA@", "`string'");
-        expect("??_C@_13NEIAEHFG@?$AA?$CC?$AA?$AA@", "`string'");
-        expect("??_C@_13GMDMCADD@?$AA?$CD?$AA?$AA@", "`string'");
-        expect("??_C@_13PBOLBIIK@?$AA$?$AA?$AA@", "`string'");
-        expect("??_C@_13EJFHHPOP@?$AA?$CF?$AA?$AA@", "`string'");
-        expect("??_C@_13FLOCNAAB@?$AA?$CG?$AA?$AA@", "`string'");
-        expect("??_C@_13ODFOLHGE@?$AA?8?$AA?$AA@", "`string'");
-        expect("??_C@_13LLDNKHDC@?$AA?$CI?$AA?$AA@", "`string'");
-        expect("??_C@_13DIBMAFH@?$AA?$CJ?$AA?$AA@", "`string'");
-        expect("??_C@_13BBDEGPLJ@?$AA?$CK?$AA?$AA@", "`string'");
-        expect("??_C@_13KJIIAINM@?$AA?$CL?$AA?$AA@", "`string'");
-        expect("??_C@_13DEFPDAGF@?$AA?0?$AA?$AA@", "`string'");
-        expect("??_C@_13IMODFHAA@?$AA?9?$AA?$AA@", "`string'");
-        expect("??_C@_13JOFGPIOO@?$AA?4?$AA?$AA@", "`string'");
-        expect("??_C@_13CGOKJPIL@?$AA?1?$AA?$AA@", "`string'");
-        expect("??_C@_13COJANIEC@?$AA0?$AA?$AA@", "`string'");
-        expect("??_C@_13JGCMLPCH@?$AA1?$AA?$AA@", "`string'");
-        expect("??_C@_13IEJJBAMJ@?$AA2?$AA?$AA@", "`string'");
-        expect("??_C@_13DMCFHHKM@?$AA3?$AA?$AA@", "`string'");
-        expect("??_C@_13KBPCEPBF@?$AA4?$AA?$AA@", "`string'");
-        expect("??_C@_13BJEOCIHA@?$AA5?$AA?$AA@", "`string'");
-        expect("??_C@_13LPLIHJO@?$AA6?$AA?$AA@", "`string'");
-        expect("??_C@_13LDEHOAPL@?$AA7?$AA?$AA@", "`string'");
-        expect("??_C@_13OLCEPAKN@?$AA8?$AA?$AA@", "`string'");
-        expect("??_C@_13FDJIJHMI@?$AA9?$AA?$AA@", "`string'");
-        expect("??_C@_13EBCNDICG@?$AA?3?$AA?$AA@", "`string'");
-        expect("??_C@_13PJJBFPED@?$AA?$DL?$AA?$AA@", "`string'");
-        expect("??_C@_13GEEGGHPK@?$AA?$DM?$AA?$AA@", "`string'");
-        expect("??_C@_13NMPKAAJP@?$AA?$DN?$AA?$AA@", "`string'");
-        expect("??_C@_13MOEPKPHB@?$AA?$DO?$AA?$AA@", "`string'");
-        expect("??_C@_13HGPDMIBE@?$AA?$DP?$AA?$AA@", "`string'");
-        expect("??_C@_13EFKPHINO@?$AA?$EA?$AA?$AA@", "`string'");
-        expect("??_C@_13PNBDBPLL@?$AAA?$AA?$AA@", "`string'");
-        expect("??_C@_13OPKGLAFF@?$AAB?$AA?$AA@", "`string'");
-        expect("??_C@_13FHBKNHDA@?$AAC?$AA?$AA@", "`string'");
-        expect("??_C@_13MKMNOPIJ@?$AAD?$AA?$AA@", "`string'");
-        expect("??_C@_13HCHBIIOM@?$AAE?$AA?$AA@", "`string'");
-        expect("??_C@_13GAMECHAC@?$AAF?$AA?$AA@", "`string'");
-        expect("??_C@_13NIHIEAGH@?$AAG?$AA?$AA@", "`string'");
-        expect("??_C@_13IABLFADB@?$AAH?$AA?$AA@", "`string'");
-        expect("??_C@_13DIKHDHFE@?$AAI?$AA?$AA@", "`string'");
-        expect("??_C@_13CKBCJILK@?$AAJ?$AA?$AA@", "`string'");
-        expect("??_C@_13JCKOPPNP@?$AAK?$AA?$AA@", "`string'");
-        expect("??_C@_13PHJMHGG@?$AAL?$AA?$AA@", "`string'");
-        expect("??_C@_13LHMFKAAD@?$AAM?$AA?$AA@", "`string'");
-        expect("??_C@_13KFHAAPON@?$AAN?$AA?$AA@", "`string'");
-        expect("??_C@_13BNMMGIII@?$AAO?$AA?$AA@", "`string'");
-        expect("??_C@_13BFLGCPEB@?$AAP?$AA?$AA@", "`string'");
-        expect("??_C@_13KNAKEICE@?$AAQ?$AA?$AA@", "`string'");
-        expect("??_C@_13LPLPOHMK@?$AAR?$AA?$AA@", "`string'");
-        expect("??_C@_13HADIAKP@?$AAS?$AA?$AA@", "`string'");
-        expect("??_C@_13JKNELIBG@?$AAT?$AA?$AA@", "`string'");
-        expect("??_C@_13CCGINPHD@?$AAU?$AA?$AA@", "`string'");
-        expect("??_C@_13DANNHAJN@?$AAV?$AA?$AA@", "`string'");
-        expect("??_C@_13IIGBBHPI@?$AAW?$AA?$AA@", "`string'");
-        expect("??_C@_13NAACAHKO@?$AAX?$AA?$AA@", "`string'");
-        expect("??_C@_13GILOGAML@?$AAY?$AA?$AA@", "`string'");
-        expect("??_C@_13HKALMPCF@?$AAZ?$AA?$AA@", "`string'");
-        expect("??_C@_13MCLHKIEA@?$AA?$FL?$AA?$AA@", "`string'");
-        expect("??_C@_13FPGAJAPJ@?$AA?2?$AA?$AA@", "`string'");
-        expect("??_C@_13OHNMPHJM@?$AA?$FN?$AA?$AA@", "`string'");
-        expect("??_C@_13PFGJFIHC@?$AA?$FO?$AA?$AA@", "`string'");
-        expect("??_C@_13ENNFDPBH@?$AA_?$AA?$AA@", "`string'");
-        expect("??_C@_13OFJNNHOA@?$AA?$GA?$AA?$AA@", "`string'");
-        expect("??_C@_13FNCBLAIF@?$AAa?$AA?$AA@", "`string'");
-        expect("??_C@_13EPJEBPGL@?$AAb?$AA?$AA@", "`string'");
-        expect("??_C@_13PHCIHIAO@?$AAc?$AA?$AA@", "`string'");
-        expect("??_C@_13GKPPEALH@?$AAd?$AA?$AA@", "`string'");
-        expect("??_C@_13NCEDCHNC@?$AAe?$AA?$AA@", "`string'");
-        expect("??_C@_13MAPGIIDM@?$AAf?$AA?$AA@", "`string'");
-        expect("??_C@_13HIEKOPFJ@?$AAg?$AA?$AA@", "`string'");
-        expect("??_C@_13CACJPPAP@?$AAh?$AA?$AA@", "`string'");
-        expect("??_C@_13JIJFJIGK@?$AAi?$AA?$AA@", "`string'");
-        expect("??_C@_13IKCADHIE@?$AAj?$AA?$AA@", "`string'");
-        expect("??_C@_13DCJMFAOB@?$AAk?$AA?$AA@", "`string'");
-        expect("??_C@_13KPELGIFI@?$AAl?$AA?$AA@", "`string'");
-        expect("??_C@_13BHPHAPDN@?$AAm?$AA?$AA@", "`string'");
-        expect("??_C@_13FECKAND@?$AAn?$AA?$AA@", "`string'");
-        expect("??_C@_13LNPOMHLG@?$AAo?$AA?$AA@", "`string'");
-        expect("??_C@_13LFIEIAHP@?$AAp?$AA?$AA@", "`string'");
-        expect("??_C@_13NDIOHBK@?$AAq?$AA?$AA@", "`string'");
-        expect("??_C@_13BPINEIPE@?$AAr?$AA?$AA@", "`string'");
-        expect("??_C@_13KHDBCPJB@?$AAs?$AA?$AA@", "`string'");
-        expect("??_C@_13DKOGBHCI@?$AAt?$AA?$AA@", "`string'");
-        expect("??_C@_13ICFKHAEN@?$AAu?$AA?$AA@", "`string'");
-        expect("??_C@_13JAOPNPKD@?$AAv?$AA?$AA@", "`string'");
-        expect("??_C@_13CIFDLIMG@?$AAw?$AA?$AA@", "`string'");
-        expect("??_C@_13HADAKIJA@?$AAx?$AA?$AA@", "`string'");
-        expect("??_C@_13MIIMMPPF@?$AAy?$AA?$AA@", "`string'");
-        expect("??_C@_13NKDJGABL@?$AAz?$AA?$AA@", "`string'");
-        expect("??_C@_13GCIFAHHO@?$AA?$HL?$AA?$AA@", "`string'");
-        expect("??_C@_13PPFCDPMH@?$AA?$HM?$AA?$AA@", "`string'");
-        expect("??_C@_13EHOOFIKC@?$AA?$HN?$AA?$AA@", "`string'");
-        expect("??_C@_13FFFLPHEM@?$AA?$HO?$AA?$AA@", "`string'");
+        // Wide characters.
+        expect("??_C@_13KDLDGPGJ@?$AA?7?$AA?$AA@", "`string'");
+        expect("??_C@_13LBAGMAIH@?$AA?6?$AA?$AA@", "`string'");
+        expect("??_C@_13JLKKHOC@?$AA?$AL?$AA?$AA@", "`string'");
+        expect("??_C@_13HOIJIPNN@?$AA?5?$AA?$AA@", "`string'");
+        expect("??_C@_13MGDFOILI@?$AA?$CB?$AA?$AA@", "`string'");
+        expect("??_C@_13NEIAEHFG@?$AA?$CC?$AA?$AA@", "`string'");
+        expect("??_C@_13GMDMCADD@?$AA?$CD?$AA?$AA@", "`string'");
+        expect("??_C@_13PBOLBIIK@?$AA$?$AA?$AA@", "`string'");
+        expect("??_C@_13EJFHHPOP@?$AA?$CF?$AA?$AA@", "`string'");
+        expect("??_C@_13FLOCNAAB@?$AA?$CG?$AA?$AA@", "`string'");
+        expect("??_C@_13ODFOLHGE@?$AA?8?$AA?$AA@", "`string'");
+        expect("??_C@_13LLDNKHDC@?$AA?$CI?$AA?$AA@", "`string'");
+        expect("??_C@_13DIBMAFH@?$AA?$CJ?$AA?$AA@", "`string'");
+        expect("??_C@_13BBDEGPLJ@?$AA?$CK?$AA?$AA@", "`string'");
+        expect("??_C@_13KJIIAINM@?$AA?$CL?$AA?$AA@", "`string'");
+        expect("??_C@_13DEFPDAGF@?$AA?0?$AA?$AA@", "`string'");
+        expect("??_C@_13IMODFHAA@?$AA?9?$AA?$AA@", "`string'");
+        expect("??_C@_13JOFGPIOO@?$AA?4?$AA?$AA@", "`string'");
+        expect("??_C@_13CGOKJPIL@?$AA?1?$AA?$AA@", "`string'");
+        expect("??_C@_13COJANIEC@?$AA0?$AA?$AA@", "`string'");
+        expect("??_C@_13JGCMLPCH@?$AA1?$AA?$AA@", "`string'");
+        expect("??_C@_13IEJJBAMJ@?$AA2?$AA?$AA@", "`string'");
+        expect("??_C@_13DMCFHHKM@?$AA3?$AA?$AA@", "`string'");
+        expect("??_C@_13KBPCEPBF@?$AA4?$AA?$AA@", "`string'");
+        expect("??_C@_13BJEOCIHA@?$AA5?$AA?$AA@", "`string'");
+        expect("??_C@_13LPLIHJO@?$AA6?$AA?$AA@", "`string'");
+        expect("??_C@_13LDEHOAPL@?$AA7?$AA?$AA@", "`string'");
+        expect("??_C@_13OLCEPAKN@?$AA8?$AA?$AA@", "`string'");
+        expect("??_C@_13FDJIJHMI@?$AA9?$AA?$AA@", "`string'");
+        expect("??_C@_13EBCNDICG@?$AA?3?$AA?$AA@", "`string'");
+        expect("??_C@_13PJJBFPED@?$AA?$DL?$AA?$AA@", "`string'");
+        expect("??_C@_13GEEGGHPK@?$AA?$DM?$AA?$AA@", "`string'");
+        expect("??_C@_13NMPKAAJP@?$AA?$DN?$AA?$AA@", "`string'");
+        expect("??_C@_13MOEPKPHB@?$AA?$DO?$AA?$AA@", "`string'");
+        expect("??_C@_13HGPDMIBE@?$AA?$DP?$AA?$AA@", "`string'");
+        expect("??_C@_13EFKPHINO@?$AA?$EA?$AA?$AA@", "`string'");
+        expect("??_C@_13PNBDBPLL@?$AAA?$AA?$AA@", "`string'");
+        expect("??_C@_13OPKGLAFF@?$AAB?$AA?$AA@", "`string'");
+        expect("??_C@_13FHBKNHDA@?$AAC?$AA?$AA@", "`string'");
+        expect("??_C@_13MKMNOPIJ@?$AAD?$AA?$AA@", "`string'");
+        expect("??_C@_13HCHBIIOM@?$AAE?$AA?$AA@", "`string'");
+        expect("??_C@_13GAMECHAC@?$AAF?$AA?$AA@", "`string'");
+        expect("??_C@_13NIHIEAGH@?$AAG?$AA?$AA@", "`string'");
+        expect("??_C@_13IABLFADB@?$AAH?$AA?$AA@", "`string'");
+        expect("??_C@_13DIKHDHFE@?$AAI?$AA?$AA@", "`string'");
+        expect("??_C@_13CKBCJILK@?$AAJ?$AA?$AA@", "`string'");
+        expect("??_C@_13JCKOPPNP@?$AAK?$AA?$AA@", "`string'");
+        expect("??_C@_13PHJMHGG@?$AAL?$AA?$AA@", "`string'");
+        expect("??_C@_13LHMFKAAD@?$AAM?$AA?$AA@", "`string'");
+        expect("??_C@_13KFHAAPON@?$AAN?$AA?$AA@", "`string'");
+        expect("??_C@_13BNMMGIII@?$AAO?$AA?$AA@", "`string'");
+        expect("??_C@_13BFLGCPEB@?$AAP?$AA?$AA@", "`string'");
+        expect("??_C@_13KNAKEICE@?$AAQ?$AA?$AA@", "`string'");
+        expect("??_C@_13LPLPOHMK@?$AAR?$AA?$AA@", "`string'");
+        expect("??_C@_13HADIAKP@?$AAS?$AA?$AA@", "`string'");
+        expect("??_C@_13JKNELIBG@?$AAT?$AA?$AA@", "`string'");
+        expect("??_C@_13CCGINPHD@?$AAU?$AA?$AA@", "`string'");
+        expect("??_C@_13DANNHAJN@?$AAV?$AA?$AA@", "`string'");
+        expect("??_C@_13IIGBBHPI@?$AAW?$AA?$AA@", "`string'");
+        expect("??_C@_13NAACAHKO@?$AAX?$AA?$AA@", "`string'");
+        expect("??_C@_13GILOGAML@?$AAY?$AA?$AA@", "`string'");
+        expect("??_C@_13HKALMPCF@?$AAZ?$AA?$AA@", "`string'");
+        expect("??_C@_13MCLHKIEA@?$AA?$FL?$AA?$AA@", "`string'");
+        expect("??_C@_13FPGAJAPJ@?$AA?2?$AA?$AA@", "`string'");
+        expect("??_C@_13OHNMPHJM@?$AA?$FN?$AA?$AA@", "`string'");
+        expect("??_C@_13PFGJFIHC@?$AA?$FO?$AA?$AA@", "`string'");
+        expect("??_C@_13ENNFDPBH@?$AA_?$AA?$AA@", "`string'");
+        expect("??_C@_13OFJNNHOA@?$AA?$GA?$AA?$AA@", "`string'");
+        expect("??_C@_13FNCBLAIF@?$AAa?$AA?$AA@", "`string'");
+        expect("??_C@_13EPJEBPGL@?$AAb?$AA?$AA@", "`string'");
+        expect("??_C@_13PHCIHIAO@?$AAc?$AA?$AA@", "`string'");
+        expect("??_C@_13GKPPEALH@?$AAd?$AA?$AA@", "`string'");
+        expect("??_C@_13NCEDCHNC@?$AAe?$AA?$AA@", "`string'");
+        expect("??_C@_13MAPGIIDM@?$AAf?$AA?$AA@", "`string'");
+        expect("??_C@_13HIEKOPFJ@?$AAg?$AA?$AA@", "`string'");
+        expect("??_C@_13CACJPPAP@?$AAh?$AA?$AA@", "`string'");
+        expect("??_C@_13JIJFJIGK@?$AAi?$AA?$AA@", "`string'");
+        expect("??_C@_13IKCADHIE@?$AAj?$AA?$AA@", "`string'");
+        expect("??_C@_13DCJMFAOB@?$AAk?$AA?$AA@", "`string'");
+        expect("??_C@_13KPELGIFI@?$AAl?$AA?$AA@", "`string'");
+        expect("??_C@_13BHPHAPDN@?$AAm?$AA?$AA@", "`string'");
+        expect("??_C@_13FECKAND@?$AAn?$AA?$AA@", "`string'");
+        expect("??_C@_13LNPOMHLG@?$AAo?$AA?$AA@", "`string'");
+        expect("??_C@_13LFIEIAHP@?$AAp?$AA?$AA@", "`string'");
+        expect("??_C@_13NDIOHBK@?$AAq?$AA?$AA@", "`string'");
+        expect("??_C@_13BPINEIPE@?$AAr?$AA?$AA@", "`string'");
+        expect("??_C@_13KHDBCPJB@?$AAs?$AA?$AA@", "`string'");
+        expect("??_C@_13DKOGBHCI@?$AAt?$AA?$AA@", "`string'");
+        expect("??_C@_13ICFKHAEN@?$AAu?$AA?$AA@", "`string'");
+        expect("??_C@_13JAOPNPKD@?$AAv?$AA?$AA@", "`string'");
+        expect("??_C@_13CIFDLIMG@?$AAw?$AA?$AA@", "`string'");
+        expect("??_C@_13HADAKIJA@?$AAx?$AA?$AA@", "`string'");
+        expect("??_C@_13MIIMMPPF@?$AAy?$AA?$AA@", "`string'");
+        expect("??_C@_13NKDJGABL@?$AAz?$AA?$AA@", "`string'");
+        expect("??_C@_13GCIFAHHO@?$AA?$HL?$AA?$AA@", "`string'");
+        expect("??_C@_13PPFCDPMH@?$AA?$HM?$AA?$AA@", "`string'");
+        expect("??_C@_13EHOOFIKC@?$AA?$HN?$AA?$AA@", "`string'");
+        expect("??_C@_13FFFLPHEM@?$AA?$HO?$AA?$AA@", "`string'");
+
+        // Tests for maximum string length
+        expect("??_C@_0CF@LABBIIMO@012345678901234567890123456789AB@", "`string'");
+        expect("??_C@_1EK@KFPEBLPK@?$AA0?$AA1?$AA2?$AA3?$AA4?$AA5?$AA6?$AA7?$AA8?$AA9?$AA0?$AA1?$AA2?$AA3?$AA4?$AA5?$AA6?$AA7?$AA8?$AA9?$AA0?$AA1?$AA2?$AA3?$AA4?$AA5?$AA6?$AA7?$AA8?$AA9?$AAA?$AAB@", "`string'");
+        // Unicode character.
+        expect("??_C@_13IIHIAFKH@?W?$PP?$AA?$AA@", "`string'");
+        // u8/u/U literal strings.
+        expect("??_C@_02PCEFGMJL@hi?$AA@", "`string'");
+        expect("??_C@_05OMLEGLOC@h?$AAi?$AA?$AA?$AA@", "`string'");
+        expect("??_C@_0M@GFNAJIPG@h?$AA?$AA?$AAi?$AA?$AA?$AA?$AA?$AA?$AA?$AA@", "`string'");
+    }
+
+    #[test]
+    fn upstream_tests() {
+        let expect = |input, reference| {
+            expect_with_flags(input, reference, ::DemangleOptions::LESS_WHITESPACE);
+        };
+        expect("?x@@3HA", "int x");
+        expect("?x@@3PEAHEA", "int*x");
+        expect("?x@@3PEAPEAHEA", "int**x");
+        expect("?x@@3PEAY02HEA", "int(*x)[3]");
+        expect("?x@@3PEAY124HEA", "int(*x)[3][5]");
+        expect("?x@@3PEAY02$$CBHEA", "int const(*x)[3]");
+        expect("?x@@3PEAEEA", "unsigned char*x");
+        expect("?x@@3PEAY1NKM@5HEA", "int(*x)[3500][6]");
+        expect("?x@@YAXMH@Z", "void __cdecl x(float,int)");
+        expect("?x@@YAXMH@Z", "void __cdecl x(float,int)");
+        expect("?x@@3P6AHMNH@ZEA", "int __cdecl (*x)(float,double,int)");
+        expect("?x@@3P6AHP6AHM@ZN@ZEA", "int __cdecl (*x)(int __cdecl (*)(float),double)");
+        expect(
+            "?x@@3P6AHP6AHM@Z0@ZEA",
+            "int __cdecl (*x)(int __cdecl (*)(float),int __cdecl (*)(float))",
+        );
+
+        expect("?x@ns@@3HA", "int ns::x");
+
+        // Microsoft's undname returns "int const * const x" for this symbol.
+        // I believe it's their bug.
+        expect("?x@@3PEBHEB", "int const*x");
+
+        expect("?x@@3QEAHEB", "int*const x");
+        expect("?x@@3QEBHEB", "int const*const x");
+
+        expect("?x@@3AEBHEB", "int const&x");
+
+        expect("?x@@3PEAUty@@EA", "struct ty*x");
+        expect("?x@@3PEATty@@EA", "union ty*x");
+        expect("?x@@3PEAUty@@EA", "struct ty*x");
+        expect("?x@@3PEAW4ty@@EA", "enum ty*x");
+        expect("?x@@3PEAVty@@EA", "class ty*x");
+
+        expect("?x@@3PEAV?$tmpl@H@@EA", "class tmpl<int>*x");
+        expect("?x@@3PEAU?$tmpl@H@@EA", "struct tmpl<int>*x");
+        expect("?x@@3PEAT?$tmpl@H@@EA", "union tmpl<int>*x");
+        expect("?instance@@3Vklass@@A", "class klass instance");
+        expect(
+            "?instance$initializer$@@3P6AXXZEA",
+            "void __cdecl (*instance$initializer$)(void)",
+        );
+        expect("??0klass@@QEAA@XZ", "public: __cdecl klass::klass(void)");
+        expect("??1klass@@QEAA@XZ", "public: __cdecl klass::~klass(void)");
+        expect(
+            "?x@@YAHPEAVklass@@AEAV1@@Z",
+            "int __cdecl x(class klass*,class klass&)",
+        );
+        expect(
+            "?x@ns@@3PEAV?$klass@HH@1@EA",
+            "class ns::klass<int,int>*ns::x",
+        );
+        expect(
+            "?fn@?$klass@H@ns@@QEBAIXZ",
+            "public: unsigned int __cdecl ns::klass<int>::fn(void)const",
+        );
+
+        expect(
+            "??4klass@@QEAAAEBV0@AEBV0@@Z",
+            "public: class klass const& __cdecl klass::operator=(class klass const&)",
+        );
+        expect("??7klass@@QEAA_NXZ",
+               "public: bool __cdecl klass::operator!(void)");
+        expect(
+            "??8klass@@QEAA_NAEBV0@@Z",
+            "public: bool __cdecl klass::operator==(class klass const&)",
+        );
+        expect(
+            "??9klass@@QEAA_NAEBV0@@Z",
+            "public: bool __cdecl klass::operator!=(class klass const&)",
+        );
+        expect("??Aklass@@QEAAH_K@Z",
+               "public: int __cdecl klass::operator[](uint64_t)");
+        expect("??Cklass@@QEAAHXZ",
+               "public: int __cdecl klass::operator->(void)");
+        expect("??Dklass@@QEAAHXZ",
+               "public: int __cdecl klass::operator*(void)");
+        expect("??Eklass@@QEAAHXZ",
+               "public: int __cdecl klass::operator++(void)");
+        expect("??Eklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator++(int)");
+        expect("??Fklass@@QEAAHXZ",
+               "public: int __cdecl klass::operator--(void)");
+        expect("??Fklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator--(int)");
+        expect("??Hklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator+(int)");
+        expect("??Gklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator-(int)");
+        expect("??Iklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator&(int)");
+        expect("??Jklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator->*(int)");
+        expect("??Kklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator/(int)");
+        expect("??Mklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator<(int)");
+        expect("??Nklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator<=(int)");
+        expect("??Oklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator>(int)");
+        expect("??Pklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator>=(int)");
+        expect("??Qklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator,(int)");
+        expect("??Rklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator()(int)");
+        expect("??Sklass@@QEAAHXZ",
+               "public: int __cdecl klass::operator~(void)");
+        expect("??Tklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator^(int)");
+        expect("??Uklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator|(int)");
+        expect("??Vklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator&&(int)");
+        expect("??Wklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator||(int)");
+        expect("??Xklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator*=(int)");
+        expect("??Yklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator+=(int)");
+        expect("??Zklass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator-=(int)");
+        expect("??_0klass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator/=(int)");
+        expect("??_1klass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator%=(int)");
+        expect("??_2klass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator>>=(int)");
+        expect("??_3klass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator<<=(int)");
+        expect("??_6klass@@QEAAHH@Z",
+               "public: int __cdecl klass::operator^=(int)");
+        expect(
+            "??6@YAAEBVklass@@AEBV0@H@Z",
+            "class klass const& __cdecl operator<<(class klass const&,int)",
+        );
+        expect(
+            "??5@YAAEBVklass@@AEBV0@_K@Z",
+            "class klass const& __cdecl operator>>(class klass const&,uint64_t)",
+        );
+        expect(
+            "??2@YAPEAX_KAEAVklass@@@Z",
+            "void* __cdecl operator new(uint64_t,class klass&)",
+        );
+        expect(
+            "??_U@YAPEAX_KAEAVklass@@@Z",
+            "void* __cdecl operator new[](uint64_t,class klass&)",
+        );
+        expect(
+            "??3@YAXPEAXAEAVklass@@@Z",
+            "void __cdecl operator delete(void*,class klass&)",
+        );
+        expect(
+            "??_V@YAXPEAXAEAVklass@@@Z",
+            "void __cdecl operator delete[](void*,class klass&)",
+        );
+    }
+
+    #[test]
+    fn option_tests() {
+        expect_with_flags(
+            "?x@@YAHPEAVklass@@AEAV1@@Z",
+            "__cdecl x(class klass *,class klass &)",
+            ::DemangleOptions::NO_RETURN_TYPE,
+        );
+        expect_with_flags(
+            "??4klass@@QEAAAEBV0@AEBV0@@Z",
+            "public: __cdecl klass::operator=(class klass const&)",
+            ::DemangleOptions::NO_RETURN_TYPE | ::DemangleOptions::LESS_WHITESPACE,
+        );
+        expect_with_flags(
+            "?x@@3P6AHMNH@ZEA",
+            "int __cdecl (*x)(float,double,int)",
+            ::DemangleOptions::NO_RETURN_TYPE,
+        );
+        expect_with_flags(
+            "??_GnsWindowsShellService@@EAEPAXI@Z",
+            "virtual void * __thiscall nsWindowsShellService::`scalar deleting destructor'(unsigned int)",
+            ::DemangleOptions::NO_ACCESS_SPECIFIERS,
+        );
+        expect_with_flags(
+            "??_GnsWindowsShellService@@EAEPAXI@Z",
+            "private: void * __thiscall nsWindowsShellService::`scalar deleting destructor'(unsigned int)",
+            ::DemangleOptions::NO_MEMBER_TYPE,
+        );
+        expect_with_flags(
+            "??$load@M@UnsharedOps@js@@SAMV?$SharedMem@PAM@@@Z",
+            "float __cdecl js::UnsharedOps::load<float>(class SharedMem<float *>)",
+            ::DemangleOptions::NO_ACCESS_SPECIFIERS | ::DemangleOptions::NO_MEMBER_TYPE,
+        );
+        expect_with_flags(
+            "?fn@?$klass@H@ns@@QEBAIXZ",
+            "public: unsigned int __cdecl ns::klass<int>::fn",
+            ::DemangleOptions::NO_ARGUMENTS,
+        );
+        expect_with_flags(
+            "?x@@3P6AHMNH@ZEA",
+            "int __cdecl (*x)(float,double,int)",
+            ::DemangleOptions::NO_ARGUMENTS,
+        );
+        expect_with_flags(
+            "?fn@?$klass@H@ns@@QEBAIXZ",
+            "public: unsigned int __cdecl ns::klass<int>::fn(void)",
+            ::DemangleOptions::NO_CV_THISTYPE,
+        );
+        expect_with_flags(
+            "?fn@?$klass@H@ns@@QEDAIXZ",
+            "public: unsigned int __cdecl ns::klass<int>::fn(void)const volatile ",
+            ::DemangleOptions::new(),
+        );
+        expect_with_flags(
+            "?foo@A@PR19361@@QIHBEXXZ",
+            "public: void __thiscall PR19361::A::foo(void)__restrict && ",
+            ::DemangleOptions::NO_CV_THISTYPE,
+        );
+        expect_with_flags(
+            "??0?$A@_K@B@@QAE@$$QAV01@@Z",
+            "public: __thiscall B::A<unsigned __int64>::A<unsigned __int64>(class B::A<unsigned __int64> &&)",
+            ::DemangleOptions::new().int64_spelling(::Int64Spelling::Ms),
+        );
+        expect_with_flags(
+            "??0?$Klass@V?$Mass@_N@@@std@@QEAA@AEBV01@@Z",
+            "public: __cdecl std::Klass<Mass<bool> >::Klass<Mass<bool> >(std::Klass<Mass<bool> > const &)",
+            ::DemangleOptions::NO_CLASS_KEYWORDS,
+        );
+        expect_with_flags(
+            "?x@@YAXW4E@@TU@@@Z",
+            "void __cdecl x(E,U)",
+            ::DemangleOptions::NO_CLASS_KEYWORDS,
+        );
+        expect_with_flags(
+            "?f@@YA_J_K@Z",
+            "long long __cdecl f(unsigned long long)",
+            ::DemangleOptions::new().int64_spelling(::Int64Spelling::LongLong),
+        );
+        expect_with_flags(
+            "?f@@YA_J_K@Z",
+            "__int64 __cdecl f(unsigned __int64)",
+            ::DemangleOptions::new().int64_spelling(::Int64Spelling::LongLong)
+                | ::DemangleOptions::new().int64_spelling(::Int64Spelling::Ms),
+        );
+    }
 
-        // Tests for maximum string length
-        expect("??_C@_0CF@LABBIIMO@012345678901234567890123456789AB@", "`string'");
-        expect("??_C@_1EK@KFPEBLPK@?$AA0?$AA1?$AA2?$AA3?$AA4?$AA5?$AA6?$AA7?$AA8?$AA9?$AA0?$AA1?$AA2?$AA3?$AA4?$AA5?$AA6?$AA7?$AA8?$AA9?$AA0?$AA1?$AA2?$AA3?$AA4?$AA5?$AA6?$AA7?$AA8?$AA9?$AAA?$AAB@", "`string'");
-        // Unicode character.
-        expect("??_C@_13IIHIAFKH@?W?$PP?$AA?$AA@", "`string'");
-        // u8/u/U literal strings.
-        expect("??_C@_02PCEFGMJL@hi?$AA@", "`string'");
-        expect("??_C@_05OMLEGLOC@h?$AAi?$AA?$AA?$AA@", "`string'");
-        expect("??_C@_0M@GFNAJIPG@h?$AA?$AA?$AAi?$AA?$AA?$AA?$AA?$AA?$AA?$AA@", "`string'");
+    #[test]
+    fn undname_compat_tests() {
+        let expect = |input, reference| {
+            expect_with_flags(input, reference, ::DemangleOptions::undname());
+        };
+
+        expect(
+            "??_7?$RunnableMethodImpl@PEAVLazyIdleThread@mozilla@@P812@EAAXXZ$0A@$0A@$$V@detail@mozilla@@6BnsIRunnable@@@",
+            "const mozilla::detail::RunnableMethodImpl<class mozilla::LazyIdleThread * __ptr64,void __cdecl (mozilla::LazyIdleThread::*)(void) __ptr64,0,0>::`vftable\'{for `nsIRunnable\'}",
+        );
+        expect(
+            "??1?$function@$$A6AXXZ@std@@QAE@XZ",
+            "public: __thiscall std::function<void __cdecl(void)>::~function<void __cdecl(void)>(void)",
+        );
+        expect(
+            "??B?$function@$$A6AXXZ@std@@QBE_NXZ",
+            "public: __thiscall std::function<void __cdecl(void)>::operator bool(void)const",
+        );
+        expect(
+            "?fn@?$klass@H@ns@@QEBAIXZ",
+            "public: unsigned int __cdecl ns::klass<int>::fn(void)const __ptr64",
+        );
+        expect(
+            "?x@@YAHPEAVklass@@AEAV1@@Z",
+            "int __cdecl x(class klass * __ptr64,class klass & __ptr64)",
+        );
+        expect("?x@@3QEBHEB", "int const * __ptr64 const x");
+        expect(
+            "??_I@YGXPAXIIP6EX0@Z@Z",
+            "void __stdcall `vector destructor iterator'(void *,unsigned int,unsigned int,void (__thiscall*)(void *))",
+        );
+        expect(
+            "?x@@YAXP6AXPEAX@Z@Z",
+            "void __cdecl x(void (__cdecl*)(void * __ptr64))",
+        );
+        expect(
+            "?x@@YAXA6AXXZ@Z",
+            "void __cdecl x(void (__cdecl&)(void))",
+        );
+        expect("??Aklass@@QEAAH_K@Z", "public: int __cdecl klass::operator[](unsigned __int64) __ptr64");
+        expect(
+            "?foo@A@PR19361@@QIHAEXXZ",
+            "public: void __thiscall PR19361::A::foo(void) __restrict&&",
+        );
+        expect(
+            "?Release@ContentSignatureVerifier@@WBA@AGKXZ",
+            "[thunk]:public: virtual unsigned long __stdcall ContentSignatureVerifier::Release`adjustor{16}' (void)",
+        );
+        expect("?x@C@@1HA", "protected: static int C::x");
+    }
+
+    #[test]
+    fn llvm_undname_compat_tests() {
+        let expect = |input, reference| {
+            expect_with_flags(input, reference, ::DemangleOptions::llvm_undname());
+        };
+
+        expect("?x@@3PEAHEA", "int *x");
+        expect("?x@@3PEAPEAHEA", "int **x");
+        expect("?x@@3QEBHEB", "int const *const x");
+        expect("?f@@YAHQBH@Z", "int __cdecl f(int const *const)");
+        expect("?g@@YAHQAY0EA@$$CBH@Z", "int __cdecl g(int const (*const)[64])");
+        expect("?x@@YAXMH@Z", "void __cdecl x(float, int)");
+        expect("?x@@3P6AHMNH@ZEA", "int (__cdecl *x)(float, double, int)");
+        expect(
+            "?x@@3P6AHP6AHM@ZN@ZEA",
+            "int (__cdecl *x)(int (__cdecl *)(float), double)",
+        );
+        expect(
+            "?x@@YAHPEAVklass@@AEAV1@@Z",
+            "int __cdecl x(class klass *, class klass &)",
+        );
+        expect(
+            "??0?$Klass@V?$Mass@_N@@@std@@QEAA@AEBV01@@Z",
+            "public: __cdecl std::Klass<class Mass<bool>>::Klass<class Mass<bool>>(class std::Klass<class Mass<bool>> const &)",
+        );
+        expect(
+            "??_7?$RunnableMethodImpl@PEAVLazyIdleThread@mozilla@@P812@EAAXXZ$0A@$0A@$$V@detail@mozilla@@6BnsIRunnable@@@",
+            "const mozilla::detail::RunnableMethodImpl<class mozilla::LazyIdleThread *, void (__cdecl mozilla::LazyIdleThread::*)(void), 0, 0>::`vftable'{for `nsIRunnable'}",
+        );
+        expect(
+            "??_I@YGXPAXIIP6EX0@Z@Z",
+            "void __stdcall `vector dtor iterator'(void *, unsigned int, unsigned int, void (__thiscall *)(void *))",
+        );
+        expect("??_7W@?A@@6B@", "const `anonymous namespace'::W::`vftable'");
+        expect(
+            "??B?$function@$$A6AXXZ@std@@QBE_NXZ",
+            "public: __thiscall std::function<void __cdecl(void)>::operator bool(void) const",
+        );
+        expect(
+            "?fn@?$klass@H@ns@@QEDAIXZ",
+            "public: unsigned int __cdecl ns::klass<int>::fn(void) const volatile",
+        );
+        expect(
+            "?foo@A@PR19361@@QIHAEXXZ",
+            "public: void __thiscall PR19361::A::foo(void) __restrict &&",
+        );
+        expect(
+            "??0?$A@_K@B@@QAE@$$QAV01@@Z",
+            "public: __thiscall B::A<unsigned __int64>::A<unsigned __int64>(class B::A<unsigned __int64> &&)",
+        );
+        expect(
+            "?Release@ContentSignatureVerifier@@WBA@AGKXZ",
+            "[thunk]: public: virtual unsigned long __stdcall ContentSignatureVerifier::Release`adjustor{16}'(void)",
+        );
+        expect(
+            "??_GC@@UAEPAXI@Z",
+            "public: virtual void * __thiscall C::`scalar deleting dtor'(unsigned int)",
+        );
+        expect("??_EC@@UAEPAXI@Z", "public: virtual void * __thiscall C::`vector deleting dtor'(unsigned int)");
+        expect("??$?6H@C@@QAEXXZ", "public: void __thiscall C::operator<<<int>(void)");
+        expect("?x@C@@2HA", "public: static int C::x");
+        expect("?x@C@@0PAHA", "private: static int *C::x");
+    }
+
+    #[test]
+    fn std_typedefs() {
+        let expect = |input, reference| {
+            expect_with_flags(input, reference, ::DemangleOptions::STD_TYPEDEFS);
+        };
+
+        expect(
+            "?f@@YAXAEBV?$basic_string@DU?$char_traits@D@std@@V?$allocator@D@2@@std@@@Z",
+            "void __cdecl f(std::string const &)",
+        );
+        expect(
+            "?size@?$basic_string@DU?$char_traits@D@std@@V?$allocator@D@2@@std@@QEBA_KXZ",
+            "public: uint64_t __cdecl std::string::size(void)const ",
+        );
+        expect(
+            "??1?$basic_string@_WU?$char_traits@_W@std@@V?$allocator@_W@2@@std@@QEAA@XZ",
+            "public: __cdecl std::wstring::~wstring(void)",
+        );
+        expect(
+            "?f@@YAXV?$vector@V?$basic_string@_SU?$char_traits@_S@std@@V?$allocator@_S@2@@std@@V?$allocator@V?$basic_string@_SU?$char_traits@_S@std@@V?$allocator@_S@2@@std@@@2@@std@@@Z",
+            "void __cdecl f(class std::vector<std::u16string,class std::allocator<std::u16string> >)",
+        );
+        expect(
+            "??6?$basic_ostream@DU?$char_traits@D@std@@@std@@QEAAAEAV01@H@Z",
+            "public: std::ostream & __cdecl std::ostream::operator<<(int)",
+        );
+        expect(
+            "?str@?$basic_stringstream@_WU?$char_traits@_W@std@@V?$allocator@_W@2@@std@@QEBA?AV?$basic_string@_WU?$char_traits@_W@std@@V?$allocator@_W@2@@2@XZ",
+            "public: std::wstring __cdecl std::wstringstream::str(void)const ",
+        );
+        expect(
+            "?f@@YAXV?$basic_string_view@DU?$char_traits@D@std@@@std@@@Z",
+            "void __cdecl f(std::string_view)",
+        );
+        // Only the default traits and allocator are abbreviated.
+        expect(
+            "?f@@YAXAEBV?$basic_string@DUtraits@@V?$allocator@D@std@@@std@@@Z",
+            "void __cdecl f(class std::basic_string<char,struct traits,class std::allocator<char> > const &)",
+        );
     }
 
     #[test]
-    fn upstream_tests() {
+    fn no_default_template_args() {
         let expect = |input, reference| {
-            expect_with_flags(input, reference, ::DemangleFlags::LessWhitespace);
+            expect_with_flags(input, reference, ::DemangleOptions::NO_DEFAULT_TEMPLATE_ARGS);
         };
-        expect("?x@@3HA", "int x");
-        expect("?x@@3PEAHEA", "int*x");
-        expect("?x@@3PEAPEAHEA", "int**x");
-        expect("?x@@3PEAY02HEA", "int(*x)[3]");
-        expect("?x@@3PEAY124HEA", "int(*x)[3][5]");
-        expect("?x@@3PEAY02$$CBHEA", "int const(*x)[3]");
-        expect("?x@@3PEAEEA", "unsigned char*x");
-        expect("?x@@3PEAY1NKM@5HEA", "int(*x)[3500][6]");
-        expect("?x@@YAXMH@Z", "void __cdecl x(float,int)");
-        expect("?x@@YAXMH@Z", "void __cdecl x(float,int)");
-        expect("?x@@3P6AHMNH@ZEA", "int __cdecl (*x)(float,double,int)");
-        expect("?x@@3P6AHP6AHM@ZN@ZEA", "int __cdecl (*x)(int __cdecl (*)(float),double)");
+
         expect(
-            "?x@@3P6AHP6AHM@Z0@ZEA",
-            "int __cdecl (*x)(int __cdecl (*)(float),int __cdecl (*)(float))",
+            "?f@@YAXAEBV?$vector@HV?$allocator@H@std@@@std@@@Z",
+            "void __cdecl f(class std::vector<int> const &)",
         );
-
-        expect("?x@ns@@3HA", "int ns::x");
-
-        // Microsoft's undname returns "int const * const x" for this symbol.
-        // I believe it's their bug.
-        expect("?x@@3PEBHEB", "int const*x");
-
-        expect("?x@@3QEAHEB", "int*const x");
-        expect("?x@@3QEBHEB", "int const*const x");
-
-        expect("?x@@3AEBHEB", "int const&x");
-
-        expect("?x@@3PEAUty@@EA", "struct ty*x");
-        expect("?x@@3PEATty@@EA", "union ty*x");
-        expect("?x@@3PEAUty@@EA", "struct ty*x");
-        expect("?x@@3PEAW4ty@@EA", "enum ty*x");
-        expect("?x@@3PEAVty@@EA", "class ty*x");
-
-        expect("?x@@3PEAV?$tmpl@H@@EA", "class tmpl<int>*x");
-        expect("?x@@3PEAU?$tmpl@H@@EA", "struct tmpl<int>*x");
-        expect("?x@@3PEAT?$tmpl@H@@EA", "union tmpl<int>*x");
-        expect("?instance@@3Vklass@@A", "class klass instance");
         expect(
-            "?instance$initializer$@@3P6AXXZEA",
-            "void __cdecl (*instance$initializer$)(void)",
+            "?f@@YAXAEBV?$vector@HVMyAlloc@@@std@@@Z",
+            "void __cdecl f(class std::vector<int,class MyAlloc> const &)",
         );
-        expect("??0klass@@QEAA@XZ", "public: __cdecl klass::klass(void)");
-        expect("??1klass@@QEAA@XZ", "public: __cdecl klass::~klass(void)");
         expect(
-            "?x@@YAHPEAVklass@@AEAV1@@Z",
-            "int __cdecl x(class klass*,class klass&)",
+            "?f@@YAXV?$map@HNU?$less@H@std@@V?$allocator@U?$pair@$$CBHN@std@@@2@@std@@@Z",
+            "void __cdecl f(class std::map<int,double>)",
         );
         expect(
-            "?x@ns@@3PEAV?$klass@HH@1@EA",
-            "class ns::klass<int,int>*ns::x",
+            "?f@@YAXV?$map@HNUcmp@@V?$allocator@U?$pair@$$CBHN@std@@@std@@@std@@@Z",
+            "void __cdecl f(class std::map<int,double,struct cmp>)",
         );
         expect(
-            "?fn@?$klass@H@ns@@QEBAIXZ",
-            "public: unsigned int __cdecl ns::klass<int>::fn(void)const",
+            "?f@@YAXV?$map@HNU?$less@H@std@@V?$allocator@U?$pair@$$CBDN@std@@@2@@std@@@Z",
+            "void __cdecl f(class std::map<int,double,struct std::less<int>,class std::allocator<struct std::pair<char const,double> > >)",
         );
-
+        // Only templates in std have defaults.
         expect(
-            "??4klass@@QEAAAEBV0@AEBV0@@Z",
-            "public: class klass const& __cdecl klass::operator=(class klass const&)",
+            "?f@@YAXV?$vector@HV?$allocator@H@std@@@ns@@@Z",
+            "void __cdecl f(class ns::vector<int,class std::allocator<int> >)",
         );
-        expect("??7klass@@QEAA_NXZ",
-               "public: bool __cdecl klass::operator!(void)");
         expect(
-            "??8klass@@QEAA_NAEBV0@@Z",
-            "public: bool __cdecl klass::operator==(class klass const&)",
+            "?f@@YAXV?$vector@HV?$allocator@H@std@@@ns@@V?$vector@HV?$allocator@H@std@@@std@@@Z",
+            "void __cdecl f(class ns::vector<int,class std::allocator<int> >,class std::vector<int>)",
         );
         expect(
-            "??9klass@@QEAA_NAEBV0@@Z",
-            "public: bool __cdecl klass::operator!=(class klass const&)",
+            "?f@@YAXV?$unique_ptr@VFoo@@U?$default_delete@VFoo@@@std@@@std@@@Z",
+            "void __cdecl f(class std::unique_ptr<class Foo>)",
         );
-        expect("??Aklass@@QEAAH_K@Z",
-               "public: int __cdecl klass::operator[](uint64_t)");
-        expect("??Cklass@@QEAAHXZ",
-               "public: int __cdecl klass::operator->(void)");
-        expect("??Dklass@@QEAAHXZ",
-               "public: int __cdecl klass::operator*(void)");
-        expect("??Eklass@@QEAAHXZ",
-               "public: int __cdecl klass::operator++(void)");
-        expect("??Eklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator++(int)");
-        expect("??Fklass@@QEAAHXZ",
-               "public: int __cdecl klass::operator--(void)");
-        expect("??Fklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator--(int)");
-        expect("??Hklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator+(int)");
-        expect("??Gklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator-(int)");
-        expect("??Iklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator&(int)");
-        expect("??Jklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator->*(int)");
-        expect("??Kklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator/(int)");
-        expect("??Mklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator<(int)");
-        expect("??Nklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator<=(int)");
-        expect("??Oklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator>(int)");
-        expect("??Pklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator>=(int)");
-        expect("??Qklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator,(int)");
-        expect("??Rklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator()(int)");
-        expect("??Sklass@@QEAAHXZ",
-               "public: int __cdecl klass::operator~(void)");
-        expect("??Tklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator^(int)");
-        expect("??Uklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator|(int)");
-        expect("??Vklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator&&(int)");
-        expect("??Wklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator||(int)");
-        expect("??Xklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator*=(int)");
-        expect("??Yklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator+=(int)");
-        expect("??Zklass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator-=(int)");
-        expect("??_0klass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator/=(int)");
-        expect("??_1klass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator%=(int)");
-        expect("??_2klass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator>>=(int)");
-        expect("??_3klass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator<<=(int)");
-        expect("??_6klass@@QEAAHH@Z",
-               "public: int __cdecl klass::operator^=(int)");
         expect(
-            "??6@YAAEBVklass@@AEBV0@H@Z",
-            "class klass const& __cdecl operator<<(class klass const&,int)",
+            "?size@?$basic_string@DU?$char_traits@D@std@@V?$allocator@D@2@@std@@QEBA_KXZ",
+            "public: uint64_t __cdecl std::basic_string<char>::size(void)const ",
+        );
+    }
+
+    #[test]
+    fn passes() {
+        // The flags run after the passes of a pipeline.
+        let pipeline = ::Pipeline::new().pass(::StripScopes::new(&["__1"]));
+        assert_eq!(
+            pipeline.demangle(
+                "?f@__1@std@@YAXAEBV?$basic_string@DU?$char_traits@D@__1@std@@V?$allocator@D@23@@12@@Z",
+                ::DemangleOptions::cxxfilt() | ::DemangleOptions::STD_TYPEDEFS,
+            ),
+            Ok("std::f(std::string const&)".to_owned())
+        );
+
+        // Typedefs are substituted before default arguments are left out.
+        let input = "?f@@YAXV?$vector@V?$basic_string@_SU?$char_traits@_S@std@@V?$allocator@_S@2@@std@@V?$allocator@V?$basic_string@_SU?$char_traits@_S@std@@V?$allocator@_S@2@@std@@@2@@std@@@Z";
+        let flags = ::DemangleOptions::cxxfilt();
+        assert_eq!(
+            ::demangle(input, flags | ::DemangleOptions::STD_TYPEDEFS | ::DemangleOptions::NO_DEFAULT_TEMPLATE_ARGS),
+            Ok("f(std::vector<std::u16string>)".to_owned())
+        );
+        assert_eq!(
+            ::demangle(input, flags | ::DemangleOptions::STD_TYPEDEFS | ::DemangleOptions::NO_TEMPLATE_ARGS),
+            Ok("f(std::vector<>)".to_owned())
+        );
+
+        // Serializing leaves the parsed symbol as it is.
+        let parsed = ::parse(input).unwrap();
+        let full = ::serialize(&parsed, flags).unwrap();
+        assert!(full.starts_with("f(std::vector<std::basic_string<char16_t,"));
+        assert_eq!(
+            parsed.parameter_strings(flags | ::DemangleOptions::NO_TEMPLATE_ARGS),
+            Ok(vec!["std::vector<>".to_owned()])
+        );
+        assert_eq!(::serialize(&parsed, flags), Ok(full));
+
+        let pipeline = ::Pipeline::new()
+            .pass(::StripScopes::new(&["__1"]))
+            .pass(|parse_result: &mut ::ParseResult| parse_result.symbol.name = ::Name::identifier("g"));
+        assert_eq!(
+            pipeline.demangle("?f@__1@std@@YAXV?$vector@H@12@@Z", ::DemangleOptions::cxxfilt()),
+            Ok("std::g(std::vector<int>)".to_owned())
+        );
+    }
+
+    #[test]
+    fn max_template_depth() {
+        let input = "??0?$Klass@V?$Mass@_N@@@std@@QEAA@AEBV01@@Z";
+        expect_with_flags(
+            input,
+            "public: __cdecl std::Klass<...>::Klass<...>(class std::Klass<...> const &)",
+            ::DemangleOptions::new().max_template_depth(0),
+        );
+        expect_with_flags(
+            input,
+            "public: __cdecl std::Klass<class Mass<...> >::Klass<class Mass<...> >(class std::Klass<class Mass<...> > const &)",
+            ::DemangleOptions::new().max_template_depth(1),
+        );
+        expect_with_flags(
+            input,
+            "public: __cdecl std::Klass<class Mass<bool> >::Klass<class Mass<bool> >(class std::Klass<class Mass<bool> > const &)",
+            ::DemangleOptions::new().max_template_depth(2),
+        );
+    }
+
+    #[test]
+    fn max_length() {
+        let input = "??0?$Klass@V?$Mass@_N@@@std@@QEAA@AEBV01@@Z";
+        expect_with_flags(
+            input,
+            "public: __cdecl std::Klass<class Mass<bool> >::K...",
+            ::DemangleOptions::new().max_length(48),
+        );
+        expect_with_flags(input, "...", ::DemangleOptions::new().max_length(0));
+        expect_with_flags(
+            "?x@@3HA",
+            "int x",
+            ::DemangleOptions::new().max_length(5),
+        );
+        expect_with_flags(
+            "?f@@YAXXZ",
+            "void __cdecl f(void)",
+            ::DemangleOptions::new().max_length(200),
+        );
+    }
+
+    #[test]
+    fn wrap_width() {
+        let input = "??_7?$RunnableMethodImpl@PEAVLazyIdleThread@mozilla@@P812@EAAXXZ$0A@$0A@$$V@detail@mozilla@@6BnsIRunnable@@@";
+        expect_with_flags(
+            input,
+            "const mozilla::detail::RunnableMethodImpl<\n    class mozilla::LazyIdleThread *,\n    void __cdecl (mozilla::LazyIdleThread::*)(void),\n    0,\n    0\n>::`vftable'{for `nsIRunnable'}",
+            ::DemangleOptions::new().wrap_width(80),
+        );
+        expect_with_flags(
+            "?x@@YAXMH@Z",
+            "void __cdecl x(float,int)",
+            ::DemangleOptions::new().wrap_width(40),
         );
+        expect_with_flags(
+            "??0?$Klass@V?$Mass@_N@@@std@@QEAA@AEBV01@@Z",
+            "public: __cdecl std::Klass<class Mass<bool> >::Klass<class Mass<bool> >(\n    class std::Klass<class Mass<bool> > const &\n)",
+            ::DemangleOptions::new().wrap_width(80),
+        );
+    }
+
+    // The output of a format profile must never change. Only add cases
+    // here, never edit them.
+    #[test]
+    fn format_profile_v1() {
+        let v1 = |options: ::DemangleOptions| options.format_profile(::FormatProfile::V1);
+        let cases = [
+            (v1(::DemangleOptions::new()), "?f@@YAXXZ", "void __cdecl f(void)"),
+            (v1(::DemangleOptions::new()), "?x@@3PEAY01HEA", "int (*x)[2]"),
+            (v1(::DemangleOptions::new()), "??1?$A@_J@@QAE@XZ", "public: __thiscall A<int64_t>::~A<int64_t>(void)"),
+            (v1(::DemangleOptions::new()), "??_7Foo@ns@@6B@", "const ns::Foo::`vftable'"),
+            (
+                v1(::DemangleOptions::undname()),
+                "?bar@Foo@ns@@QEBAHPEBDAEAV?$vector@HV?$allocator@H@std@@@std@@@Z",
+                "public: int __cdecl ns::Foo::bar(char const * __ptr64,class std::vector<int,class std::allocator<int> > & __ptr64)const __ptr64",
+            ),
+            (v1(::DemangleOptions::undname()), "??$f@$0PPPP@@@YAXP6AHH@Z@Z", "void __cdecl f<65535>(int (__cdecl*)(int))"),
+            (
+                v1(::DemangleOptions::llvm_undname()),
+                "?bar@Foo@ns@@QEBAHPEBDAEAV?$vector@HV?$allocator@H@std@@@std@@@Z",
+                "public: int __cdecl ns::Foo::bar(char const *, class std::vector<int, class std::allocator<int>> &) const",
+            ),
+            (v1(::DemangleOptions::llvm_undname()), "??$f@$0PPPP@@@YAXP6AHH@Z@Z", "void __cdecl f<65535>(int (__cdecl *)(int))"),
+            (
+                v1(::DemangleOptions::cxxfilt()),
+                "?bar@Foo@ns@@QEBAHPEBDAEAV?$vector@HV?$allocator@H@std@@@std@@@Z",
+                "ns::Foo::bar(char const*, std::vector<int, std::allocator<int> >&) const",
+            ),
+            (
+                v1(::DemangleOptions::cxxfilt()),
+                "?cached@?1??GetLong@BinaryPath@mozilla@@SA?AW4nsresult@@QA_W@Z@4_NA",
+                "bool mozilla::BinaryPath::GetLong(wchar_t* const)::`2'::cached",
+            ),
+        ];
+        for &(options, input, reference) in cases.iter() {
+            expect_with_flags(input, reference, options);
+        }
+    }
+
+    #[test]
+    fn summary() {
+        let expect = |input, reference| {
+            expect_with_flags(input, reference, ::DemangleOptions::summary());
+        };
+        expect("??_7Foo@ns@@6B@", "vtable for ns::Foo");
+        expect("??_8Foo@@7BBar@@@", "vbtable for Foo");
+        expect("??_GFoo@ns@@UAEPAXI@Z", "scalar deleting destructor for ns::Foo");
+        expect("?$TSS0@?1??f@@YAXXZ@4HA", "guard variable for f()");
+        expect("?bar@Foo@ns@@QAEXH@Z", "ns::Foo::bar(int)");
+    }
+
+    #[test]
+    fn no_template_args() {
+        let expect = |input, reference| {
+            expect_with_flags(input, reference, ::DemangleOptions::NO_TEMPLATE_ARGS);
+        };
+        expect("??$f@H@@YAXXZ", "void __cdecl f<>(void)");
+        expect("??$f@M@@YAXXZ", "void __cdecl f<>(void)");
+        expect("?x@@3V?$A@V?$B@H@@@@A", "class A<> x");
+        expect("??$?MH@@YA_NXZ", "bool __cdecl operator< <>(void)");
+    }
+
+    #[test]
+    fn no_special_syms() {
+        let expect = |input, reference| {
+            expect_with_flags(input, reference, ::DemangleOptions::NO_SPECIAL_SYMS);
+        };
+        expect("??_GFoo@@UAEPAXI@Z", "Foo");
+        expect("??_EFoo@ns@@UAEPAXI@Z", "ns::Foo");
+        expect("??_7Foo@@6B@", "Foo");
+        expect("??_8Foo@@7BBar@@@", "Foo");
+        expect("?$TSS0@?1??f@@YAXXZ@4HA", "`void __cdecl f(void)'");
+        expect("?f@Foo@@QAEXXZ", "public: void __thiscall Foo::f(void)");
+    }
+
+    #[test]
+    fn no_leading_underscores() {
+        let expect = |input, reference| {
+            expect_with_flags(input, reference, ::DemangleOptions::undname() | ::DemangleOptions::NO_LEADING_UNDERSCORES);
+        };
+        expect("?f@@YAXXZ", "void cdecl f(void)");
+        expect("?f@C@@QEAAXXZ", "public: void cdecl C::f(void) ptr64");
+        expect("?f@@YA_JXZ", "int64 cdecl f(void)");
+        expect("?x@@3PEAHEA", "int * ptr64 x");
+    }
+
+    #[test]
+    fn enum_underlying_types() {
+        let expect = |input, reference| {
+            expect_with_flags(input, reference, ::DemangleOptions::ENUM_UNDERLYING_TYPES);
+        };
+        expect("?x@@3W0E@@A", "enum E : char x");
+        expect("?x@@3W1E@@A", "enum E : unsigned char x");
+        expect("?x@@3W4E@@A", "enum E : int x");
+        expect("?x@@3PEAW7E@@EA", "enum E : unsigned long *x");
+        expect_with_flags("?x@@3W1E@@A", "enum E x", ::DemangleOptions::new());
+    }
+
+    #[test]
+    fn hex_constants() {
+        let expect = |input, reference| {
+            expect_with_flags(input, reference, ::DemangleOptions::new().hex_constants_above(255));
+        };
+        expect("??$f@$0PPPP@@@YAXXZ", "void __cdecl f<0xffff>(void)");
+        expect("??$f@$0?PPPP@@@YAXXZ", "void __cdecl f<-0xffff>(void)");
+        expect("??$f@$0PP@@@YAXXZ", "void __cdecl f<255>(void)");
+        expect("??$f@$09@@YAXXZ", "void __cdecl f<10>(void)");
+    }
+
+    #[test]
+    fn cxxfilt_tests() {
+        let expect = |input, reference| {
+            expect_with_flags(input, reference, ::DemangleOptions::cxxfilt());
+        };
+
+        expect("?x@@YAXMH@Z", "x(float, int)");
+        expect("?fn@?$klass@H@ns@@QEBAIXZ", "ns::klass<int>::fn() const");
         expect(
-            "??5@YAAEBVklass@@AEBV0@_K@Z",
-            "class klass const& __cdecl operator>>(class klass const&,uint64_t)",
+            "?x@@YAHPEAVklass@@AEAV1@@Z",
+            "x(klass*, klass&)",
         );
+        expect("?f@@YAHQBH@Z", "f(int const* const)");
+        expect("?f@@YA_J_K@Z", "f(unsigned long long)");
         expect(
-            "??2@YAPEAX_KAEAVklass@@@Z",
-            "void* __cdecl operator new(uint64_t,class klass&)",
+            "??_GnsWindowsShellService@@EAEPAXI@Z",
+            "nsWindowsShellService::`scalar deleting destructor'(unsigned int)",
         );
         expect(
-            "??_U@YAPEAX_KAEAVklass@@@Z",
-            "void* __cdecl operator new[](uint64_t,class klass&)",
+            "??_I@YGXPAXIIP6EX0@Z@Z",
+            "`vector destructor iterator'(void*, unsigned int, unsigned int, void (*)(void*))",
         );
         expect(
-            "??3@YAXPEAXAEAVklass@@@Z",
-            "void __cdecl operator delete(void*,class klass&)",
+            "??0?$Klass@V?$Mass@_N@@@std@@QEAA@AEBV01@@Z",
+            "std::Klass<Mass<bool> >::Klass<Mass<bool> >(std::Klass<Mass<bool> > const&)",
         );
         expect(
-            "??_V@YAXPEAXAEAVklass@@@Z",
-            "void __cdecl operator delete[](void*,class klass&)",
+            "??B?$function@$$A6AXXZ@std@@QBE_NXZ",
+            "std::function<void()>::operator bool() const",
+        );
+        expect("?foo@A@PR19361@@QIHAEXXZ", "PR19361::A::foo() &&");
+        expect("??_7W@?A@@6B@", "const (anonymous namespace)::W::`vftable'");
+        expect("?x@@3PEAHEA", "int* x");
+        expect("?x@@3P6AHMNH@ZEA", "int (*x)(float, double, int)");
+    }
+
+    #[test]
+    fn spans() {
+        let (s, spans) =
+            ::demangle_with_spans("?x@ns@@YAHPEAVklass@@AEAV2@@Z", ::DemangleOptions::new())
+                .unwrap();
+        assert_eq!(s, "int __cdecl ns::x(class klass *,class klass &)");
+        let spans: Vec<_> = spans
+            .iter()
+            .map(|span| (&s[span.range.clone()], span.kind))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("int", ::SpanKind::ReturnType),
+                ("__cdecl", ::SpanKind::CallingConvention),
+                ("ns::x", ::SpanKind::SymbolName),
+                ("ns", ::SpanKind::Identifier),
+                ("x", ::SpanKind::Identifier),
+                ("class klass *", ::SpanKind::Parameter),
+                ("klass", ::SpanKind::TypeName),
+                ("klass", ::SpanKind::Identifier),
+                ("class klass &", ::SpanKind::Parameter),
+                ("klass", ::SpanKind::TypeName),
+                ("klass", ::SpanKind::Identifier),
+            ]
+        );
+
+        let (s, spans) = ::demangle_with_spans(
+            "??$?HH@S@@QEAAAEAU0@H@Z",
+            ::DemangleOptions::NO_ACCESS_SPECIFIERS,
+        ).unwrap();
+        assert_eq!(s, "struct S & __cdecl S::operator+<int>(int)");
+        let spans: Vec<_> = spans
+            .iter()
+            .map(|span| (&s[span.range.clone()], span.kind))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("struct S &", ::SpanKind::ReturnType),
+                ("S", ::SpanKind::TypeName),
+                ("S", ::SpanKind::Identifier),
+                ("__cdecl", ::SpanKind::CallingConvention),
+                ("S::operator+<int>", ::SpanKind::SymbolName),
+                ("S", ::SpanKind::Identifier),
+                ("operator+", ::SpanKind::Operator),
+                ("int", ::SpanKind::TemplateArgument),
+                ("int", ::SpanKind::Parameter),
+            ]
+        );
+    }
+
+    #[test]
+    fn dump() {
+        assert_eq!(
+            ::parse("?f@@YAXPBH@Z").unwrap().dump(),
+            "ParseResult Function\n\
+             |-Symbol\n\
+             | `-NonTemplate \"f\"\n\
+             `-NonMemberFunction Cdecl\n  \
+             |-Return\n  \
+             | `-Void\n  \
+             `-Params\n    \
+             `-Ptr\n      \
+             `-Int const\n"
+        );
+        assert_eq!(
+            ::parse("?fn@?$klass@H@ns@@QEBAIXZ").unwrap().dump(),
+            "ParseResult Function\n\
+             |-Symbol\n\
+             | |-Scope\n\
+             | | |-NonTemplate \"ns\"\n\
+             | | `-Backref 0\n\
+             | |   `-Template\n\
+             | |     |-NonTemplate \"klass\"\n\
+             | |     `-TemplateArgs\n\
+             | |       `-Int\n\
+             | `-NonTemplate \"fn\"\n\
+             `-MemberFunction public Cdecl const ptr64\n  \
+             |-Return\n  \
+             | `-Uint\n  \
+             `-Params\n    \
+             `-Void\n"
+        );
+    }
+
+    #[test]
+    fn html() {
+        let flags = ::DemangleOptions::NO_ACCESS_SPECIFIERS;
+        assert_eq!(
+            ::demangle_html("??$?HH@S@@QEAAAEAU0@H@Z", flags).unwrap(),
+            "<span class=\"keyword\">struct</span> <span class=\"type\">S</span> &amp; \
+             <span class=\"keyword\">__cdecl</span> \
+             <span class=\"name\">S::operator+&lt;<span class=\"template-arg\">\
+             <span class=\"keyword\">int</span></span>&gt;</span>(<span class=\"keyword\">int</span>)"
+        );
+        // Identifiers are never keywords.
+        assert_eq!(
+            ::demangle_html("?x@?A0x1@int@@3HA", flags).unwrap(),
+            "<span class=\"keyword\">int</span> <span class=\"name\">int::`anonymous namespace`::x</span>"
+        );
+        let parsed = ::parse("?f@@YAXV?$Foo@$0BA@@@@Z").unwrap();
+        assert_eq!(
+            ::serialize_html(&parsed, flags).unwrap(),
+            "<span class=\"keyword\">void</span> <span class=\"keyword\">__cdecl</span> \
+             <span class=\"name\">f</span>(<span class=\"keyword\">class</span> \
+             <span class=\"type\">Foo&lt;<span class=\"template-arg\">16</span>&gt;</span>)"
+        );
+    }
+
+    #[test]
+    fn estimated_len() {
+        for name in &["?x@@3HA", "?fn@?$klass@H@ns@@QEBAIXZ", "??$f@V?$vector@H@std@@@@YAXV?$vector@H@std@@@Z"] {
+            let parsed = ::parse(name).unwrap();
+            let s = ::serialize(&parsed, ::DemangleOptions::new()).unwrap();
+            let estimate = ::estimated_len(&parsed, usize::MAX);
+            assert!(estimate >= s.len() && estimate < 2 * s.len(), "{} for {:?}", estimate, s);
+            assert!(s.capacity() >= estimate);
+        }
+    }
+
+    #[test]
+    fn serialize_to() {
+        let parsed = ::parse("?fn@?$klass@H@ns@@QEBAIXZ").unwrap();
+        let mut s = String::from("fn: ");
+        ::serialize_to(&parsed, ::DemangleOptions::new(), &mut s).unwrap();
+        assert_eq!(s, "fn: public: unsigned int __cdecl ns::klass<int>::fn(void)const ");
+
+        let mut v = Vec::new();
+        ::serialize_to_writer(&parsed, ::DemangleOptions::undname(), &mut v).unwrap();
+        assert_eq!(
+            v,
+            b"public: unsigned int __cdecl ns::klass<int>::fn(void)const __ptr64".to_vec()
+        );
+    }
+
+    #[test]
+    fn name_writer() {
+        use std::fmt;
+
+        struct Hooks;
+
+        impl ::NameWriter for Hooks {
+            fn write_identifier(&mut self, w: &mut dyn fmt::Write, identifier: &str) -> fmt::Result {
+                if identifier == "mozilla" {
+                    w.write_str("moz")
+                } else {
+                    w.write_str(identifier)
+                }
+            }
+
+            fn write_type_name(&mut self, w: &mut dyn fmt::Write, ty: &::Type) -> Result<bool, fmt::Error> {
+                match *ty {
+                    ::Type::Class(ref names, _) if names.name == ::Name::NonTemplate("nsString".into()) => {
+                        w.write_str("nsAString")?;
+                        Ok(true)
+                    }
+                    _ => Ok(false),
+                }
+            }
+        }
+
+        let parsed = ::parse("?f@mozilla@@YAXAEBVnsString@@PEAVnsCString@@@Z").unwrap();
+        assert_eq!(
+            ::serialize_with_name_writer(&parsed, ::DemangleOptions::new(), &mut Hooks).unwrap(),
+            "void __cdecl moz::f(nsAString const &,class nsCString *)"
+        );
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn deprecated_flags() {
+        assert_eq!(
+            ::demangle("?x@@3PEAHEA", ::DemangleFlags::LessWhitespace),
+            ::demangle("?x@@3PEAHEA", ::DemangleOptions::LESS_WHITESPACE),
+        );
+        assert_eq!(
+            ::demangle("?x@@3PEAHEA", ::DemangleFlags::LotsOfWhitespace),
+            ::demangle("?x@@3PEAHEA", ::DemangleOptions::new()),
         );
     }
 }
@@ -0,0 +1,867 @@
+// This file is dual licensed under the MIT and the University of Illinois Open
+// Source Licenses. See LICENSE.TXT for details.
+//
+// The reverse of the demangler: the decorated name of a simple C++
+// declaration, e.g. for link-time lookups in binding generators and tests.
+
+use std::borrow::Cow;
+
+use super::{
+    CallingConv, Error, FuncClass, Name, NameSequence, Operator, Params, ParseOptions, ParserState, Result,
+    StorageClass, Symbol, Type,
+};
+
+/// The MSVC-decorated name of the C++ function or variable declared by
+/// `declaration`, for 32-bit x86:
+///
+/// ```
+/// use msvc_demangler::mangle::mangle;
+///
+/// assert_eq!(mangle("int ns::Foo::bar(char const*) const").unwrap(), "?bar@Foo@ns@@QBEHPBD@Z");
+/// assert_eq!(mangle("void __stdcall f(int, int)").unwrap(), "?f@@YGXHH@Z");
+/// assert_eq!(mangle("static int Foo::count").unwrap(), "?count@Foo@@2HA");
+/// ```
+///
+/// Declarations can use the built-in types, including the `int64_t`,
+/// `uint64_t` and `std::nullptr_t` that `demangle()` writes, classes,
+/// structs, unions and enums, pointers and references, `const` and
+/// `volatile`, templates with type and integer arguments, calling
+/// conventions and operators.
+/// Functions with a return type are members when they have an access
+/// specifier, `static`, `virtual` or `const` or `volatile` after the
+/// parameters, or are operators that must be members, such as
+/// `operator=`, and free functions otherwise, as declarations don't tell
+/// classes from namespaces; members are public unless specified. Class
+/// names default to `class`. Function pointers, arrays and conversion
+/// operators are not supported.
+pub fn mangle(declaration: &str) -> Result<String> {
+    let declaration = DeclParser {
+        input: declaration,
+        pos: 0,
+    }
+    .declaration()?;
+    let mut mangler = Mangler::default();
+    mangler.declaration(&declaration)?;
+    Ok(mangler.out)
+}
+
+struct Declaration<'a> {
+    symbol: Symbol<'a>,
+    access: Option<FuncClass>,
+    storage: Option<FuncClass>,
+    kind: DeclarationKind<'a>,
+}
+
+enum DeclarationKind<'a> {
+    Variable(Type<'a>),
+    Function {
+        return_type: Type<'a>,
+        calling_conv: Option<CallingConv>,
+        params: Vec<Type<'a>>,
+        variadic: bool,
+        this_quals: StorageClass,
+    },
+}
+
+// The declarable operators, with their spellings without `operator` and
+// their codes, found by running the parser on every code.
+fn operators() -> Vec<(&'static str, String)> {
+    let mut codes: Vec<String> = Vec::new();
+    for c in (b'0'..=b'9').chain(b'A'..=b'Z') {
+        codes.push((c as char).to_string());
+        codes.push(format!("_{}", c as char));
+    }
+    codes
+        .into_iter()
+        .filter_map(|code| {
+            let spelling = {
+                let mut state = ParserState::new(&code, ParseOptions::new());
+                let op = state.read_operator_name().ok()?;
+                let spelling = op.to_str().strip_prefix("operator")?.trim_start();
+                if !state.input.is_empty() || spelling.is_empty() || spelling == "cast" {
+                    return None;
+                }
+                spelling
+            };
+            Some((spelling, code))
+        })
+        .collect()
+}
+
+// A recursive descent parser of declarations into the types of the parser.
+struct DeclParser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> DeclParser<'a> {
+    fn error<T>(&self, what: &str) -> Result<T> {
+        Err(Error::new(format!("expected {} at offset {}: {:?}", what, self.pos, &self.input[self.pos..])))
+    }
+
+    fn rest(&mut self) -> &'a str {
+        let rest = &self.input[self.pos..];
+        let trimmed = rest.trim_start();
+        self.pos += rest.len() - trimmed.len();
+        trimmed
+    }
+
+    // Consumes the punctuation `token`.
+    fn consume(&mut self, token: &str) -> bool {
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<()> {
+        if self.consume(token) {
+            Ok(())
+        } else {
+            self.error(&format!("{:?}", token))
+        }
+    }
+
+    fn peek_identifier(&mut self) -> Option<&'a str> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+            .unwrap_or(rest.len());
+        if len == 0 || rest.as_bytes()[0].is_ascii_digit() {
+            None
+        } else {
+            Some(&rest[..len])
+        }
+    }
+
+    fn identifier(&mut self) -> Option<&'a str> {
+        let identifier = self.peek_identifier()?;
+        self.pos += identifier.len();
+        Some(identifier)
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        if self.peek_identifier() == Some(keyword) {
+            self.pos += keyword.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn cv(&mut self) -> StorageClass {
+        let mut sc = StorageClass::empty();
+        loop {
+            if self.keyword("const") {
+                sc |= StorageClass::CONST;
+            } else if self.keyword("volatile") {
+                sc |= StorageClass::VOLATILE;
+            } else {
+                return sc;
+            }
+        }
+    }
+
+    fn calling_conv(&mut self) -> Option<CallingConv> {
+        let calling_conv = match self.peek_identifier()? {
+            "__cdecl" => CallingConv::Cdecl,
+            "__pascal" => CallingConv::Pascal,
+            "__thiscall" => CallingConv::Thiscall,
+            "__stdcall" => CallingConv::Stdcall,
+            "__fastcall" => CallingConv::Fastcall,
+            _ => return None,
+        };
+        self.identifier();
+        Some(calling_conv)
+    }
+
+    fn declaration(&mut self) -> Result<Declaration<'a>> {
+        let access = if self.keyword("public") {
+            Some(FuncClass::PUBLIC)
+        } else if self.keyword("protected") {
+            Some(FuncClass::PROTECTED)
+        } else if self.keyword("private") {
+            Some(FuncClass::PRIVATE)
+        } else {
+            None
+        };
+        if access.is_some() {
+            self.expect(":")?;
+        }
+        let storage = if self.keyword("static") {
+            Some(FuncClass::STATIC)
+        } else if self.keyword("virtual") {
+            Some(FuncClass::VIRTUAL)
+        } else {
+            None
+        };
+
+        // Constructors and destructors have no return type.
+        let start = self.pos;
+        let calling_conv = self.calling_conv();
+        if let Ok(symbol) = self.qualified_name(true) {
+            if matches!(symbol.name, Name::Operator(Operator::Ctor | Operator::Dtor)) && self.consume("(") {
+                return self.function(symbol, access, storage, Type::None, calling_conv);
+            }
+        }
+        self.pos = start;
+
+        let t = self.type_()?;
+        let calling_conv = self.calling_conv();
+        let symbol = self.qualified_name(true)?;
+        if self.consume("(") {
+            self.function(symbol, access, storage, t, calling_conv)
+        } else if calling_conv.is_some() {
+            self.error("\"(\"")
+        } else {
+            self.end()?;
+            Ok(Declaration {
+                symbol,
+                access,
+                storage,
+                kind: DeclarationKind::Variable(t),
+            })
+        }
+    }
+
+    fn end(&mut self) -> Result<()> {
+        if self.rest().is_empty() {
+            Ok(())
+        } else {
+            self.error("the end of the declaration")
+        }
+    }
+
+    fn function(
+        &mut self,
+        symbol: Symbol<'a>,
+        access: Option<FuncClass>,
+        storage: Option<FuncClass>,
+        return_type: Type<'a>,
+        calling_conv: Option<CallingConv>,
+    ) -> Result<Declaration<'a>> {
+        let mut params = Vec::new();
+        let mut variadic = false;
+        let start = self.pos;
+        let empty = self.consume(")") || (self.keyword("void") && self.consume(")"));
+        if !empty {
+            self.pos = start;
+            loop {
+                if self.consume("...") {
+                    variadic = true;
+                    self.expect(")")?;
+                    break;
+                }
+                params.push(self.type_()?);
+                if self.consume(")") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        let this_quals = self.cv();
+        self.end()?;
+        Ok(Declaration {
+            symbol,
+            access,
+            storage,
+            kind: DeclarationKind::Function {
+                return_type,
+                calling_conv,
+                params,
+                variadic,
+                this_quals,
+            },
+        })
+    }
+
+    // A type: qualifiers, a built-in type or class name, and pointers and
+    // references to it.
+    fn type_(&mut self) -> Result<Type<'a>> {
+        let mut sc = self.cv();
+        let mut t = self.base_type()?;
+        sc |= self.cv();
+        set_storage_class(&mut t, sc);
+        loop {
+            if self.consume("&&") {
+                t = Type::RValueRef(Box::new(t), StorageClass::empty());
+            } else if self.consume("&") {
+                t = Type::Ref(Box::new(t), StorageClass::empty());
+            } else if self.consume("*") {
+                t = Type::Ptr(Box::new(t), StorageClass::empty());
+            } else {
+                break;
+            }
+            let mut sc = self.cv();
+            if self.keyword("__ptr64") {
+                sc |= StorageClass::PTR64;
+            }
+            set_storage_class(&mut t, sc);
+        }
+        Ok(t)
+    }
+
+    fn base_type(&mut self) -> Result<Type<'a>> {
+        let sc = StorageClass::empty();
+        let class = |symbol| Box::new(symbol);
+        if self.keyword("class") {
+            return Ok(Type::Class(class(self.qualified_name(false)?), sc));
+        } else if self.keyword("struct") {
+            return Ok(Type::Struct(class(self.qualified_name(false)?), sc));
+        } else if self.keyword("union") {
+            return Ok(Type::Union(class(self.qualified_name(false)?), sc));
+        } else if self.keyword("enum") {
+            let symbol = class(self.qualified_name(false)?);
+            return Ok(Type::Enum(symbol, Box::new(Type::Int(sc)), sc));
+        }
+
+        // The typedefs the demangler writes for some built-in types.
+        let start = self.pos;
+        if self.keyword("std") && !self.consume("::") {
+            self.pos = start;
+        }
+        let typedef = match self.identifier() {
+            Some("nullptr_t") => Some(Type::Nullptr),
+            Some("int64_t") => Some(Type::Int64(sc)),
+            Some("uint64_t") => Some(Type::Uint64(sc)),
+            _ => None,
+        };
+        match typedef {
+            Some(t) if !self.rest().starts_with("::") => return Ok(t),
+            _ => self.pos = start,
+        }
+
+        let mut words = Vec::new();
+        while let Some(word) = self.peek_identifier() {
+            let builtin = [
+                "void", "bool", "char", "signed", "unsigned", "short", "int", "long", "float", "double", "wchar_t",
+                "char16_t", "char32_t", "__int64",
+            ];
+            if !builtin.contains(&word) {
+                break;
+            }
+            self.identifier();
+            words.push(word);
+        }
+        if words.is_empty() {
+            return Ok(Type::Class(class(self.qualified_name(false)?), sc));
+        }
+        let has = |word| words.contains(&word);
+        let longs = words.iter().filter(|&&word| word == "long").count();
+        let unsigned = has("unsigned");
+        Ok(if has("void") {
+            Type::Void(sc)
+        } else if has("bool") {
+            Type::Bool(sc)
+        } else if has("char") {
+            if unsigned {
+                Type::Uchar(sc)
+            } else if has("signed") {
+                Type::Schar(sc)
+            } else {
+                Type::Char(sc)
+            }
+        } else if has("wchar_t") {
+            Type::Wchar(sc)
+        } else if has("char16_t") {
+            Type::Char16(sc)
+        } else if has("char32_t") {
+            Type::Char32(sc)
+        } else if has("float") {
+            Type::Float(sc)
+        } else if has("double") {
+            if longs > 0 {
+                Type::Ldouble(sc)
+            } else {
+                Type::Double(sc)
+            }
+        } else if has("short") {
+            if unsigned {
+                Type::Ushort(sc)
+            } else {
+                Type::Short(sc)
+            }
+        } else if longs > 1 || has("__int64") {
+            if unsigned {
+                Type::Uint64(sc)
+            } else {
+                Type::Int64(sc)
+            }
+        } else if longs == 1 {
+            if unsigned {
+                Type::Ulong(sc)
+            } else {
+                Type::Long(sc)
+            }
+        } else if unsigned {
+            Type::Uint(sc)
+        } else {
+            Type::Int(sc)
+        })
+    }
+
+    // A name such as `ns::Foo<int>::bar`. Only the names of functions and
+    // variables can be operators, constructors or destructors.
+    fn qualified_name(&mut self, declarator: bool) -> Result<Symbol<'a>> {
+        self.consume("::");
+        let mut names = vec![self.unqualified_name(declarator, None)?];
+        while self.consume("::") {
+            let class = names.last();
+            let name = self.unqualified_name(declarator, class)?;
+            names.push(name);
+        }
+        let name = names.pop().unwrap();
+        names.reverse();
+        Ok(Symbol {
+            name,
+            scope: NameSequence { names },
+        })
+    }
+
+    fn unqualified_name(&mut self, declarator: bool, class: Option<&Name<'a>>) -> Result<Name<'a>> {
+        let class_name = match class {
+            Some(Name::NonTemplate(name)) => Some(&name[..]),
+            Some(Name::Template(name, _)) => match **name {
+                Name::NonTemplate(ref name) => Some(&name[..]),
+                _ => None,
+            },
+            _ => None,
+        };
+        if declarator && self.consume("~") {
+            return match self.identifier() {
+                Some(name) if Some(name) == class_name => Ok(Name::Operator(Operator::Dtor)),
+                _ => self.error("the name of the class"),
+            };
+        }
+        let name = match self.identifier() {
+            Some("operator") if declarator => return self.operator(),
+            Some(name) if declarator && Some(name) == class_name && !self.rest().starts_with("::") => {
+                return Ok(Name::Operator(Operator::Ctor));
+            }
+            Some(name) => Name::NonTemplate(Cow::Borrowed(name)),
+            None => return self.error("a name"),
+        };
+        if !self.consume("<") {
+            return Ok(name);
+        }
+        let mut args = Vec::new();
+        if !self.consume(">") {
+            loop {
+                args.push(self.template_arg()?);
+                if self.consume(">") {
+                    break;
+                }
+                self.expect(",")?;
+            }
+        }
+        Ok(Name::Template(Box::new(name), Params { types: args }))
+    }
+
+    fn template_arg(&mut self) -> Result<Type<'a>> {
+        let rest = self.rest();
+        let len = rest
+            .char_indices()
+            .find(|&(i, c)| !(c.is_ascii_digit() || (i == 0 && c == '-')))
+            .map_or(rest.len(), |(i, _)| i);
+        if len == 0 || &rest[..len] == "-" {
+            return self.type_();
+        }
+        match rest[..len].parse() {
+            Ok(n) => {
+                self.pos += len;
+                Ok(Type::Constant(n))
+            }
+            Err(_) => self.error("a 32-bit integer"),
+        }
+    }
+
+    fn operator(&mut self) -> Result<Name<'a>> {
+        let rest = self.rest();
+        let spelling = operators()
+            .into_iter()
+            .filter(|&(spelling, _)| {
+                rest.starts_with(spelling)
+                    && !(spelling.ends_with(|c: char| c.is_ascii_alphabetic())
+                        && rest[spelling.len()..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_'))
+            })
+            .map(|(spelling, _)| spelling)
+            .max_by_key(|spelling| spelling.len());
+        let spelling = match spelling {
+            Some(spelling) => spelling,
+            None => return self.error("an operator"),
+        };
+        self.pos += spelling.len();
+        let code = operators().into_iter().find(|&(s, _)| s == spelling).unwrap().1;
+        let mut state = ParserState::new(&code, ParseOptions::new());
+        let op = state.read_operator_name()?.into_owned();
+        Ok(Name::Operator(op))
+    }
+}
+
+fn set_storage_class(t: &mut Type, storage_class: StorageClass) {
+    match *t {
+        Type::Ptr(_, ref mut sc)
+        | Type::Ref(_, ref mut sc)
+        | Type::RValueRef(_, ref mut sc)
+        | Type::Struct(_, ref mut sc)
+        | Type::Union(_, ref mut sc)
+        | Type::Class(_, ref mut sc)
+        | Type::Enum(_, _, ref mut sc)
+        | Type::Void(ref mut sc)
+        | Type::Bool(ref mut sc)
+        | Type::Char(ref mut sc)
+        | Type::Schar(ref mut sc)
+        | Type::Uchar(ref mut sc)
+        | Type::Short(ref mut sc)
+        | Type::Ushort(ref mut sc)
+        | Type::Int(ref mut sc)
+        | Type::Uint(ref mut sc)
+        | Type::Long(ref mut sc)
+        | Type::Ulong(ref mut sc)
+        | Type::Int64(ref mut sc)
+        | Type::Uint64(ref mut sc)
+        | Type::Wchar(ref mut sc)
+        | Type::Char16(ref mut sc)
+        | Type::Char32(ref mut sc)
+        | Type::Float(ref mut sc)
+        | Type::Double(ref mut sc)
+        | Type::Ldouble(ref mut sc) => *sc |= storage_class,
+        _ => {}
+    }
+}
+
+// Writes the decorated name, back-referencing names and parameter types the
+// way the parser reads them.
+#[derive(Default)]
+struct Mangler<'a> {
+    out: String,
+    // The first 10 names, by their mangled form.
+    names: Vec<String>,
+    // The first 10 parameter types longer than one character.
+    types: Vec<Type<'a>>,
+}
+
+impl<'a> Mangler<'a> {
+    fn declaration(&mut self, declaration: &Declaration<'a>) -> Result<()> {
+        self.out.push('?');
+        self.symbol(&declaration.symbol, true)?;
+        let is_structor = matches!(declaration.symbol.name, Name::Operator(Operator::Ctor | Operator::Dtor));
+        match declaration.kind {
+            DeclarationKind::Variable(ref t) => {
+                self.out.push(match (declaration.access, declaration.storage) {
+                    (_, Some(FuncClass::VIRTUAL)) => return Err(Error::new("virtual variable".to_owned())),
+                    (Some(FuncClass::PRIVATE), _) => '0',
+                    (Some(FuncClass::PROTECTED), _) => '1',
+                    (Some(_), _) | (None, Some(_)) => '2',
+                    (None, None) => '3',
+                });
+                self.type_(t)?;
+                let sc = match *t {
+                    Type::Ptr(..) | Type::Ref(..) | Type::RValueRef(..) => StorageClass::empty(),
+                    ref t => t.storage_class(),
+                };
+                self.out.push(cv_code(sc));
+            }
+            DeclarationKind::Function {
+                ref return_type,
+                calling_conv,
+                ref params,
+                variadic,
+                this_quals,
+            } => {
+                // Assignment, call, subscript and arrow operators can only
+                // be members.
+                let member = is_structor
+                    || matches!(
+                        declaration.symbol.name,
+                        Name::Operator(Operator::Equal | Operator::Call | Operator::Subscript | Operator::Arrow)
+                    )
+                    || declaration.access.is_some()
+                    || declaration.storage.is_some()
+                    || !this_quals.is_empty();
+                let is_static = declaration.storage == Some(FuncClass::STATIC);
+                if !member {
+                    self.out.push('Y');
+                } else {
+                    let column = match declaration.storage {
+                        Some(FuncClass::STATIC) => 2,
+                        Some(_) => 4,
+                        None => 0,
+                    };
+                    let row = match declaration.access {
+                        Some(FuncClass::PRIVATE) => b'A',
+                        Some(FuncClass::PROTECTED) => b'I',
+                        _ => b'Q',
+                    };
+                    self.out.push((row + column) as char);
+                    if is_static && !this_quals.is_empty() {
+                        return Err(Error::new("static member function with qualifiers".to_owned()));
+                    }
+                    if !is_static {
+                        self.out.push(cv_code(this_quals));
+                    }
+                }
+                let default = if member && !is_static { CallingConv::Thiscall } else { CallingConv::Cdecl };
+                self.out.push(match calling_conv.unwrap_or(default) {
+                    CallingConv::Cdecl => 'A',
+                    CallingConv::Pascal => 'C',
+                    CallingConv::Thiscall => 'E',
+                    CallingConv::Stdcall => 'G',
+                    CallingConv::Fastcall | CallingConv::_Regcall => 'I',
+                });
+                // Returned classes and qualified values have their
+                // qualifiers written first, `?A` for none.
+                match *return_type {
+                    Type::None => self.out.push('@'),
+                    Type::Ptr(..) | Type::Ref(..) | Type::RValueRef(..) => self.type_(return_type)?,
+                    ref t => {
+                        let class = matches!(*t, Type::Class(..) | Type::Struct(..) | Type::Union(..) | Type::Enum(..));
+                        if class || !t.storage_class().is_empty() {
+                            self.out.push('?');
+                            self.out.push(cv_code(t.storage_class()));
+                        }
+                        self.type_(t)?;
+                    }
+                }
+                if params.is_empty() && !variadic {
+                    self.out.push('X');
+                } else {
+                    self.params(params)?;
+                    self.out.push(if variadic { 'Z' } else { '@' });
+                }
+                self.out.push('Z');
+            }
+        }
+        Ok(())
+    }
+
+    // Writes `A@B@C@@` for `C::B::A`. The names of functions and variables
+    // can be operators and are not memorized if they are templates.
+    fn symbol(&mut self, symbol: &Symbol<'a>, declarator: bool) -> Result<()> {
+        match symbol.name {
+            Name::Operator(ref op) => {
+                self.out.push('?');
+                self.out.push_str(&operator_code(op)?);
+            }
+            ref name => self.name(name, !declarator)?,
+        }
+        for name in &symbol.scope.names {
+            self.name(name, true)?;
+        }
+        self.out.push('@');
+        Ok(())
+    }
+
+    fn name(&mut self, name: &Name<'a>, memorize: bool) -> Result<()> {
+        let mangled = match *name {
+            Name::NonTemplate(ref name) => format!("{}@", name),
+            Name::Template(ref name, ref args) => {
+                // Templates have their own context for back-references.
+                let mut mangler = Mangler::default();
+                mangler.out.push_str("?$");
+                mangler.name(name, true)?;
+                mangler.template_args(&args.types)?;
+                mangler.out.push('@');
+                mangler.out
+            }
+            _ => return Err(Error::new(format!("can't mangle {:?}", name))),
+        };
+        if let Some(i) = self.names.iter().position(|m| *m == mangled) {
+            self.out.push((b'0' + i as u8) as char);
+            return Ok(());
+        }
+        if (memorize || matches!(*name, Name::NonTemplate(_))) && self.names.len() < 10 {
+            self.names.push(mangled.clone());
+        }
+        self.out.push_str(&mangled);
+        Ok(())
+    }
+
+    fn params(&mut self, params: &[Type<'a>]) -> Result<()> {
+        self.params_with(params, Self::type_)
+    }
+
+    // Template arguments, unlike parameters, keep their qualifiers, e.g.
+    // `$$CBH` for the `int const` of `std::pair<int const,double>`.
+    fn template_args(&mut self, args: &[Type<'a>]) -> Result<()> {
+        self.params_with(args, |this, t| {
+            let sc = t.storage_class() & (StorageClass::CONST | StorageClass::VOLATILE);
+            if !matches!(*t, Type::Ptr(..) | Type::Ref(..) | Type::RValueRef(..)) && !sc.is_empty() {
+                this.out.push_str("$$C");
+                this.out.push(cv_code(sc));
+            }
+            this.type_(t)
+        })
+    }
+
+    // Writes `params` with `write`, back-referencing the repeated ones.
+    fn params_with<F>(&mut self, params: &[Type<'a>], mut write: F) -> Result<()>
+    where
+        F: FnMut(&mut Self, &Type<'a>) -> Result<()>,
+    {
+        for t in params {
+            if let Some(i) = self.types.iter().position(|m| m == t) {
+                self.out.push((b'0' + i as u8) as char);
+                continue;
+            }
+            let start = self.out.len();
+            write(self, t)?;
+            if self.out.len() - start > 1 && self.types.len() < 10 {
+                self.types.push(t.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn type_(&mut self, t: &Type<'a>) -> Result<()> {
+        let code = match *t {
+            Type::Ptr(ref inner, sc) | Type::Ref(ref inner, sc) | Type::RValueRef(ref inner, sc) => {
+                self.out.push_str(match *t {
+                    Type::Ref(..) => "A",
+                    Type::RValueRef(..) => "$$Q",
+                    _ => ["P", "Q", "R", "S"][cv_code(sc) as usize - 'A' as usize],
+                });
+                if sc.contains(StorageClass::PTR64) {
+                    self.out.push('E');
+                }
+                let inner_sc = match **inner {
+                    Type::Ptr(..) | Type::Ref(..) | Type::RValueRef(..) => StorageClass::empty(),
+                    ref inner => inner.storage_class(),
+                };
+                self.out.push(cv_code(inner_sc));
+                return self.type_(inner);
+            }
+            Type::Class(ref symbol, _)
+            | Type::Struct(ref symbol, _)
+            | Type::Union(ref symbol, _)
+            | Type::Enum(ref symbol, _, _) => {
+                self.out.push_str(match *t {
+                    Type::Class(..) => "V",
+                    Type::Struct(..) => "U",
+                    Type::Union(..) => "T",
+                    _ => "W4",
+                });
+                return self.symbol(symbol, false);
+            }
+            Type::Constant(n) => {
+                self.out.push_str("$0");
+                self.number(n);
+                return Ok(());
+            }
+            Type::Nullptr => "$$T",
+            Type::Void(_) => "X",
+            Type::Bool(_) => "_N",
+            Type::Char(_) => "D",
+            Type::Schar(_) => "C",
+            Type::Uchar(_) => "E",
+            Type::Short(_) => "F",
+            Type::Ushort(_) => "G",
+            Type::Int(_) => "H",
+            Type::Uint(_) => "I",
+            Type::Long(_) => "J",
+            Type::Ulong(_) => "K",
+            Type::Int64(_) => "_J",
+            Type::Uint64(_) => "_K",
+            Type::Wchar(_) => "_W",
+            Type::Char16(_) => "_S",
+            Type::Char32(_) => "_U",
+            Type::Float(_) => "M",
+            Type::Double(_) => "N",
+            Type::Ldouble(_) => "O",
+            _ => return Err(Error::new(format!("can't mangle {:?}", t))),
+        };
+        self.out.push_str(code);
+        Ok(())
+    }
+
+    // <number> ::= [?] <decimal digit>  # 1 to 10
+    //          ::= [?] <hex digit>+ @   # 0 or more than 10, A = 0, B = 1, ...
+    fn number(&mut self, n: i32) {
+        if n < 0 {
+            self.out.push('?');
+        }
+        let n = n.unsigned_abs();
+        if (1..=10).contains(&n) {
+            self.out.push((b'0' + n as u8 - 1) as char);
+            return;
+        }
+        let digits = format!("{:X}", n);
+        for digit in digits.bytes() {
+            let value = (digit as char).to_digit(16).unwrap() as u8;
+            self.out.push((b'A' + value) as char);
+        }
+        self.out.push('@');
+    }
+}
+
+fn cv_code(sc: StorageClass) -> char {
+    match (sc.contains(StorageClass::CONST), sc.contains(StorageClass::VOLATILE)) {
+        (false, false) => 'A',
+        (true, false) => 'B',
+        (false, true) => 'C',
+        (true, true) => 'D',
+    }
+}
+
+fn operator_code(op: &Operator) -> Result<String> {
+    if let Operator::Ctor | Operator::Dtor = *op {
+        return Ok(if *op == Operator::Ctor { "0" } else { "1" }.to_owned());
+    }
+    operators()
+        .into_iter()
+        .find(|&(spelling, _)| op.to_str().strip_prefix("operator").map(str::trim_start) == Some(spelling))
+        .map(|(_, code)| code)
+        .ok_or_else(|| Error::new(format!("can't mangle {:?}", op)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mangle;
+
+    #[test]
+    fn declarations() {
+        // Each declaration, its decorated name, and how that demangles.
+        let cases = [
+            ("int ns::Foo::bar(char const*) const", "?bar@Foo@ns@@QBEHPBD@Z", "public: int __thiscall ns::Foo::bar(char const *)const "),
+            ("void __stdcall f(int, int)", "?f@@YGXHH@Z", "void __stdcall f(int,int)"),
+            ("static int Foo::count", "?count@Foo@@2HA", "int Foo::count"),
+            ("int const x", "?x@@3HB", "int x"),
+            ("int* p", "?p@@3PAHA", "int *p"),
+            ("Foo::Foo(int)", "??0Foo@@QAE@H@Z", "public: __thiscall Foo::Foo(int)"),
+            ("virtual Foo::~Foo()", "??1Foo@@UAE@XZ", "public: virtual __thiscall Foo::~Foo(void)"),
+            ("private: static void __cdecl Foo::g(void)", "?g@Foo@@CAXXZ", "private: static void __cdecl Foo::g(void)"),
+            (
+                "std::vector<int> make(std::vector<int>, std::vector<int> const&)",
+                "?make@@YA?AV?$vector@H@std@@V12@ABV12@@Z",
+                "class std::vector<int> __cdecl make(class std::vector<int>,class std::vector<int> const &)",
+            ),
+            ("void f(Foo*, Foo*, Foo&&, ...)", "?f@@YAXPAVFoo@@0$$QAV1@ZZ", "void __cdecl f(class Foo *,class Foo *,class Foo &&,...)"),
+            ("Foo& Foo::operator=(Foo const&)", "??4Foo@@QAEAAV0@ABV0@@Z", "public: class Foo & __thiscall Foo::operator=(class Foo const &)"),
+            ("bool operator==(Foo const&, Foo const&)", "??8@YA_NABVFoo@@0@Z", "bool __cdecl operator==(class Foo const &,class Foo const &)"),
+            ("void* operator new[](unsigned int)", "??_U@YAPAXI@Z", "void * __cdecl operator new[](unsigned int)"),
+            ("void f(A<1, -5, 100>)", "?f@@YAXV?$A@$00$0?4$0GE@@@@Z", "void __cdecl f(class A<1,-5,100>)"),
+            ("struct S const f(enum E, union U)", "?f@@YA?BUS@@W4E@@TU@@@Z", "struct S const __cdecl f(enum E,union U)"),
+            ("void f(int* const, long double, unsigned __int64)", "?f@@YAXQAHO_K@Z", "void __cdecl f(int * const,long double,uint64_t)"),
+            ("int64_t __cdecl f(uint64_t)", "?f@@YA_J_K@Z", "int64_t __cdecl f(uint64_t)"),
+            ("void f(std::nullptr_t, nullptr_t, std::int64_t)", "?f@@YAX$$T0_J@Z", "void __cdecl f(std::nullptr_t,std::nullptr_t,int64_t)"),
+            ("void f(struct std::pair<int const, double>)", "?f@@YAXU?$pair@$$CBHN@std@@@Z", "void __cdecl f(struct std::pair<int const,double>)"),
+            (
+                "void f(class std::map<int,double,struct std::less<int>,class std::allocator<struct std::pair<int const,double> > >)",
+                "?f@@YAXV?$map@HNU?$less@H@std@@V?$allocator@U?$pair@$$CBHN@std@@@2@@std@@@Z",
+                "void __cdecl f(class std::map<int,double,struct std::less<int>,class std::allocator<struct std::pair<int const,double> > >)",
+            ),
+        ];
+        for &(declaration, mangled, demangled) in &cases {
+            assert_eq!(mangle(declaration).as_ref().map(|s| &s[..]), Ok(mangled), "{}", declaration);
+            assert_eq!(::demangle(mangled, ::DemangleOptions::new()).unwrap(), demangled);
+        }
+
+        let err = mangle("int f() junk").unwrap_err();
+        assert_eq!(err.to_string(), "expected the end of the declaration at offset 8: \"junk\"");
+        assert!(mangle("void f(int").is_err());
+        assert!(mangle("void (*f)(int)").is_err());
+        assert!(mangle("Foo::~Bar()").is_err());
+    }
+}
@@ -0,0 +1,128 @@
+// This file is dual licensed under the MIT and the University of Illinois Open
+// Source Licenses. See LICENSE.TXT for details.
+//
+// Python bindings, enabled with the "python" feature. Build the module with
+//
+//   maturin build --release --features python
+//
+// or with
+//
+//   cargo rustc --release --lib --features python --crate-type cdylib
+//
+// and rename the library to msvc_demangler.so (msvc_demangler.pyd on
+// Windows). Then
+//
+//   >>> import msvc_demangler
+//   >>> msvc_demangler.demangle("?f@@YAXXZ")
+//   'void __cdecl f(void)'
+
+// The code #[pyfunction] generates for functions returning PyResult.
+#![allow(clippy::useless_conversion)]
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use super::{parse, serialize, DemangleOptions, Error, OptionFlags};
+
+fn value_error(err: Error) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+/// Demangles `name`. `flags` is a combination of the flag constants of
+/// this module. Raises a ValueError if `name` is not a valid MSVC-mangled
+/// name.
+#[pyfunction]
+#[pyo3(signature = (name, flags = 0))]
+fn demangle(name: &str, flags: u32) -> PyResult<String> {
+    super::demangle(name, DemangleOptions::from_bits_truncate(flags)).map_err(value_error)
+}
+
+/// Demangles `name`, or returns it unchanged if it is not a valid
+/// MSVC-mangled name.
+#[pyfunction]
+#[pyo3(signature = (name, flags = 0))]
+fn try_demangle(name: &str, flags: u32) -> String {
+    super::try_demangle(name, DemangleOptions::from_bits_truncate(flags)).into_owned()
+}
+
+/// The parts of a demangled symbol, as returned by `parse()`. The parts are
+/// formatted with the flags given to `parse()`.
+#[pyclass(frozen, get_all)]
+struct Symbol {
+    /// The complete demangled name.
+    demangled: String,
+    /// What the symbol is, e.g. "Function" or "GlobalVariable".
+    kind: String,
+    /// The unqualified name, e.g. "push_back".
+    name: String,
+    /// The namespaces and classes the symbol is declared in, or "".
+    scope: String,
+    /// The return type of a function, or None.
+    return_type: Option<String>,
+    /// The parameter types of a function.
+    parameters: Vec<String>,
+}
+
+#[pymethods]
+impl Symbol {
+    fn __repr__(&self) -> String {
+        format!("<Symbol {}: {}>", self.kind, self.demangled)
+    }
+
+    fn __str__(&self) -> String {
+        self.demangled.clone()
+    }
+}
+
+/// Parses `name` into a Symbol. Raises a ValueError if `name` is not a
+/// valid MSVC-mangled name.
+#[pyfunction]
+#[pyo3(name = "parse", signature = (name, flags = 0))]
+fn parse_symbol(name: &str, flags: u32) -> PyResult<Symbol> {
+    let flags = DemangleOptions::from_bits_truncate(flags);
+    let parsed = parse(name).map_err(value_error)?;
+    Ok(Symbol {
+        demangled: serialize(&parsed, flags).map_err(value_error)?,
+        kind: format!("{:?}", parsed.kind()),
+        name: parsed.function_name(flags).map_err(value_error)?,
+        scope: parsed.qualified_scope(flags).map_err(value_error)?,
+        return_type: parsed.return_type_string(flags).map_err(value_error)?,
+        parameters: parsed.parameter_strings(flags).map_err(value_error)?,
+    })
+}
+
+#[pymodule]
+fn msvc_demangler(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(self::demangle, m)?)?;
+    m.add_function(wrap_pyfunction!(self::try_demangle, m)?)?;
+    m.add_function(wrap_pyfunction!(self::parse_symbol, m)?)?;
+    m.add_class::<Symbol>()?;
+    // The same values as in the C interface, see include/msvc_demangler.h.
+    let flags = [
+        ("LESS_WHITESPACE", OptionFlags::LESS_WHITESPACE),
+        ("NO_RETURN_TYPE", OptionFlags::NO_RETURN_TYPE),
+        ("NO_ACCESS_SPECIFIERS", OptionFlags::NO_ACCESS_SPECIFIERS),
+        ("NO_MEMBER_TYPE", OptionFlags::NO_MEMBER_TYPE),
+        ("NO_ARGUMENTS", OptionFlags::NO_ARGUMENTS),
+        ("NO_CV_THISTYPE", OptionFlags::NO_CV_THISTYPE),
+        ("UNDNAME_COMPAT", OptionFlags::UNDNAME_COMPAT),
+        ("LLVM_UNDNAME_COMPAT", OptionFlags::LLVM_UNDNAME_COMPAT),
+        ("NO_MS_KEYWORDS", OptionFlags::NO_MS_KEYWORDS),
+        ("GNU_STYLE", OptionFlags::GNU_STYLE),
+        ("NO_CLASS_KEYWORDS", OptionFlags::NO_CLASS_KEYWORDS),
+        ("STD_TYPEDEFS", OptionFlags::STD_TYPEDEFS),
+        ("NO_DEFAULT_TEMPLATE_ARGS", OptionFlags::NO_DEFAULT_TEMPLATE_ARGS),
+        ("ENUM_UNDERLYING_TYPES", OptionFlags::ENUM_UNDERLYING_TYPES),
+        ("NO_LEADING_UNDERSCORES", OptionFlags::NO_LEADING_UNDERSCORES),
+        ("NO_SPECIAL_SYMS", OptionFlags::NO_SPECIAL_SYMS),
+        ("NO_TEMPLATE_ARGS", OptionFlags::NO_TEMPLATE_ARGS),
+        ("DESCRIBE_SPECIAL_SYMS", OptionFlags::DESCRIBE_SPECIAL_SYMS),
+        ("NAME_ONLY", OptionFlags::NAME_ONLY),
+        ("ANONYMOUS_NAMESPACE_PARENS", OptionFlags::ANONYMOUS_NAMESPACE_PARENS),
+        ("STRICT", OptionFlags::STRICT),
+    ];
+    for &(name, flag) in &flags {
+        m.add(name, flag.bits())?;
+    }
+    Ok(())
+}
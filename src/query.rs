@@ -0,0 +1,224 @@
+// This file is dual licensed under the MIT and the University of Illinois Open
+// Source Licenses. See LICENSE.TXT for details.
+//
+// Matching of parsed function symbols against a name and parameter types,
+// e.g. to find an overload in a symbol table without writing every symbol
+// as a string.
+
+use super::{resolve_name, Name, NameSequence, Operator, ParseResult, Symbol, Type};
+
+/// A function to look for: its name, optionally qualified, and the number
+/// or the types of its parameters.
+///
+/// ```
+/// use msvc_demangler::query::{Query, TypePattern};
+/// use msvc_demangler::{parse, StorageClass, Type};
+///
+/// let int = TypePattern::Exact(Type::Int(StorageClass::empty()));
+/// let query = Query::new("Foo::bar").params(vec![int.clone(), int]);
+/// let symbols = ["?bar@Foo@@QAEXH@Z", "?bar@Foo@@QAEXHH@Z", "?bar@Foo@@QAEXHN@Z"];
+/// let found: Vec<_> = symbols.iter().cloned().filter(|s| query.matches(&parse(s).unwrap())).collect();
+/// assert_eq!(found, ["?bar@Foo@@QAEXHH@Z"]);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Query {
+    // The components of the name, outermost first.
+    name: Vec<String>,
+    arity: Option<usize>,
+    params: Option<Vec<TypePattern>>,
+}
+
+/// A pattern for a parameter type, see `Query::params()`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum TypePattern {
+    /// Any type.
+    Any,
+    /// Exactly this type, including its `const` and `volatile`
+    /// qualifiers, e.g. `Type::Int(StorageClass::empty())`. Class types are
+    /// better matched with `Named`, as their names may be back-references.
+    Exact(Type<'static>),
+    /// A class, struct, union or enum with the given name, qualified as in
+    /// `Query::new()`, with any template arguments and qualifiers.
+    Named(String),
+    /// A pointer to a type matching the pattern, with any qualifiers.
+    Pointer(Box<TypePattern>),
+    /// An lvalue or rvalue reference to a type matching the pattern.
+    Reference(Box<TypePattern>),
+}
+
+impl Query {
+    /// Looks for functions named `name`, e.g. `bar`, `Foo::bar` or
+    /// `ns::Foo::operator==`. A qualified name matches the innermost
+    /// scopes of the symbol, so `Foo::bar` matches `ns::Foo::bar` as well.
+    /// Template arguments are not part of the name: `vector::push_back`
+    /// matches `push_back` of all instances of `vector`. Constructors and
+    /// destructors are named like in C++, e.g. `Foo::Foo` and `Foo::~Foo`.
+    pub fn new(name: &str) -> Query {
+        Query {
+            name: name.split("::").map(|component| component.trim().to_owned()).collect(),
+            arity: None,
+            params: None,
+        }
+    }
+
+    /// Only matches functions with `arity` parameters, not counting the
+    /// `...` of variadic functions.
+    pub fn arity(mut self, arity: usize) -> Query {
+        self.arity = Some(arity);
+        self
+    }
+
+    /// Only matches functions whose parameters match `params`, one pattern
+    /// for each parameter.
+    pub fn params(mut self, params: Vec<TypePattern>) -> Query {
+        self.params = Some(params);
+        self
+    }
+
+    /// Whether `parse_result` is a function matching the query. Symbols
+    /// other than functions, such as variables and vftables, never match.
+    pub fn matches(&self, parse_result: &ParseResult) -> bool {
+        let signature = match parse_result.signature() {
+            Some(signature) => signature,
+            None => return false,
+        };
+        let params = signature.parameters();
+        if self.arity.is_some_and(|arity| arity != params.len()) {
+            return false;
+        }
+        if let Some(ref patterns) = self.params {
+            if patterns.len() != params.len()
+                || !patterns.iter().zip(params).all(|(pattern, t)| pattern.matches(t, &parse_result.names))
+            {
+                return false;
+            }
+        }
+        let names = &parse_result.names;
+        let symbol = &parse_result.symbol;
+        let (name, scope) = self.name.split_last().expect("split() returns at least one component");
+        let mut unqualified = resolve_name(names, &symbol.name);
+        while let Name::Template(ref template, _) = *unqualified {
+            unqualified = resolve_name(names, template);
+        }
+        let name_matches = match *unqualified {
+            // Constructors and destructors take the name of their class.
+            Name::Operator(Operator::Ctor) => class_name(names, symbol) == Some(name),
+            Name::Operator(Operator::Dtor) => {
+                name.strip_prefix('~').is_some_and(|name| class_name(names, symbol) == Some(name))
+            }
+            _ => identifier(names, unqualified) == Some(name),
+        };
+        name_matches && scope_matches(names, &symbol.scope, scope)
+    }
+}
+
+impl TypePattern {
+    // Whether `t`, a type of the symbol with the name table `names`,
+    // matches the pattern.
+    fn matches<'a>(&self, t: &Type<'a>, names: &[Name<'a>]) -> bool {
+        let t = t.resolve();
+        match *self {
+            TypePattern::Any => true,
+            TypePattern::Exact(ref expected) => expected == t,
+            TypePattern::Named(ref expected) => match *t {
+                Type::Class(ref symbol, _)
+                | Type::Struct(ref symbol, _)
+                | Type::Union(ref symbol, _)
+                | Type::Enum(ref symbol, _, _)
+                | Type::Typedef(ref symbol, _) => {
+                    let components: Vec<&str> = expected.split("::").map(str::trim).collect();
+                    let (name, scope) = components.split_last().expect("split() returns at least one component");
+                    identifier(names, &symbol.name) == Some(name) && scope_matches(names, &symbol.scope, scope)
+                }
+                _ => false,
+            },
+            TypePattern::Pointer(ref pointee) => match *t {
+                Type::Ptr(ref t, _) => pointee.matches(t, names),
+                _ => false,
+            },
+            TypePattern::Reference(ref referee) => match *t {
+                Type::Ref(ref t, _) | Type::RValueRef(ref t, _) => referee.matches(t, names),
+                _ => false,
+            },
+        }
+    }
+}
+
+// Whether the innermost names of `scope` are `components`, given
+// outermost first.
+fn scope_matches<'a, S: AsRef<str>>(names: &[Name<'a>], scope: &NameSequence<'a>, components: &[S]) -> bool {
+    components.len() <= scope.names.len()
+        && components
+            .iter()
+            .rev()
+            .zip(&scope.names)
+            .all(|(component, name)| identifier(names, name) == Some(component.as_ref()))
+}
+
+// The name of the class a constructor or destructor belongs to.
+fn class_name<'b, 'a>(names: &'b [Name<'a>], symbol: &'b Symbol<'a>) -> Option<&'b str> {
+    identifier(names, symbol.scope.names.first()?)
+}
+
+// The identifier of a name without its template arguments, or `None` for
+// names that have none, such as anonymous namespaces.
+fn identifier<'b, 'a>(names: &'b [Name<'a>], name: &'b Name<'a>) -> Option<&'b str> {
+    match *resolve_name(names, name) {
+        Name::NonTemplate(ref name) => Some(name),
+        Name::Template(ref name, _) => identifier(names, name),
+        Name::Operator(ref op) => Some(op.to_str()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Query, TypePattern};
+    use {StorageClass, Type};
+
+    #[test]
+    fn query() {
+        let matches = |query: &Query, input: &str| query.matches(&::parse(input).unwrap());
+        let int = TypePattern::Exact(Type::Int(StorageClass::empty()));
+
+        let query = Query::new("Foo::bar").params(vec![int.clone(), int.clone()]);
+        assert!(matches(&query, "?bar@Foo@@QAEXHH@Z"));
+        assert!(matches(&query, "?bar@Foo@ns@@SAHHH@Z"));
+        assert!(!matches(&query, "?bar@Foo@@QAEXH@Z"));
+        assert!(!matches(&query, "?bar@Foo@@QAEXHN@Z"));
+        assert!(!matches(&query, "?bar@Fooo@@QAEXHH@Z"));
+        assert!(!matches(&query, "?bar@@YAXHH@Z"));
+        assert!(!matches(&query, "?baz@Foo@@QAEXHH@Z"));
+        assert!(!matches(&Query::new("Foo::bar"), "?bar@Foo@@2HA"));
+
+        // Arity, without the `...` and `(void)`.
+        assert!(matches(&Query::new("f").arity(0), "?f@@YAXXZ"));
+        assert!(matches(&Query::new("f").arity(1), "?f@@YAXHZZ"));
+        assert!(!matches(&Query::new("f").arity(2), "?f@@YAXHZZ"));
+        assert!(matches(&Query::new("ns::f"), "?f@ns@@YAXHZZ"));
+        assert!(!matches(&Query::new("ns::f"), "?f@@YAXHZZ"));
+
+        // Constructors, destructors, operators and templates.
+        assert!(matches(&Query::new("Foo::Foo").arity(1), "??0Foo@@QAE@H@Z"));
+        assert!(matches(&Query::new("Foo::~Foo"), "??1Foo@@UAE@XZ"));
+        assert!(!matches(&Query::new("Foo::~Foo"), "??0Foo@@QAE@H@Z"));
+        assert!(matches(&Query::new("vector::vector"), "??0?$vector@H@std@@QAE@XZ"));
+        assert!(matches(&Query::new("Foo::operator=="), "??8Foo@@QBE_NABV0@@Z"));
+        assert!(matches(&Query::new("std::vector::push_back"), "?push_back@?$vector@HV?$allocator@H@std@@@std@@QAEXABH@Z"));
+        assert!(matches(&Query::new("max"), "??$max@H@@YAHHH@Z"));
+
+        // Parameter patterns, including back-referenced parameter types.
+        let foo_ref = TypePattern::Reference(Box::new(TypePattern::Named("Foo".to_owned())));
+        let query = Query::new("f").params(vec![foo_ref.clone(), foo_ref, TypePattern::Any]);
+        assert!(matches(&query, "?f@@YAXABVFoo@@0PAD@Z"));
+        assert!(matches(&query, "?f@@YAXAAUFoo@ns@@$$QAU12@H@Z"));
+        assert!(!matches(&query, "?f@@YAXPAVFoo@@0H@Z"));
+        let query = Query::new("f").params(vec![TypePattern::Pointer(Box::new(TypePattern::Named("ns::E".to_owned())))]);
+        assert!(matches(&query, "?f@@YAXPAW4E@ns@@@Z"));
+        assert!(!matches(&query, "?f@@YAXPAW4E@@@Z"));
+        let const_char_ptr = TypePattern::Exact(Type::pointer(Type::Char(StorageClass::CONST)));
+        assert!(matches(&Query::new("f").params(vec![const_char_ptr.clone()]), "?f@@YAXPBD@Z"));
+        assert!(!matches(&Query::new("f").params(vec![const_char_ptr]), "?f@@YAXPAD@Z"));
+    }
+}
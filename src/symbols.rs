@@ -0,0 +1,200 @@
+// This file is dual licensed under the MIT and the University of Illinois Open
+// Source Licenses. See LICENSE.TXT for details.
+//
+// Readers of the symbol tables of PDB files and object files, enabled with
+// the "pdb" and "object" features.
+
+#[cfg(feature = "pdb")]
+use pdb::FallibleIterator;
+
+#[cfg(feature = "object")]
+use object::ObjectSymbol;
+#[cfg(feature = "object")]
+use std::str;
+
+use super::{parse_with_options, serialize, DemangleOptions, Result, SymbolKind};
+
+/// A symbol of a symbol table and its demangled name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DemangledSymbol {
+    /// The address of the symbol, relative to the image base for PDB
+    /// files, or `None` if it has none.
+    pub address: Option<u64>,
+    pub mangled: String,
+    /// The kind of symbol, or `None` if it couldn't be demangled.
+    pub kind: Option<SymbolKind>,
+    /// The demangled name, or the error for names that aren't MSVC-mangled,
+    /// such as those of C functions.
+    pub demangled: Result<String>,
+}
+
+impl DemangledSymbol {
+    fn new(address: Option<u64>, mangled: String, flags: DemangleOptions) -> DemangledSymbol {
+        let parsed = parse_with_options(&mangled, flags);
+        let kind = parsed.as_ref().ok().map(|parsed| parsed.kind());
+        let demangled = parsed.and_then(|parsed| serialize(&parsed, flags));
+        DemangledSymbol {
+            address,
+            mangled,
+            kind,
+            demangled,
+        }
+    }
+}
+
+/// The public, procedure and data symbols of a PDB file, e.g. of its global
+/// symbol table, with their addresses translated by `address_map`.
+///
+/// ```no_run
+/// # extern crate msvc_demangler;
+/// # extern crate pdb;
+/// # fn main() -> pdb::Result<()> {
+/// use msvc_demangler::symbols::pdb_symbols;
+/// use msvc_demangler::DemangleOptions;
+///
+/// let mut pdb = pdb::PDB::open(std::fs::File::open("app.pdb")?)?;
+/// let symbol_table = pdb.global_symbols()?;
+/// let address_map = pdb.address_map()?;
+/// for symbol in pdb_symbols(symbol_table.iter(), &address_map, DemangleOptions::new()) {
+///     let symbol = symbol?;
+///     if let Ok(demangled) = symbol.demangled {
+///         println!("{:x?} {}", symbol.address, demangled);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "pdb")]
+pub fn pdb_symbols<'m, 's, 't, O: Into<DemangleOptions>>(
+    symbols: pdb::SymbolIter<'t>,
+    address_map: &'m pdb::AddressMap<'s>,
+    flags: O,
+) -> PdbSymbols<'m, 's, 't> {
+    PdbSymbols {
+        symbols,
+        address_map,
+        flags: flags.into(),
+    }
+}
+
+/// The iterator returned by `pdb_symbols()`.
+#[cfg(feature = "pdb")]
+pub struct PdbSymbols<'m, 's, 't> {
+    symbols: pdb::SymbolIter<'t>,
+    address_map: &'m pdb::AddressMap<'s>,
+    flags: DemangleOptions,
+}
+
+#[cfg(feature = "pdb")]
+impl<'m, 's, 't> Iterator for PdbSymbols<'m, 's, 't> {
+    type Item = pdb::Result<DemangledSymbol>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let symbol = match self.symbols.next() {
+                Ok(symbol) => symbol?,
+                Err(err) => return Some(Err(err)),
+            };
+            // Symbols of kinds the pdb crate can't parse don't have names.
+            let (name, offset) = match symbol.parse() {
+                Ok(pdb::SymbolData::Public(data)) => (data.name, data.offset),
+                Ok(pdb::SymbolData::Procedure(data)) => (data.name, data.offset),
+                Ok(pdb::SymbolData::Data(data)) => (data.name, data.offset),
+                _ => continue,
+            };
+            let address = offset.to_rva(self.address_map).map(|rva| u64::from(rva.0));
+            return Some(Ok(DemangledSymbol::new(address, name.to_string().into_owned(), self.flags)));
+        }
+    }
+}
+
+/// The named symbols of an object file, executable or library read with
+/// the `object` crate, e.g. those of `object::Object::symbols()`. Names
+/// that aren't valid UTF-8 are left out unless `DemangleOptions::LOSSY` is
+/// given.
+///
+/// ```no_run
+/// # extern crate msvc_demangler;
+/// # extern crate object;
+/// use msvc_demangler::symbols::object_symbols;
+/// use msvc_demangler::DemangleOptions;
+/// use object::Object;
+///
+/// let data = std::fs::read("app.obj").unwrap();
+/// let file = object::File::parse(&*data).unwrap();
+/// for symbol in object_symbols(file.symbols(), DemangleOptions::new()) {
+///     println!("{:x?} {}", symbol.address, symbol.demangled.unwrap_or(symbol.mangled));
+/// }
+/// ```
+#[cfg(feature = "object")]
+pub fn object_symbols<'data, I, O>(symbols: I, flags: O) -> ObjectSymbols<I::IntoIter>
+where
+    I: IntoIterator,
+    I::Item: object::ObjectSymbol<'data>,
+    O: Into<DemangleOptions>,
+{
+    ObjectSymbols {
+        symbols: symbols.into_iter(),
+        flags: flags.into(),
+    }
+}
+
+/// The iterator returned by `object_symbols()`.
+#[cfg(feature = "object")]
+pub struct ObjectSymbols<I> {
+    symbols: I,
+    flags: DemangleOptions,
+}
+
+#[cfg(feature = "object")]
+impl<'data, I> Iterator for ObjectSymbols<I>
+where
+    I: Iterator,
+    I::Item: object::ObjectSymbol<'data>,
+{
+    type Item = DemangledSymbol;
+
+    fn next(&mut self) -> Option<DemangledSymbol> {
+        loop {
+            let symbol = self.symbols.next()?;
+            let name = match symbol.name_bytes() {
+                Ok(name) if !name.is_empty() => name,
+                _ => continue,
+            };
+            // Names that aren't UTF-8 are skipped unless lossy.
+            let name = match str::from_utf8(name) {
+                Ok(name) => name.to_owned(),
+                Err(_) if self.flags.contains(DemangleOptions::LOSSY) => String::from_utf8_lossy(name).into_owned(),
+                Err(_) => continue,
+            };
+            let address = if symbol.is_undefined() { None } else { Some(symbol.address()) };
+            return Some(DemangledSymbol::new(address, name, self.flags));
+        }
+    }
+}
+
+#[cfg(all(test, feature = "object"))]
+mod tests {
+    #[test]
+    fn object_symbols() {
+        use object::Object;
+
+        // A COFF object without sections, with the absolute symbol
+        // "?x@@3HA" and the undefined symbol "main", which isn't mangled.
+        let mut data = vec![0x4c, 0x01, 0, 0, 0, 0, 0, 0, 20, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0];
+        data.extend_from_slice(&[0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0x20, 0, 2, 0]);
+        data.extend_from_slice(b"main\0\0\0\0\0\0\0\0\0\0\x20\0\x02\0");
+        data.extend_from_slice(b"\x0c\0\0\0?x@@3HA\0");
+        let file = object::File::parse(&*data).unwrap();
+        let symbols: Vec<_> = super::object_symbols(file.symbols(), ::DemangleOptions::new()).collect();
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols[0].address, Some(0));
+        assert_eq!(symbols[0].mangled, "?x@@3HA");
+        assert_eq!(symbols[0].kind, Some(::SymbolKind::GlobalVariable));
+        assert_eq!(symbols[0].demangled, Ok("int x".to_owned()));
+        assert_eq!(symbols[1].mangled, "main");
+        assert_eq!(symbols[1].address, None);
+        assert_eq!(symbols[1].kind, None);
+        assert!(symbols[1].demangled.is_err());
+    }
+}
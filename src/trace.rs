@@ -0,0 +1,123 @@
+// This file is dual licensed under the MIT and the University of Illinois Open
+// Source Licenses. See LICENSE.TXT for details.
+//
+// Tracing of the parser, enabled with the "trace" feature, to find out
+// where the parsing of an unusual symbol goes wrong.
+
+use std::fmt;
+use std::mem;
+use std::ops::Range;
+
+use super::{ParseOptions, ParseResult, ParserState, Result};
+
+/// A grammar production the parser went through, see `parse_traced()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceStep {
+    /// What was parsed, e.g. "type" or "template name".
+    pub production: &'static str,
+    /// The bytes of the input that make up the production. For a failed
+    /// production, the bytes read before the failure.
+    pub range: Range<usize>,
+    /// The number of productions this one is part of.
+    pub depth: usize,
+    /// Whether the production was parsed successfully.
+    pub ok: bool,
+}
+
+/// The productions the parser went through for a symbol, in the order
+/// they were started. Displaying a trace draws them as a tree, along with
+/// the input they cover:
+///
+/// ```text
+/// symbol                ?x@@3HA
+///   name                 x@@
+///     unqualified name   x@
+///       identifier       x@
+///     scope                @
+///   type                     H
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Trace<'a> {
+    input: &'a str,
+    pub steps: Vec<TraceStep>,
+}
+
+impl<'a> fmt::Display for Trace<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self
+            .steps
+            .iter()
+            .map(|step| 2 * step.depth + step.production.len())
+            .max()
+            .unwrap_or(0);
+        for step in &self.steps {
+            let label = format!("{:indent$}{}", "", step.production, indent = 2 * step.depth);
+            // The input is ASCII up to where the parser stops.
+            let covered = self.input.get(step.range.clone()).unwrap_or("");
+            write!(
+                f,
+                "{:width$}  {:offset$}{}",
+                label,
+                "",
+                covered,
+                width = width,
+                offset = step.range.start
+            )?;
+            if !step.ok {
+                write!(f, "  <- failed")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Like `parse_with_options()`, but also records the grammar productions
+/// the parser goes through. The trace ends where parsing failed, if it did.
+pub fn parse_traced<O: Into<ParseOptions>>(input: &str, options: O) -> (Result<ParseResult<'_>>, Trace<'_>) {
+    let mut state = ParserState::new(input, options.into());
+    state.trace = Some(Vec::new());
+    let mut result = state.parse();
+    if let Ok(ref mut parse_result) = result {
+        parse_result.names = mem::take(&mut state.names);
+    }
+    let trace = Trace {
+        input,
+        steps: state.trace.unwrap_or_default(),
+    };
+    (result, trace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_traced;
+
+    #[test]
+    fn trace() {
+        let (result, trace) = parse_traced("?x@@3HA", ::DemangleOptions::new());
+        assert!(result.is_ok());
+        assert_eq!(
+            trace.to_string(),
+            "\
+symbol                ?x@@3HA
+  name                 x@@
+    unqualified name   x@
+      identifier       x@
+    scope                @
+  type                     H
+"
+        );
+
+        // The trace shows where the parser gave up.
+        let (result, trace) = parse_traced("?x@@3_$A", ::DemangleOptions::new());
+        assert!(result.is_err());
+        let last = trace.steps.last().unwrap();
+        assert_eq!((last.production, last.ok), ("type", false));
+        assert!(trace.to_string().ends_with("  type                     _$  <- failed\n"));
+
+        // Only parse_traced() records the productions.
+        let mut state = ::ParserState::new("?x@@3HA", ::DemangleOptions::new().into());
+        assert!(state.parse().is_ok());
+        assert_eq!(state.trace, None);
+    }
+}
@@ -0,0 +1,352 @@
+// This file is dual licensed under the MIT and the University of Illinois Open
+// Source Licenses. See LICENSE.TXT for details.
+//
+// A tree of the namespaces and classes of many symbols, e.g. of a symbol
+// table, for class browsers and reports, and an inventory of the
+// constructors, tables and so on of each class.
+
+use std::collections::{btree_map, BTreeMap};
+use std::fmt;
+
+use super::{
+    parse_with_options, serialize, serialize_part, simplify, Access, ClassMember, DemangleOptions, ParseResult, Result, SymbolKind,
+    VariableKind,
+};
+
+/// The symbols of a symbol table, arranged by the namespaces and classes
+/// they are declared in.
+///
+/// ```
+/// use msvc_demangler::tree::SymbolTree;
+/// use msvc_demangler::DemangleOptions;
+///
+/// let symbols = ["?f@C@ns@@QAEXXZ", "??_7C@ns@@6B@", "?g@ns@@YAXXZ"];
+/// let tree = SymbolTree::from_symbols(symbols.iter().cloned(), DemangleOptions::new());
+/// let class = tree.get(&["ns", "C"]).unwrap();
+/// assert!(class.is_class());
+/// assert_eq!(class.members.len(), 2);
+/// assert_eq!(tree.get(&["ns"]).unwrap().members[0].name, "g");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolTree {
+    flags: DemangleOptions,
+    root: Scope,
+    unparsed: Vec<String>,
+}
+
+/// A namespace or class, or the global namespace at the root of a
+/// `SymbolTree`. Mangled names don't tell namespaces and classes apart, see
+/// `is_class()`. Local scopes such as `` `f'::`2' `` are scopes, too.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Scope {
+    /// The name of the scope within its parent, e.g. `vector<int>`. Empty
+    /// for the global namespace.
+    pub name: String,
+    /// The scopes declared in this one, by name.
+    pub children: BTreeMap<String, Scope>,
+    /// The symbols declared in this scope, in the order they were added.
+    pub members: Vec<Member>,
+}
+
+/// A function, variable, table or other symbol in a `Scope`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Member {
+    pub mangled: String,
+    /// The unqualified name, e.g. `push_back` or `` `vftable' ``.
+    pub name: String,
+    /// The complete demangled symbol.
+    pub demangled: String,
+    pub kind: SymbolKind,
+    /// The access specifier of a member function or static data member,
+    /// or `None` for other symbols.
+    pub access: Option<Access>,
+}
+
+impl SymbolTree {
+    /// Creates an empty tree, writing names with the options `flags`.
+    pub fn new<O: Into<DemangleOptions>>(flags: O) -> SymbolTree {
+        SymbolTree {
+            flags: flags.into(),
+            root: Scope::default(),
+            unparsed: Vec::new(),
+        }
+    }
+
+    /// Creates a tree of `symbols`. Symbols that can't be demangled are
+    /// listed by `unparsed()`.
+    pub fn from_symbols<'s, I, O>(symbols: I, flags: O) -> SymbolTree
+    where
+        I: IntoIterator<Item = &'s str>,
+        O: Into<DemangleOptions>,
+    {
+        let mut tree = SymbolTree::new(flags);
+        for symbol in symbols {
+            if tree.insert(symbol).is_err() {
+                tree.unparsed.push(symbol.to_owned());
+            }
+        }
+        tree
+    }
+
+    /// Adds the symbol `mangled` to the scope it is declared in, creating
+    /// the scope and its parents as needed.
+    pub fn insert(&mut self, mangled: &str) -> Result<()> {
+        let mut parsed = parse_with_options(mangled, self.flags)?;
+        simplify(&mut parsed, self.flags);
+        let path = scope_path(&parsed, self.flags)?;
+        let member = Member {
+            mangled: mangled.to_owned(),
+            name: parsed.function_name(self.flags)?,
+            demangled: serialize(&parsed, self.flags)?,
+            kind: parsed.kind(),
+            access: match parsed.variable_kind() {
+                Some(VariableKind::StaticMember(access)) => Some(access),
+                _ => parsed.signature().and_then(|signature| signature.access()),
+            },
+        };
+        let mut scope = &mut self.root;
+        for name in path {
+            scope = scope.children.entry(name.clone()).or_insert_with(|| Scope {
+                name,
+                ..Scope::default()
+            });
+        }
+        scope.members.push(member);
+        Ok(())
+    }
+
+    /// The global namespace.
+    pub fn root(&self) -> &Scope {
+        &self.root
+    }
+
+    /// The scope with the names `path`, outermost first, e.g.
+    /// `["std", "vector<int>"]`.
+    pub fn get(&self, path: &[&str]) -> Option<&Scope> {
+        path.iter().try_fold(&self.root, |scope, name| scope.children.get(*name))
+    }
+
+    /// The symbols `from_symbols()` couldn't demangle.
+    pub fn unparsed(&self) -> &[String] {
+        &self.unparsed
+    }
+}
+
+impl Scope {
+    /// Whether this is a class, as far as its members tell: it has member
+    /// functions, static data members or virtual tables.
+    pub fn is_class(&self) -> bool {
+        self.members.iter().any(|member| {
+            member.access.is_some()
+                || matches!(
+                    member.kind,
+                    SymbolKind::StaticMemberVariable | SymbolKind::VTable | SymbolKind::VBTable
+                )
+        })
+    }
+}
+
+// Draws the scopes as an indented tree, each followed by its members.
+impl fmt::Display for SymbolTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_scope(f, &self.root, 0)
+    }
+}
+
+fn write_scope(f: &mut fmt::Formatter, scope: &Scope, depth: usize) -> fmt::Result {
+    for member in &scope.members {
+        writeln!(f, "{:indent$}{}", "", member.demangled, indent = 2 * depth)?;
+    }
+    for child in scope.children.values() {
+        writeln!(f, "{:indent$}{}", "", child.name, indent = 2 * depth)?;
+        write_scope(f, child, depth + 1)?;
+    }
+    Ok(())
+}
+
+// The names of the scopes `parsed` is declared in, outermost first.
+fn scope_path(parsed: &ParseResult, flags: DemangleOptions) -> Result<Vec<String>> {
+    let names = &parsed.symbol.scope.names;
+    (0..names.len())
+        .rev()
+        .map(|i| {
+            serialize_part(flags, &parsed.names, |serializer| {
+                serializer.write_one_name(&names[i])
+            })
+        })
+        .collect()
+}
+
+/// The constructors, destructors, tables and RTTI data of the classes in
+/// a symbol table, by class, see `ParseResult::class_member()`.
+///
+/// ```
+/// use msvc_demangler::tree::ClassInventory;
+/// use msvc_demangler::DemangleOptions;
+///
+/// let symbols = ["??0C@ns@@QAE@XZ", "??_GC@ns@@UAEPAXI@Z", "??_7C@ns@@6B@", "??_R4C@ns@@6B@"];
+/// let inventory = ClassInventory::from_symbols(symbols.iter().cloned(), DemangleOptions::new());
+/// let class = inventory.get("ns::C").unwrap();
+/// assert_eq!(class.constructors, ["??0C@ns@@QAE@XZ"]);
+/// assert_eq!(class.rtti, ["??_R4C@ns@@6B@"]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClassInventory {
+    flags: DemangleOptions,
+    classes: BTreeMap<String, ClassSymbols>,
+}
+
+/// The mangled constructors, destructors, tables and RTTI data of a class.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClassSymbols {
+    pub constructors: Vec<String>,
+    pub destructors: Vec<String>,
+    pub deleting_destructors: Vec<String>,
+    pub vftables: Vec<String>,
+    pub vbtables: Vec<String>,
+    pub rtti: Vec<String>,
+}
+
+impl ClassInventory {
+    /// Creates an empty inventory, writing class names with the options
+    /// `flags`.
+    pub fn new<O: Into<DemangleOptions>>(flags: O) -> ClassInventory {
+        ClassInventory {
+            flags: flags.into(),
+            classes: BTreeMap::new(),
+        }
+    }
+
+    /// Creates an inventory of `symbols`, skipping those that can't be
+    /// demangled.
+    pub fn from_symbols<'s, I, O>(symbols: I, flags: O) -> ClassInventory
+    where
+        I: IntoIterator<Item = &'s str>,
+        O: Into<DemangleOptions>,
+    {
+        let mut inventory = ClassInventory::new(flags);
+        for symbol in symbols {
+            let _ = inventory.insert(symbol);
+        }
+        inventory
+    }
+
+    /// Adds the symbol `mangled` to its class. Returns whether it belongs to
+    /// a class.
+    pub fn insert(&mut self, mangled: &str) -> Result<bool> {
+        let parsed = parse_with_options(mangled, self.flags)?;
+        let (class, member) = match parsed.class_member(self.flags)? {
+            Some(class_member) => class_member,
+            None => return Ok(false),
+        };
+        let symbols = self.classes.entry(class).or_default();
+        let list = match member {
+            ClassMember::Constructor => &mut symbols.constructors,
+            ClassMember::Destructor => &mut symbols.destructors,
+            ClassMember::DeletingDestructor => &mut symbols.deleting_destructors,
+            ClassMember::VFTable => &mut symbols.vftables,
+            ClassMember::VBTable => &mut symbols.vbtables,
+            ClassMember::Rtti => &mut symbols.rtti,
+        };
+        list.push(mangled.to_owned());
+        Ok(true)
+    }
+
+    /// The symbols of the class `class`, written as with the options of
+    /// the inventory, e.g. `std::vector<int,class std::allocator<int> >`.
+    pub fn get(&self, class: &str) -> Option<&ClassSymbols> {
+        self.classes.get(class)
+    }
+
+    /// The classes and their symbols, by name.
+    pub fn classes(&self) -> btree_map::Iter<'_, String, ClassSymbols> {
+        self.classes.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClassInventory, SymbolTree};
+
+    #[test]
+    fn class_inventory() {
+        let symbols = [
+            "??0C@ns@@QAE@XZ",
+            "??0C@ns@@QAE@ABV01@@Z",
+            "??1C@ns@@UAE@XZ",
+            "??_GC@ns@@UAEPAXI@Z",
+            "??_EC@ns@@UAEPAXI@Z",
+            "??_7C@ns@@6B@",
+            "??_8C@ns@@7B@",
+            "??_R0?AVC@ns@@@8",
+            "??_R4C@ns@@6B@",
+            "?f@C@ns@@QAEXXZ",
+            "??0D@@QAE@XZ",
+            "??_R0H@8",
+        ];
+        let flags = ::DemangleOptions::new();
+        let member = |input| ::parse(input).unwrap().class_member(flags).unwrap();
+        assert_eq!(member("??0C@ns@@QAE@XZ"), Some(("ns::C".to_owned(), ::ClassMember::Constructor)));
+        assert_eq!(member("??_R0?AVC@ns@@@8"), Some(("ns::C".to_owned(), ::ClassMember::Rtti)));
+        assert_eq!(member("?f@C@ns@@QAEXXZ"), None);
+        assert_eq!(member("??_R0H@8"), None);
+
+        let inventory = ClassInventory::from_symbols(symbols.iter().cloned(), flags);
+        assert_eq!(
+            inventory.classes().map(|(name, _)| &name[..]).collect::<Vec<_>>(),
+            ["D", "ns::C"]
+        );
+        let class = inventory.get("ns::C").unwrap();
+        assert_eq!(class.constructors, ["??0C@ns@@QAE@XZ", "??0C@ns@@QAE@ABV01@@Z"]);
+        assert_eq!(class.destructors, ["??1C@ns@@UAE@XZ"]);
+        assert_eq!(class.deleting_destructors, ["??_GC@ns@@UAEPAXI@Z", "??_EC@ns@@UAEPAXI@Z"]);
+        assert_eq!(class.vftables, ["??_7C@ns@@6B@"]);
+        assert_eq!(class.vbtables, ["??_8C@ns@@7B@"]);
+        assert_eq!(class.rtti, ["??_R0?AVC@ns@@@8", "??_R4C@ns@@6B@"]);
+    }
+
+    #[test]
+    fn symbol_tree() {
+        let symbols = [
+            "??0C@ns@@QAE@XZ",
+            "?f@C@ns@@QAEXXZ",
+            "?x@C@ns@@2HA",
+            "??_7C@ns@@6B@",
+            "?g@ns@@YAXXZ",
+            "?size@?$vector@H@std@@QBEIXZ",
+            "?h@@YAXXZ",
+            "not a symbol",
+        ];
+        let tree = SymbolTree::from_symbols(symbols.iter().cloned(), ::DemangleOptions::cxxfilt());
+        assert_eq!(tree.unparsed(), &["not a symbol".to_owned()]);
+        let class = tree.get(&["ns", "C"]).unwrap();
+        assert!(class.is_class());
+        assert!(!tree.get(&["ns"]).unwrap().is_class());
+        let members: Vec<_> = class.members.iter().map(|member| (&member.name[..], member.kind)).collect();
+        assert_eq!(
+            members,
+            [
+                ("C", ::SymbolKind::Function),
+                ("f", ::SymbolKind::Function),
+                ("x", ::SymbolKind::StaticMemberVariable),
+                ("`vftable'", ::SymbolKind::VTable),
+            ]
+        );
+        assert_eq!(class.members[1].access, Some(::Access::Public));
+        assert_eq!(class.members[2].access, Some(::Access::Public));
+        assert_eq!(
+            tree.to_string(),
+            "h()\n\
+             ns\n\
+            \x20 ns::g()\n\
+            \x20 C\n\
+            \x20   ns::C::C()\n\
+            \x20   ns::C::f()\n\
+            \x20   int ns::C::x\n\
+            \x20   const ns::C::`vftable'\n\
+             std\n\
+            \x20 vector<int>\n\
+            \x20   std::vector<int>::size() const\n"
+        );
+    }
+}
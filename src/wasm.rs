@@ -0,0 +1,32 @@
+// This file is dual licensed under the MIT and the University of Illinois Open
+// Source Licenses. See LICENSE.TXT for details.
+//
+// WebAssembly bindings, enabled with the "wasm" feature, e.g. for
+//
+//   wasm-pack build --target web -- --features wasm
+
+use wasm_bindgen::prelude::*;
+
+use super::{demangle_html as demangle_html_impl, push_html_escaped, try_demangle, DemangleOptions};
+
+/// Demangles `input`, or returns it unchanged if it isn't a valid
+/// MSVC-mangled name. `flags` takes the same values as the `flags` of the
+/// C interface, see include/msvc_demangler.h.
+#[wasm_bindgen]
+pub fn demangle(input: &str, flags: u32) -> String {
+    try_demangle(input, DemangleOptions::from_bits_truncate(flags)).into_owned()
+}
+
+/// Like `demangle()`, but returns HTML with the parts of the name wrapped
+/// in `<span>`s, see `msvc_demangler::serialize_html()`. Invalid names are
+/// returned escaped.
+#[wasm_bindgen]
+pub fn demangle_html(input: &str, flags: u32) -> String {
+    demangle_html_impl(input, DemangleOptions::from_bits_truncate(flags)).unwrap_or_else(|_| {
+        let mut html = String::with_capacity(input.len());
+        for c in input.chars() {
+            push_html_escaped(&mut html, c);
+        }
+        html
+    })
+}
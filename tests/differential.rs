@@ -0,0 +1,156 @@
+// Compares our output against the output of llvm-undname, recorded in
+// tests/corpus/llvm-undname.tsv.gz as lines of "<mangled name>\t<output>".
+// The names are mostly those of real programs, along with a sample of the
+// string literals, which all demangle alike.
+//
+// Run with `cargo test --test differential -- --nocapture` to see a
+// scoreboard of the differences by kind of symbol. To add names to the
+// corpus, append the output of
+//
+//   printf '%s\t%s\n' <name> "$(llvm-undname -- <name> | sed -n 2p)"
+//
+// for them to the uncompressed file and compress it again with
+// `gzip -9n`. The names we are known to demangle differently are listed in
+// tests/corpus/llvm-undname-mismatches.txt; the test fails if any other
+// name differs, and if a listed name matches, so that the list only
+// shrinks.
+
+extern crate flate2;
+extern crate msvc_demangler;
+
+use flate2::read::GzDecoder;
+use msvc_demangler::DemangleOptions;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+
+const CORPUS: &[u8] = include_bytes!("corpus/llvm-undname.tsv.gz");
+const MISMATCHES: &str = include_str!("corpus/llvm-undname-mismatches.txt");
+
+#[derive(Default)]
+struct Score {
+    matches: usize,
+    diffs: Vec<(String, String, String)>,
+}
+
+#[test]
+fn llvm_undname() {
+    let known: BTreeSet<&str> = MISMATCHES.lines().filter(|line| !line.is_empty()).collect();
+    let mut scores: BTreeMap<String, Score> = BTreeMap::new();
+    let mut mismatches = BTreeSet::new();
+    let mut corpus = String::new();
+    GzDecoder::new(CORPUS).read_to_string(&mut corpus).unwrap();
+    for line in corpus.lines() {
+        let mut fields = line.splitn(2, '\t');
+        let (input, reference) = match (fields.next(), fields.next()) {
+            (Some(input), Some(reference)) => (input, reference),
+            _ => panic!("bad corpus line: {:?}", line),
+        };
+        let (category, output) = match msvc_demangler::parse(input) {
+            Ok(parsed) => {
+                let output = msvc_demangler::serialize(&parsed, DemangleOptions::llvm_undname());
+                (format!("{:?}", parsed.kind()), output)
+            }
+            Err(err) => ("parse error".to_owned(), Err(err)),
+        };
+        let score = scores.entry(category).or_default();
+        match output {
+            Ok(ref output) if output == reference => score.matches += 1,
+            output => {
+                mismatches.insert(input);
+                score.diffs.push((input.to_owned(), reference.to_owned(), format!("{:?}", output)));
+            }
+        }
+    }
+
+    let mut matches = 0;
+    let mut total = 0;
+    for score in scores.values() {
+        for (input, reference, output) in &score.diffs {
+            println!("{}\n  llvm-undname: {}\n  ours:         {}", input, reference, output);
+        }
+        matches += score.matches;
+        total += score.matches + score.diffs.len();
+    }
+    println!();
+    for (category, score) in &scores {
+        println!(
+            "{:>24}: {:4} of {:4} match",
+            category,
+            score.matches,
+            score.matches + score.diffs.len()
+        );
+    }
+    println!("{:>24}: {:4} of {:4} match", "total", matches, total);
+
+    let unexpected: Vec<&str> = mismatches.difference(&known).cloned().collect();
+    assert!(
+        unexpected.is_empty(),
+        "these names differ from llvm-undname:\n{}",
+        unexpected.join("\n")
+    );
+    let fixed: Vec<&str> = known.difference(&mismatches).cloned().collect();
+    assert!(
+        fixed.is_empty(),
+        "these names match llvm-undname now, remove them from llvm-undname-mismatches.txt:\n{}",
+        fixed.join("\n")
+    );
+}
+
+// demangle_qualified_name() is a shortcut for demangle() with NAME_ONLY,
+// so the two must agree on every name of the corpus that demangle()
+// accepts. The shortcut doesn't read the parameters, so it also accepts
+// names whose parameters are invalid.
+#[test]
+fn qualified_name() {
+    let mut corpus = String::new();
+    GzDecoder::new(CORPUS).read_to_string(&mut corpus).unwrap();
+    let mut diffs = Vec::new();
+    for line in corpus.lines() {
+        let input = line.split('\t').next().unwrap();
+        for &flags in &[DemangleOptions::new(), DemangleOptions::llvm_undname(), DemangleOptions::cxxfilt()] {
+            let name_only = match msvc_demangler::demangle(input, flags | DemangleOptions::NAME_ONLY) {
+                Ok(name_only) => name_only,
+                Err(_) => continue,
+            };
+            let qualified = msvc_demangler::demangle_qualified_name(input, flags);
+            if qualified.as_ref() != Ok(&name_only) {
+                diffs.push(format!("{}\n  NAME_ONLY: {:?}\n  qualified: {:?}", input, name_only, qualified));
+            }
+        }
+    }
+    assert!(diffs.is_empty(), "{} differences:\n{}", diffs.len(), diffs.join("\n"));
+}
+
+// mangle() is the reverse of demangle(): the declarations it accepts must
+// mangle back into the names of the corpus they were demangled from. The
+// demangled names don't tell 64-bit pointers, static members or the
+// spelling of back-references apart, so the names are compared by how they
+// demangle.
+#[test]
+fn mangle_round_trip() {
+    let mut corpus = String::new();
+    GzDecoder::new(CORPUS).read_to_string(&mut corpus).unwrap();
+    let mut round_trips = 0;
+    let mut diffs = Vec::new();
+    for line in corpus.lines() {
+        let input = line.split('\t').next().unwrap();
+        let demangled = match msvc_demangler::demangle(input, DemangleOptions::new()) {
+            Ok(demangled) => demangled,
+            Err(_) => continue,
+        };
+        let mangled = match msvc_demangler::mangle::mangle(&demangled) {
+            Ok(mangled) => mangled,
+            Err(_) => continue,
+        };
+        round_trips += 1;
+        let again = msvc_demangler::demangle(&mangled, DemangleOptions::new());
+        if again.as_ref() != Ok(&demangled) {
+            diffs.push(format!(
+                "{}\n  demangled: {}\n  mangled:   {}\n  again:     {:?}",
+                input, demangled, mangled, again
+            ));
+        }
+    }
+    assert!(round_trips > 200, "only {} declarations mangle", round_trips);
+    assert!(diffs.is_empty(), "{} differences:\n{}", diffs.len(), diffs.join("\n"));
+}
@@ -0,0 +1,33 @@
+// Checks that `FormatProfile::V1` reproduces the output of msvc-demangler
+// 0.5.1. tests/corpus/format-v1.tsv holds lines of
+// "<mangled name>\t<output>\t<output with LessWhitespace>", as written by
+// 0.5.1 with `DemangleFlags::LotsOfWhitespace` and `LessWhitespace`, for
+// the names of its test suite that it could demangle.
+//
+// The corpus must never change: the output of a profile is frozen.
+
+extern crate msvc_demangler;
+
+use msvc_demangler::{DemangleOptions, FormatProfile};
+
+const CORPUS: &str = include_str!("corpus/format-v1.tsv");
+
+#[test]
+fn format_profile_v1() {
+    // The options that `DemangleFlags::LotsOfWhitespace` and
+    // `LessWhitespace` stand for.
+    let lots_flags = DemangleOptions::new().format_profile(FormatProfile::V1);
+    let less_flags = DemangleOptions::LESS_WHITESPACE.format_profile(FormatProfile::V1);
+    let mut lines = 0;
+    for line in CORPUS.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        let (input, lots, less) = match fields[..] {
+            [input, lots, less] => (input, lots, less),
+            _ => panic!("bad corpus line: {:?}", line),
+        };
+        assert_eq!(msvc_demangler::demangle(input, lots_flags).as_deref(), Ok(lots), "{}", input);
+        assert_eq!(msvc_demangler::demangle(input, less_flags).as_deref(), Ok(less), "{}", input);
+        lines += 1;
+    }
+    assert_eq!(lines, 542);
+}